@@ -0,0 +1,173 @@
+//! Conversion latency and throughput, and the cost of the text store's
+//! `GetText` and lock handling. Needs a Japanese TIP for the conversion
+//! groups.
+
+#[cfg(windows)]
+mod benches {
+    use std::{cell::RefCell, hint::black_box, rc::Rc};
+
+    use criterion::{BenchmarkId, Criterion, Throughput};
+    use iatjc_rs::{com::Com, pool::TsfPool, store_trace, tsf::TSF};
+    use windows::Win32::{
+        UI::TextServices::{ITextStoreACP, ITextStoreACPSink, ITextStoreACPSink_Impl, TEXT_STORE_LOCK_FLAGS, TEXT_STORE_TEXT_CHANGE_FLAGS, TS_E_SYNCHRONOUS, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_RUNINFO, TS_S_ASYNC, TS_TEXTCHANGE, TsLayoutCode},
+    };
+    use windows_core::{implement, IUnknown, Interface, GUID, HRESULT};
+
+    const READINGS: [&str; 4] = ["へんかん", "にほんご", "きょうはいいてんきです", "わたしはにほんごをべんきょうしています"];
+
+    type OnLock = Rc<RefCell<Option<Box<dyn Fn(&ITextStoreACP, u32)>>>>;
+
+    /// Runs `on_lock` against the store with the flags of every lock granted.
+    #[implement(ITextStoreACPSink)]
+    struct Sink {
+        store: RefCell<Option<ITextStoreACP>>,
+        on_lock: OnLock,
+    }
+
+    impl ITextStoreACPSink_Impl for Sink {
+        fn OnTextChange(&self, _dwflags: TEXT_STORE_TEXT_CHANGE_FLAGS, _pchange: *const TS_TEXTCHANGE) -> windows_core::Result<()> {
+            Ok(())
+        }
+
+        fn OnSelectionChange(&self) -> windows_core::Result<()> {
+            Ok(())
+        }
+
+        fn OnLayoutChange(&self, _lcode: TsLayoutCode, _vcview: u32) -> windows_core::Result<()> {
+            Ok(())
+        }
+
+        fn OnStatusChange(&self, _dwflags: u32) -> windows_core::Result<()> {
+            Ok(())
+        }
+
+        fn OnAttrsChange(&self, _acpstart: i32, _acpend: i32, _cattrs: u32, _paattrs: *const GUID) -> windows_core::Result<()> {
+            Ok(())
+        }
+
+        fn OnLockGranted(&self, dwlockflags: TEXT_STORE_LOCK_FLAGS) -> windows_core::Result<()> {
+            if let Some(store) = &*self.store.borrow() && let Some(on_lock) = &*self.on_lock.borrow() {
+                on_lock(store, dwlockflags.0);
+            }
+            Ok(())
+        }
+
+        fn OnStartEditTransaction(&self) -> windows_core::Result<()> {
+            Ok(())
+        }
+
+        fn OnEndEditTransaction(&self) -> windows_core::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A standalone store holding `text`, with a [`Sink`] advised.
+    fn advised_store(text: &str) -> (ITextStoreACP, OnLock) {
+        let store = store_trace::store(text).unwrap();
+        let on_lock: OnLock = Rc::new(RefCell::new(None));
+        let sink: ITextStoreACPSink = Sink { store: RefCell::new(Some(store.clone())), on_lock: on_lock.clone() }.into();
+        let unknown: IUnknown = sink.cast().unwrap();
+        unsafe { store.AdviseSink(&ITextStoreACPSink::IID, &unknown, 0) }.unwrap();
+        (store, on_lock)
+    }
+
+    fn request_lock(store: &ITextStoreACP, flags: u32) -> HRESULT {
+        unsafe { store.RequestLock(flags) }.unwrap()
+    }
+
+    pub fn convert(c: &mut Criterion) {
+        let com = Com::new().unwrap();
+        let mut tsf = TSF::new(&com);
+        tsf.initialize().unwrap();
+
+        let mut group = c.benchmark_group("convert");
+        for reading in READINGS {
+            group.bench_with_input(BenchmarkId::from_parameter(reading.chars().count()), reading, |b, reading| {
+                b.iter(|| tsf.convert(black_box(reading)).unwrap());
+            });
+        }
+        group.finish();
+
+        tsf.uninitialize().unwrap();
+    }
+
+    pub fn batch(c: &mut Criterion) {
+        let readings: Vec<&str> = READINGS.iter().copied().cycle().take(32).collect();
+
+        let mut group = c.benchmark_group("convert_batch");
+        group.throughput(Throughput::Elements(readings.len() as u64));
+        group.sample_size(10);
+        for size in [1, 2, 4] {
+            let pool = TsfPool::spawn(size).unwrap();
+            group.bench_with_input(BenchmarkId::from_parameter(size), &readings, |b, readings| {
+                b.iter(|| {
+                    for result in pool.convert_batch(readings) {
+                        result.unwrap();
+                    }
+                });
+            });
+        }
+        group.finish();
+    }
+
+    pub fn get_text(c: &mut Criterion) {
+        let _com = Com::new().unwrap();
+
+        let mut group = c.benchmark_group("get_text");
+        for len in [1 << 10, 1 << 16, 1 << 20] {
+            let text: String = "へんかんテキスト".chars().cycle().take(len).collect();
+            let (store, on_lock) = advised_store(&text);
+            *on_lock.borrow_mut() = Some(Box::new(move |store, _| {
+                let mut buffer = vec![0u16; len];
+                let mut runs = [TS_RUNINFO::default(); 1];
+                let (mut copied, mut run_count, mut next) = (0, 0, 0);
+                unsafe { store.GetText(0, -1, &mut buffer, &mut copied, &mut runs, &mut run_count, &mut next) }.unwrap();
+                black_box(&buffer);
+            }));
+
+            group.throughput(Throughput::Elements(len as u64));
+            group.bench_function(BenchmarkId::from_parameter(len), |b| {
+                b.iter(|| request_lock(&store, TS_LF_READ.0 | TS_LF_SYNC));
+            });
+        }
+        group.finish();
+    }
+
+    pub fn lock(c: &mut Criterion) {
+        let _com = Com::new().unwrap();
+        let (store, on_lock) = advised_store("へんかん");
+
+        let mut group = c.benchmark_group("lock");
+        group.bench_function("uncontended", |b| {
+            *on_lock.borrow_mut() = None;
+            b.iter(|| request_lock(&store, TS_LF_READWRITE.0 | TS_LF_SYNC));
+        });
+
+        // A synchronous request made while the store is locked is refused.
+        group.bench_function("contended_sync", |b| {
+            *on_lock.borrow_mut() = Some(Box::new(|store, _| {
+                assert_eq!(request_lock(store, TS_LF_READ.0 | TS_LF_SYNC), TS_E_SYNCHRONOUS);
+            }));
+            b.iter(|| request_lock(&store, TS_LF_READWRITE.0 | TS_LF_SYNC));
+        });
+
+        // An asynchronous one is queued and granted once the lock is released.
+        group.bench_function("contended_async", |b| {
+            *on_lock.borrow_mut() = Some(Box::new(|store, flags| {
+                if flags & TS_LF_READWRITE.0 == TS_LF_READWRITE.0 {
+                    assert_eq!(request_lock(store, TS_LF_READ.0), TS_S_ASYNC);
+                }
+            }));
+            b.iter(|| request_lock(&store, TS_LF_READWRITE.0 | TS_LF_SYNC));
+        });
+        group.finish();
+    }
+}
+
+#[cfg(windows)]
+criterion::criterion_group!(tsf, benches::convert, benches::batch, benches::get_text, benches::lock);
+#[cfg(windows)]
+criterion::criterion_main!(tsf);
+
+#[cfg(not(windows))]
+fn main() {}
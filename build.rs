@@ -0,0 +1,6 @@
+fn main() {
+    // N-API modules resolve node's symbols at load time, which needs extra
+    // linker flags on some targets.
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
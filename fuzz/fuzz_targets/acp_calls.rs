@@ -0,0 +1,148 @@
+//! Drives a standalone text store with arbitrary ITextStoreACP call
+//! sequences, each batch under an arbitrary lock, and checks that the
+//! document stays consistent. libFuzzer's panic hook aborts on any panic,
+//! including those the store's COM entry points catch.
+//!
+//! Run with `cargo fuzz run acp_calls` on Windows.
+
+#![no_main]
+#![cfg_attr(not(windows), allow(dead_code))]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum LockKind {
+    None,
+    Read,
+    ReadWrite,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Call {
+    GetText { start: i32, end: i32, max: u16, runs: u8, null_outputs: bool },
+    SetText { flags: u32, start: i32, end: i32, text: String },
+    InsertTextAtSelection { flags: u32, text: String, null_outputs: bool },
+    GetSelection { index: u32, max: u8, null_fetched: bool },
+    SetSelection { selections: Vec<(i32, i32)> },
+    GetEndAcp,
+    /// An edit from the application side, as `TSF::set_text` makes.
+    Replace { text: String },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    text: String,
+    batches: Vec<(LockKind, Vec<Call>)>,
+}
+
+#[cfg(windows)]
+mod run {
+    use iatjc_rs::{store_trace, testing::{self, MockSink}};
+    use windows::Win32::{
+        Foundation::BOOL,
+        UI::TextServices::{ITextStoreACP, TS_AE_END, TS_AS_SEL_CHANGE, TS_AS_TEXT_CHANGE, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_RUNINFO, TS_SELECTIONSTYLE, TS_SELECTION_ACP, TS_TEXTCHANGE},
+    };
+
+    use super::{Call, Input, LockKind};
+
+    /// Longest selection array passed to `SetSelection`.
+    const MAX_SELECTIONS: usize = 4;
+
+    pub fn input(input: Input) {
+        let Ok(store) = store_trace::store(&input.text) else {
+            return;
+        };
+        let Ok(sink) = MockSink::advise(&store, TS_AS_TEXT_CHANGE | TS_AS_SEL_CHANGE) else {
+            return;
+        };
+
+        for (lock, calls) in input.batches {
+            let flags = match lock {
+                LockKind::None => {
+                    calls.into_iter().for_each(|c| call(&store, c));
+                    continue;
+                }
+                LockKind::Read => TS_LF_READ.0,
+                LockKind::ReadWrite => TS_LF_READWRITE.0,
+            };
+            sink.on_lock(move |store| calls.into_iter().for_each(|c| call(store, c)));
+            let _ = unsafe { store.RequestLock(flags | TS_LF_SYNC) };
+
+            check(&store, &sink);
+        }
+    }
+
+    fn call(store: &ITextStoreACP, call: Call) {
+        unsafe {
+            match call {
+                Call::GetText { start, end, max, runs, null_outputs } => {
+                    let mut text = vec![0u16; max as usize];
+                    let mut run_info = vec![TS_RUNINFO::default(); runs as usize];
+                    let (mut copied, mut run_count, mut next) = (0, 0, 0);
+                    let result = if null_outputs {
+                        store.GetText(start, end, &mut text, std::ptr::null_mut(), &mut run_info, std::ptr::null_mut(), std::ptr::null_mut())
+                    } else {
+                        store.GetText(start, end, &mut text, &mut copied, &mut run_info, &mut run_count, &mut next)
+                    };
+                    if result.is_ok() && !null_outputs {
+                        assert!(copied as usize <= text.len());
+                        assert!(run_count as usize <= run_info.len());
+                    }
+                }
+                Call::SetText { flags, start, end, text } => {
+                    let text: Vec<u16> = text.encode_utf16().collect();
+                    let _ = store.SetText(flags, start, end, &text);
+                }
+                Call::InsertTextAtSelection { flags, text, null_outputs } => {
+                    let text: Vec<u16> = text.encode_utf16().collect();
+                    let (mut start, mut end, mut change) = (0, 0, TS_TEXTCHANGE::default());
+                    let _ = if null_outputs {
+                        store.InsertTextAtSelection(flags, &text, std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null_mut())
+                    } else {
+                        store.InsertTextAtSelection(flags, &text, &mut start, &mut end, &mut change)
+                    };
+                }
+                Call::GetSelection { index, max, null_fetched } => {
+                    let mut selection = vec![TS_SELECTION_ACP::default(); max as usize];
+                    let mut fetched = 0;
+                    let fetched_ptr = if null_fetched { std::ptr::null_mut() } else { &mut fetched };
+                    if store.GetSelection(index, &mut selection, fetched_ptr).is_ok() && !null_fetched {
+                        assert!(fetched as usize <= selection.len());
+                    }
+                }
+                Call::SetSelection { selections } => {
+                    let style = TS_SELECTIONSTYLE { ase: TS_AE_END, fInterimChar: BOOL(0) };
+                    let selections: Vec<_> = selections.into_iter().take(MAX_SELECTIONS).map(|(start, end)| TS_SELECTION_ACP { acpStart: start, acpEnd: end, style }).collect();
+                    let _ = store.SetSelection(&selections);
+                }
+                Call::GetEndAcp => {
+                    let _ = store.GetEndACP();
+                }
+                Call::Replace { text } => {
+                    let _ = testing::set_text(store, &text);
+                }
+            }
+        }
+    }
+
+    /// The end ACP matches the text and the selection lies within it.
+    fn check(store: &ITextStoreACP, sink: &MockSink) {
+        let (end, (text, next), (start, stop)) = sink
+            .under_lock(store, TS_LF_READ.0, |store| {
+                let end = unsafe { store.GetEndACP() }.unwrap();
+                (end, testing::get_text(store, 0, -1, end as usize + 1).unwrap(), testing::selection(store).unwrap())
+            })
+            .expect("read lock was not granted");
+        assert_eq!(text.encode_utf16().count() as i32, end);
+        assert_eq!(next, end);
+        assert!(0 <= start && start <= stop && stop <= end, "{start}..{stop} outside 0..{end}");
+    }
+}
+
+fuzz_target!(|input: Input| {
+    #[cfg(windows)]
+    run::input(input);
+    #[cfg(not(windows))]
+    let _ = input;
+});
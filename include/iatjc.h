@@ -0,0 +1,36 @@
+/* C interface of iatjc-rs, built with `cargo build --release --features capi`. */
+#ifndef IATJC_H
+#define IATJC_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Iatjc Iatjc;
+
+typedef struct IatjcCandidates {
+    char **items;
+    size_t count;
+} IatjcCandidates;
+
+/* Starts a session on a dedicated worker thread. NULL on failure. */
+Iatjc *iatjc_init(void);
+
+/* Stops the worker and releases the session. */
+void iatjc_shutdown(Iatjc *session);
+
+/* Converts a UTF-8 kana reading. NULL on failure; release with iatjc_free. */
+IatjcCandidates *iatjc_convert(const Iatjc *session, const char *reading);
+
+void iatjc_free(IatjcCandidates *candidates);
+
+/* Message of the last failure on the calling thread, or NULL. */
+const char *iatjc_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
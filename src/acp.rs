@@ -0,0 +1,144 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Which way to move an ACP that falls inside a character.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Snap {
+    Backward,
+    Forward,
+}
+
+/// Clamps `acp` to `0..=text.len()`.
+pub fn clamp(text: &[u16], acp: i32) -> i32 {
+    acp.clamp(0, text.len() as i32)
+}
+
+/// Whether `acp` does not fall between the halves of a surrogate pair.
+/// Positions outside the buffer are not boundaries.
+pub fn is_code_point_boundary(text: &[u16], acp: i32) -> bool {
+    if acp < 0 || acp as usize > text.len() {
+        return false;
+    }
+
+    let acp = acp as usize;
+    !(acp > 0 && acp < text.len() && is_high_surrogate(text[acp - 1]) && is_low_surrogate(text[acp]))
+}
+
+/// Whether `acp` falls between two extended grapheme clusters.
+pub fn is_grapheme_boundary(text: &[u16], acp: i32) -> bool {
+    grapheme_boundaries(text).contains(&acp)
+}
+
+/// Moves `acp` into the buffer and off the inside of a surrogate pair.
+pub fn snap_to_code_point(text: &[u16], acp: i32, snap: Snap) -> i32 {
+    let acp = clamp(text, acp);
+    if is_code_point_boundary(text, acp) {
+        return acp;
+    }
+
+    match snap {
+        Snap::Backward => acp - 1,
+        Snap::Forward => acp + 1,
+    }
+}
+
+/// Moves `acp` into the buffer and onto the nearest grapheme boundary in
+/// the direction of `snap`.
+pub fn snap_to_grapheme(text: &[u16], acp: i32, snap: Snap) -> i32 {
+    let acp = clamp(text, acp);
+    let boundaries = grapheme_boundaries(text);
+
+    match snap {
+        Snap::Backward => boundaries.iter().rev().find(|&&boundary| boundary <= acp),
+        Snap::Forward => boundaries.iter().find(|&&boundary| boundary >= acp),
+    }
+    .copied()
+    .unwrap_or(acp)
+}
+
+/// Widens `start..end` so that neither end splits a surrogate pair. `-1` for
+/// `end` means the end of the buffer, as in `ITextStoreACP`.
+pub fn snap_range_to_code_points(text: &[u16], start: i32, end: i32) -> (i32, i32) {
+    let end = if end == -1 { text.len() as i32 } else { end };
+    let start = snap_to_code_point(text, start, Snap::Backward);
+    (start, snap_to_code_point(text, end, Snap::Forward).max(start))
+}
+
+/// Widens `start..end` to whole grapheme clusters.
+pub fn snap_range_to_graphemes(text: &[u16], start: i32, end: i32) -> (i32, i32) {
+    let end = if end == -1 { text.len() as i32 } else { end };
+    let start = snap_to_grapheme(text, start, Snap::Backward);
+    (start, snap_to_grapheme(text, end, Snap::Forward).max(start))
+}
+
+/// ACPs of every grapheme boundary, including `0` and the buffer length.
+pub fn grapheme_boundaries(text: &[u16]) -> Vec<i32> {
+    // Unpaired surrogates decode to U+FFFD, which is one unit long as well,
+    // so offsets in the decoded string map back one to one.
+    let decoded = String::from_utf16_lossy(text);
+    let mut boundaries = Vec::with_capacity(text.len() + 1);
+    let mut acp = 0;
+
+    for grapheme in decoded.graphemes(true) {
+        boundaries.push(acp);
+        acp += grapheme.encode_utf16().count() as i32;
+    }
+    boundaries.push(acp);
+    boundaries
+}
+
+fn is_high_surrogate(unit: u16) -> bool {
+    (0xD800..0xDC00).contains(&unit)
+}
+
+fn is_low_surrogate(unit: u16) -> bool {
+    (0xDC00..0xE000).contains(&unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    #[test]
+    fn finds_code_point_boundaries() {
+        let text = utf16("a\u{1F600}b");
+        assert_eq!((-1..=5).map(|acp| is_code_point_boundary(&text, acp)).collect::<Vec<_>>(), [false, true, true, false, true, true, false]);
+        assert!(is_code_point_boundary(&[0xD800], 1));
+        assert!(is_code_point_boundary(&[0xDC00, 0xD800], 1));
+    }
+
+    #[test]
+    fn snaps_off_surrogate_pairs() {
+        let text = utf16("a\u{1F600}b");
+        assert_eq!(snap_to_code_point(&text, 2, Snap::Backward), 1);
+        assert_eq!(snap_to_code_point(&text, 2, Snap::Forward), 3);
+        assert_eq!(snap_to_code_point(&text, 9, Snap::Backward), 4);
+        assert_eq!(snap_to_code_point(&text, -3, Snap::Forward), 0);
+        assert_eq!(snap_range_to_code_points(&text, 2, 2), (1, 3));
+        assert_eq!(snap_range_to_code_points(&text, 2, -1), (1, 4));
+    }
+
+    #[test]
+    fn finds_grapheme_boundaries() {
+        // か with a combining voiced sound mark, then a family emoji.
+        let text = utf16("か\u{3099}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}x");
+        assert_eq!(grapheme_boundaries(&text), [0, 2, 10, 11]);
+        assert_eq!(grapheme_boundaries(&[]), [0]);
+        assert_eq!(grapheme_boundaries(&[0xD800, 0x61]), [0, 1, 2]);
+        assert!(is_grapheme_boundary(&text, 2));
+        assert!(!is_grapheme_boundary(&text, 1));
+    }
+
+    #[test]
+    fn snaps_to_whole_graphemes() {
+        let text = utf16("か\u{3099}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}x");
+        assert_eq!(snap_to_grapheme(&text, 5, Snap::Backward), 2);
+        assert_eq!(snap_to_grapheme(&text, 5, Snap::Forward), 10);
+        assert_eq!(snap_to_grapheme(&text, 2, Snap::Forward), 2);
+        assert_eq!(snap_range_to_graphemes(&text, 1, 3), (0, 10));
+        assert_eq!(snap_range_to_graphemes(&text, 4, -1), (2, 11));
+    }
+}
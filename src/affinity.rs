@@ -0,0 +1,50 @@
+use std::{fmt, thread::{self, ThreadId}};
+
+use windows::Win32::Foundation::RPC_E_WRONG_THREAD;
+
+/// Returned when an STA-bound object is used from a thread other than the one
+/// that created it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongThread {
+    pub owner: ThreadId,
+    pub current: ThreadId,
+}
+
+impl fmt::Display for WrongThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "called from thread {:?}, but the object belongs to thread {:?}", self.current, self.owner)
+    }
+}
+
+impl std::error::Error for WrongThread {}
+
+impl From<WrongThread> for windows_core::Error {
+    fn from(e: WrongThread) -> Self {
+        windows_core::Error::new(RPC_E_WRONG_THREAD, e.to_string())
+    }
+}
+
+/// Remembers the thread an object was created on.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadAffinity {
+    owner: ThreadId,
+}
+
+impl ThreadAffinity {
+    pub fn current() -> Self {
+        Self { owner: thread::current().id() }
+    }
+
+    pub fn owner(&self) -> ThreadId {
+        self.owner
+    }
+
+    pub fn check(&self) -> Result<(), WrongThread> {
+        let current = thread::current().id();
+        if current == self.owner {
+            Ok(())
+        } else {
+            Err(WrongThread { owner: self.owner, current })
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Plumbing for running a server as a per-user background agent: one
+//! instance per session, started at logon, and stopped cleanly when the
+//! session ends.
+
+use std::{
+    sync::{mpsc, Mutex, OnceLock},
+    thread::{self, JoinHandle},
+};
+
+use crate::trace::{debug, error, info, warn};
+use windows::Win32::{
+    Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE, HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
+    System::{LibraryLoader::GetModuleHandleW, Threading::CreateMutexW},
+    UI::WindowsAndMessaging::{CreateWindowExW, DefWindowProcW, DestroyWindow, RegisterClassExW, WINDOW_EX_STYLE, WM_ENDSESSION, WM_QUERYENDSESSION, WNDCLASSEXW, WS_OVERLAPPED},
+};
+use windows_core::{w, HSTRING, PCWSTR};
+
+use crate::{
+    error::{call, hresult, Recover, Result, TsfError},
+    pump::{run_message_loop, QuitSignal},
+    registry,
+};
+
+const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+
+/// Marks the one running instance of an agent in the current session.
+///
+/// Backed by a named mutex in the session's `Local\` namespace, which the
+/// system releases if the process dies.
+pub struct SingleInstance(HANDLE);
+
+impl SingleInstance {
+    /// Claims `name`, or returns `None` when another process in this session
+    /// already holds it.
+    pub fn acquire(name: &str) -> Result<Option<Self>> {
+        let handle = call!(CreateMutexW(None, true, &HSTRING::from(format!(r"Local\{name}"))))?;
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            debug!("Another instance holds {}", name);
+            let _ = unsafe { CloseHandle(handle) };
+            return Ok(None);
+        }
+        Ok(Some(Self(handle)))
+    }
+}
+
+impl Drop for SingleInstance {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { CloseHandle(self.0) } {
+            warn!("Failed to close the instance mutex: {:?}", e);
+        }
+    }
+}
+
+/// Starts `command` (the quoted executable path and its arguments) at every
+/// logon of the current user, under the `Run` value `name`.
+pub fn enable_autostart(name: &str, command: &str) -> Result<()> {
+    registry::set_string(RUN_KEY, Some(name), command)?;
+    info!("Enabled autostart of {}", name);
+    Ok(())
+}
+
+/// Removes the `Run` value `name`. Not an error when it is missing.
+pub fn disable_autostart(name: &str) -> Result<()> {
+    registry::delete_value(RUN_KEY, name)?;
+    info!("Disabled autostart of {}", name);
+    Ok(())
+}
+
+/// The command started at logon under `name`, if any.
+pub fn autostart(name: &str) -> Option<String> {
+    registry::get_string(RUN_KEY, Some(name))
+}
+
+const CLASS_NAME: PCWSTR = w!("iatjc_session_window");
+
+type SessionEndHook = Box<dyn FnOnce() + Send>;
+
+/// The hook of the live [`SessionWatcher`]; there is at most one.
+static ON_SESSION_END: Mutex<Option<SessionEndHook>> = Mutex::new(None);
+static CLASS_ATOM: OnceLock<u16> = OnceLock::new();
+
+extern "system" fn session_window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    match msg {
+        WM_QUERYENDSESSION => LRESULT(1),
+        WM_ENDSESSION if wparam.0 != 0 => {
+            info!("Session is ending");
+            let hook = ON_SESSION_END.lock().recover().take();
+            if let Some(hook) = hook
+                && std::panic::catch_unwind(std::panic::AssertUnwindSafe(hook)).is_err()
+            {
+                error!("Panic in the session end hook");
+            }
+            LRESULT(0)
+        }
+        _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
+    }
+}
+
+/// Runs a hook when the user logs off or the system shuts down.
+///
+/// Listens with a hidden top-level window on its own thread, since only
+/// top-level windows receive `WM_ENDSESSION`. Windows may end the process
+/// as soon as the hook returns, so the hook should stop the server and wait
+/// for it to finish. Only one watcher can be live at a time.
+pub struct SessionWatcher {
+    quit: QuitSignal,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SessionWatcher {
+    pub fn new(on_end: impl FnOnce() + Send + 'static) -> Result<Self> {
+        {
+            let mut hook = ON_SESSION_END.lock().recover();
+            if hook.is_some() {
+                return Err(TsfError::InvalidArgument("a session watcher is already running"));
+            }
+            *hook = Some(Box::new(on_end));
+        }
+
+        let (started, result) = mpsc::channel();
+        let thread = thread::Builder::new().name("iatjc-session".to_string()).spawn(move || {
+            let quit = QuitSignal::for_current_thread();
+            let hwnd = match create_window() {
+                Ok(hwnd) => hwnd,
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                    return;
+                }
+            };
+            let _ = started.send(Ok(quit.clone()));
+
+            if let Err(e) = run_message_loop(&quit) {
+                warn!("Session watcher loop failed: {:?}", e);
+            }
+            if let Err(e) = unsafe { DestroyWindow(hwnd) } {
+                warn!("Failed to destroy the session window: {:?}", e);
+            }
+        });
+
+        let started = thread.map_err(TsfError::WorkerSpawn).and_then(|thread| match result.recv() {
+            Ok(Ok(quit)) => Ok(Self { quit, thread: Some(thread) }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(TsfError::WorkerStopped),
+        });
+        if started.is_err() {
+            ON_SESSION_END.lock().recover().take();
+        }
+        started
+    }
+}
+
+impl Drop for SessionWatcher {
+    fn drop(&mut self) {
+        if let Err(e) = self.quit.quit() {
+            warn!("Failed to stop the session watcher: {:?}", e);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        ON_SESSION_END.lock().recover().take();
+    }
+}
+
+fn create_window() -> Result<HWND> {
+    let instance: HINSTANCE = call!(GetModuleHandleW(None))?.into();
+    let atom = *CLASS_ATOM.get_or_init(|| {
+        let class = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(session_window_proc),
+            hInstance: instance,
+            lpszClassName: CLASS_NAME,
+            ..Default::default()
+        };
+        unsafe { RegisterClassExW(&class) }
+    });
+    if atom == 0 {
+        return Err(hresult("RegisterClassExW")(windows_core::Error::from_win32()));
+    }
+
+    let hwnd = unsafe { CreateWindowExW(WINDOW_EX_STYLE(0), CLASS_NAME, w!("iatjc"), WS_OVERLAPPED, 0, 0, 0, 0, HWND(0), None, instance, None) };
+    if hwnd.0 == 0 {
+        return Err(hresult("CreateWindowExW")(windows_core::Error::from_win32()));
+    }
+    Ok(hwnd)
+}
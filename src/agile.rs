@@ -0,0 +1,90 @@
+use std::marker::PhantomData;
+
+use crate::trace::{debug, warn};
+use windows::Win32::{
+    System::Com::{CoCreateInstance, IGlobalInterfaceTable, CLSCTX_INPROC_SERVER},
+    UI::TextServices::{ITfContext, ITfDocumentMgr, ITfFnReconversion, ITfThreadMgr2},
+};
+use windows_core::{IUnknown, Interface, GUID};
+
+use crate::error::{call, Result};
+
+const CLSID_STD_GLOBAL_INTERFACE_TABLE: GUID = GUID::from_u128(0x00000323_0000_0000_c000_000000000046);
+
+fn global_interface_table() -> Result<IGlobalInterfaceTable> {
+    call!(CoCreateInstance(&CLSID_STD_GLOBAL_INTERFACE_TABLE, None, CLSCTX_INPROC_SERVER))
+}
+
+/// An interface registered in the process-wide Global Interface Table.
+///
+/// Only the cookie is stored, so the handle can be sent to any thread. Calling
+/// [`GlobalInterface::resolve`] returns a proxy that is valid on the calling
+/// thread, which must have COM initialized. Calls through the proxy are
+/// marshaled to the owning STA, so that thread has to keep pumping messages.
+pub struct GlobalInterface<T: Interface> {
+    cookie: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Interface> GlobalInterface<T> {
+    pub fn register(interface: &T) -> Result<Self> {
+        let git = global_interface_table()?;
+        let unknown = call!(interface, cast::<IUnknown>())?;
+        let cookie = call!(git, RegisterInterfaceInGlobal(&unknown, &T::IID))?;
+        debug!("Registered interface in GIT with cookie: {}", cookie);
+
+        Ok(Self { cookie, _marker: PhantomData })
+    }
+
+    pub fn resolve(&self) -> Result<T> {
+        let git = global_interface_table()?;
+        let mut raw = std::ptr::null_mut();
+        call!(git, GetInterfaceFromGlobal(self.cookie, &T::IID, &mut raw))?;
+        Ok(unsafe { T::from_raw(raw) })
+    }
+
+    pub fn cookie(&self) -> u32 {
+        self.cookie
+    }
+}
+
+impl<T: Interface> Drop for GlobalInterface<T> {
+    fn drop(&mut self) {
+        let revoked = global_interface_table()
+            .and_then(|git| call!(git, RevokeInterfaceFromGlobal(self.cookie)));
+
+        match revoked {
+            Ok(_) => debug!("Revoked GIT cookie: {}", self.cookie),
+            Err(e) => warn!("Failed to revoke GIT cookie {}: {:?}", self.cookie, e),
+        }
+    }
+}
+
+/// A thread-agile view of an initialized [`crate::tsf::TSF`].
+///
+/// Created with [`crate::tsf::TSF::agile`] on the TSF thread, then moved to
+/// other threads that need to reach the same pipeline.
+pub struct AgileTsf {
+    pub(crate) thread_mgr: GlobalInterface<ITfThreadMgr2>,
+    pub(crate) doc_mgr: GlobalInterface<ITfDocumentMgr>,
+    pub(crate) context: GlobalInterface<ITfContext>,
+    pub(crate) reconvert: Option<GlobalInterface<ITfFnReconversion>>,
+}
+
+impl AgileTsf {
+    pub fn thread_mgr(&self) -> Result<ITfThreadMgr2> {
+        self.thread_mgr.resolve()
+    }
+
+    pub fn document_mgr(&self) -> Result<ITfDocumentMgr> {
+        self.doc_mgr.resolve()
+    }
+
+    pub fn context(&self) -> Result<ITfContext> {
+        self.context.resolve()
+    }
+
+    pub fn reconversion(&self) -> Result<Option<ITfFnReconversion>> {
+        self.reconvert.as_ref().map(GlobalInterface::resolve).transpose()
+    }
+}
@@ -0,0 +1,110 @@
+use tokio::sync::oneshot;
+
+use crate::{cancel::CancellationToken, error::{Result, TsfError}, events::EventStream, felang::Clause, handle::TsfHandle, service::{Command, Reply, RequestOptions, TsfService}, timeout::{Operation, Timeout}};
+
+/// An async facade over [`TsfService`].
+///
+/// Requests are queued to the worker thread and awaited through oneshot
+/// channels, so callers never block their runtime. Cloning is cheap and all
+/// clones share the same worker.
+#[derive(Clone)]
+pub struct AsyncTsf {
+    handle: TsfHandle,
+}
+
+impl AsyncTsf {
+    /// Starts a new worker thread without blocking the runtime.
+    pub async fn spawn() -> Result<Self> {
+        let service = tokio::task::spawn_blocking(TsfService::spawn).await.map_err(|_| TsfError::WorkerStopped)??;
+        Ok(Self::from_service(service))
+    }
+
+    pub fn from_service(service: TsfService) -> Self {
+        Self::from_handle(TsfHandle::from_service(service))
+    }
+
+    /// Shares the worker behind an existing blocking handle.
+    pub fn from_handle(handle: TsfHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn handle(&self) -> &TsfHandle {
+        &self.handle
+    }
+
+    pub async fn convert(&self, reading: &str) -> Result<Vec<String>> {
+        self.convert_with(reading, &RequestOptions::default()).await
+    }
+
+    pub async fn convert_cancellable(&self, reading: &str, token: &CancellationToken) -> Result<Vec<String>> {
+        self.convert_with(reading, &RequestOptions::with_token(token)).await
+    }
+
+    pub async fn convert_with(&self, reading: &str, options: &RequestOptions) -> Result<Vec<String>> {
+        let reading = reading.to_string();
+        let (normalization, max_candidates) = (options.normalization, options.max_candidates);
+        self.request(Operation::Conversion, options, |token, reply| Command::Convert { reading, token, normalization, max_candidates, reply }).await
+    }
+
+    pub async fn set_text(&self, text: &str) -> Result<()> {
+        self.set_text_with(text, &RequestOptions::default()).await
+    }
+
+    pub async fn set_text_cancellable(&self, text: &str, token: &CancellationToken) -> Result<()> {
+        self.set_text_with(text, &RequestOptions::with_token(token)).await
+    }
+
+    pub async fn set_text_with(&self, text: &str, options: &RequestOptions) -> Result<()> {
+        let text = text.to_string();
+        let normalization = options.normalization;
+        self.request(Operation::SetText, options, |token, reply| Command::SetText { text, token, normalization, reply }).await
+    }
+
+    pub async fn reading(&self, text: &str) -> Result<String> {
+        self.reading_with(text, &RequestOptions::default()).await
+    }
+
+    pub async fn reading_with(&self, text: &str, options: &RequestOptions) -> Result<String> {
+        let text = text.to_string();
+        self.request(Operation::Conversion, options, |token, reply| Command::Reading { text, token, reply }).await
+    }
+
+    pub async fn segment(&self, reading: &str) -> Result<Vec<Clause>> {
+        self.segment_with(reading, &RequestOptions::default()).await
+    }
+
+    pub async fn segment_with(&self, reading: &str, options: &RequestOptions) -> Result<Vec<Clause>> {
+        let reading = reading.to_string();
+        self.request(Operation::Conversion, options, |token, reply| Command::Segment { reading, token, reply }).await
+    }
+
+    /// Returns a stream of events raised on the worker thread.
+    pub fn events(&self) -> EventStream {
+        self.handle.events().stream()
+    }
+
+    async fn request<T, F>(&self, operation: Operation, options: &RequestOptions, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(CancellationToken, Reply<T>) -> Command,
+    {
+        let service = self.handle.service();
+        let (token, timeout) = options.resolve(&service.options().timeouts, operation);
+        let (sender, receiver) = oneshot::channel();
+        service.submit(options.priority, command(token.clone(), Box::new(move |result| {
+            let _ = sender.send(result);
+        })))?;
+
+        let Some(after) = timeout else {
+            return receiver.await.map_err(|_| TsfError::WorkerStopped)?;
+        };
+
+        match tokio::time::timeout(after, receiver).await {
+            Ok(result) => result.map_err(|_| TsfError::WorkerStopped)?,
+            Err(_) => {
+                token.cancel();
+                Err(Timeout { operation, after }.into())
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::{sync::mpsc, time::Duration};
+
+use iatjc_rs::{
+    agent::{self, SessionWatcher, SingleInstance},
+    com::Com,
+    Result,
+};
+
+use crate::{rpc::Handler, serve, Engine};
+
+/// Name of the session-wide mutex marking the running agent.
+const INSTANCE_NAME: &str = "iatjc-agent";
+/// Name of the `Run` value starting the agent at logon.
+const AUTOSTART_NAME: &str = "iatjc";
+const DEFAULT_PIPE: &str = r"\\.\pipe\iatjc";
+/// How long the session end hook waits for the engines to shut down, about
+/// what Windows allows before ending the process anyway.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Serve JSON-RPC 2.0 on this named pipe, framed as by `serve --pipe`.
+    #[arg(long, value_name = "NAME", default_value = DEFAULT_PIPE)]
+    pipe: String,
+
+    /// Serve the JSON HTTP API on this loopback address instead of a pipe.
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ADDRESS", conflicts_with = "pipe")]
+    http: Option<std::net::SocketAddr>,
+
+    /// Let web pages from this origin call the HTTP API; repeat for more.
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ORIGIN", requires = "http")]
+    allow_origin: Vec<String>,
+
+    /// Engines to convert with, in order. Defaults to tsf,felang,imm32. The
+    /// HTTP API only converts with tsf.
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    engine: Vec<Engine>,
+
+    /// Start the agent with the other arguments given here at every logon,
+    /// and exit.
+    #[arg(long, conflicts_with = "uninstall")]
+    install: bool,
+
+    /// Stop starting the agent at logon, and exit.
+    #[arg(long)]
+    uninstall: bool,
+}
+
+pub fn run(com: &Com, args: &Args) -> Result<()> {
+    if args.uninstall {
+        return agent::disable_autostart(AUTOSTART_NAME);
+    }
+    if args.install {
+        return install();
+    }
+
+    let Some(_instance) = SingleInstance::acquire(INSTANCE_NAME)? else {
+        eprintln!("iatjc: an agent is already running in this session");
+        return Ok(());
+    };
+
+    #[cfg(feature = "http")]
+    if let Some(address) = args.http {
+        crate::http::check_engines(&args.engine)?;
+        return http(address, crate::http::Origins::new(args.allow_origin.clone()));
+    }
+
+    let handler = Handler::new(com, &args.engine)?;
+    let (finished, done) = mpsc::channel::<()>();
+    let mut watcher = None;
+    let result = serve::pipe(&handler, &args.pipe, |stop| {
+        watcher = Some(SessionWatcher::new(move || {
+            stop.stop();
+            let _ = done.recv_timeout(SHUTDOWN_GRACE);
+        })?);
+        Ok(())
+    });
+
+    // Release the engines before letting the session end hook return.
+    drop(handler);
+    let _ = finished.send(());
+    drop(watcher);
+    result
+}
+
+#[cfg(feature = "http")]
+fn http(address: std::net::SocketAddr, origins: crate::http::Origins) -> Result<()> {
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let (finished, done) = mpsc::channel::<()>();
+    let watcher = SessionWatcher::new(move || {
+        let _ = stop.send(());
+        let _ = done.recv_timeout(SHUTDOWN_GRACE);
+    })?;
+
+    let result = crate::http::serve(address, origins, async {
+        let _ = stopped.await;
+    });
+    let _ = finished.send(());
+    drop(watcher);
+    result
+}
+
+/// Registers this executable with the current arguments, minus `--install`
+/// and `--uninstall`.
+fn install() -> Result<()> {
+    let executable = std::env::current_exe()?;
+    let arguments = std::env::args().skip(1).filter(|argument| !is_install_flag(argument));
+    let command: Vec<String> = std::iter::once(executable.display().to_string()).chain(arguments).map(|argument| quote(&argument)).collect();
+    agent::enable_autostart(AUTOSTART_NAME, &command.join(" "))
+}
+
+fn is_install_flag(argument: &str) -> bool {
+    ["--install", "--uninstall"].iter().any(|flag| argument.strip_prefix(flag).is_some_and(|rest| rest.is_empty() || rest.starts_with('=')))
+}
+
+/// Quotes `argument` so that the MSVCRT command-line parser reads it back
+/// unchanged: backslashes are doubled before a quote and quotes escaped.
+fn quote(argument: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in argument.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_quoted_for_msvcrt() {
+        let cases = [
+            ("--pipe", r#""--pipe""#),
+            ("a b\tc", "\"a b\tc\""),
+            ("", r#""""#),
+            (r#"say "hi""#, r#""say \"hi\"""#),
+            (r"C:\dir\", r#""C:\dir\\""#),
+            (r#"a\"b"#, r#""a\\\"b""#),
+            (r"a\\b", r#""a\\b""#),
+        ];
+        for (argument, quoted) in cases {
+            assert_eq!(quote(argument), quoted, "{argument}");
+        }
+    }
+
+    #[test]
+    fn install_flags_are_not_persisted() {
+        for flag in ["--install", "--uninstall", "--install=true", "--uninstall=false"] {
+            assert!(is_install_flag(flag), "{flag}");
+        }
+        for argument in ["--pipe", "--installer", "install"] {
+            assert!(!is_install_flag(argument), "{argument}");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use iatjc_rs::{clipboard, com::Com, felang::FeLanguage, Result, TsfError};
+use serde::Serialize;
+
+use crate::{
+    open_chain,
+    output::{self, Format, Row},
+    Engine,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Print at most this many candidates.
+    #[arg(short = 'n', long)]
+    count: Option<usize>,
+
+    /// Engines to try, in order; repeat or separate with commas. Defaults to
+    /// tsf,felang,imm32.
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    engine: Vec<Engine>,
+
+    /// Also split the reading into clauses.
+    #[arg(short, long)]
+    segment: bool,
+
+    /// Copy candidate N (as numbered in the output) back to the clipboard.
+    #[arg(short, long, value_name = "N")]
+    write: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Reconversion<'a> {
+    text: &'a str,
+    reading: &'a str,
+    engine: Engine,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clauses: Option<Vec<Clause>>,
+    candidates: Vec<Candidate<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    written: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Clause {
+    reading: String,
+    surface: String,
+}
+
+#[derive(Serialize)]
+struct Candidate<'a> {
+    /// 1-based, the number `--write` takes.
+    index: usize,
+    text: &'a str,
+}
+
+pub fn run(com: &Com, args: &Args, format: Format) -> Result<()> {
+    let chain = open_chain(com, &args.engine)?;
+    let reconversion = clipboard::reconvert(&chain, args.count)?;
+    let candidates: Vec<&str> = reconversion.candidates.value.iter().map(|candidate| candidate.text.as_str()).collect();
+
+    let clauses = if args.segment {
+        let clauses = FeLanguage::new(com)?.segment(&reconversion.reading)?;
+        Some(clauses.into_iter().map(|clause| Clause { reading: clause.reading, surface: clause.surface }).collect::<Vec<_>>())
+    } else {
+        None
+    };
+
+    let written = match args.write {
+        Some(n) => {
+            let candidate = n.checked_sub(1).and_then(|index| candidates.get(index)).ok_or(TsfError::InvalidArgument("--write is not the number of a listed candidate"))?;
+            clipboard::write_text(candidate)?;
+            Some(*candidate)
+        }
+        None => None,
+    };
+    if format != Format::Json
+        && let Some(written) = written
+    {
+        eprintln!("Copied {written} to the clipboard.");
+    }
+
+    match format {
+        Format::Json => output::json(&Reconversion {
+            text: &reconversion.text,
+            reading: &reconversion.reading,
+            engine: reconversion.candidates.engine.into(),
+            clauses,
+            candidates: candidates.iter().enumerate().map(|(index, text)| Candidate { index: index + 1, text }).collect(),
+            written,
+        }),
+        Format::Text | Format::RubyHtml | Format::Anki => {
+            println!("text\t{}", reconversion.text);
+            println!("reading\t{}", reconversion.reading);
+            if let Some(clauses) = &clauses {
+                let boundaries: Vec<&str> = clauses.iter().map(|clause| clause.reading.as_str()).collect();
+                println!("clauses\t{}", boundaries.join("|"));
+            }
+            for (index, candidate) in candidates.iter().enumerate() {
+                println!("{}\t{candidate}", index + 1);
+            }
+            Ok(())
+        }
+        Format::Tsv | Format::Csv => {
+            output::header(format)?;
+            for (index, candidate) in candidates.iter().enumerate() {
+                output::row(format, &Row { input: &reconversion.reading, clause: 0, rank: index + 1, candidate })?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use iatjc_rs::{com_server, service::TsfService, Result};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Register `Iatjc.Converter` for the current user, pointing COM at this
+    /// executable, and exit.
+    #[arg(long, conflicts_with = "unregister")]
+    register: bool,
+
+    /// Remove the registration and exit.
+    #[arg(long)]
+    unregister: bool,
+
+    /// `-Embedding`, appended by COM when it starts the server.
+    #[arg(hide = true, allow_hyphen_values = true)]
+    embedding: Option<String>,
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    if args.unregister {
+        return com_server::unregister();
+    }
+
+    if args.register {
+        let executable = std::env::current_exe()?;
+        return com_server::register(&format!("\"{}\" com-server", executable.display()));
+    }
+
+    com_server::serve(TsfService::spawn()?)
+}
@@ -0,0 +1,225 @@
+use std::{
+    io::{BufRead, Write},
+    time::Instant,
+};
+
+use iatjc_rs::{com::Com, engine::EngineChain, felang::FeLanguage, Result};
+use serde::Serialize;
+
+use crate::{
+    open_chain,
+    output::{self, Format, Row},
+    Engine,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The kana reading to convert.
+    #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+    reading: Option<String>,
+
+    /// Convert every line of stdin instead, reusing one session. Each input
+    /// line yields one output line: tab-separated candidates, or one JSON
+    /// document with `--format json`. Blank lines and failed conversions
+    /// yield an empty line, or a record without candidates or with an
+    /// `error`. With `--format tsv` or `csv`, each line yields a row per
+    /// candidate, or a single row without one that carries any `error`.
+    #[arg(long)]
+    stdin: bool,
+
+    /// Print at most this many candidates.
+    #[arg(short = 'n', long)]
+    count: Option<usize>,
+
+    /// Engines to try, in order; repeat or separate with commas. Defaults to
+    /// tsf,felang,imm32.
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    engine: Vec<Engine>,
+}
+
+#[derive(Serialize)]
+struct Conversion<'a> {
+    input: &'a str,
+    engine: Engine,
+    clauses: Vec<Clause>,
+    candidates: Vec<Candidate<'a>>,
+    timings: Timings,
+}
+
+/// A line of `--stdin` output that could not be converted.
+#[derive(Serialize)]
+struct Failure<'a> {
+    input: &'a str,
+    error: String,
+}
+
+/// A line of `--stdin` output for a blank input line.
+#[derive(Serialize)]
+struct Blank<'a> {
+    input: &'a str,
+    candidates: [Candidate<'a>; 0],
+}
+
+#[derive(Serialize)]
+struct Clause {
+    reading: String,
+    surface: String,
+}
+
+#[derive(Serialize)]
+struct Candidate<'a> {
+    /// 1-based, like the numbers in the text output.
+    index: usize,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotation: Option<&'a str>,
+}
+
+/// Milliseconds spent in each step.
+#[derive(Serialize)]
+struct Timings {
+    open: f64,
+    convert: f64,
+    clauses: f64,
+}
+
+/// Engines opened once and used for every reading.
+struct Session<'com> {
+    chain: EngineChain<'com>,
+    /// Only needed for clauses in JSON output, and only opened when felang is
+    /// among the engines.
+    felang: Option<FeLanguage<'com>>,
+    format: Format,
+    count: Option<usize>,
+    /// Time spent opening the engines, reported with the first conversion.
+    open: f64,
+}
+
+pub fn run(com: &Com, args: &Args, format: Format) -> Result<()> {
+    let started = Instant::now();
+    let chain = open_chain(com, &args.engine)?;
+    // Clauses come from IFELanguage, so only when it is one of the engines.
+    let felang = match format {
+        Format::Json if args.engine.is_empty() || args.engine.contains(&Engine::Felang) => FeLanguage::new(com).ok(),
+        Format::Json | Format::Text | Format::Tsv | Format::Csv | Format::RubyHtml | Format::Anki => None,
+    };
+    let mut session = Session {
+        chain,
+        felang,
+        format,
+        count: args.count,
+        open: output::millis(started.elapsed()),
+    };
+
+    if format.is_tabular() {
+        output::header(format)?;
+    }
+
+    if let Some(reading) = &args.reading {
+        return session.convert(reading);
+    }
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let reading = line.trim();
+        if reading.is_empty() {
+            match format {
+                Format::Text | Format::RubyHtml | Format::Anki => println!(),
+                Format::Json => output::json(&Blank { input: reading, candidates: [] })?,
+                Format::Tsv | Format::Csv => output::empty_row(format, reading, None)?,
+            }
+            std::io::stdout().flush()?;
+            continue;
+        }
+
+        if let Err(e) = session.convert_line(reading) {
+            match format {
+                Format::Text | Format::RubyHtml | Format::Anki => {
+                    eprintln!("iatjc: {reading}: {e}");
+                    println!();
+                }
+                Format::Tsv | Format::Csv => output::empty_row(format, reading, Some(&e.to_string()))?,
+                Format::Json => output::json(&Failure { input: reading, error: e.to_string() })?,
+            }
+        }
+        std::io::stdout().flush()?;
+    }
+
+    Ok(())
+}
+
+impl Session<'_> {
+    /// Converts a single reading given on the command line.
+    fn convert(&mut self, reading: &str) -> Result<()> {
+        match self.format {
+            Format::Text | Format::RubyHtml | Format::Anki => {
+                for (index, candidate) in self.chain.convert(reading, self.count)?.value.iter().enumerate() {
+                    println!("{}\t{}", index + 1, candidate.text);
+                }
+                Ok(())
+            }
+            Format::Json => self.json(reading),
+            Format::Tsv | Format::Csv => self.rows(reading),
+        }
+    }
+
+    /// Converts one line of `--stdin` input into one line of output.
+    fn convert_line(&mut self, reading: &str) -> Result<()> {
+        match self.format {
+            Format::Text | Format::RubyHtml | Format::Anki => {
+                let answered = self.chain.convert(reading, self.count)?;
+                let candidates: Vec<&str> = answered.value.iter().map(|candidate| candidate.text.as_str()).collect();
+                println!("{}", candidates.join("\t"));
+                Ok(())
+            }
+            Format::Json => self.json(reading),
+            Format::Tsv | Format::Csv => self.rows(reading),
+        }
+    }
+
+    /// Writes one row per candidate, all in clause 0, or an empty row when
+    /// there are none.
+    fn rows(&self, reading: &str) -> Result<()> {
+        let candidates = self.chain.convert(reading, self.count)?.value;
+        if candidates.is_empty() {
+            return output::empty_row(self.format, reading, None);
+        }
+        for (index, candidate) in candidates.iter().enumerate() {
+            output::row(self.format, &Row { input: reading, clause: 0, rank: index + 1, candidate: &candidate.text })?;
+        }
+        Ok(())
+    }
+
+    fn json(&mut self, reading: &str) -> Result<()> {
+        let started = Instant::now();
+        let answered = self.chain.convert(reading, self.count)?;
+        let converted = Instant::now();
+        let clauses = self.clauses(reading);
+
+        output::json(&Conversion {
+            input: reading,
+            engine: answered.engine.into(),
+            clauses,
+            candidates: answered
+                .value
+                .iter()
+                .enumerate()
+                .map(|(index, candidate)| Candidate { index: index + 1, text: &candidate.text, annotation: candidate.annotation.as_deref() })
+                .collect(),
+            timings: Timings {
+                open: std::mem::take(&mut self.open),
+                convert: output::millis(converted - started),
+                clauses: output::millis(converted.elapsed()),
+            },
+        })
+    }
+
+    /// Splits `reading` into clauses with `IFELanguage`, which is the only
+    /// engine exposing them. Empty when it is not available.
+    fn clauses(&self, reading: &str) -> Vec<Clause> {
+        let Some(Ok(clauses)) = self.felang.as_ref().map(|felang| felang.segment(reading)) else {
+            return Vec::new();
+        };
+        clauses.into_iter().map(|clause| Clause { reading: clause.reading, surface: clause.surface }).collect()
+    }
+}
@@ -0,0 +1,146 @@
+use iatjc_rs::{
+    com::Com,
+    engine::{self, Engine, EngineKind, FALLBACK_ORDER},
+    environment::{Blocker, Report},
+    tsf::{TsfState, TSF},
+    Result,
+};
+use serde::Serialize;
+use windows::Win32::System::Com::{CoGetApartmentType, APTTYPE, APTTYPEQUALIFIER, APTTYPE_MAINSTA, APTTYPE_STA};
+
+use crate::output::{self, Format};
+
+#[derive(clap::Args)]
+pub struct Args {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    /// What to try when the check does not pass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Pass, detail: detail.into(), hint: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self { name, status: Status::Warn, detail: detail.into(), hint: Some(hint) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self { name, status: Status::Fail, detail: detail.into(), hint: Some(hint) }
+    }
+}
+
+pub fn run(com: &Com, _args: &Args, format: Format) -> Result<()> {
+    let mut checks = vec![apartment(), environment()];
+
+    let mut tsf = TSF::new(com);
+    match tsf.initialize() {
+        Ok(()) => {
+            checks.push(activation(tsf.state()));
+            checks.extend(profile(&tsf));
+        }
+        Err(e) => checks.push(Check::fail(
+            "tsf",
+            format!("initialization failed: {e}"),
+            "run from an interactive desktop session; see the environment check",
+        )),
+    }
+    drop(tsf);
+
+    checks.extend(FALLBACK_ORDER.iter().map(|&kind| fallback(com, kind)));
+
+    match format {
+        Format::Text | Format::Tsv | Format::Csv | Format::RubyHtml | Format::Anki => {
+            for check in &checks {
+                let status = match check.status {
+                    Status::Pass => "PASS",
+                    Status::Warn => "WARN",
+                    Status::Fail => "FAIL",
+                };
+                println!("[{status}] {}: {}", check.name, check.detail);
+                if let Some(hint) = check.hint {
+                    println!("       hint: {hint}");
+                }
+            }
+            Ok(())
+        }
+        Format::Json => output::json(&checks),
+    }
+}
+
+fn apartment() -> Check {
+    let mut kind = APTTYPE::default();
+    let mut qualifier = APTTYPEQUALIFIER::default();
+    match unsafe { CoGetApartmentType(&mut kind, &mut qualifier) } {
+        Ok(()) if kind == APTTYPE_STA || kind == APTTYPE_MAINSTA => Check::pass("com", "single-threaded apartment"),
+        Ok(()) => Check::fail("com", format!("apartment type {}", kind.0), "TSF must be driven from an STA thread"),
+        Err(e) => Check::fail("com", format!("CoGetApartmentType failed: {e}"), "COM is not initialized on this thread"),
+    }
+}
+
+fn environment() -> Check {
+    let report = Report::detect();
+    let Some(&blocker) = report.blockers().first() else {
+        return Check::pass("environment", format!("session {:?}, desktop {:?}", report.session_id, report.input_desktop));
+    };
+
+    let (detail, hint) = match blocker {
+        Blocker::ServiceSession => ("running in session 0", "run as the logged-on user instead of as a service"),
+        Blocker::SecureDesktop => ("input desktop is not the default desktop", "retry once the lock screen or UAC prompt is dismissed"),
+        Blocker::TextServicesDisabled => (
+            "text services are disabled for this user",
+            "delete HKCU\\Software\\Microsoft\\CTF\\Disable Thread Input Manager",
+        ),
+    };
+    Check::fail("environment", detail, hint)
+}
+
+fn activation(state: &TsfState) -> Check {
+    match state {
+        TsfState::Focused => Check::pass("tsf", "activated and focused"),
+        TsfState::Degraded { missing } => Check::warn("tsf", format!("activated without {missing:?}"), "run with RUST_LOG=debug for the failing calls"),
+        state => Check::fail("tsf", format!("unexpected state {state:?}"), "run with RUST_LOG=debug for the failing calls"),
+    }
+}
+
+fn profile(tsf: &TSF) -> Vec<Check> {
+    let Some(profile) = tsf.profile() else {
+        return vec![Check::fail("profile", "no input processor is active", "switch to a Japanese IME before running")];
+    };
+
+    let capabilities = tsf.capabilities();
+    let reconversion = if capabilities.supports_reconversion {
+        Check::pass("reconversion", "function provider offers ITfFnReconversion")
+    } else {
+        Check::fail("reconversion", "no function provider offers ITfFnReconversion", "use --engine felang or --engine imm32")
+    };
+
+    vec![Check::pass("profile", format!("{:?} (langid 0x{:04x})", profile.tip, profile.langid)), reconversion]
+}
+
+fn fallback(com: &Com, kind: EngineKind) -> Check {
+    let name = match kind {
+        EngineKind::Tsf => "engine.tsf",
+        EngineKind::FeLanguage => "engine.felang",
+        EngineKind::Imm32 => "engine.imm32",
+    };
+    match engine::open(kind, com) {
+        Ok(engine) => Check::pass(name, format!("{:?}", engine.capabilities())),
+        Err(e) => Check::warn(name, format!("not available: {e}"), "this engine is skipped in the fallback chain"),
+    }
+}
@@ -0,0 +1,61 @@
+use iatjc_rs::{
+    com::Com,
+    furigana::{self, RubyFormat},
+    Result,
+};
+use serde::Serialize;
+
+use crate::{
+    open_chain,
+    output::{self, Format},
+    Engine,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Kanji-kana text to annotate.
+    text: String,
+
+    /// Engines to read kanji with, in order. Defaults to tsf,felang,imm32.
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    engine: Vec<Engine>,
+}
+
+#[derive(Serialize)]
+struct Annotated<'a> {
+    input: &'a str,
+    reading: &'a str,
+    ruby: Vec<Ruby<'a>>,
+}
+
+#[derive(Serialize)]
+struct Ruby<'a> {
+    base: &'a str,
+    ruby: Option<&'a str>,
+}
+
+pub fn run(com: &Com, args: &Args, format: Format) -> Result<()> {
+    let chain = open_chain(com, &args.engine)?;
+    let reading = furigana::extract_reading(&args.text, |kanji| Ok(chain.reading(kanji)?.value))?;
+    let rubies = furigana::align_reading(&reading);
+
+    match format {
+        Format::Text | Format::Tsv | Format::Csv => {
+            println!("{}", furigana::render(&rubies, RubyFormat::Parenthesized));
+            Ok(())
+        }
+        Format::RubyHtml => {
+            println!("{}", furigana::render(&rubies, RubyFormat::Html));
+            Ok(())
+        }
+        Format::Anki => {
+            println!("{}", furigana::render(&rubies, RubyFormat::Anki));
+            Ok(())
+        }
+        Format::Json => output::json(&Annotated {
+            input: &args.text,
+            reading: &reading.text,
+            ruby: rubies.iter().map(|ruby| Ruby { base: &ruby.base, ruby: ruby.ruby.as_deref() }).collect(),
+        }),
+    }
+}
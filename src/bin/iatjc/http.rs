@@ -0,0 +1,198 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN, VARY},
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use iatjc_rs::{async_tsf::AsyncTsf, service::RequestOptions, Result, TsfError};
+use serde::{Deserialize, Serialize};
+
+use crate::Engine;
+
+#[derive(Deserialize)]
+pub(crate) struct ConvertRequest {
+    reading: String,
+    #[serde(default)]
+    count: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ConvertResponse {
+    candidates: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SegmentRequest {
+    reading: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SegmentResponse {
+    clauses: Vec<Clause>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Clause {
+    index: usize,
+    start: usize,
+    reading: String,
+    surface: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReadingRequest {
+    text: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ReadingResponse {
+    reading: String,
+}
+
+/// A failed request, answered with status 500 and `{"error": ...}`.
+struct Failure(TsfError);
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for Failure {
+    fn into_response(self) -> Response {
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorBody { error: self.0.to_string() })).into_response()
+    }
+}
+
+impl From<TsfError> for Failure {
+    fn from(e: TsfError) -> Self {
+        Self(e)
+    }
+}
+
+/// Origins of the web pages allowed to call the servers, such as
+/// `http://localhost:3000`. Requests without an `Origin` header come from
+/// other programs and are always allowed.
+#[derive(Clone, Default)]
+pub(crate) struct Origins(Arc<Vec<String>>);
+
+impl Origins {
+    pub(crate) fn new(origins: Vec<String>) -> Self {
+        Self(Arc::new(origins))
+    }
+
+    /// Whether a request with `headers` may be answered.
+    pub(crate) fn allow(&self, headers: &HeaderMap) -> bool {
+        match headers.get(ORIGIN) {
+            Some(origin) => self.0.iter().any(|allowed| allowed.as_bytes() == origin.as_bytes()),
+            None => true,
+        }
+    }
+}
+
+/// Serves `/convert`, `/segment` and `/reading` on `address` until
+/// `shutdown` completes, then finishes the requests in flight. Pages from
+/// `origins` may call them through CORS; other pages are refused.
+pub fn serve(address: SocketAddr, origins: Origins, shutdown: impl Future<Output = ()> + Send + 'static) -> Result<()> {
+    run(address, "http", shutdown, |tsf| {
+        Router::new()
+            .route("/convert", post(convert))
+            .route("/segment", post(segment))
+            .route("/reading", post(reading))
+            .layer(middleware::from_fn_with_state(origins, cors))
+            .with_state(tsf)
+    })
+}
+
+/// Refuses requests from pages outside `origins` with 403, answers CORS
+/// preflight requests, and lets the allowed pages read the responses.
+async fn cors(State(origins): State<Origins>, request: Request, next: Next) -> Response {
+    if !origins.allow(request.headers()) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(origin) = request.headers().get(ORIGIN).cloned() else {
+        return next.run(request).await;
+    };
+
+    let preflight = request.method() == Method::OPTIONS;
+    let mut response = if preflight { StatusCode::NO_CONTENT.into_response() } else { next.run(request).await };
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(VARY, HeaderValue::from_static("Origin"));
+    if preflight {
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("POST"));
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("content-type"));
+    }
+    response
+}
+
+/// Fails unless `engines` is empty or only tsf, the one engine the
+/// servers' async facade converts with.
+pub(crate) fn check_engines(engines: &[Engine]) -> Result<()> {
+    if engines.iter().any(|&engine| engine != Engine::Tsf) {
+        return Err(TsfError::InvalidArgument("the HTTP and WebSocket servers only convert with --engine tsf"));
+    }
+    Ok(())
+}
+
+/// Serves the router built by `app` on `address`. Only loopback addresses
+/// are accepted, as the servers have no authentication.
+pub(crate) fn run(address: SocketAddr, scheme: &str, shutdown: impl Future<Output = ()> + Send + 'static, app: impl FnOnce(AsyncTsf) -> Router) -> Result<()> {
+    if !address.ip().is_loopback() {
+        return Err(TsfError::InvalidArgument("the server only listens on loopback addresses"));
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_io().build()?;
+    runtime.block_on(async {
+        let app = app(AsyncTsf::spawn().await?);
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        eprintln!("iatjc: listening on {scheme}://{}", listener.local_addr()?);
+        axum::serve(listener, app).with_graceful_shutdown(shutdown).await?;
+        Ok(())
+    })
+}
+
+impl ConvertRequest {
+    pub(crate) async fn answer(self, tsf: &AsyncTsf) -> Result<ConvertResponse> {
+        let options = RequestOptions { max_candidates: self.count, ..Default::default() };
+        let candidates = tsf.convert_with(&self.reading, &options).await?;
+        Ok(ConvertResponse { candidates })
+    }
+}
+
+impl SegmentRequest {
+    pub(crate) async fn answer(self, tsf: &AsyncTsf) -> Result<SegmentResponse> {
+        let clauses = tsf
+            .segment(&self.reading)
+            .await?
+            .into_iter()
+            .enumerate()
+            .map(|(index, clause)| Clause { index, start: clause.start, reading: clause.reading, surface: clause.surface })
+            .collect();
+        Ok(SegmentResponse { clauses })
+    }
+}
+
+impl ReadingRequest {
+    pub(crate) async fn answer(self, tsf: &AsyncTsf) -> Result<ReadingResponse> {
+        Ok(ReadingResponse { reading: tsf.reading(&self.text).await? })
+    }
+}
+
+async fn convert(State(tsf): State<AsyncTsf>, Json(request): Json<ConvertRequest>) -> std::result::Result<Json<ConvertResponse>, Failure> {
+    Ok(Json(request.answer(&tsf).await?))
+}
+
+async fn segment(State(tsf): State<AsyncTsf>, Json(request): Json<SegmentRequest>) -> std::result::Result<Json<SegmentResponse>, Failure> {
+    Ok(Json(request.answer(&tsf).await?))
+}
+
+async fn reading(State(tsf): State<AsyncTsf>, Json(request): Json<ReadingRequest>) -> std::result::Result<Json<ReadingResponse>, Failure> {
+    Ok(Json(request.answer(&tsf).await?))
+}
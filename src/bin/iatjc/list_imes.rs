@@ -0,0 +1,66 @@
+use iatjc_rs::{
+    profiles::{self, Profile, ProfileKind},
+    Result,
+};
+use serde::Serialize;
+
+use crate::output::{self, Format};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Only list enabled profiles.
+    #[arg(long)]
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+pub struct Entry {
+    langid: String,
+    kind: &'static str,
+    description: String,
+    clsid: String,
+    profile: String,
+    hkl: String,
+    enabled: bool,
+    active: bool,
+    default: bool,
+}
+
+impl From<&Profile> for Entry {
+    fn from(profile: &Profile) -> Self {
+        Self {
+            langid: format!("0x{:04x}", profile.langid),
+            kind: match profile.kind {
+                ProfileKind::InputProcessor => "tip",
+                ProfileKind::KeyboardLayout => "layout",
+            },
+            description: profile.description.clone(),
+            clsid: format!("{:?}", profile.clsid),
+            profile: format!("{:?}", profile.profile),
+            hkl: format!("0x{:08x}", profile.hkl.0),
+            enabled: profile.enabled,
+            active: profile.active,
+            default: profile.default,
+        }
+    }
+}
+
+pub fn run(args: &Args, format: Format) -> Result<()> {
+    let entries: Vec<Entry> = profiles::installed()?.iter().filter(|profile| profile.enabled || !args.enabled).map(Entry::from).collect();
+
+    match format {
+        Format::Text | Format::Tsv | Format::Csv | Format::RubyHtml | Format::Anki => {
+            println!("{:<6}  {:<6}  {:<3}  {:<38}  {:<38}  DESCRIPTION", "LANG", "KIND", "EAD", "CLSID", "PROFILE");
+            for entry in &entries {
+                let flags: String = [(entry.enabled, 'E'), (entry.active, 'A'), (entry.default, 'D')]
+                    .iter()
+                    .map(|&(set, flag)| if set { flag } else { '-' })
+                    .collect();
+                let description = if entry.kind == "layout" { format!("keyboard layout {}", entry.hkl) } else { entry.description.clone() };
+                println!("{:<6}  {:<6}  {:<3}  {:<38}  {:<38}  {}", entry.langid, entry.kind, flags, entry.clsid, entry.profile, description);
+            }
+            Ok(())
+        }
+        Format::Json => output::json(&entries),
+    }
+}
@@ -0,0 +1,187 @@
+mod agent;
+mod clipboard;
+mod com_server;
+mod convert;
+mod doctor;
+mod furigana;
+#[cfg(feature = "http")]
+mod http;
+mod list_imes;
+mod output;
+mod pipe;
+mod repl;
+mod rpc;
+mod segment;
+mod serve;
+mod watch;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+use std::process::ExitCode;
+#[cfg(feature = "test-utils")]
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::OnceLock};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use output::Format;
+use serde::Serialize;
+use iatjc_rs::{
+    com::Com,
+    engine::{EngineChain, EngineKind, FALLBACK_ORDER},
+    Result, TsfError,
+};
+#[cfg(feature = "test-utils")]
+use iatjc_rs::{fake_tip::Script, test_mode::TestMode};
+
+#[derive(Parser)]
+#[command(name = "iatjc", version, about = "Japanese conversion through the installed Windows IME")]
+struct Cli {
+    /// Output format.
+    #[arg(long, value_enum, global = true, default_value_t)]
+    format: Format,
+
+    /// Convert through a scripted input processor instead of the installed
+    /// one, for testing: a JSON object mapping readings to candidates.
+    #[cfg(feature = "test-utils")]
+    #[arg(long, global = true, value_name = "FILE", hide = true)]
+    fake_tip: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Converts a kana reading and prints the candidates.
+    Convert(convert::Args),
+    /// Converts readings interactively and commits the chosen candidates.
+    Repl(repl::Args),
+    /// Lists the installed input processors and keyboard layouts.
+    ListImes(list_imes::Args),
+    /// Checks the environment and prints what keeps conversion from working.
+    Doctor(doctor::Args),
+    /// Prints IME state changes as they happen, until Ctrl+C.
+    Watch(watch::Args),
+    /// Splits a reading into clauses and converts each of them.
+    Segment(segment::Args),
+    /// Annotates kanji with their readings.
+    Furigana(furigana::Args),
+    /// Serves conversions to other programs.
+    Serve(serve::Args),
+    /// Runs the `Iatjc.Converter` local COM server for scripts and other
+    /// COM clients, or registers it.
+    ComServer(com_server::Args),
+    /// Keeps a pipe or HTTP server running for the rest of the session, or
+    /// sets it to start at logon.
+    Agent(agent::Args),
+    /// Reconverts the text on the clipboard and optionally copies a
+    /// candidate back.
+    Clipboard(clipboard::Args),
+}
+
+/// [`EngineKind`] as a command-line value.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Engine {
+    Tsf,
+    Felang,
+    Imm32,
+}
+
+impl From<Engine> for EngineKind {
+    fn from(engine: Engine) -> Self {
+        match engine {
+            Engine::Tsf => EngineKind::Tsf,
+            Engine::Felang => EngineKind::FeLanguage,
+            Engine::Imm32 => EngineKind::Imm32,
+        }
+    }
+}
+
+impl From<EngineKind> for Engine {
+    fn from(kind: EngineKind) -> Self {
+        match kind {
+            EngineKind::Tsf => Engine::Tsf,
+            EngineKind::FeLanguage => Engine::Felang,
+            EngineKind::Imm32 => Engine::Imm32,
+        }
+    }
+}
+
+/// The test mode of a run with `--fake-tip`.
+#[cfg(feature = "test-utils")]
+static TEST_MODE: OnceLock<TestMode> = OnceLock::new();
+
+/// Whether this run answers from a `--fake-tip` script.
+fn is_scripted() -> bool {
+    #[cfg(feature = "test-utils")]
+    return TEST_MODE.get().is_some();
+    #[cfg(not(feature = "test-utils"))]
+    false
+}
+
+/// Opens the engines selected on the command line, or the default fallback
+/// order when none was.
+fn open_chain<'com>(com: &'com Com, engines: &[Engine]) -> Result<EngineChain<'com>> {
+    let order: Vec<EngineKind> = if engines.is_empty() { FALLBACK_ORDER.to_vec() } else { engines.iter().copied().map(EngineKind::from).collect() };
+
+    #[cfg(feature = "test-utils")]
+    if let Some(test_mode) = TEST_MODE.get() {
+        return EngineChain::with_test_mode(test_mode, &order);
+    }
+    EngineChain::with_order(com, &order)
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if cli.format.is_tabular() && !matches!(cli.command, Command::Convert(_) | Command::Segment(_) | Command::Clipboard(_)) {
+        return Err(TsfError::InvalidArgument("--format tsv and csv are only supported by convert, segment and clipboard"));
+    }
+    if cli.format.is_ruby() && !matches!(cli.command, Command::Furigana(_)) {
+        return Err(TsfError::InvalidArgument("--format ruby-html and anki are only supported by furigana"));
+    }
+
+    #[cfg(feature = "test-utils")]
+    if let Some(path) = &cli.fake_tip {
+        let _ = TEST_MODE.set(TestMode::new(load_script(path)?));
+    }
+
+    let com = Com::new()?;
+    match cli.command {
+        Command::Convert(args) => convert::run(&com, &args, cli.format),
+        Command::Repl(args) => repl::run(&com, &args, cli.format),
+        Command::ListImes(args) => list_imes::run(&args, cli.format),
+        Command::Doctor(args) => doctor::run(&com, &args, cli.format),
+        Command::Watch(args) => watch::run(&com, &args, cli.format),
+        Command::Segment(args) => segment::run(&com, &args, cli.format),
+        Command::Furigana(args) => furigana::run(&com, &args, cli.format),
+        Command::Serve(args) => serve::run(&com, &args),
+        Command::ComServer(args) => com_server::run(&args),
+        Command::Agent(args) => agent::run(&com, &args),
+        Command::Clipboard(args) => clipboard::run(&com, &args, cli.format),
+    }
+}
+
+#[cfg(feature = "test-utils")]
+fn load_script(path: &Path) -> Result<Script> {
+    let candidates: HashMap<String, Vec<String>> = serde_json::from_slice(&std::fs::read(path)?)
+        .map_err(|_| TsfError::InvalidArgument("--fake-tip expects a JSON object of candidate lists"))?;
+    Ok(Script::from(candidates))
+}
+
+fn main() -> ExitCode {
+    #[cfg(feature = "tracing")]
+    {
+        use tracing_subscriber::{fmt::Subscriber, EnvFilter};
+
+        // `RUST_LOG` picks the levels; `iatjc doctor` points at it.
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        Subscriber::builder().with_writer(std::io::stderr).with_env_filter(filter).init();
+    }
+
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("iatjc: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
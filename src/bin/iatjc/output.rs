@@ -0,0 +1,105 @@
+use std::{io::Write, time::Duration};
+
+use clap::ValueEnum;
+use iatjc_rs::Result;
+use serde::Serialize;
+
+/// How results are printed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum Format {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON document per result.
+    Json,
+    /// Tab-separated rows with the columns of [`Row`].
+    Tsv,
+    /// Comma-separated rows with the columns of [`Row`].
+    Csv,
+    /// Furigana as HTML `<ruby>` elements.
+    RubyHtml,
+    /// Furigana as `漢字[かんじ]`, for Anki.
+    Anki,
+}
+
+impl Format {
+    /// Whether results are printed as [`Row`]s.
+    pub fn is_tabular(self) -> bool {
+        matches!(self, Self::Tsv | Self::Csv)
+    }
+
+    /// Whether results are printed as furigana markup.
+    pub fn is_ruby(self) -> bool {
+        matches!(self, Self::RubyHtml | Self::Anki)
+    }
+}
+
+/// One candidate of a batch result. The columns are fixed so that output
+/// can be imported without per-subcommand handling.
+pub struct Row<'a> {
+    pub input: &'a str,
+    /// Index of the clause, from 0. Conversions that are not split into
+    /// clauses are a single clause 0.
+    pub clause: usize,
+    /// 1 for the best candidate.
+    pub rank: usize,
+    pub candidate: &'a str,
+}
+
+const COLUMNS: [&str; 5] = ["input", "clause", "rank", "candidate", "error"];
+
+/// Writes the header line of a tabular format.
+pub fn header(format: Format) -> Result<()> {
+    line(format, &COLUMNS)
+}
+
+/// Writes `row` in a tabular format.
+pub fn row(format: Format, row: &Row) -> Result<()> {
+    line(format, &[row.input, &row.clause.to_string(), &row.rank.to_string(), row.candidate, ""])
+}
+
+/// Writes a row without a candidate for `input`, which was blank, had no
+/// candidates, or failed with `error`.
+pub fn empty_row(format: Format, input: &str, error: Option<&str>) -> Result<()> {
+    line(format, &[input, "", "", "", error.unwrap_or_default()])
+}
+
+fn line(format: Format, fields: &[&str]) -> Result<()> {
+    let fields: Vec<String> = match format {
+        // TSV has no quoting, so separators inside fields become spaces.
+        Format::Tsv => fields.iter().map(|field| field.replace(['\t', '\n', '\r'], " ")).collect(),
+        Format::Csv => fields.iter().map(|field| csv_field(field)).collect(),
+        Format::Text | Format::Json | Format::RubyHtml | Format::Anki => fields.iter().map(|field| field.to_string()).collect(),
+    };
+    let separator = if format == Format::Csv { "," } else { "\t" };
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", fields.join(separator))?;
+    Ok(())
+}
+
+/// Quotes a CSV field as in RFC 4180 when it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes `value` as a single line of JSON to stdout.
+pub fn json(value: &impl Serialize) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value).map_err(std::io::Error::from)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// A duration in milliseconds, as printed in JSON output. Always zero with
+/// `--fake-tip`.
+pub fn millis(duration: Duration) -> f64 {
+    if crate::is_scripted() {
+        return 0.0;
+    }
+    duration.as_secs_f64() * 1000.0
+}
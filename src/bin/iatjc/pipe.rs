@@ -0,0 +1,149 @@
+use std::io::{self, Read, Write};
+
+use windows::Win32::{
+    Foundation::{CloseHandle, LocalFree, ERROR_PIPE_CONNECTED, HANDLE, HLOCAL},
+    Security::{
+        Authorization::{ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1},
+        GetTokenInformation, TokenUser, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, TOKEN_QUERY, TOKEN_USER,
+    },
+    Storage::FileSystem::{FlushFileBuffers, ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+    System::{
+        Pipes::{ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT},
+        Threading::{GetCurrentProcess, OpenProcessToken},
+    },
+};
+use windows_core::{HSTRING, PWSTR};
+
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+/// One server-side instance of a byte-mode named pipe, accepting local
+/// clients of the current user only.
+pub struct PipeInstance(HANDLE);
+
+impl PipeInstance {
+    /// Creates an instance of the pipe `name`. The `first` instance fails
+    /// if another process already owns the name, so that it cannot be
+    /// squatted.
+    pub fn create(name: &str, first: bool) -> io::Result<Self> {
+        let security = OwnerOnly::new()?;
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: security.0 .0,
+            bInheritHandle: false.into(),
+        };
+        let mode = if first { PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE } else { PIPE_ACCESS_DUPLEX };
+        let handle = unsafe {
+            CreateNamedPipeW(
+                &HSTRING::from(name),
+                mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                Some(&attributes),
+            )
+        };
+        if handle.is_invalid() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+
+    /// Blocks until a client connects.
+    pub fn accept(self) -> io::Result<PipeStream> {
+        match unsafe { ConnectNamedPipe(self.0, None) } {
+            Ok(()) => {}
+            // The client connected between creation and this call.
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => {}
+            Err(e) => return Err(e.into()),
+        }
+        let stream = PipeStream(self.0);
+        std::mem::forget(self);
+        Ok(stream)
+    }
+}
+
+impl Drop for PipeInstance {
+    fn drop(&mut self) {
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// A connected pipe instance, disconnected and closed on drop.
+pub struct PipeStream(HANDLE);
+
+impl Read for PipeStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        match unsafe { ReadFile(self.0, Some(buffer), Some(&mut read), None) } {
+            Ok(()) => Ok(read as usize),
+            // A closed client reads as end of file.
+            Err(e) if e.code() == windows::Win32::Foundation::ERROR_BROKEN_PIPE.to_hresult() => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        unsafe { WriteFile(self.0, Some(buffer), Some(&mut written), None) }?;
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        unsafe { FlushFileBuffers(self.0) }?;
+        Ok(())
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = DisconnectNamedPipe(self.0);
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+/// A security descriptor whose DACL grants the current user, and nobody
+/// else, access.
+struct OwnerOnly(PSECURITY_DESCRIPTOR);
+
+impl OwnerOnly {
+    fn new() -> io::Result<Self> {
+        let sid = current_user_sid()?;
+        let sddl = HSTRING::from(format!("D:P(A;;GA;;;{sid})"));
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe { ConvertStringSecurityDescriptorToSecurityDescriptorW(&sddl, SDDL_REVISION_1, &mut descriptor, None) }?;
+        Ok(Self(descriptor))
+    }
+}
+
+impl Drop for OwnerOnly {
+    fn drop(&mut self) {
+        unsafe { LocalFree(HLOCAL(self.0 .0)) };
+    }
+}
+
+/// The SID of the user running this process, as a string.
+fn current_user_sid() -> io::Result<String> {
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) }?;
+
+    let mut length = 0;
+    let _ = unsafe { GetTokenInformation(token, TokenUser, None, 0, &mut length) };
+    // u64s keep the buffer aligned for TOKEN_USER.
+    let mut buffer = vec![0u64; (length as usize).div_ceil(8)];
+    let queried = unsafe { GetTokenInformation(token, TokenUser, Some(buffer.as_mut_ptr().cast()), length, &mut length) };
+    let _ = unsafe { CloseHandle(token) };
+    queried?;
+
+    let user = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+    let mut sid = PWSTR::null();
+    unsafe { ConvertSidToStringSidW(user.User.Sid, &mut sid) }?;
+    let string = unsafe { sid.to_string() };
+    unsafe { LocalFree(HLOCAL(sid.0.cast())) };
+    string.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
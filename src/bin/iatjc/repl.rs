@@ -0,0 +1,149 @@
+use std::io::{BufRead, Write};
+
+use iatjc_rs::{
+    com::Com,
+    kana, romaji,
+    tsf::TSF,
+    width::{self, WidthClasses},
+    Result,
+};
+use serde::Serialize;
+
+use crate::output::{self, Format};
+
+const HELP: &str = "\
+Type a reading (kana or romaji) to list candidates, then a number to commit one.
+  :convert    convert through the IME (default)
+  :hiragana   show the reading as hiragana
+  :katakana   show the reading as katakana
+  :halfwidth  show the reading as half-width katakana
+  :help       show this help
+  :quit       exit";
+
+#[derive(clap::Args)]
+pub struct Args {}
+
+/// What a reading is turned into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    Convert,
+    Hiragana,
+    Katakana,
+    Halfwidth,
+}
+
+#[derive(Serialize)]
+struct Candidates<'a> {
+    input: &'a str,
+    mode: Mode,
+    candidates: &'a [String],
+}
+
+#[derive(Serialize)]
+struct Committed<'a> {
+    input: &'a str,
+    committed: &'a str,
+    learned: bool,
+}
+
+struct Repl<'com> {
+    tsf: TSF<'com>,
+    format: Format,
+    mode: Mode,
+    /// The last reading and its candidates, for selection by number.
+    pending: Option<(String, Vec<String>)>,
+}
+
+pub fn run(com: &Com, _args: &Args, format: Format) -> Result<()> {
+    let mut tsf = TSF::new(com);
+    tsf.initialize()?;
+    let mut repl = Repl { tsf, format, mode: Mode::Convert, pending: None };
+
+    if format == Format::Text {
+        println!("{HELP}");
+    }
+
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        if format == Format::Text {
+            print!("{:?}> ", repl.mode);
+            std::io::stdout().flush()?;
+        }
+
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        let line = line.trim();
+
+        match line {
+            "" => {}
+            ":q" | ":quit" | ":exit" => return Ok(()),
+            ":help" => println!("{HELP}"),
+            ":convert" => repl.mode = Mode::Convert,
+            ":hiragana" => repl.mode = Mode::Hiragana,
+            ":katakana" => repl.mode = Mode::Katakana,
+            ":halfwidth" => repl.mode = Mode::Halfwidth,
+            _ if line.starts_with(':') => eprintln!("unknown command {line}; see :help"),
+            _ => {
+                if let Err(e) = repl.input(line) {
+                    eprintln!("iatjc: {e}");
+                }
+            }
+        }
+    }
+}
+
+impl Repl<'_> {
+    fn input(&mut self, line: &str) -> Result<()> {
+        if let Ok(number) = line.parse::<usize>() {
+            return self.commit(number);
+        }
+
+        let reading = if line.is_ascii() { romaji::to_hiragana(line) } else { line.to_string() };
+        let candidates = match self.mode {
+            Mode::Convert => self.tsf.convert(&reading)?,
+            Mode::Hiragana => vec![kana::to_hiragana(&reading)],
+            Mode::Katakana => vec![kana::to_katakana(&reading)],
+            Mode::Halfwidth => vec![width::to_halfwidth(&kana::to_katakana(&reading), WidthClasses::KATAKANA)],
+        };
+
+        match self.format {
+            Format::Text | Format::Tsv | Format::Csv | Format::RubyHtml | Format::Anki => {
+                for (index, candidate) in candidates.iter().enumerate() {
+                    println!("{:>3}  {candidate}", index + 1);
+                }
+            }
+            Format::Json => output::json(&Candidates { input: &reading, mode: self.mode, candidates: &candidates })?,
+        }
+
+        self.pending = Some((reading, candidates));
+        Ok(())
+    }
+
+    /// Commits the `number`th candidate of the last list. Only IME
+    /// candidates are reported back for learning.
+    fn commit(&mut self, number: usize) -> Result<()> {
+        let Some((reading, candidates)) = &self.pending else {
+            eprintln!("nothing to select; type a reading first");
+            return Ok(());
+        };
+        let Some(candidate) = number.checked_sub(1).and_then(|index| candidates.get(index)) else {
+            eprintln!("no candidate {number}");
+            return Ok(());
+        };
+
+        let learned = self.mode == Mode::Convert;
+        if learned {
+            self.tsf.commit(reading, candidate)?;
+        }
+
+        match self.format {
+            Format::Text | Format::Tsv | Format::Csv | Format::RubyHtml | Format::Anki => println!("{candidate}"),
+            Format::Json => output::json(&Committed { input: reading, committed: candidate, learned })?,
+        }
+
+        self.pending = None;
+        Ok(())
+    }
+}
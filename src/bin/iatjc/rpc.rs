@@ -0,0 +1,148 @@
+use iatjc_rs::{com::Com, engine::EngineChain, felang::FeLanguage, profiles, TsfError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{list_imes::Entry, open_chain, Engine};
+
+// JSON-RPC 2.0 error codes.
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+/// A request that was understood but failed in the engine.
+pub const ENGINE_ERROR: i32 = -32000;
+
+#[derive(Deserialize)]
+pub struct Request {
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Serialize)]
+pub struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Error>,
+}
+
+impl Response {
+    pub fn new(id: Value, outcome: std::result::Result<Value, Error>) -> Self {
+        match outcome {
+            Ok(result) => Self { jsonrpc: "2.0", id, result: Some(result), error: None },
+            Err(error) => Self { jsonrpc: "2.0", id, result: None, error: Some(error) },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Error {
+    pub code: i32,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<TsfError> for Error {
+    fn from(e: TsfError) -> Self {
+        Self::new(ENGINE_ERROR, e.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct ConvertParams {
+    reading: String,
+    #[serde(default)]
+    count: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct SegmentParams {
+    reading: String,
+}
+
+#[derive(Deserialize)]
+struct ReadingParams {
+    text: String,
+}
+
+/// Whether the server should keep serving after a request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flow {
+    Continue,
+    Shutdown,
+}
+
+/// Engines shared by every request of a server.
+pub struct Handler<'com> {
+    chain: EngineChain<'com>,
+    felang: Option<FeLanguage<'com>>,
+}
+
+impl<'com> Handler<'com> {
+    pub fn new(com: &'com Com, engines: &[Engine]) -> iatjc_rs::Result<Self> {
+        Ok(Self { chain: open_chain(com, engines)?, felang: FeLanguage::new(com).ok() })
+    }
+
+    /// Parses and answers one JSON-RPC message. Notifications yield no
+    /// response.
+    pub fn handle(&self, message: &[u8]) -> (Option<Response>, Flow) {
+        let request: Request = match serde_json::from_slice::<Value>(message) {
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => return (Some(Response::new(Value::Null, Err(Error::new(INVALID_REQUEST, e.to_string())))), Flow::Continue),
+            },
+            Err(e) => return (Some(Response::new(Value::Null, Err(Error::new(PARSE_ERROR, e.to_string())))), Flow::Continue),
+        };
+
+        let flow = if request.method == "shutdown" { Flow::Shutdown } else { Flow::Continue };
+        let outcome = self.call(&request.method, request.params);
+        (request.id.map(|id| Response::new(id, outcome)), flow)
+    }
+
+    pub fn call(&self, method: &str, params: Value) -> std::result::Result<Value, Error> {
+        match method {
+            "convert" => {
+                let params: ConvertParams = parse(params)?;
+                let answered = self.chain.convert(&params.reading, params.count)?;
+                let candidates: Vec<String> = answered.value.into_iter().map(|candidate| candidate.text).collect();
+                Ok(json!({ "engine": Engine::from(answered.engine), "candidates": candidates }))
+            }
+            "segment" => {
+                let params: SegmentParams = parse(params)?;
+                let felang = self.felang.as_ref().ok_or_else(|| Error::new(ENGINE_ERROR, "IFELanguage is not available"))?;
+                let clauses: Vec<Value> = felang
+                    .segment(&params.reading)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, clause)| json!({ "index": index, "start": clause.start, "reading": clause.reading, "surface": clause.surface }))
+                    .collect();
+                Ok(json!({ "clauses": clauses }))
+            }
+            "reading" => {
+                let params: ReadingParams = parse(params)?;
+                let answered = self.chain.reading(&params.text)?;
+                Ok(json!({ "engine": Engine::from(answered.engine), "reading": answered.value }))
+            }
+            "listImes" => {
+                let entries: Vec<Entry> = profiles::installed()?.iter().map(Entry::from).collect();
+                Ok(serde_json::to_value(entries).map_err(|e| Error::new(ENGINE_ERROR, e.to_string()))?)
+            }
+            "shutdown" => Ok(Value::Null),
+            _ => Err(Error::new(METHOD_NOT_FOUND, format!("unknown method {method}"))),
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> std::result::Result<T, Error> {
+    serde_json::from_value(params).map_err(|e| Error::new(INVALID_PARAMS, e.to_string()))
+}
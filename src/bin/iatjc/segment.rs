@@ -0,0 +1,90 @@
+use iatjc_rs::{com::Com, felang::FeLanguage, Result};
+use serde::Serialize;
+
+use crate::{
+    open_chain,
+    output::{self, Format, Row},
+    Engine,
+};
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// The kana reading to segment.
+    reading: String,
+
+    /// Also list the candidates of every clause.
+    #[arg(short, long)]
+    candidates: bool,
+
+    /// List at most this many candidates per clause.
+    #[arg(short = 'n', long, requires = "candidates")]
+    count: Option<usize>,
+
+    /// Engines to convert clauses with, in order. Defaults to
+    /// tsf,felang,imm32.
+    #[arg(short, long, value_enum, value_delimiter = ',', requires = "candidates")]
+    engine: Vec<Engine>,
+}
+
+#[derive(Serialize)]
+struct Segmentation<'a> {
+    input: &'a str,
+    clauses: Vec<Clause>,
+}
+
+#[derive(Serialize)]
+struct Clause {
+    index: usize,
+    /// Offset in the input, in chars.
+    start: usize,
+    reading: String,
+    best: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidates: Option<Vec<String>>,
+}
+
+pub fn run(com: &Com, args: &Args, format: Format) -> Result<()> {
+    let segmented = FeLanguage::new(com)?.segment(&args.reading)?;
+    let chain = if args.candidates { Some(open_chain(com, &args.engine)?) } else { None };
+
+    let mut clauses = Vec::with_capacity(segmented.len());
+    for (index, clause) in segmented.into_iter().enumerate() {
+        let candidates = match &chain {
+            Some(chain) => Some(
+                chain
+                    .convert(&clause.reading, args.count)?
+                    .value
+                    .into_iter()
+                    .map(|candidate| candidate.text)
+                    .collect(),
+            ),
+            None => None,
+        };
+        clauses.push(Clause { index, start: clause.start, reading: clause.reading, best: clause.surface, candidates });
+    }
+
+    match format {
+        Format::Text | Format::RubyHtml | Format::Anki => {
+            let boundaries: Vec<&str> = clauses.iter().map(|clause| clause.reading.as_str()).collect();
+            println!("{}", boundaries.join("|"));
+            for clause in &clauses {
+                println!("{}\t{}\t{}", clause.index + 1, clause.reading, clause.best);
+                for (rank, candidate) in clause.candidates.iter().flatten().enumerate() {
+                    println!("\t{}\t{candidate}", rank + 1);
+                }
+            }
+            Ok(())
+        }
+        Format::Json => output::json(&Segmentation { input: &args.reading, clauses }),
+        Format::Tsv | Format::Csv => {
+            output::header(format)?;
+            for clause in &clauses {
+                let candidates = clause.candidates.as_deref().unwrap_or(std::slice::from_ref(&clause.best));
+                for (index, candidate) in candidates.iter().enumerate() {
+                    output::row(format, &Row { input: &args.reading, clause: clause.index, rank: index + 1, candidate })?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    io::{BufRead, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread,
+};
+
+use clap::ArgGroup;
+use iatjc_rs::{com::Com, Result, TsfError};
+use serde_json::Value;
+
+use crate::{
+    pipe::{PipeInstance, PipeStream},
+    rpc::{self, Flow, Handler, Response},
+    Engine,
+};
+
+#[derive(clap::Args)]
+#[group(skip)]
+#[command(group(ArgGroup::new("transport").required(true)))]
+pub struct Args {
+    /// Serve JSON-RPC 2.0 on stdin/stdout, framed with `Content-Length`
+    /// headers like the Language Server Protocol.
+    #[arg(long, group = "transport")]
+    stdio: bool,
+
+    /// Serve JSON-RPC 2.0 on a named pipe such as `\\.\pipe\iatjc`. Each
+    /// message is a little-endian u32 byte length followed by the JSON.
+    /// Any number of local clients share this process's engines.
+    #[arg(long, group = "transport", value_name = "NAME")]
+    pipe: Option<String>,
+
+    /// Serve a JSON HTTP API (`POST /convert`, `/segment`, `/reading`) on a
+    /// loopback address such as `127.0.0.1:8080`.
+    #[cfg(feature = "http")]
+    #[arg(long, group = "transport", value_name = "ADDRESS")]
+    http: Option<std::net::SocketAddr>,
+
+    /// Serve requests and live IME events over a WebSocket on a loopback
+    /// address such as `127.0.0.1:8081`. Requests are `{"id", "method",
+    /// "params"}` with the methods of the HTTP API; events arrive as
+    /// `{"event", ...}` messages.
+    #[cfg(feature = "websocket")]
+    #[arg(long, group = "transport", value_name = "ADDRESS")]
+    websocket: Option<std::net::SocketAddr>,
+
+    /// Let web pages from this origin, such as `http://localhost:3000`, call
+    /// the HTTP or WebSocket server; repeat for more. Pages from other
+    /// origins are refused.
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ORIGIN")]
+    allow_origin: Vec<String>,
+
+    /// Engines to convert with, in order. Defaults to tsf,felang,imm32. The
+    /// HTTP and WebSocket servers only convert with tsf.
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    engine: Vec<Engine>,
+}
+
+pub fn run(com: &Com, args: &Args) -> Result<()> {
+    // The HTTP and WebSocket servers run on the async facade's own worker thread.
+    #[cfg(feature = "http")]
+    if let Some(address) = args.http {
+        crate::http::check_engines(&args.engine)?;
+        return crate::http::serve(address, crate::http::Origins::new(args.allow_origin.clone()), std::future::pending());
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(address) = args.websocket {
+        crate::http::check_engines(&args.engine)?;
+        return crate::websocket::serve(address, crate::http::Origins::new(args.allow_origin.clone()));
+    }
+
+    let handler = Handler::new(com, &args.engine)?;
+    match &args.pipe {
+        Some(name) => pipe(&handler, name, |_| Ok(())),
+        None => stdio(&handler),
+    }
+}
+
+fn stdio(handler: &Handler) -> Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+
+    while let Some(message) = read_message(&mut input)? {
+        let (response, flow) = handler.handle(&message);
+        if let Some(response) = response {
+            let body = serde_json::to_vec(&response).map_err(std::io::Error::from)?;
+            write!(output, "Content-Length: {}\r\n\r\n", body.len())?;
+            output.write_all(&body)?;
+            output.flush()?;
+        }
+        if flow == Flow::Shutdown {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads one `Content-Length`-framed message, or `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or(TsfError::InvalidArgument("message without a valid Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// A request forwarded from a connection thread to the engine thread.
+pub struct Job {
+    message: Vec<u8>,
+    reply: Sender<Option<Response>>,
+}
+
+/// State kept per pipe connection.
+struct Session {
+    id: u64,
+    /// Candidate limit applied to `convert` requests that do not set one,
+    /// changed with the `configure` method.
+    count: Option<usize>,
+}
+
+/// Stops a pipe server from another thread.
+pub struct Stop(Sender<Job>);
+
+impl Stop {
+    /// Queues a `shutdown` notification behind the requests already waiting
+    /// and blocks until the engine thread has reached it.
+    pub fn stop(&self) {
+        let (reply, done) = mpsc::channel();
+        let message = br#"{"jsonrpc":"2.0","method":"shutdown"}"#.to_vec();
+        if self.0.send(Job { message, reply }).is_ok() {
+            let _ = done.recv();
+        }
+    }
+}
+
+/// Accepts clients on their own threads and runs every request here, on
+/// the thread owning the engines. `started` receives a [`Stop`] for the
+/// server once it is listening.
+pub fn pipe(handler: &Handler, name: &str, started: impl FnOnce(Stop) -> Result<()>) -> Result<()> {
+    // Created up front so that a bad name fails right away.
+    let first = PipeInstance::create(name, true)?;
+    let (jobs, receiver) = mpsc::channel::<Job>();
+    started(Stop(jobs.clone()))?;
+    let name = name.to_string();
+    thread::Builder::new().name("iatjc-pipe".to_string()).spawn(move || listen(first, &name, jobs))?;
+
+    for job in receiver {
+        let (response, flow) = handler.handle(&job.message);
+        let _ = job.reply.send(response);
+        if flow == Flow::Shutdown {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn listen(mut instance: PipeInstance, name: &str, jobs: Sender<Job>) {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    loop {
+        let stream = match instance.accept() {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("iatjc: failed to accept a pipe client: {e}");
+                return;
+            }
+        };
+
+        let session = Session { id: NEXT_ID.fetch_add(1, Ordering::Relaxed), count: None };
+        let jobs = jobs.clone();
+        let spawned = thread::Builder::new().name(format!("iatjc-pipe-{}", session.id)).spawn(move || {
+            if let Err(e) = serve_connection(stream, session, &jobs) {
+                eprintln!("iatjc: pipe client failed: {e}");
+            }
+        });
+        if let Err(e) = spawned {
+            eprintln!("iatjc: failed to start a pipe client thread: {e}");
+        }
+
+        instance = match PipeInstance::create(name, false) {
+            Ok(instance) => instance,
+            Err(e) => {
+                eprintln!("iatjc: failed to create a pipe instance: {e}");
+                return;
+            }
+        };
+    }
+}
+
+fn serve_connection(mut stream: PipeStream, mut session: Session, jobs: &Sender<Job>) -> Result<()> {
+    while let Some(message) = read_frame(&mut stream)? {
+        let response = match session.apply(&message) {
+            Some(local) => local,
+            None => {
+                let (reply, response) = mpsc::channel();
+                let message = session.rewrite(message);
+                if jobs.send(Job { message, reply }).is_err() {
+                    // The server is shutting down.
+                    return Ok(());
+                }
+                response.recv().map_err(|_| TsfError::WorkerStopped)?
+            }
+        };
+
+        if let Some(response) = response {
+            let body = serde_json::to_vec(&response).map_err(std::io::Error::from)?;
+            stream.write_all(&(body.len() as u32).to_le_bytes())?;
+            stream.write_all(&body)?;
+            stream.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Session {
+    /// Answers requests about the connection itself without involving the
+    /// engines.
+    fn apply(&mut self, message: &[u8]) -> Option<Option<Response>> {
+        let request: rpc::Request = serde_json::from_slice(message).ok()?;
+        let outcome = match request.method.as_str() {
+            "configure" => match request.params.get("count") {
+                Some(Value::Null) | None => {
+                    self.count = None;
+                    Ok(Value::Null)
+                }
+                Some(count) => match count.as_u64() {
+                    Some(count) => {
+                        self.count = Some(count as usize);
+                        Ok(Value::Null)
+                    }
+                    None => Err(rpc::Error::new(rpc::INVALID_PARAMS, "count must be a non-negative integer")),
+                },
+            },
+            "session" => Ok(serde_json::json!({ "id": self.id, "count": self.count })),
+            // Clients share the server, so only its owner may stop it, with
+            // a `Stop`.
+            "shutdown" => Err(rpc::Error::new(rpc::INVALID_REQUEST, "only the server's owner can shut it down")),
+            _ => return None,
+        };
+        Some(request.id.map(|id| Response::new(id, outcome)))
+    }
+
+    /// Fills in the connection's defaults.
+    fn rewrite(&self, message: Vec<u8>) -> Vec<u8> {
+        let Some(count) = self.count else {
+            return message;
+        };
+        let Ok(mut value) = serde_json::from_slice::<Value>(&message) else {
+            return message;
+        };
+
+        if value.get("method").and_then(Value::as_str) == Some("convert")
+            && let Some(params) = value.get_mut("params").and_then(Value::as_object_mut)
+            && !params.contains_key("count")
+        {
+            params.insert("count".to_string(), count.into());
+        }
+        serde_json::to_vec(&value).unwrap_or(message)
+    }
+}
+
+/// The largest message a pipe client may send.
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Reads one length-prefixed message, or `None` when the client closed the
+/// pipe.
+fn read_frame(stream: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match stream.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(TsfError::InvalidArgument("frame is larger than 16 MiB"));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok(Some(body))
+}
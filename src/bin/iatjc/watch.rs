@@ -0,0 +1,117 @@
+use std::{sync::OnceLock, time::Instant};
+
+use iatjc_rs::{
+    com::Com,
+    events::{EventReceiver, TsfEvent},
+    pump::{IdleAction, MessageLoop, QuitSignal},
+    tsf::TSF,
+    Result,
+};
+use serde::Serialize;
+use windows::Win32::{
+    Foundation::{BOOL, FALSE, TRUE},
+    System::Console::SetConsoleCtrlHandler,
+    UI::TextServices::{GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION, GUID_COMPARTMENT_KEYBOARD_INPUTMODE_SENTENCE, GUID_COMPARTMENT_KEYBOARD_OPENCLOSE},
+};
+
+use crate::output::{self, Format};
+
+#[derive(clap::Args)]
+pub struct Args {}
+
+/// The loop to stop on Ctrl+C.
+static QUIT: OnceLock<QuitSignal> = OnceLock::new();
+
+#[derive(Serialize)]
+struct Entry {
+    /// Milliseconds since the watch started.
+    elapsed: f64,
+    event: &'static str,
+    detail: String,
+}
+
+pub fn run(com: &Com, _args: &Args, format: Format) -> Result<()> {
+    let mut tsf = TSF::new(com);
+    tsf.initialize()?;
+    let events = tsf.events().subscribe();
+
+    let quit = QuitSignal::for_current_thread();
+    let _ = QUIT.set(quit.clone());
+    if let Err(e) = unsafe { SetConsoleCtrlHandler(Some(on_ctrl), TRUE) } {
+        eprintln!("iatjc: Ctrl+C will not stop the watch cleanly: {e}");
+    }
+
+    let started = Instant::now();
+    let print = |event: &'static str, detail: String| {
+        let entry = Entry { elapsed: output::millis(started.elapsed()), event, detail };
+        match format {
+            Format::Text | Format::Tsv | Format::Csv | Format::RubyHtml | Format::Anki => {
+                println!("[{:>10.3}s] {:<12} {}", entry.elapsed / 1000.0, entry.event, entry.detail);
+                Ok(())
+            }
+            Format::Json => output::json(&entry),
+        }
+    };
+
+    print("start", format!("active profile {:?}, state {:?}", tsf.profile().map(|profile| profile.tip), tsf.state()))?;
+    if format == Format::Text {
+        eprintln!("Watching for IME changes; press Ctrl+C to stop.");
+    }
+
+    let mut failed = None;
+    MessageLoop::new(quit)
+        .on_idle(|| {
+            if let Err(e) = drain(&events, &print) {
+                failed.get_or_insert(e);
+                if let Some(quit) = QUIT.get() {
+                    let _ = quit.quit();
+                }
+            }
+            IdleAction::Wait
+        })
+        .run()?;
+
+    match failed {
+        Some(e) => Err(e),
+        None => print("stop", String::new()),
+    }
+}
+
+fn drain(events: &EventReceiver, print: &impl Fn(&'static str, String) -> Result<()>) -> Result<()> {
+    while let Some(event) = events.try_recv() {
+        let (name, detail) = describe(event);
+        print(name, detail)?;
+    }
+    Ok(())
+}
+
+fn describe(event: TsfEvent) -> (&'static str, String) {
+    match event {
+        TsfEvent::ProfileActivated { clsid, profile, langid, active } => {
+            ("profile", format!("{} {clsid:?} profile {profile:?} langid 0x{langid:04x}", if active { "activated" } else { "deactivated" }))
+        }
+        TsfEvent::CompartmentChanged { compartment, value } => {
+            let name = match compartment {
+                GUID_COMPARTMENT_KEYBOARD_OPENCLOSE => "open/close".to_string(),
+                GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION => "conversion mode".to_string(),
+                GUID_COMPARTMENT_KEYBOARD_INPUTMODE_SENTENCE => "sentence mode".to_string(),
+                other => format!("{other:?}"),
+            };
+            ("compartment", format!("{name} = {}", value.map_or("(empty)".to_string(), |value| format!("0x{value:x}"))))
+        }
+        TsfEvent::ThreadFocusChanged { focused } => ("focus", if focused { "thread focused" } else { "thread lost focus" }.to_string()),
+        TsfEvent::CandidatesUpdated { candidates, selection } => ("candidates", format!("{candidates:?}, selected {selection}")),
+        TsfEvent::CandidatesClosed => ("candidates", "closed".to_string()),
+        TsfEvent::CompositionStarted => ("composition", "started".to_string()),
+        TsfEvent::CompositionUpdated => ("composition", "updated".to_string()),
+        TsfEvent::CompositionEnded => ("composition", "ended".to_string()),
+        TsfEvent::TextChanged { start, old_end, new_end } => ("text", format!("{start}..{old_end} -> {start}..{new_end}")),
+    }
+}
+
+extern "system" fn on_ctrl(_kind: u32) -> BOOL {
+    match QUIT.get() {
+        Some(quit) if quit.quit().is_ok() => TRUE,
+        _ => FALSE,
+    }
+}
@@ -0,0 +1,146 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures_core::Stream;
+use iatjc_rs::{
+    async_tsf::AsyncTsf,
+    events::{EventStream, TsfEvent},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::http::{self, ConvertRequest, Origins, ReadingRequest, SegmentRequest};
+
+/// A request sent by the client, answered with a [`Reply`] carrying the same
+/// `id`.
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    call: Call,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "lowercase")]
+enum Call {
+    Convert(ConvertRequest),
+    Segment(SegmentRequest),
+    Reading(ReadingRequest),
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Result { id: Value, result: Value },
+    Error { id: Value, error: String },
+}
+
+/// A [`TsfEvent`] pushed to every client, tagged with `event`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "camelCase", rename_all_fields = "camelCase")]
+enum Notification {
+    CompositionStarted,
+    CompositionUpdated,
+    CompositionEnded,
+    CandidatesUpdated { candidates: Vec<String>, selection: u32 },
+    CandidatesClosed,
+    ProfileActivated { clsid: String, profile: String, langid: u16, active: bool },
+    TextChanged { start: i32, old_end: i32, new_end: i32 },
+    ThreadFocusChanged { focused: bool },
+    CompartmentChanged { compartment: String, value: Option<i32> },
+}
+
+impl From<TsfEvent> for Notification {
+    fn from(event: TsfEvent) -> Self {
+        match event {
+            TsfEvent::CompositionStarted => Self::CompositionStarted,
+            TsfEvent::CompositionUpdated => Self::CompositionUpdated,
+            TsfEvent::CompositionEnded => Self::CompositionEnded,
+            TsfEvent::CandidatesUpdated { candidates, selection } => Self::CandidatesUpdated { candidates, selection },
+            TsfEvent::CandidatesClosed => Self::CandidatesClosed,
+            TsfEvent::ProfileActivated { clsid, profile, langid, active } => Self::ProfileActivated { clsid: format!("{clsid:?}"), profile: format!("{profile:?}"), langid, active },
+            TsfEvent::TextChanged { start, old_end, new_end } => Self::TextChanged { start, old_end, new_end },
+            TsfEvent::ThreadFocusChanged { focused } => Self::ThreadFocusChanged { focused },
+            TsfEvent::CompartmentChanged { compartment, value } => Self::CompartmentChanged { compartment: format!("{compartment:?}"), value },
+        }
+    }
+}
+
+/// Accepts WebSocket connections on `ws://address/`. Clients send
+/// `{"id", "method", "params"}` text messages with the same methods and
+/// parameters as the HTTP API and receive every IME event as it happens.
+/// Browsers may only connect from pages in `origins`.
+pub fn serve(address: SocketAddr, origins: Origins) -> Result<()> {
+    http::run(address, "ws", std::future::pending(), |tsf| Router::new().route("/", get(upgrade)).with_state((tsf, origins)))
+}
+
+/// Refuses connections from pages outside the allowed origins with 403,
+/// since browsers let any page open a WebSocket to a local server.
+async fn upgrade(State((tsf, origins)): State<(AsyncTsf, Origins)>, headers: HeaderMap, upgrade: WebSocketUpgrade) -> Response {
+    if !origins.allow(&headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    upgrade.on_upgrade(move |socket| connection(socket, tsf))
+}
+
+async fn connection(mut socket: WebSocket, tsf: AsyncTsf) {
+    let mut events = tsf.events();
+    loop {
+        let outgoing = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => json(&answer(&tsf, &text).await),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = next(&mut events) => match event {
+                Some(event) => json(&Notification::from(event)),
+                None => break,
+            },
+        };
+
+        if socket.send(Message::Text(outgoing.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn answer(tsf: &AsyncTsf, text: &str) -> Reply {
+    let request: Request = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return Reply::Error { id: Value::Null, error: format!("invalid request: {e}") },
+    };
+
+    let id = request.id;
+    match request.call {
+        Call::Convert(request) => reply(id, request.answer(tsf).await),
+        Call::Segment(request) => reply(id, request.answer(tsf).await),
+        Call::Reading(request) => reply(id, request.answer(tsf).await),
+    }
+}
+
+fn reply(id: Value, result: Result<impl Serialize>) -> Reply {
+    match result.map(|response| serde_json::to_value(response)) {
+        Ok(Ok(result)) => Reply::Result { id, result },
+        Ok(Err(e)) => Reply::Error { id, error: e.to_string() },
+        Err(e) => Reply::Error { id, error: e.to_string() },
+    }
+}
+
+fn json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+async fn next(events: &mut EventStream) -> Option<TsfEvent> {
+    std::future::poll_fn(|cx| Pin::new(&mut *events).poll_next(cx)).await
+}
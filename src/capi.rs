@@ -0,0 +1,124 @@
+//! C ABI over [`TsfService`], built with the `capi` feature.
+//!
+//! Every function may be called from any thread: conversions run on the
+//! service's own STA worker. Strings are UTF-8 and NUL-terminated. On
+//! failure a function returns null (or a negative status) and the message is
+//! available from [`iatjc_last_error`] on the calling thread.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::{error::TsfError, service::TsfService};
+
+/// An opaque conversion session.
+pub struct Iatjc {
+    service: TsfService,
+}
+
+/// Candidates returned by [`iatjc_convert`], released with [`iatjc_free`].
+#[repr(C)]
+pub struct IatjcCandidates {
+    pub items: *mut *mut c_char,
+    pub count: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `body`, recording its error or panic and returning `fallback`
+/// instead.
+fn guard<T>(fallback: T, body: impl FnOnce() -> Result<T, TsfError>) -> T {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            fallback
+        }
+        Err(_) => {
+            set_last_error("panic inside iatjc".to_string());
+            fallback
+        }
+    }
+}
+
+/// Starts a session on a dedicated worker thread. Returns null on failure.
+#[unsafe(no_mangle)]
+pub extern "C" fn iatjc_init() -> *mut Iatjc {
+    guard(ptr::null_mut(), || Ok(Box::into_raw(Box::new(Iatjc { service: TsfService::spawn()? }))))
+}
+
+/// Stops the worker and releases `session`. Null is ignored.
+///
+/// # Safety
+///
+/// `session` must come from [`iatjc_init`] and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn iatjc_shutdown(session: *mut Iatjc) {
+    if !session.is_null() {
+        guard((), || {
+            drop(unsafe { Box::from_raw(session) });
+            Ok(())
+        });
+    }
+}
+
+/// Converts a kana reading. Returns null on failure.
+///
+/// # Safety
+///
+/// `session` must be a live session and `reading` a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn iatjc_convert(session: *const Iatjc, reading: *const c_char) -> *mut IatjcCandidates {
+    guard(ptr::null_mut(), || {
+        let session = unsafe { session.as_ref() }.ok_or(TsfError::InvalidArgument("session is null"))?;
+        if reading.is_null() {
+            return Err(TsfError::InvalidArgument("reading is null"));
+        }
+        let reading = unsafe { CStr::from_ptr(reading) }.to_str().map_err(|_| TsfError::InvalidArgument("reading is not UTF-8"))?;
+
+        let items: Box<[*mut c_char]> = session
+            .service
+            .convert(reading)?
+            .into_iter()
+            .map(|candidate| CString::new(candidate.replace('\0', "")).unwrap_or_default().into_raw())
+            .collect();
+        let count = items.len();
+        let items = Box::into_raw(items) as *mut *mut c_char;
+        Ok(Box::into_raw(Box::new(IatjcCandidates { items, count })))
+    })
+}
+
+/// Releases candidates returned by [`iatjc_convert`]. Null is ignored.
+///
+/// # Safety
+///
+/// `candidates` must come from [`iatjc_convert`] and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn iatjc_free(candidates: *mut IatjcCandidates) {
+    if candidates.is_null() {
+        return;
+    }
+
+    let candidates = unsafe { Box::from_raw(candidates) };
+    let items = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(candidates.items, candidates.count)) };
+    for &item in items.iter() {
+        drop(unsafe { CString::from_raw(item) });
+    }
+}
+
+/// The message of the last failure on this thread, or null. Valid until
+/// the next call into the library on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn iatjc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
@@ -0,0 +1,129 @@
+use std::{thread, time::Duration};
+
+use crate::trace::{debug, warn};
+use windows::Win32::{
+    Foundation::{GlobalFree, HANDLE, HGLOBAL, HWND},
+    System::{
+        DataExchange::{CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard, SetClipboardData},
+        Memory::{GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE},
+        Ole::CF_UNICODETEXT,
+    },
+};
+
+use crate::{
+    engine::{Answered, Candidate, EngineChain},
+    error::{call, hresult, Result, TsfError},
+    kana,
+    window::{HiddenWindow, WindowKind},
+};
+
+/// Attempts to open the clipboard while another program holds it.
+const OPEN_ATTEMPTS: u32 = 10;
+const OPEN_RETRY: Duration = Duration::from_millis(20);
+
+/// The clipboard, open until dropped.
+struct Clipboard;
+
+impl Clipboard {
+    fn open(owner: HWND) -> Result<Self> {
+        let mut attempt = 1;
+        loop {
+            match call!(OpenClipboard(owner)) {
+                Ok(()) => return Ok(Self),
+                Err(e) if attempt == OPEN_ATTEMPTS => return Err(e),
+                Err(_) => {
+                    debug!("Clipboard is busy, retrying");
+                    attempt += 1;
+                    thread::sleep(OPEN_RETRY);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Clipboard {
+    fn drop(&mut self) {
+        if let Err(e) = unsafe { CloseClipboard() } {
+            warn!("Failed to close the clipboard: {:?}", e);
+        }
+    }
+}
+
+/// Returns the clipboard's `CF_UNICODETEXT`, or `None` when it holds no
+/// text.
+pub fn read_text() -> Result<Option<String>> {
+    if unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT.0 as u32) }.is_err() {
+        return Ok(None);
+    }
+
+    let _clipboard = Clipboard::open(HWND(0))?;
+    let data = call!(GetClipboardData(CF_UNICODETEXT.0 as u32))?;
+    let memory = HGLOBAL(data.0 as *mut _);
+    let text = unsafe { GlobalLock(memory) } as *const u16;
+    if text.is_null() {
+        return Err(hresult("GlobalLock")(windows_core::Error::from_win32()));
+    }
+
+    let capacity = unsafe { GlobalSize(memory) } / 2;
+    let units = unsafe { std::slice::from_raw_parts(text, capacity) };
+    let length = units.iter().position(|&c| c == 0).unwrap_or(capacity);
+    let text = String::from_utf16_lossy(&units[..length]);
+    // Fails with NO_ERROR once the lock count drops to zero.
+    let _ = unsafe { GlobalUnlock(memory) };
+    Ok(Some(text))
+}
+
+/// Replaces the clipboard's contents with `text`.
+pub fn write_text(text: &str) -> Result<()> {
+    let units: Vec<u16> = text.encode_utf16().chain([0]).collect();
+    let memory = call!(GlobalAlloc(GMEM_MOVEABLE, units.len() * 2))?;
+    let target = unsafe { GlobalLock(memory) } as *mut u16;
+    if target.is_null() {
+        let _ = unsafe { GlobalFree(memory) };
+        return Err(hresult("GlobalLock")(windows_core::Error::from_win32()));
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(units.as_ptr(), target, units.len());
+        let _ = GlobalUnlock(memory);
+    }
+
+    // With no owner window EmptyClipboard leaves the clipboard ownerless and
+    // SetClipboardData fails.
+    let owner = HiddenWindow::new(WindowKind::MessageOnly)?;
+    let result = Clipboard::open(owner.hwnd()).and_then(|_clipboard| {
+        call!(EmptyClipboard())?;
+        call!(SetClipboardData(CF_UNICODETEXT.0 as u32, HANDLE(memory.0 as isize)))
+    });
+
+    match result {
+        // The clipboard owns the memory now.
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = unsafe { GlobalFree(memory) };
+            Err(e)
+        }
+    }
+}
+
+/// Text taken from the clipboard with its reading and conversion
+/// candidates.
+#[derive(Clone, Debug)]
+pub struct Reconversion {
+    pub text: String,
+    pub reading: String,
+    pub candidates: Answered<Vec<Candidate>>,
+}
+
+/// Reconverts the text on the clipboard: looks up its reading, unless it is
+/// kana already, and converts that reading with `chain` into at most
+/// `max_candidates` candidates.
+pub fn reconvert(chain: &EngineChain, max_candidates: Option<usize>) -> Result<Reconversion> {
+    let text = read_text()?.map(|text| text.trim().to_string()).unwrap_or_default();
+    if text.is_empty() {
+        return Err(TsfError::InvalidArgument("the clipboard holds no text"));
+    }
+
+    let reading = if text.chars().all(kana::is_kana) { kana::to_hiragana(&text) } else { chain.reading(&text)?.value };
+    let candidates = chain.convert(&reading, max_candidates)?;
+    Ok(Reconversion { text, reading, candidates })
+}
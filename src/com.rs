@@ -1,24 +1,106 @@
-use anyhow::Result;
-use tracing::debug;
-use windows::Win32::System::Com::{CoInitialize, CoUninitialize};
+use crate::trace::{debug, error};
+use windows::Win32::{
+    Security::PSECURITY_DESCRIPTOR,
+    System::{
+        Com::{CoInitialize, CoInitializeSecurity, CoUninitialize, EOAC_NONE, EOLE_AUTHENTICATION_CAPABILITIES, RPC_C_AUTHN_LEVEL, RPC_C_AUTHN_LEVEL_DEFAULT, RPC_C_IMP_LEVEL, RPC_C_IMP_LEVEL_IMPERSONATE},
+        Ole::{OleInitialize, OleUninitialize},
+    },
+};
 
-pub struct Com;
+use crate::error::{call, Result, TsfError};
+
+/// Process-wide COM security settings passed to `CoInitializeSecurity`.
+///
+/// Hosts running as services or under restricted tokens may need to relax
+/// these so that out-of-process TSF components can call back into them.
+#[derive(Clone, Copy, Debug)]
+pub struct ComSecurity {
+    pub authentication_level: RPC_C_AUTHN_LEVEL,
+    pub impersonation_level: RPC_C_IMP_LEVEL,
+    pub capabilities: EOLE_AUTHENTICATION_CAPABILITIES,
+}
+
+impl Default for ComSecurity {
+    fn default() -> Self {
+        Self {
+            authentication_level: RPC_C_AUTHN_LEVEL_DEFAULT,
+            impersonation_level: RPC_C_IMP_LEVEL_IMPERSONATE,
+            capabilities: EOAC_NONE,
+        }
+    }
+}
+
+pub struct Com {
+    ole: bool,
+}
 
 impl Drop for Com {
     fn drop(&mut self) {
         debug!("Dropping Com instance");
         unsafe {
-            CoUninitialize();
-            debug!("CoUninitialize called");
+            if self.ole {
+                OleUninitialize();
+                debug!("OleUninitialize called");
+            } else {
+                CoUninitialize();
+                debug!("CoUninitialize called");
+            }
         };
     }
 }
 
 impl Com {
     pub fn new() -> Result<Self> {
+        if let Err(e) = unsafe { CoInitialize(None) }.ok() {
+            error!("Failed to initialize COM: {:?}", e);
+            return Err(TsfError::ComInit(e));
+        }
+        Ok(Com { ole: false })
+    }
+
+    /// Initializes OLE instead of plain COM on the current thread.
+    ///
+    /// Use this when the host also needs drag-drop or the clipboard on the
+    /// same STA thread as the text store. OLE is uninitialized on drop.
+    pub fn new_ole() -> Result<Self> {
         unsafe {
-            let _ = CoInitialize(None);
+            OleInitialize(None).map_err(TsfError::ComInit)?;
         };
-        Ok(Com)
+        debug!("OleInitialize called");
+        Ok(Com { ole: true })
+    }
+
+    /// Initializes COM and applies `security` for the whole process.
+    pub fn with_security(security: ComSecurity) -> Result<Self> {
+        let com = Com::new()?;
+        com.initialize_security(security)?;
+        Ok(com)
+    }
+
+    /// Calls `CoInitializeSecurity`. This only succeeds once per process and
+    /// must happen before any interface is marshaled.
+    pub fn initialize_security(&self, security: ComSecurity) -> Result<()> {
+        debug!("Initializing COM security: {:?}", security);
+        let result = call!(
+            CoInitializeSecurity(
+                PSECURITY_DESCRIPTOR::default(),
+                -1,
+                None,
+                None,
+                security.authentication_level,
+                security.impersonation_level,
+                None,
+                security.capabilities,
+                None,
+            )
+        );
+
+        if let Err(e) = result {
+            error!("Failed to initialize COM security: {:?}", e);
+            return Err(e);
+        }
+
+        debug!("COM security initialized");
+        Ok(())
     }
 }
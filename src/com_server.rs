@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use crate::trace::{debug, info, warn};
+use windows::Win32::{
+    Foundation::{BOOL, CLASS_E_NOAGGREGATION, DISP_E_BADPARAMCOUNT, DISP_E_EXCEPTION, DISP_E_MEMBERNOTFOUND, DISP_E_UNKNOWNNAME, DISP_E_TYPEMISMATCH, E_NOTIMPL, E_POINTER},
+    System::Com::{CoAddRefServerProcess, CoRegisterClassObject, CoReleaseServerProcess, CoResumeClassObjects, CoRevokeClassObject, IClassFactory, IClassFactory_Impl, IDispatch, IDispatch_Impl, ITypeInfo, CLSCTX_LOCAL_SERVER, DISPATCH_FLAGS, DISPATCH_METHOD, DISPPARAMS, EXCEPINFO, REGCLS, REGCLS_MULTIPLEUSE, REGCLS_SUSPENDED},
+};
+use windows_core::{implement, IUnknown, Interface, BSTR, GUID, PCWSTR, VARIANT};
+
+use crate::{
+    error::{call, com_entry, Result},
+    pump::{run_message_loop, QuitSignal},
+    registry,
+    service::TsfService,
+};
+
+/// CLSID of the `Iatjc.Converter` class.
+pub const CLSID_CONVERTER: GUID = GUID::from_u128(0x0349079a_3e46_47be_8cc9_feeb860c7819);
+
+/// ProgID scripts pass to `CreateObject`.
+pub const PROG_ID: &str = "Iatjc.Converter";
+
+const DESCRIPTION: &str = "iatjc Converter";
+
+const DISPID_CONVERT: i32 = 1;
+const DISPID_READING: i32 = 2;
+const DISPID_UNKNOWN: i32 = -1;
+
+/// Methods callable through `IDispatch`, so that late-bound clients such as
+/// VBA and VBScript need no type library.
+const METHODS: [(&str, i32); 2] = [("Convert", DISPID_CONVERT), ("Reading", DISPID_READING)];
+
+/// Live objects and `LockServer` locks are counted on the process's COM
+/// server reference count. The server exits once it drops to zero after the
+/// first client has connected; COM suspends the class objects at that point,
+/// so no new client can activate the exiting server.
+struct Server {
+    service: TsfService,
+    quit: QuitSignal,
+}
+
+impl Server {
+    fn add_ref(&self) {
+        unsafe { CoAddRefServerProcess() };
+    }
+
+    fn release(&self) {
+        if unsafe { CoReleaseServerProcess() } == 0 {
+            debug!("Last COM client released the server");
+            if let Err(e) = self.quit.quit() {
+                warn!("Failed to stop the COM server loop: {:?}", e);
+            }
+        }
+    }
+}
+
+/// The scriptable conversion object.
+///
+/// `Convert(reading)` returns the candidates separated by line feeds and
+/// `Reading(text)` returns the hiragana reading of `text`.
+#[implement(IDispatch)]
+struct Converter {
+    server: Arc<Server>,
+}
+
+impl Converter {
+    fn new(server: Arc<Server>) -> Self {
+        server.add_ref();
+        Self { server }
+    }
+
+    fn call(&self, dispid: i32, argument: &str) -> Result<String> {
+        match dispid {
+            DISPID_CONVERT => Ok(self.server.service.convert(argument)?.join("\n")),
+            _ => self.server.service.reading(argument),
+        }
+    }
+}
+
+impl Drop for Converter {
+    fn drop(&mut self) {
+        self.server.release();
+    }
+}
+
+impl IDispatch_Impl for Converter {
+    fn GetTypeInfoCount(&self) -> windows_core::Result<u32> {
+        Ok(0)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> windows_core::Result<ITypeInfo> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetIDsOfNames(&self, _riid: *const GUID, rgsznames: *const PCWSTR, cnames: u32, _lcid: u32, rgdispid: *mut i32) -> windows_core::Result<()> {
+        com_entry("IDispatch::GetIDsOfNames", || {
+            if rgsznames.is_null() || rgdispid.is_null() {
+                return Err(E_POINTER.into());
+            }
+
+            // Only the method name is resolved; named arguments are not
+            // supported.
+            let mut result = Ok(());
+            for index in 0..cnames as usize {
+                let name = unsafe { (*rgsznames.add(index)).to_string() }.unwrap_or_default();
+                let dispid = METHODS.iter().find(|(method, _)| index == 0 && method.eq_ignore_ascii_case(&name)).map(|(_, dispid)| *dispid);
+                unsafe { *rgdispid.add(index) = dispid.unwrap_or(DISPID_UNKNOWN) };
+                if dispid.is_none() {
+                    result = Err(DISP_E_UNKNOWNNAME.into());
+                }
+            }
+            result
+        })
+    }
+
+    fn Invoke(&self, dispidmember: i32, _riid: *const GUID, _lcid: u32, wflags: DISPATCH_FLAGS, pdispparams: *const DISPPARAMS, pvarresult: *mut VARIANT, pexcepinfo: *mut EXCEPINFO, puargerr: *mut u32) -> windows_core::Result<()> {
+        com_entry("IDispatch::Invoke", || {
+            if !METHODS.iter().any(|(_, dispid)| *dispid == dispidmember) || wflags & DISPATCH_METHOD != DISPATCH_METHOD {
+                return Err(DISP_E_MEMBERNOTFOUND.into());
+            }
+
+            let Some(params) = (unsafe { pdispparams.as_ref() }) else {
+                return Err(E_POINTER.into());
+            };
+            if params.cArgs != 1 || params.cNamedArgs != 0 {
+                return Err(DISP_E_BADPARAMCOUNT.into());
+            }
+
+            let Ok(argument) = BSTR::try_from(unsafe { &*params.rgvarg }) else {
+                if !puargerr.is_null() {
+                    unsafe { *puargerr = 0 };
+                }
+                return Err(DISP_E_TYPEMISMATCH.into());
+            };
+
+            match self.call(dispidmember, &argument.to_string()) {
+                Ok(value) => {
+                    if !pvarresult.is_null() {
+                        unsafe { pvarresult.write(VARIANT::from(BSTR::from(value))) };
+                    }
+                    Ok(())
+                }
+                Err(e) => {
+                    warn!("COM client call {} failed: {}", dispidmember, e);
+                    if let Some(exception) = unsafe { pexcepinfo.as_mut() } {
+                        *exception = EXCEPINFO {
+                            bstrSource: std::mem::ManuallyDrop::new(BSTR::from(PROG_ID)),
+                            bstrDescription: std::mem::ManuallyDrop::new(BSTR::from(e.to_string())),
+                            scode: e.hresult().unwrap_or(DISP_E_EXCEPTION).0,
+                            ..Default::default()
+                        };
+                    }
+                    Err(DISP_E_EXCEPTION.into())
+                }
+            }
+        })
+    }
+}
+
+#[implement(IClassFactory)]
+struct ConverterFactory {
+    server: Arc<Server>,
+}
+
+impl IClassFactory_Impl for ConverterFactory {
+    fn CreateInstance(&self, punkouter: Option<&IUnknown>, riid: *const GUID, ppvobject: *mut *mut core::ffi::c_void) -> windows_core::Result<()> {
+        com_entry("IClassFactory::CreateInstance", || {
+            if punkouter.is_some() {
+                return Err(CLASS_E_NOAGGREGATION.into());
+            }
+
+            debug!("Creating a Converter for a COM client");
+            let object: IDispatch = Converter::new(self.server.clone()).into();
+            unsafe { object.query(riid, ppvobject) }.ok()
+        })
+    }
+
+    fn LockServer(&self, flock: BOOL) -> windows_core::Result<()> {
+        com_entry("IClassFactory::LockServer", || {
+            if flock.as_bool() {
+                self.server.add_ref();
+            } else {
+                self.server.release();
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Registers the `Iatjc.Converter` class object and pumps messages until the
+/// last client lets go of it.
+///
+/// Call on a thread that initialized COM as a single-threaded apartment; COM
+/// delivers client calls through this thread's message queue.
+pub fn serve(service: TsfService) -> Result<()> {
+    let quit = QuitSignal::for_current_thread();
+    let server = Arc::new(Server { service, quit: quit.clone() });
+    let factory: IClassFactory = ConverterFactory { server }.into();
+
+    // Registered suspended and resumed once ready, as COM expects of
+    // servers counting references with `CoAddRefServerProcess`.
+    let cookie = call!(CoRegisterClassObject(&CLSID_CONVERTER, &factory, CLSCTX_LOCAL_SERVER, REGCLS(REGCLS_MULTIPLEUSE.0 | REGCLS_SUSPENDED.0)))?;
+    let result = call!(CoResumeClassObjects()).and_then(|()| {
+        info!("Serving {} as a local COM server", PROG_ID);
+        run_message_loop(&quit)
+    });
+    if let Err(e) = call!(CoRevokeClassObject(cookie)) {
+        warn!("Failed to revoke the COM class object: {:?}", e);
+    }
+    result.map(drop)
+}
+
+/// Registers the class for the current user so that COM starts `command`
+/// (the quoted executable path and its arguments) on activation.
+pub fn register(command: &str) -> Result<()> {
+    let clsid = format!("{{{:?}}}", CLSID_CONVERTER);
+    let class = format!(r"Software\Classes\CLSID\{clsid}");
+
+    registry::set_string(&class, None, DESCRIPTION)?;
+    registry::set_string(&format!(r"{class}\LocalServer32"), None, command)?;
+    registry::set_string(&format!(r"{class}\ProgID"), None, PROG_ID)?;
+    registry::set_string(&format!(r"Software\Classes\{PROG_ID}"), None, DESCRIPTION)?;
+    registry::set_string(&format!(r"Software\Classes\{PROG_ID}\CLSID"), None, &clsid)?;
+    info!("Registered {} as {}", PROG_ID, clsid);
+    Ok(())
+}
+
+/// Removes what [`register`] wrote. Missing keys are not an error.
+pub fn unregister() -> Result<()> {
+    let clsid = format!("{{{:?}}}", CLSID_CONVERTER);
+    registry::delete_tree(&format!(r"Software\Classes\CLSID\{clsid}"))?;
+    registry::delete_tree(&format!(r"Software\Classes\{PROG_ID}"))?;
+    info!("Unregistered {}", PROG_ID);
+    Ok(())
+}
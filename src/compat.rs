@@ -0,0 +1,150 @@
+use windows::Win32::{
+    System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+    UI::TextServices::{ITfInputProcessorProfileMgr, ITfInputProcessorProfiles, CLSID_TF_InputProcessorProfiles, GUID_TFCAT_TIP_KEYBOARD, TF_INPUTPROCESSORPROFILE, TF_PROFILETYPE_INPUTPROCESSOR},
+};
+use windows_core::{Interface, GUID};
+
+use crate::{
+    error::{call, Result},
+    trace::debug,
+};
+
+const CLSID_MS_IME_JAPANESE: GUID = GUID::from_u128(0x03b5835f_f03c_411b_9ce2_aa23e1171e36);
+const CLSID_GOOGLE_JAPANESE_INPUT: GUID = GUID::from_u128(0xd5a86fd5_5308_47ea_ad16_9c4eb160ec3c);
+const CLSID_MOZC: GUID = GUID::from_u128(0x10a67bc8_22fa_4a59_90dc_2546652c56bf);
+
+/// Known text input processors.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Tip {
+    MsIme,
+    /// Google 日本語入力.
+    GoogleJapaneseInput,
+    /// The open-source build of Google 日本語入力.
+    Mozc,
+    /// JustSystems ATOK. Each release registers its own CLSID, so it is
+    /// recognised by profile description instead.
+    Atok,
+    Other(GUID),
+}
+
+impl Tip {
+    pub fn from_clsid(clsid: GUID) -> Self {
+        match clsid {
+            CLSID_MS_IME_JAPANESE => Self::MsIme,
+            CLSID_GOOGLE_JAPANESE_INPUT => Self::GoogleJapaneseInput,
+            CLSID_MOZC => Self::Mozc,
+            other => Self::Other(other),
+        }
+    }
+
+    /// Identifies a TIP by CLSID, falling back to its profile description.
+    pub fn detect(clsid: GUID, description: &str) -> Self {
+        match Self::from_clsid(clsid) {
+            Self::Other(_) if description.trim_start().starts_with("ATOK") => Self::Atok,
+            tip => tip,
+        }
+    }
+
+    pub fn is_mozc(&self) -> bool {
+        matches!(self, Self::GoogleJapaneseInput | Self::Mozc)
+    }
+}
+
+/// Where to look for `ITfFnReconversion`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Provider {
+    /// `GUID_SYSTEM_FUNCTIONPROVIDER`, which forwards to the active TIP.
+    System,
+    /// The function provider registered under the TIP's own CLSID.
+    Tip,
+}
+
+/// How the pipeline adapts to the active input processor, and what it can
+/// be expected to deliver.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    /// Function providers to ask for reconversion, in order.
+    pub providers: Vec<Provider>,
+    /// Whether `ITfFnReconversion::QueryRange` is reliable. When it is not,
+    /// the whole document is reconverted as is.
+    pub query_range: bool,
+    /// Whether reconversion may be available at all.
+    pub reconversion: bool,
+    /// Whether the TIP records `GUID_PROP_READING`.
+    pub reading_property: bool,
+    /// Whether the reconversion candidate list repeats the reading itself,
+    /// in which case its first occurrence is dropped from the results.
+    pub candidates_echo_reading: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            providers: vec![Provider::System],
+            query_range: true,
+            reconversion: true,
+            reading_property: true,
+            candidates_echo_reading: false,
+        }
+    }
+}
+
+impl Quirks {
+    pub fn for_tip(tip: Tip) -> Self {
+        match tip {
+            // Google 日本語入力 does not always answer through the system
+            // provider, and its QueryRange rejects whole-document ranges.
+            Tip::GoogleJapaneseInput | Tip::Mozc => Self {
+                providers: vec![Provider::Tip, Provider::System],
+                query_range: false,
+                reconversion: true,
+                reading_property: false,
+                ..Self::default()
+            },
+            // ATOK lists the reading among its reconversion candidates.
+            Tip::Atok => Self {
+                reading_property: false,
+                candidates_echo_reading: true,
+                ..Self::default()
+            },
+            Tip::MsIme | Tip::Other(_) => Self::default(),
+        }
+    }
+}
+
+/// The keyboard profile active on the current thread.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ActiveProfile {
+    pub tip: Tip,
+    pub clsid: GUID,
+    pub profile: GUID,
+    pub langid: u16,
+}
+
+impl ActiveProfile {
+    pub fn quirks(&self) -> Quirks {
+        Quirks::for_tip(self.tip)
+    }
+}
+
+/// Returns the active keyboard TIP, or `None` when a plain keyboard layout
+/// is active.
+pub fn active_profile() -> Result<Option<ActiveProfile>> {
+    let profiles: ITfInputProcessorProfileMgr = call!(CoCreateInstance(&CLSID_TF_InputProcessorProfiles, None, CLSCTX_INPROC_SERVER))?;
+    let mut profile = TF_INPUTPROCESSORPROFILE::default();
+    call!(profiles, GetActiveProfile(&GUID_TFCAT_TIP_KEYBOARD, &mut profile))?;
+
+    if profile.dwProfileType != TF_PROFILETYPE_INPUTPROCESSOR {
+        debug!("No input processor is active");
+        return Ok(None);
+    }
+
+    let description = call!(profiles, cast::<ITfInputProcessorProfiles>())
+        .and_then(|profiles| call!(profiles, GetLanguageProfileDescription(&profile.clsid, profile.langid, &profile.guidProfile)))
+        .map(|description| description.to_string())
+        .unwrap_or_default();
+
+    let tip = Tip::detect(profile.clsid, &description);
+    debug!("Active input processor: {:?}", tip);
+    Ok(Some(ActiveProfile { tip, clsid: profile.clsid, profile: profile.guidProfile, langid: profile.langid }))
+}
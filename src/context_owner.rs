@@ -0,0 +1,179 @@
+//! The lighter way to host a document, for [`crate::tsf::TSF::with_context_owner`]:
+//! TSF keeps the text in a store of its own, and the crate only answers
+//! what it cannot know, such as the window, the layout and the attributes
+//! of the document, as its `ITfContextOwner`.
+
+use std::sync::{Arc, RwLock};
+
+use windows::Win32::{
+    Foundation::{BOOL, E_INVALIDARG, E_NOTIMPL, HWND, POINT, RECT},
+    UI::TextServices::{ITfCompositionView, ITfContextOwner, ITfContextOwner_Impl, ITfContextOwnerCompositionSink, ITfContextOwnerCompositionSink_Impl, ITfRange, GUID_PROP_INPUTSCOPE, TS_E_NOLAYOUT, TS_SS_TRANSITORY, TS_STATUS},
+};
+use windows_core::{implement, IUnknown, GUID, VARIANT};
+
+use crate::{
+    affinity::{ThreadAffinity, WrongThread},
+    error::{com_entry, Recover},
+    events::{EventHub, TsfEvent},
+    input_scope::{InputScope, InputScopes},
+    layout::LayoutProvider,
+    notifications::Notification,
+};
+
+#[implement(ITfContextOwner, ITfContextOwnerCompositionSink)]
+pub(crate) struct ContextOwner {
+    window: HWND,
+    events: EventHub,
+    transitory: bool,
+    layout: RwLock<Option<Arc<dyn LayoutProvider>>>,
+    input_scopes: RwLock<Vec<InputScope>>,
+    affinity: ThreadAffinity,
+}
+
+impl ContextOwner {
+    pub(crate) fn new(window: HWND, events: EventHub, transitory: bool) -> Self {
+        Self {
+            window,
+            events,
+            transitory,
+            layout: RwLock::new(None),
+            input_scopes: RwLock::new(Vec::new()),
+            affinity: ThreadAffinity::current(),
+        }
+    }
+
+    pub(crate) fn set_layout_provider(&self, provider: Option<Arc<dyn LayoutProvider>>) -> Result<(), WrongThread> {
+        self.affinity.check()?;
+        *self.layout.write().recover() = provider;
+        Ok(())
+    }
+
+    fn layout(&self) -> Option<Arc<dyn LayoutProvider>> {
+        self.layout.read().recover().clone()
+    }
+
+    pub(crate) fn has_layout(&self) -> bool {
+        self.layout.read().recover().is_some()
+    }
+
+    /// Returns whether the scopes changed.
+    pub(crate) fn set_input_scopes(&self, scopes: &[InputScope]) -> Result<bool, WrongThread> {
+        self.affinity.check()?;
+        let mut current = self.input_scopes.write().recover();
+        if *current == scopes {
+            return Ok(false);
+        }
+        *current = scopes.to_vec();
+        Ok(true)
+    }
+
+    fn composition(&self, notification: Notification, event: TsfEvent) {
+        self.events.notifications().push(notification);
+        self.events.emit(event);
+    }
+}
+
+impl ITfContextOwner_Impl for ContextOwner {
+    fn GetACPFromPoint(&self, _ptscreen: *const POINT, _dwflags: u32) -> windows_core::Result<i32> {
+        com_entry("ITfContextOwner::GetACPFromPoint", || {
+            self.affinity.check()?;
+
+            Err(E_NOTIMPL.into())
+        })
+    }
+
+    fn GetTextExt(&self, acpstart: i32, acpend: i32, prc: *mut RECT, pfclipped: *mut BOOL) -> windows_core::Result<()> {
+        com_entry("ITfContextOwner::GetTextExt", || {
+            self.affinity.check()?;
+
+            if prc.is_null() || pfclipped.is_null() || acpstart < 0 || acpend < acpstart {
+                return Err(E_INVALIDARG.into());
+            }
+            let Some(layout) = self.layout() else {
+                return Err(E_NOTIMPL.into());
+            };
+
+            let (rect, clipped) = layout.text_ext(acpstart, acpend).ok_or(windows_core::Error::from(TS_E_NOLAYOUT))?;
+            unsafe {
+                *prc = rect;
+                *pfclipped = clipped.into();
+            }
+            Ok(())
+        })
+    }
+
+    fn GetScreenExt(&self) -> windows_core::Result<RECT> {
+        com_entry("ITfContextOwner::GetScreenExt", || {
+            self.affinity.check()?;
+
+            let Some(layout) = self.layout() else {
+                return Err(E_NOTIMPL.into());
+            };
+            layout.screen_ext().ok_or(TS_E_NOLAYOUT.into())
+        })
+    }
+
+    fn GetStatus(&self) -> windows_core::Result<TS_STATUS> {
+        com_entry("ITfContextOwner::GetStatus", || {
+            self.affinity.check()?;
+
+            Ok(TS_STATUS {
+                dwDynamicFlags: 0,
+                dwStaticFlags: if self.transitory { TS_SS_TRANSITORY } else { 0 },
+            })
+        })
+    }
+
+    fn GetWnd(&self) -> windows_core::Result<HWND> {
+        com_entry("ITfContextOwner::GetWnd", || {
+            self.affinity.check()?;
+
+            Ok(self.window)
+        })
+    }
+
+    fn GetAttribute(&self, rguidattribute: *const GUID) -> windows_core::Result<VARIANT> {
+        com_entry("ITfContextOwner::GetAttribute", || {
+            self.affinity.check()?;
+
+            if rguidattribute.is_null() {
+                return Err(E_INVALIDARG.into());
+            }
+            let scopes = self.input_scopes.read().recover();
+            if unsafe { *rguidattribute } != GUID_PROP_INPUTSCOPE || scopes.is_empty() {
+                return Ok(VARIANT::default());
+            }
+            let unknown: IUnknown = InputScopes::create(&scopes).into();
+            Ok(VARIANT::from(unknown))
+        })
+    }
+}
+
+impl ITfContextOwnerCompositionSink_Impl for ContextOwner {
+    fn OnStartComposition(&self, _pcomposition: Option<&ITfCompositionView>) -> windows_core::Result<BOOL> {
+        com_entry("ITfContextOwnerCompositionSink::OnStartComposition", || {
+            self.affinity.check()?;
+
+            self.composition(Notification::CompositionStarted, TsfEvent::CompositionStarted);
+            Ok(BOOL(1))
+        })
+    }
+
+    fn OnUpdateComposition(&self, _pcomposition: Option<&ITfCompositionView>, _prangenew: Option<&ITfRange>) -> windows_core::Result<()> {
+        com_entry("ITfContextOwnerCompositionSink::OnUpdateComposition", || {
+            self.affinity.check()?;
+
+            self.composition(Notification::CompositionUpdated, TsfEvent::CompositionUpdated);
+            Ok(())
+        })
+    }
+
+    fn OnEndComposition(&self, _pcomposition: Option<&ITfCompositionView>) -> windows_core::Result<()> {
+        com_entry("ITfContextOwnerCompositionSink::OnEndComposition", || {
+            self.affinity.check()?;
+
+            self.composition(Notification::CompositionEnded, TsfEvent::CompositionEnded);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt, time::{Duration, Instant}};
+
+use crate::trace::{debug, error, info, trace, warn};
+
+/// Severity used for diagnostic output.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    #[default]
+    Trace,
+}
+
+/// Controls what the text store logs about the calls it receives.
+///
+/// Document text is user input once the store is fed real keystrokes, so it
+/// is redacted unless `redact_text` is turned off explicitly.
+#[derive(Clone, Copy, Debug)]
+pub struct Diagnostics {
+    /// Log every `ITextStoreACP` call together with its outcome.
+    pub log_store_calls: bool,
+    /// Replace document text in log messages with its length.
+    pub redact_text: bool,
+    /// Level at which store calls are logged.
+    pub level: LogLevel,
+    /// Durations above which calls into the input processor are logged as
+    /// warnings.
+    pub slow_calls: SlowCallThresholds,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self {
+            log_store_calls: false,
+            redact_text: true,
+            level: LogLevel::Trace,
+            slow_calls: SlowCallThresholds::default(),
+        }
+    }
+}
+
+/// An outbound call timed against [`SlowCallThresholds`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SlowCall {
+    /// `ITextStoreACPSink::OnLockGranted`, during which the input processor
+    /// does its edit work.
+    LockGranted,
+    /// `ITfFnReconversion::QueryRange` and `GetReconversion`.
+    Reconversion,
+    /// Reading every candidate out of an `ITfCandidateList`.
+    Candidates,
+}
+
+/// Per-call thresholds; `None` never warns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SlowCallThresholds {
+    pub lock_granted: Option<Duration>,
+    pub reconversion: Option<Duration>,
+    pub candidates: Option<Duration>,
+}
+
+impl Default for SlowCallThresholds {
+    fn default() -> Self {
+        Self {
+            lock_granted: Some(Duration::from_millis(100)),
+            reconversion: Some(Duration::from_millis(250)),
+            candidates: Some(Duration::from_millis(100)),
+        }
+    }
+}
+
+impl SlowCallThresholds {
+    pub fn get(&self, call: SlowCall) -> Option<Duration> {
+        match call {
+            SlowCall::LockGranted => self.lock_granted,
+            SlowCall::Reconversion => self.reconversion,
+            SlowCall::Candidates => self.candidates,
+        }
+    }
+}
+
+impl Diagnostics {
+    /// Wraps `text` so that it is displayed according to `redact_text`.
+    pub fn text<'a>(&self, text: &'a str) -> Redacted<'a> {
+        Redacted { text, redact: self.redact_text }
+    }
+
+    /// Runs `body`, warning when it takes longer than the threshold for
+    /// `call`.
+    pub(crate) fn timed<T>(&self, call: SlowCall, body: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = body();
+        let elapsed = started.elapsed();
+        if let Some(threshold) = self.slow_calls.get(call) && elapsed > threshold {
+            warn!("{:?} took {:?}, over the {:?} threshold", call, elapsed, threshold);
+        }
+        result
+    }
+
+    pub(crate) fn log(&self, args: fmt::Arguments<'_>) {
+        match self.level {
+            LogLevel::Error => error!("{}", args),
+            LogLevel::Warn => warn!("{}", args),
+            LogLevel::Info => info!("{}", args),
+            LogLevel::Debug => debug!("{}", args),
+            LogLevel::Trace => trace!("{}", args),
+        }
+    }
+}
+
+/// Document text as it may appear in logs.
+pub struct Redacted<'a> {
+    text: &'a str,
+    redact: bool,
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.redact {
+            write!(f, "<{} chars redacted>", self.text.chars().count())
+        } else {
+            write!(f, "{:?}", self.text)
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, io};
+
+use windows::Win32::{
+    Globalization::{MultiByteToWideChar, CP_ACP, MULTI_BYTE_TO_WIDE_CHAR_FLAGS},
+    System::Com::{CLSIDFromProgID, CoCreateInstance, CLSCTX_SERVER},
+    UI::Input::Ime::{IFEDictionary, IFED_POS_ALL, IFED_REG_USER, IFED_SELECT_ALL, IFED_S_MORE_ENTRIES, IMESHF, IMEWRD, POSTBL},
+};
+use windows_core::{w, Interface, PCWSTR};
+
+use crate::{
+    affinity::ThreadAffinity,
+    com::Com,
+    error::{call, hresult, Result},
+    trace::{debug, warn},
+};
+
+/// Size of the buffer handed to `GetWords`/`NextWords`. Entries that do not
+/// fit are returned by the following `NextWords` call.
+const WORD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A word registered in a dictionary.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DictionaryEntry {
+    pub reading: String,
+    pub word: String,
+    /// MS-IME's part-of-speech number; see [`UserDictionary::pos_names`].
+    pub pos: u16,
+}
+
+/// The MS-IME user dictionary, opened through `IFEDictionary`.
+///
+/// Borrows the thread's [`Com`] guard and must stay on that thread.
+pub struct UserDictionary<'com> {
+    dictionary: IFEDictionary,
+    affinity: ThreadAffinity,
+    _com: &'com Com,
+}
+
+impl<'com> UserDictionary<'com> {
+    pub fn open(com: &'com Com) -> Result<Self> {
+        debug!("Opening user dictionary");
+        let clsid = call!(CLSIDFromProgID(w!("MSIME.Japan")))?;
+        let dictionary: IFEDictionary = call!(CoCreateInstance(&clsid, None, CLSCTX_SERVER))?;
+
+        let mut header = IMESHF { cbShf: std::mem::size_of::<IMESHF>() as u16, ..Default::default() };
+        call!(dictionary, Open(None, &mut header))?;
+
+        Ok(Self { dictionary, affinity: ThreadAffinity::current(), _com: com })
+    }
+
+    /// Names of the part-of-speech numbers used by [`DictionaryEntry::pos`].
+    pub fn pos_names(&self) -> Result<HashMap<u16, String>> {
+        self.affinity.check()?;
+
+        let mut table: *mut POSTBL = std::ptr::null_mut();
+        let mut count = 0;
+        call!(self.dictionary, GetPosTable(&mut table, &mut count))?;
+        if table.is_null() {
+            return Ok(HashMap::new());
+        }
+
+        // The table belongs to the dictionary and must not be freed.
+        Ok((0..count.max(0) as usize)
+            .map(|i| {
+                let entry = unsafe { table.add(i).read_unaligned() };
+                (entry.nPos, unsafe { ansi(entry.szName) })
+            })
+            .collect())
+    }
+
+    /// Every word registered by the user.
+    pub fn entries(&self) -> Result<Vec<DictionaryEntry>> {
+        self.affinity.check()?;
+
+        let mut entries = Vec::new();
+        let mut buffer = vec![0u8; WORD_BUFFER_SIZE];
+        let mut count = 0;
+
+        // `GetWords` reports more pages with the IFED_S_MORE_ENTRIES success
+        // code, which the generated wrapper discards, so the vtable is
+        // called directly.
+        let vtable = self.dictionary.vtable();
+        let mut hr = unsafe {
+            (vtable.GetWords)(
+                self.dictionary.as_raw(),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                IFED_POS_ALL,
+                IFED_SELECT_ALL,
+                IFED_REG_USER,
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                &mut count,
+            )
+        };
+
+        loop {
+            hr.ok().map_err(hresult("IFEDictionary::GetWords"))?;
+            entries.extend(unsafe { read_words(&buffer, count) });
+            if hr != IFED_S_MORE_ENTRIES {
+                break;
+            }
+
+            hr = unsafe { (vtable.NextWords)(self.dictionary.as_raw(), buffer.as_mut_ptr(), buffer.len() as u32, &mut count) };
+        }
+
+        debug!("Read {} user dictionary entries", entries.len());
+        Ok(entries)
+    }
+
+    /// Writes every user entry as `reading<TAB>word<TAB>part of speech`, the
+    /// text format MS-IME imports.
+    pub fn export(&self, writer: &mut impl io::Write) -> Result<()> {
+        let names = self.pos_names()?;
+        for entry in self.entries()? {
+            let pos = names.get(&entry.pos).cloned().unwrap_or_else(|| entry.pos.to_string());
+            writeln!(writer, "{}\t{}\t{}", entry.reading, entry.word, pos)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for UserDictionary<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = call!(self.dictionary, Close()) {
+            warn!("Failed to close user dictionary: {:?}", e);
+        }
+    }
+}
+
+/// Reads `count` packed `IMEWRD` records from the start of `buffer`. Their
+/// string pointers point further into the same buffer.
+unsafe fn read_words(buffer: &[u8], count: u32) -> Vec<DictionaryEntry> {
+    let words = buffer.as_ptr() as *const IMEWRD;
+    let count = (count as usize).min(buffer.len() / std::mem::size_of::<IMEWRD>());
+
+    (0..count)
+        .map(|i| {
+            let word = unsafe { words.add(i).read_unaligned() };
+            let (reading, display) = (word.pwchReading, word.pwchDisplay);
+            DictionaryEntry {
+                reading: unsafe { reading.to_string() }.unwrap_or_default(),
+                word: unsafe { display.to_string() }.unwrap_or_default(),
+                pos: unsafe { word.Anonymous.Anonymous.nPos1 },
+            }
+        })
+        .collect()
+}
+
+/// Decodes a NUL-terminated string in the system code page.
+unsafe fn ansi(text: *const u8) -> String {
+    if text.is_null() {
+        return String::new();
+    }
+
+    let bytes = unsafe { std::ffi::CStr::from_ptr(text as *const _) }.to_bytes();
+    let len = unsafe { MultiByteToWideChar(CP_ACP, MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0), bytes, None) };
+    let mut wide = vec![0u16; len.max(0) as usize];
+    unsafe { MultiByteToWideChar(CP_ACP, MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0), bytes, Some(&mut wide)) };
+    String::from_utf16_lossy(&wide)
+}
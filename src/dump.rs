@@ -0,0 +1,86 @@
+//! A snapshot of the live TSF object graph, taken by
+//! [`crate::tsf::TSF::debug_dump`] for bug reports and error logs.
+
+use std::fmt;
+
+use crate::{compat::ActiveProfile, locking::Lock, tsf::TsfState};
+
+/// Which document manager has the thread focus.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Focus {
+    None,
+    Ours,
+    Other,
+}
+
+/// The contexts pushed onto our document manager.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContextStack {
+    /// 0, 1 or 2; TSF stacks at most two contexts.
+    pub depth: usize,
+    pub ours_on_top: bool,
+}
+
+/// The text store's state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StoreDump {
+    pub sink_advised: bool,
+    /// The `TS_AS_*` mask passed to `AdviseSink`.
+    pub sink_mask: u32,
+    /// Document length in UTF-16 code units.
+    pub text_len: usize,
+    pub selection: (i32, i32),
+    pub lock: Lock,
+    pub pending_locks: usize,
+}
+
+/// Everything [`crate::tsf::TSF`] knows about its pipeline. Parts that could
+/// not be queried are `None`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DebugDump {
+    pub state: TsfState,
+    pub client_id: Option<u32>,
+    /// `ITfThreadMgrEx::GetActiveFlags`.
+    pub active_flags: Option<u32>,
+    pub focus: Option<Focus>,
+    pub contexts: Option<ContextStack>,
+    /// Sinks advised on the thread manager.
+    pub thread_mgr_sinks: usize,
+    /// Sinks advised on keyboard compartments.
+    pub compartment_sinks: usize,
+    pub store: Option<StoreDump>,
+    pub profile: Option<ActiveProfile>,
+}
+
+impl fmt::Display for DebugDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "state: {:?}", self.state)?;
+        writeln!(f, "client id: {}", optional(self.client_id))?;
+        match self.active_flags {
+            Some(flags) => writeln!(f, "active flags: {:#x}", flags)?,
+            None => writeln!(f, "active flags: -")?,
+        }
+        writeln!(f, "focus: {}", optional(self.focus.map(|focus| format!("{:?}", focus))))?;
+        match self.contexts {
+            Some(stack) => writeln!(f, "contexts: {} (ours on top: {})", stack.depth, stack.ours_on_top)?,
+            None => writeln!(f, "contexts: -")?,
+        }
+        writeln!(f, "sinks: {} thread manager, {} compartment", self.thread_mgr_sinks, self.compartment_sinks)?;
+        match &self.store {
+            Some(store) => {
+                writeln!(f, "store sink: {} (mask {:#x})", if store.sink_advised { "advised" } else { "none" }, store.sink_mask)?;
+                writeln!(f, "store text: {} units, selection {}..{}", store.text_len, store.selection.0, store.selection.1)?;
+                writeln!(f, "store lock: {:?}, {} pending", store.lock, store.pending_locks)?;
+            }
+            None => writeln!(f, "store: -")?,
+        }
+        match &self.profile {
+            Some(profile) => write!(f, "profile: {:?} {:?} (langid {:#06x})", profile.tip, profile.profile, profile.langid),
+            None => write!(f, "profile: -"),
+        }
+    }
+}
+
+fn optional(value: Option<impl fmt::Display>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
@@ -1,27 +1,32 @@
-use std::sync::mpsc::Sender;
-
 use windows::Win32::UI::TextServices::{ITfEditSession_Impl, ITfEditSession};
-use windows_core::{implement, HRESULT};
+use windows_core::implement;
+
+use crate::error::com_entry;
+
+type EditCallback = Box<dyn Fn(u32) -> windows_core::Result<()>>;
 
+/// Runs a callback with the edit cookie granted by TSF. The cookie is only
+/// valid for the duration of the callback.
 #[implement(ITfEditSession)]
 pub struct EditSession {
-    sender: Sender<u32>
+    callback: EditCallback
 }
 
 impl EditSession {
-    pub fn new(sender: Sender<u32>) -> EditSession {
+    pub fn new<F>(callback: F) -> EditSession
+    where
+        F: Fn(u32) -> windows_core::Result<()> + 'static
+    {
         EditSession {
-            sender
+            callback: Box::new(callback)
         }
     }
 }
 
 impl ITfEditSession_Impl for EditSession {
     fn DoEditSession(&self, ec: u32) -> windows_core::Result<()> {
-        if let Err(_) = self.sender.send(ec) {
-            return Err(windows_core::Error::new(HRESULT::from_win32(0), "Failed to send message"));
-        }
-
-        Ok(())
+        com_entry("ITfEditSession::DoEditSession", || {
+            (self.callback)(ec)
+        })
     }
 }
@@ -0,0 +1,226 @@
+use std::cell::Cell;
+
+use crate::trace::{debug, warn};
+
+#[cfg(feature = "test-utils")]
+use crate::{fake_tip::FakeEngine, test_mode::TestMode};
+
+use crate::{cancel::CancellationToken, com::Com, error::{Result, TsfError}, felang::FeLanguage, imm32::Imm32, metrics::metrics, tsf::TSF, width};
+
+/// The conversion backends the crate can drive.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EngineKind {
+    /// `ITfFnReconversion` through a TSF document.
+    Tsf,
+    /// The classic `IFELanguage` object (`MSIME.Japan`).
+    FeLanguage,
+    /// `ImmGetConversionListW` on the active keyboard layout.
+    Imm32,
+}
+
+/// What an engine can do, as far as could be determined by probing it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Capabilities {
+    pub supports_reconversion: bool,
+    pub supports_prediction: bool,
+    pub supports_reading: bool,
+    pub supports_learning: bool,
+    /// How many candidates a conversion typically yields, if known.
+    pub max_candidates_hint: Option<usize>,
+}
+
+/// A conversion candidate.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Candidate {
+    pub text: String,
+    /// The hint MS-IME shows next to the candidate, e.g. `人名` or `[半]`,
+    /// when the engine provides one.
+    pub annotation: Option<String>,
+}
+
+impl Candidate {
+    pub fn with_annotation(mut self, annotation: impl Into<String>) -> Self {
+        self.annotation = Some(annotation.into());
+        self
+    }
+}
+
+/// Annotates half-width and full-width forms, which every engine can tell.
+impl From<String> for Candidate {
+    fn from(text: String) -> Self {
+        let annotation = width::annotation(&text).map(str::to_owned);
+        Self { text, annotation }
+    }
+}
+
+/// A backend turning a kana reading into candidates.
+pub trait Engine {
+    fn kind(&self) -> EngineKind;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Candidates for `reading`, best first. With `max_candidates`, at most
+    /// that many, and engines that can stop early do not fetch the rest.
+    fn convert(&self, reading: &str, max_candidates: Option<usize>) -> Result<Vec<Candidate>>;
+
+    /// The kana reading of `text`, the inverse of [`Engine::convert`].
+    fn reading(&self, text: &str) -> Result<String>;
+}
+
+impl Engine for TSF<'_> {
+    fn kind(&self) -> EngineKind {
+        EngineKind::Tsf
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            supports_reconversion: self.has_reconversion() && self.quirks().reconversion,
+            supports_prediction: self.has_prediction(),
+            supports_reading: self.quirks().reading_property,
+            supports_learning: self.has_reconversion(),
+            ..Capabilities::default()
+        }
+    }
+
+    fn convert(&self, reading: &str, max_candidates: Option<usize>) -> Result<Vec<Candidate>> {
+        Ok(TSF::convert_with_limit(self, reading, &CancellationToken::new(), max_candidates)?.into_iter().map(Candidate::from).collect())
+    }
+
+    fn reading(&self, text: &str) -> Result<String> {
+        TSF::reading(self, text)
+    }
+}
+
+/// Opens the engine of the given kind on the current thread.
+pub fn open<'com>(kind: EngineKind, com: &'com Com) -> Result<Box<dyn Engine + 'com>> {
+    match kind {
+        EngineKind::Tsf => {
+            let mut tsf = TSF::new(com);
+            tsf.initialize()?;
+            Ok(Box::new(tsf))
+        }
+        EngineKind::FeLanguage => Ok(Box::new(FeLanguage::new(com)?)),
+        EngineKind::Imm32 => Ok(Box::new(Imm32::new()?)),
+    }
+}
+
+/// The default fallback order: TSF reconversion, then `IFELanguage`, then
+/// IMM32.
+pub const FALLBACK_ORDER: [EngineKind; 3] = [EngineKind::Tsf, EngineKind::FeLanguage, EngineKind::Imm32];
+
+/// A value together with the engine that produced it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Answered<T> {
+    pub engine: EngineKind,
+    pub value: T,
+}
+
+/// Engines tried in order until one answers.
+///
+/// Every engine is opened up front and those that fail to open are left
+/// out. A request that fails, or converts to nothing, is retried on the next
+/// engine.
+pub struct EngineChain<'com> {
+    engines: Vec<Box<dyn Engine + 'com>>,
+    last: Cell<Option<EngineKind>>,
+}
+
+impl<'com> EngineChain<'com> {
+    /// Probes the engines in [`FALLBACK_ORDER`].
+    pub fn new(com: &'com Com) -> Result<Self> {
+        Self::with_order(com, &FALLBACK_ORDER)
+    }
+
+    /// Probes `order` and keeps the engines that open. Fails with the last
+    /// error when none does.
+    pub fn with_order(com: &'com Com, order: &[EngineKind]) -> Result<Self> {
+        let mut engines = Vec::with_capacity(order.len());
+        let mut last = TsfError::InvalidArgument("engine order is empty");
+        for &kind in order {
+            match open(kind, com) {
+                Ok(engine) => engines.push(engine),
+                Err(e) => {
+                    warn!("{:?} engine is not available: {:?}", kind, e);
+                    last = e;
+                }
+            }
+        }
+
+        if engines.is_empty() {
+            return Err(last);
+        }
+
+        debug!("Engine chain: {:?}", engines.iter().map(|engine| engine.kind()).collect::<Vec<_>>());
+        Ok(Self { engines, last: Cell::new(None) })
+    }
+
+    /// [`FakeEngine`]s answering from the script of `test_mode`, reporting
+    /// themselves as the engines of `order`.
+    #[cfg(feature = "test-utils")]
+    pub fn with_test_mode(test_mode: &TestMode, order: &[EngineKind]) -> Result<Self> {
+        if order.is_empty() {
+            return Err(TsfError::InvalidArgument("engine order is empty"));
+        }
+
+        let engines = order.iter().map(|&kind| Box::new(FakeEngine::new(kind, test_mode.script().clone())) as Box<dyn Engine + 'com>).collect();
+        Ok(Self { engines, last: Cell::new(None) })
+    }
+
+    /// Capabilities of each engine, in the order they are tried.
+    pub fn capabilities(&self) -> Vec<(EngineKind, Capabilities)> {
+        self.engines.iter().map(|engine| (engine.kind(), engine.capabilities())).collect()
+    }
+
+    /// The engines that opened, in the order they are tried.
+    pub fn engines(&self) -> Vec<EngineKind> {
+        self.engines.iter().map(|engine| engine.kind()).collect()
+    }
+
+    /// The engine that answered the most recent successful request.
+    pub fn last_engine(&self) -> Option<EngineKind> {
+        self.last.get()
+    }
+
+    /// Converts with the first engine that answers, returning at most
+    /// `max_candidates` candidates.
+    pub fn convert(&self, reading: &str, max_candidates: Option<usize>) -> Result<Answered<Vec<Candidate>>> {
+        self.first(|engine| engine.convert(reading, max_candidates).map(|candidates| (!candidates.is_empty()).then_some(candidates)))
+    }
+
+    pub fn reading(&self, text: &str) -> Result<Answered<String>> {
+        self.first(|engine| engine.reading(text).map(|reading| (!reading.is_empty()).then_some(reading)))
+    }
+
+    /// Returns the first answer, treating `Ok(None)` as "try the next
+    /// engine". When nothing answers, the last error is returned.
+    fn first<T>(&self, mut request: impl FnMut(&dyn Engine) -> Result<Option<T>>) -> Result<Answered<T>> {
+        let mut last = TsfError::NotConvertible;
+        for engine in &self.engines {
+            let engine = engine.as_ref();
+            match request(engine) {
+                Ok(Some(value)) => {
+                    debug!("{:?} answered", engine.kind());
+                    self.last.set(Some(engine.kind()));
+                    return Ok(Answered { engine: engine.kind(), value });
+                }
+                Ok(None) => {
+                    debug!("{:?} returned nothing, trying the next engine", engine.kind());
+                    metrics().engine_fallback(engine.kind());
+                }
+                Err(e) => {
+                    warn!("{:?} failed, trying the next engine: {:?}", engine.kind(), e);
+                    metrics().engine_fallback(engine.kind());
+                    last = e;
+                }
+            }
+        }
+
+        Err(last)
+    }
+}
+
+/// Returns the reading of `text` from the first engine in
+/// [`FALLBACK_ORDER`] able to produce one.
+pub fn get_reading(com: &Com, text: &str) -> Result<String> {
+    Ok(EngineChain::new(com)?.reading(text)?.value)
+}
@@ -0,0 +1,126 @@
+use windows::Win32::{
+    Foundation::{ERROR_SUCCESS, HANDLE},
+    System::{
+        Registry::{RegGetValueW, HKEY, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, RRF_RT_REG_DWORD},
+        RemoteDesktop::ProcessIdToSessionId,
+        StationsAndDesktops::{CloseDesktop, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_READOBJECTS, UOI_NAME},
+        Threading::GetCurrentProcessId,
+    },
+};
+use windows_core::{w, PCWSTR};
+
+use crate::trace::{debug, warn};
+
+/// Something that keeps TSF from activating in this process.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Blocker {
+    /// Running in session 0, which has no interactive desktop.
+    ServiceSession,
+    /// The input desktop is not the user's default desktop (UAC prompt,
+    /// lock screen), or it cannot be opened at all.
+    SecureDesktop,
+    /// Text services are turned off for the user ("Disable Thread Input
+    /// Manager").
+    TextServicesDisabled,
+}
+
+/// What the current session looks like to TSF, gathered without creating
+/// any TSF objects.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Report {
+    /// Terminal services session of this process.
+    pub session_id: Option<u32>,
+    /// Whether legacy IMM32 applications are served through CUAS. `None`
+    /// when the setting is absent.
+    pub cuas: Option<bool>,
+    pub text_services_disabled: bool,
+    /// Name of the desktop receiving input, `None` if it cannot be opened.
+    pub input_desktop: Option<String>,
+}
+
+impl Report {
+    /// Inspects the current process and session.
+    pub fn detect() -> Self {
+        let report = Self {
+            session_id: session_id(),
+            cuas: registry_dword(HKEY_LOCAL_MACHINE, w!("SOFTWARE\\Microsoft\\CTF\\SystemShared"), w!("CUAS")).map(|value| value != 0),
+            text_services_disabled: registry_dword(HKEY_CURRENT_USER, w!("Software\\Microsoft\\CTF"), w!("Disable Thread Input Manager")) == Some(1),
+            input_desktop: input_desktop(),
+        };
+        debug!("Environment: {:?}", report);
+        report
+    }
+
+    pub fn is_service_session(&self) -> bool {
+        self.session_id == Some(0)
+    }
+
+    pub fn is_secure_desktop(&self) -> bool {
+        !self.input_desktop.as_deref().is_some_and(|name| name.eq_ignore_ascii_case("Default"))
+    }
+
+    /// Everything that would make [`crate::tsf::TSF::initialize`] fail or
+    /// come up without an input processor.
+    pub fn blockers(&self) -> Vec<Blocker> {
+        let mut blockers = Vec::new();
+        if self.is_service_session() {
+            blockers.push(Blocker::ServiceSession);
+        }
+        if self.is_secure_desktop() {
+            blockers.push(Blocker::SecureDesktop);
+        }
+        if self.text_services_disabled {
+            blockers.push(Blocker::TextServicesDisabled);
+        }
+        blockers
+    }
+
+    pub fn can_activate(&self) -> bool {
+        self.blockers().is_empty()
+    }
+}
+
+fn session_id() -> Option<u32> {
+    let mut session = 0;
+    match unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session) } {
+        Ok(()) => Some(session),
+        Err(e) => {
+            warn!("Failed to query session id: {:?}", e);
+            None
+        }
+    }
+}
+
+fn registry_dword(root: HKEY, key: PCWSTR, value: PCWSTR) -> Option<u32> {
+    let mut data = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = unsafe { RegGetValueW(root, key, value, RRF_RT_REG_DWORD, None, Some(&mut data as *mut u32 as *mut _), Some(&mut size)) };
+    (status == ERROR_SUCCESS).then_some(data)
+}
+
+fn input_desktop() -> Option<String> {
+    let desktop = match unsafe { OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS) } {
+        Ok(desktop) => desktop,
+        Err(e) => {
+            debug!("Input desktop cannot be opened: {:?}", e);
+            return None;
+        }
+    };
+
+    let mut name = [0u16; 256];
+    let mut needed = 0;
+    let result = unsafe {
+        GetUserObjectInformationW(HANDLE(desktop.0), UOI_NAME, Some(name.as_mut_ptr() as *mut _), std::mem::size_of_val(&name) as u32, Some(&mut needed))
+    };
+    if let Err(e) = unsafe { CloseDesktop(desktop) } {
+        warn!("Failed to close input desktop: {:?}", e);
+    }
+
+    if let Err(e) = result {
+        warn!("Failed to query input desktop name: {:?}", e);
+        return None;
+    }
+
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Some(String::from_utf16_lossy(&name[..len]))
+}
@@ -0,0 +1,154 @@
+use std::sync::{LockResult, PoisonError};
+
+use crate::trace::error;
+use windows::Win32::Foundation::E_FAIL;
+use windows_core::HRESULT;
+
+use crate::{affinity::WrongThread, cancel::Cancelled, timeout::Timeout};
+
+/// Result type used throughout the crate.
+pub type Result<T> = std::result::Result<T, TsfError>;
+
+/// Errors returned by the TSF pipeline.
+///
+/// New variants may be added in minor releases, so matches need a wildcard
+/// arm.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum TsfError {
+    #[error("failed to initialize COM")]
+    ComInit(#[source] windows_core::Error),
+    #[error("failed to create or activate the thread manager")]
+    ThreadMgrActivate(#[source] windows_core::Error),
+    #[error("failed to create the input context")]
+    ContextCreate(#[source] windows_core::Error),
+    #[error("system function provider is not available")]
+    NoFunctionProvider(#[source] windows_core::Error),
+    #[error("reconversion function is not available")]
+    NoReconversion,
+    #[error("prediction is not available")]
+    NoPrediction,
+    #[error("range is not convertible")]
+    NotConvertible,
+    #[error("no reading is available for the text")]
+    NoReading,
+    #[error("{0:?} engine is not available")]
+    EngineUnavailable(crate::engine::EngineKind),
+    #[error("text store is locked")]
+    StoreLocked,
+    #[error("TSF is not initialized")]
+    NotInitialized,
+    #[error("the document is not backed by the crate's text store")]
+    NoTextStore,
+    #[error("TSF worker could not be started")]
+    WorkerSpawn(#[source] std::io::Error),
+    #[error("TSF worker is not running")]
+    WorkerStopped,
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(&'static str),
+    #[error("{call} failed with {hr}")]
+    Hresult { hr: HRESULT, call: String },
+    #[error(transparent)]
+    WrongThread(#[from] WrongThread),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+    #[error(transparent)]
+    Timeout(#[from] Timeout),
+}
+
+impl TsfError {
+    /// The HRESULT behind this error, if it came from a COM call.
+    pub fn hresult(&self) -> Option<HRESULT> {
+        match self {
+            Self::ComInit(e) | Self::ThreadMgrActivate(e) | Self::ContextCreate(e) | Self::NoFunctionProvider(e) => Some(e.code()),
+            Self::Hresult { hr, .. } => Some(*hr),
+            _ => None,
+        }
+    }
+}
+
+impl From<TsfError> for windows_core::Error {
+    fn from(e: TsfError) -> Self {
+        windows_core::Error::new(e.hresult().unwrap_or(E_FAIL), e.to_string())
+    }
+}
+
+/// Maps a failed call to [`TsfError::Hresult`] naming `call`.
+pub(crate) fn hresult(call: impl Into<String>) -> impl FnOnce(windows_core::Error) -> TsfError {
+    let call = call.into();
+    move |e| TsfError::Hresult { hr: e.code(), call }
+}
+
+/// Rewraps the [`TsfError::Hresult`] of a `call!` as `variant`, keeping the
+/// call in the error's message.
+pub(crate) fn rewrap(variant: fn(windows_core::Error) -> TsfError) -> impl FnOnce(TsfError) -> TsfError {
+    move |e| match e {
+        TsfError::Hresult { hr, call } => variant(windows_core::Error::new(hr, call)),
+        e => e,
+    }
+}
+
+/// Runs the body of a COM entry point, turning a panic into `E_FAIL` so that
+/// it never unwinds across the FFI boundary into the host process.
+pub(crate) fn com_entry<T>(method: &str, body: impl FnOnce() -> windows_core::Result<T>) -> windows_core::Result<T> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
+        Ok(result) => result,
+        Err(_) => {
+            error!("Panic in {}", method);
+            Err(E_FAIL.into())
+        }
+    }
+}
+
+/// Takes the guard out of a poisoned lock. A panic in one COM callback must
+/// not wedge every later call on the same object.
+pub(crate) trait Recover<T> {
+    fn recover(self) -> T;
+}
+
+impl<T> Recover<T> for LockResult<T> {
+    fn recover(self) -> T {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Strips references and the module path from a type name.
+pub(crate) fn interface_name(type_name: &str) -> &str {
+    let name = type_name.trim_start_matches('&');
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Performs an unsafe Win32/COM call and, on failure, returns
+/// [`TsfError::Hresult`] naming the interface, method and argument list, e.g.
+/// `ITfDocumentMgr::Push(context)`.
+///
+/// `call!(object, Method(args))` for interface methods and
+/// `call!(Function(args))` for free functions.
+macro_rules! call {
+    ($function:ident ($($arg:expr),* $(,)?)) => {{
+        #[allow(unused_unsafe)]
+        let result = unsafe { $function($($arg),*) };
+        result.map_err(|e| $crate::error::TsfError::Hresult {
+            hr: e.code(),
+            call: format!("{}({})", stringify!($function), stringify!($($arg),*)),
+        })
+    }};
+    ($object:expr, $method:ident $(::<$($generic:ty),+>)? ($($arg:expr),* $(,)?)) => {{
+        let object = &$object;
+        #[allow(unused_unsafe)]
+        let result = unsafe { object.$method $(::<$($generic),+>)? ($($arg),*) };
+        result.map_err(|e| $crate::error::TsfError::Hresult {
+            hr: e.code(),
+            call: format!(
+                "{}::{}({})",
+                $crate::error::interface_name(std::any::type_name_of_val(object)),
+                stringify!($method),
+                stringify!($($arg),*)
+            ),
+        })
+    }};
+}
+
+pub(crate) use call;
@@ -0,0 +1,342 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use windows_core::GUID;
+
+use crate::{error::Recover, metrics::metrics, notifications::NotificationLog};
+
+/// Notifications raised by the TSF pipeline and its sinks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TsfEvent {
+    CompositionStarted,
+    CompositionUpdated,
+    CompositionEnded,
+    /// A candidate list UI element was shown or changed.
+    CandidatesUpdated { candidates: Vec<String>, selection: u32 },
+    CandidatesClosed,
+    /// An input processor profile or keyboard layout was (de)activated.
+    ProfileActivated { clsid: GUID, profile: GUID, langid: u16, active: bool },
+    /// The document text changed, in ACP coordinates.
+    TextChanged { start: i32, old_end: i32, new_end: i32 },
+    /// The thread gained or lost TSF focus.
+    ThreadFocusChanged { focused: bool },
+    /// A keyboard compartment (open/close, conversion or sentence mode)
+    /// changed. `value` is `None` when it does not hold an integer.
+    CompartmentChanged { compartment: GUID, value: Option<i32> },
+}
+
+/// What happens when a subscriber's queue is full.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued event to make room.
+    DropOldest,
+    /// Wait until the consumer makes room. Never use this when the consumer
+    /// runs on the TSF thread itself.
+    Block,
+    /// Replace the most recent queued event of the same kind, falling back to
+    /// dropping the oldest event.
+    Coalesce,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EventQueueConfig {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for EventQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+struct QueueState {
+    events: VecDeque<TsfEvent>,
+    receiver_alive: bool,
+    sender_alive: bool,
+    dropped: u64,
+    #[cfg(feature = "async")]
+    waker: Option<std::task::Waker>,
+}
+
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    config: EventQueueConfig,
+}
+
+impl Queue {
+    fn new(config: EventQueueConfig) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                receiver_alive: true,
+                sender_alive: true,
+                dropped: 0,
+                #[cfg(feature = "async")]
+                waker: None,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            config: EventQueueConfig {
+                capacity: config.capacity.max(1),
+                ..config
+            },
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().recover()
+    }
+
+    /// Returns `false` once the receiving side is gone.
+    fn push(&self, event: TsfEvent) -> bool {
+        let mut state = self.lock();
+        if !state.receiver_alive {
+            return false;
+        }
+
+        if state.events.len() >= self.config.capacity {
+            match self.config.policy {
+                OverflowPolicy::DropOldest => {
+                    state.events.pop_front();
+                    state.dropped += 1;
+                }
+                OverflowPolicy::Coalesce => {
+                    let kind = std::mem::discriminant(&event);
+                    match state.events.iter().rposition(|queued| std::mem::discriminant(queued) == kind) {
+                        Some(position) => {
+                            state.events.remove(position);
+                        }
+                        None => {
+                            state.events.pop_front();
+                        }
+                    }
+                    state.dropped += 1;
+                }
+                OverflowPolicy::Block => {
+                    while state.events.len() >= self.config.capacity && state.receiver_alive {
+                        state = self.not_full.wait(state).recover();
+                    }
+                    if !state.receiver_alive {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        state.events.push_back(event);
+        self.wake(&mut state);
+        true
+    }
+
+    fn pop(&self, state: &mut QueueState) -> Option<TsfEvent> {
+        let event = state.events.pop_front();
+        if event.is_some() {
+            self.not_full.notify_one();
+        }
+        event
+    }
+
+    fn close_sender(&self) {
+        let mut state = self.lock();
+        state.sender_alive = false;
+        self.wake(&mut state);
+    }
+
+    fn close_receiver(&self) {
+        let mut state = self.lock();
+        state.receiver_alive = false;
+        state.events.clear();
+        self.not_full.notify_all();
+    }
+
+    fn wake(&self, #[allow(unused_variables)] state: &mut QueueState) {
+        self.not_empty.notify_all();
+        #[cfg(feature = "async")]
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Default)]
+struct HubInner {
+    queues: Mutex<Vec<Arc<Queue>>>,
+    notifications: NotificationLog,
+}
+
+impl Drop for HubInner {
+    fn drop(&mut self) {
+        let queues = self.queues.get_mut().recover();
+        for queue in queues.drain(..) {
+            queue.close_sender();
+        }
+    }
+}
+
+/// Fans events out to every subscriber. Cheap to clone and safe to share
+/// across threads.
+///
+/// Each subscriber owns a bounded queue, so a slow consumer never makes the
+/// TSF thread wait inside a COM callback unless it opted into
+/// [`OverflowPolicy::Block`].
+#[derive(Clone, Default)]
+pub struct EventHub {
+    inner: Arc<HubInner>,
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a blocking receiver for all events emitted from now on.
+    pub fn subscribe(&self) -> EventReceiver {
+        self.subscribe_with(EventQueueConfig::default())
+    }
+
+    pub fn subscribe_with(&self, config: EventQueueConfig) -> EventReceiver {
+        EventReceiver { queue: self.register(config) }
+    }
+
+    /// Returns a [`futures_core::Stream`] of all events emitted from now on.
+    #[cfg(feature = "async")]
+    pub fn stream(&self) -> EventStream {
+        self.stream_with(EventQueueConfig::default())
+    }
+
+    #[cfg(feature = "async")]
+    pub fn stream_with(&self, config: EventQueueConfig) -> EventStream {
+        EventStream { queue: self.register(config) }
+    }
+
+    /// The latest sink notifications exchanged by the pipeline feeding this
+    /// hub.
+    pub fn notifications(&self) -> &NotificationLog {
+        &self.inner.notifications
+    }
+
+    pub fn emit(&self, event: TsfEvent) {
+        metrics().event(&event);
+        let queues = self.inner.queues.lock().recover().clone();
+        let mut closed = Vec::new();
+
+        for queue in &queues {
+            if !queue.push(event.clone()) {
+                closed.push(queue.clone());
+            }
+        }
+
+        if !closed.is_empty() {
+            let mut queues = self.inner.queues.lock().recover();
+            queues.retain(|queue| !closed.iter().any(|c| Arc::ptr_eq(c, queue)));
+        }
+    }
+
+    fn register(&self, config: EventQueueConfig) -> Arc<Queue> {
+        let queue = Arc::new(Queue::new(config));
+        self.inner.queues.lock().recover().push(queue.clone());
+        queue
+    }
+}
+
+/// Blocking end of an event subscription.
+pub struct EventReceiver {
+    queue: Arc<Queue>,
+}
+
+impl EventReceiver {
+    /// Waits for the next event. Returns `None` once every [`EventHub`] is
+    /// gone and the queue has been drained.
+    pub fn recv(&self) -> Option<TsfEvent> {
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(event) = self.queue.pop(&mut state) {
+                return Some(event);
+            }
+            if !state.sender_alive {
+                return None;
+            }
+            state = self.queue.not_empty.wait(state).recover();
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<TsfEvent> {
+        let state = self.queue.lock();
+        let (mut state, _) = self
+            .queue
+            .not_empty
+            .wait_timeout_while(state, timeout, |state| state.events.is_empty() && state.sender_alive)
+            .recover();
+        self.queue.pop(&mut state)
+    }
+
+    pub fn try_recv(&self) -> Option<TsfEvent> {
+        let mut state = self.queue.lock();
+        self.queue.pop(&mut state)
+    }
+
+    /// Number of events discarded or coalesced because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+impl Iterator for EventReceiver {
+    type Item = TsfEvent;
+
+    fn next(&mut self) -> Option<TsfEvent> {
+        self.recv()
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.queue.close_receiver();
+    }
+}
+
+#[cfg(feature = "async")]
+pub struct EventStream {
+    queue: Arc<Queue>,
+}
+
+#[cfg(feature = "async")]
+impl EventStream {
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for EventStream {
+    type Item = TsfEvent;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<TsfEvent>> {
+        let mut state = self.queue.lock();
+        if let Some(event) = self.queue.pop(&mut state) {
+            return std::task::Poll::Ready(Some(event));
+        }
+        if !state.sender_alive {
+            return std::task::Poll::Ready(None);
+        }
+
+        state.waker = Some(cx.waker().clone());
+        std::task::Poll::Pending
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.queue.close_receiver();
+    }
+}
@@ -0,0 +1,379 @@
+//! A stand-in input processor answering reconversion requests from a
+//! script, so that the conversion pipeline runs on machines without a
+//! Japanese IME. Enabled by the `test-utils` feature.
+//!
+//! [`FakeTip`] implements `ITfFunctionProvider`, `ITfFnReconversion`,
+//! `ITfFnSearchCandidateProvider` and `ITfCandidateList`. Hand its provider to
+//! [`crate::tsf::TSF::set_function_provider`] before initializing, or
+//! [`FakeTip::install`] it on the thread like a real TIP.
+//! [`FakeEngine`] answers from a script
+//! without TSF at all, for output that must not depend on the machine.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::trace::debug;
+use windows::Win32::{
+    Foundation::{BOOL, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL},
+    System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+    UI::TextServices::{CLSID_TF_ThreadMgr, IEnumTfCandidates, ITfCandidateList, ITfCandidateList_Impl, ITfCandidateString, ITfCandidateString_Impl, ITfEditSession, ITfFnReconversion, ITfFnReconversion_Impl, ITfFnSearchCandidateProvider, ITfFnSearchCandidateProvider_Impl, ITfFunction_Impl, ITfFunctionProvider, ITfFunctionProvider_Impl, ITfRange, ITfSourceSingle, ITfThreadMgr, TfCandidateResult, CAND_FINALIZED, TF_ES_READ, TF_ES_SYNC, TF_TF_MOVESTART},
+};
+use windows_core::{implement, Interface, BSTR, GUID};
+
+use crate::{
+    edit_session::EditSession,
+    engine::{Candidate, Capabilities, Engine, EngineKind},
+    error::{call, com_entry, Result, TsfError},
+};
+
+/// What `ITfFunctionProvider::GetType` reports for the fake.
+pub const CLSID_FAKE_TIP: GUID = GUID::from_u128(0x3f0c52a4_9d1e_4b6a_8c27_5e1f0a7d9b63);
+
+/// Candidates per reading. Readings without an entry are not convertible.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Script {
+    candidates: HashMap<String, Vec<String>>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offers `candidates`, in order, for `reading`.
+    pub fn with(mut self, reading: &str, candidates: &[&str]) -> Self {
+        self.insert(reading, candidates.iter().map(|candidate| candidate.to_string()).collect());
+        self
+    }
+
+    pub fn insert(&mut self, reading: &str, candidates: Vec<String>) {
+        self.candidates.insert(reading.to_string(), candidates);
+    }
+
+    pub fn candidates(&self, reading: &str) -> Option<&[String]> {
+        self.candidates.get(reading).map(Vec::as_slice)
+    }
+
+    /// The reading offering `text` as a candidate; the first in sort order
+    /// when several do.
+    pub fn reading(&self, text: &str) -> Option<&str> {
+        self.candidates
+            .iter()
+            .filter(|(_, candidates)| candidates.iter().any(|candidate| candidate == text))
+            .map(|(reading, _)| reading.as_str())
+            .min()
+    }
+
+    /// The candidates of every reading starting with `prefix`, shorter
+    /// readings first, as the fake's predictions.
+    pub fn predictions(&self, prefix: &str) -> Vec<String> {
+        let mut readings: Vec<&String> = self.candidates.keys().filter(|reading| reading.starts_with(prefix)).collect();
+        readings.sort_by_key(|reading| (reading.chars().count(), reading.as_str()));
+
+        let mut predictions: Vec<String> = Vec::new();
+        for candidate in readings.into_iter().flat_map(|reading| &self.candidates[reading]) {
+            if !predictions.contains(candidate) {
+                predictions.push(candidate.clone());
+            }
+        }
+        predictions
+    }
+}
+
+impl From<HashMap<String, Vec<String>>> for Script {
+    fn from(candidates: HashMap<String, Vec<String>>) -> Self {
+        Self { candidates }
+    }
+}
+
+/// An engine of any kind answering from a script.
+pub struct FakeEngine {
+    kind: EngineKind,
+    script: Script,
+}
+
+impl FakeEngine {
+    /// An engine reporting itself as `kind`.
+    pub fn new(kind: EngineKind, script: Script) -> Self {
+        Self { kind, script }
+    }
+}
+
+impl Engine for FakeEngine {
+    fn kind(&self) -> EngineKind {
+        self.kind
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { supports_reconversion: true, supports_reading: true, ..Capabilities::default() }
+    }
+
+    fn convert(&self, reading: &str, max_candidates: Option<usize>) -> Result<Vec<Candidate>> {
+        let candidates = self.script.candidates(reading).ok_or(TsfError::NotConvertible)?;
+        Ok(candidates.iter().take(max_candidates.unwrap_or(usize::MAX)).cloned().map(Candidate::from).collect())
+    }
+
+    fn reading(&self, text: &str) -> Result<String> {
+        self.script.reading(text).map(str::to_string).ok_or(TsfError::NoReading)
+    }
+}
+
+/// A candidate chosen through `ITfCandidateList::SetResult`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Finalized {
+    pub reading: String,
+    pub candidate: String,
+}
+
+struct TipState {
+    script: Script,
+    finalized: Vec<Finalized>,
+}
+
+/// A scripted input processor. Clones share the script and the record of
+/// finalized candidates.
+#[derive(Clone)]
+pub struct FakeTip {
+    state: Rc<RefCell<TipState>>,
+}
+
+impl FakeTip {
+    pub fn new(script: Script) -> Self {
+        Self { state: Rc::new(RefCell::new(TipState { script, finalized: Vec::new() })) }
+    }
+
+    /// Replaces the script; candidate lists handed out already keep theirs.
+    pub fn set_script(&self, script: Script) {
+        self.state.borrow_mut().script = script;
+    }
+
+    /// The candidates finalized so far, oldest first.
+    pub fn finalized(&self) -> Vec<Finalized> {
+        self.state.borrow().finalized.clone()
+    }
+
+    /// The scripted reading of `text`; see [`Script::reading`].
+    pub fn reading(&self, text: &str) -> Result<String> {
+        self.state.borrow().script.reading(text).map(str::to_string).ok_or(TsfError::NoReading)
+    }
+
+    pub fn function_provider(&self) -> ITfFunctionProvider {
+        FakeFunctionProvider { tip: self.clone() }.into()
+    }
+
+    /// Registers the provider with the thread manager of the current thread,
+    /// where `GetFunctionProvider` finds it under [`CLSID_FAKE_TIP`], until
+    /// the returned guard is dropped.
+    pub fn install(&self) -> Result<Installed> {
+        let thread_mgr: ITfThreadMgr = call!(CoCreateInstance(&CLSID_TF_ThreadMgr, None, CLSCTX_INPROC_SERVER))?;
+        let client_id = call!(thread_mgr, Activate())?;
+        let registered = call!(thread_mgr, cast::<ITfSourceSingle>())
+            .and_then(|source| call!(source, AdviseSingleSink(client_id, &ITfFunctionProvider::IID, &self.function_provider())));
+        if let Err(e) = registered {
+            let _ = call!(thread_mgr, Deactivate());
+            return Err(e);
+        }
+        Ok(Installed { thread_mgr, client_id })
+    }
+}
+
+/// A [`FakeTip`] registered with [`FakeTip::install`].
+pub struct Installed {
+    thread_mgr: ITfThreadMgr,
+    client_id: u32,
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        if let Ok(source) = call!(self.thread_mgr, cast::<ITfSourceSingle>()) {
+            let _ = call!(source, UnadviseSingleSink(self.client_id, &ITfFunctionProvider::IID));
+        }
+        let _ = call!(self.thread_mgr, Deactivate());
+    }
+}
+
+#[implement(ITfFunctionProvider)]
+struct FakeFunctionProvider {
+    tip: FakeTip,
+}
+
+impl ITfFunctionProvider_Impl for FakeFunctionProvider {
+    fn GetType(&self) -> windows_core::Result<GUID> {
+        Ok(CLSID_FAKE_TIP)
+    }
+
+    fn GetDescription(&self) -> windows_core::Result<BSTR> {
+        Ok(BSTR::from("Fake TIP"))
+    }
+
+    fn GetFunction(&self, _rguid: *const GUID, riid: *const GUID) -> windows_core::Result<windows_core::IUnknown> {
+        com_entry("ITfFunctionProvider::GetFunction", || {
+            match unsafe { riid.as_ref() } {
+                Some(&ITfFnReconversion::IID) => {
+                    let reconversion: ITfFnReconversion = FakeReconversion { tip: self.tip.clone() }.into();
+                    reconversion.cast()
+                }
+                Some(&ITfFnSearchCandidateProvider::IID) => {
+                    let search: ITfFnSearchCandidateProvider = FakeSearch { tip: self.tip.clone() }.into();
+                    search.cast()
+                }
+                Some(_) => Err(E_NOINTERFACE.into()),
+                None => Err(E_INVALIDARG.into()),
+            }
+        })
+    }
+}
+
+#[implement(ITfFnReconversion)]
+struct FakeReconversion {
+    tip: FakeTip,
+}
+
+impl FakeReconversion {
+    /// The script entry for the text of `range`, which is read in an edit
+    /// session of its own like a real input processor would.
+    fn lookup(&self, range: &ITfRange) -> windows_core::Result<(String, Option<Vec<String>>)> {
+        let reading = read_range(range)?;
+        let candidates = self.tip.state.borrow().script.candidates(&reading).map(<[String]>::to_vec);
+        debug!("Fake TIP has {:?} candidates", candidates.as_ref().map(Vec::len));
+        Ok((reading, candidates))
+    }
+}
+
+fn read_range(range: &ITfRange) -> windows_core::Result<String> {
+    let context = unsafe { range.GetContext()? };
+    let range = unsafe { range.Clone()? };
+
+    // Activating again hands back the client id of the thread's existing
+    // activation.
+    let thread_mgr: ITfThreadMgr = unsafe { CoCreateInstance(&CLSID_TF_ThreadMgr, None, CLSCTX_INPROC_SERVER)? };
+    let client_id = unsafe { thread_mgr.Activate()? };
+
+    let text = Rc::new(RefCell::new(Vec::new()));
+    let session: ITfEditSession = {
+        let text = text.clone();
+        EditSession::new(move |ec| {
+            let mut buffer = [0u16; 64];
+            loop {
+                let mut copied = 0;
+                unsafe { range.GetText(ec, TF_TF_MOVESTART, &mut buffer, &mut copied)? };
+                if copied == 0 {
+                    return Ok(());
+                }
+                text.borrow_mut().extend_from_slice(&buffer[..copied as usize]);
+            }
+        }).into()
+    };
+    let result = unsafe { context.RequestEditSession(client_id, &session, TF_ES_SYNC | TF_ES_READ) }.and_then(|hr| hr.ok());
+    unsafe { thread_mgr.Deactivate()? };
+    result?;
+
+    Ok(String::from_utf16_lossy(&text.borrow()))
+}
+
+impl ITfFunction_Impl for FakeReconversion {
+    fn GetDisplayName(&self) -> windows_core::Result<BSTR> {
+        Ok(BSTR::from("Fake reconversion"))
+    }
+}
+
+impl ITfFnReconversion_Impl for FakeReconversion {
+    fn QueryRange(&self, prange: Option<&ITfRange>, ppnewrange: *mut Option<ITfRange>, pfconvertable: *mut BOOL) -> windows_core::Result<()> {
+        com_entry("ITfFnReconversion::QueryRange", || {
+            let (Some(range), Some(convertable)) = (prange, unsafe { pfconvertable.as_mut() }) else {
+                return Err(E_INVALIDARG.into());
+            };
+
+            let (_, candidates) = self.lookup(range)?;
+            *convertable = BOOL::from(candidates.is_some());
+            if let Some(new_range) = unsafe { ppnewrange.as_mut() } {
+                *new_range = Some(unsafe { range.Clone()? });
+            }
+            Ok(())
+        })
+    }
+
+    fn GetReconversion(&self, prange: Option<&ITfRange>) -> windows_core::Result<ITfCandidateList> {
+        com_entry("ITfFnReconversion::GetReconversion", || {
+            let range = prange.ok_or(windows_core::Error::from(E_INVALIDARG))?;
+            let (reading, candidates) = self.lookup(range)?;
+            Ok(FakeCandidateList { tip: self.tip.clone(), reading, candidates: candidates.unwrap_or_default() }.into())
+        })
+    }
+
+    fn Reconvert(&self, _prange: Option<&ITfRange>) -> windows_core::Result<()> {
+        Ok(())
+    }
+}
+
+#[implement(ITfFnSearchCandidateProvider)]
+struct FakeSearch {
+    tip: FakeTip,
+}
+
+impl ITfFunction_Impl for FakeSearch {
+    fn GetDisplayName(&self) -> windows_core::Result<BSTR> {
+        Ok(BSTR::from("Fake search candidates"))
+    }
+}
+
+impl ITfFnSearchCandidateProvider_Impl for FakeSearch {
+    fn GetSearchCandidates(&self, bstrquery: &BSTR, _bstrapplicationid: &BSTR) -> windows_core::Result<ITfCandidateList> {
+        let reading = bstrquery.to_string();
+        let candidates = self.tip.state.borrow().script.predictions(&reading);
+        debug!("Fake TIP has {} predictions", candidates.len());
+        Ok(FakeCandidateList { tip: self.tip.clone(), reading, candidates }.into())
+    }
+
+    fn SetResult(&self, bstrquery: &BSTR, _bstrapplicationid: &BSTR, bstrresult: &BSTR) -> windows_core::Result<()> {
+        self.tip.state.borrow_mut().finalized.push(Finalized { reading: bstrquery.to_string(), candidate: bstrresult.to_string() });
+        Ok(())
+    }
+}
+
+#[implement(ITfCandidateList)]
+struct FakeCandidateList {
+    tip: FakeTip,
+    reading: String,
+    candidates: Vec<String>,
+}
+
+impl ITfCandidateList_Impl for FakeCandidateList {
+    fn EnumCandidates(&self) -> windows_core::Result<IEnumTfCandidates> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetCandidate(&self, nindex: u32) -> windows_core::Result<ITfCandidateString> {
+        let text = self.candidates.get(nindex as usize).ok_or(windows_core::Error::from(E_INVALIDARG))?;
+        Ok(FakeCandidateString { index: nindex, text: text.clone() }.into())
+    }
+
+    fn GetCandidateNum(&self) -> windows_core::Result<u32> {
+        Ok(self.candidates.len() as u32)
+    }
+
+    fn SetResult(&self, nindex: u32, imcr: TfCandidateResult) -> windows_core::Result<()> {
+        com_entry("ITfCandidateList::SetResult", || {
+            let candidate = self.candidates.get(nindex as usize).ok_or(windows_core::Error::from(E_INVALIDARG))?;
+            if imcr == CAND_FINALIZED {
+                self.tip.state.borrow_mut().finalized.push(Finalized { reading: self.reading.clone(), candidate: candidate.clone() });
+            }
+            Ok(())
+        })
+    }
+}
+
+#[implement(ITfCandidateString)]
+struct FakeCandidateString {
+    index: u32,
+    text: String,
+}
+
+impl ITfCandidateString_Impl for FakeCandidateString {
+    fn GetString(&self) -> windows_core::Result<BSTR> {
+        Ok(BSTR::from(self.text.as_str()))
+    }
+
+    fn GetIndex(&self) -> windows_core::Result<u32> {
+        Ok(self.index)
+    }
+}
@@ -0,0 +1,342 @@
+use std::time::Instant;
+
+use windows::Win32::{
+    System::Com::{CLSIDFromProgID, CoCreateInstance, CoTaskMemFree, CLSCTX_SERVER},
+    UI::Input::Ime::{IFELanguage, FELANG_CMODE_AUTOMATIC, FELANG_CMODE_HIRAGANAOUT, FELANG_CMODE_NOINVISIBLECHAR, FELANG_CMODE_PHRASEPREDICT, FELANG_REQ_CONV, FELANG_REQ_REV, JPOS_CHIMEI, JPOS_CHIMEI_EKI, JPOS_CHIMEI_MURA, JPOS_JINMEI, JPOS_JINMEI_MEI, JPOS_JINMEI_SEI, JPOS_SHAMEI, JPOS_SOSHIKI, JPOS_TANKANJI, JPOS_TANKANJI_KAO, MORRSLT, WDD},
+};
+use windows_core::{w, BSTR};
+
+use crate::{
+    affinity::ThreadAffinity,
+    com::Com,
+    engine::{Candidate, Capabilities, Engine, EngineKind},
+    error::{call, Result},
+    metrics,
+    trace::{debug, warn},
+};
+
+/// Conversion through the classic `IFELanguage` COM object.
+///
+/// Works without a TSF document, which makes it usable from services and on
+/// locked desktops where reconversion is not offered. Like [`crate::tsf::TSF`]
+/// it borrows the thread's [`Com`] guard and must stay on that thread.
+pub struct FeLanguage<'com> {
+    language: IFELanguage,
+    affinity: ThreadAffinity,
+    _com: &'com Com,
+}
+
+impl<'com> FeLanguage<'com> {
+    /// Creates and opens `MSIME.Japan`.
+    pub fn new(com: &'com Com) -> Result<Self> {
+        debug!("Opening IFELanguage");
+        let clsid = call!(CLSIDFromProgID(w!("MSIME.Japan")))?;
+        let language: IFELanguage = call!(CoCreateInstance(&clsid, None, CLSCTX_SERVER))?;
+        call!(language, Open())?;
+
+        Ok(Self { language, affinity: ThreadAffinity::current(), _com: com })
+    }
+
+    /// The `FELANG_CMODE_*` flags the engine supports.
+    pub fn conversion_mode_caps(&self) -> Result<u32> {
+        self.affinity.check()?;
+
+        let mut caps = 0;
+        call!(self.language, GetConversionModeCaps(&mut caps))?;
+        Ok(caps)
+    }
+
+    /// Converts `reading` with `IFELanguage::GetConversion`, returning the
+    /// engine's best guess.
+    pub fn conversion(&self, reading: &str) -> Result<String> {
+        self.affinity.check()?;
+
+        let input = BSTR::from(reading);
+        let mut output = BSTR::new();
+        call!(self.language, GetConversion(&input, 1, input.len() as i32, &mut output))?;
+        Ok(output.to_string())
+    }
+
+    /// Returns the hiragana reading of `text` with `IFELanguage::GetPhonetic`.
+    pub fn phonetic(&self, text: &str) -> Result<String> {
+        self.affinity.check()?;
+
+        let input = BSTR::from(text);
+        let mut output = BSTR::new();
+        call!(self.language, GetPhonetic(&input, 1, input.len() as i32, &mut output))?;
+        Ok(output.to_string())
+    }
+
+    /// Calls `IFELanguage::GetJMorphResult` and returns the output string of
+    /// the result record.
+    pub fn jmorph(&self, request: u32, mode: u32, input: &str) -> Result<String> {
+        Ok(self.morph(request, mode, input)?.output())
+    }
+
+    /// Segments kanji-kana text into morphemes, with readings taken from the
+    /// reverse conversion.
+    pub fn analyze(&self, text: &str) -> Result<Vec<Morpheme>> {
+        let result = self.morph(FELANG_REQ_REV, FELANG_CMODE_HIRAGANAOUT | FELANG_CMODE_NOINVISIBLECHAR, text)?;
+        Ok(result.morphemes(Direction::Reverse))
+    }
+
+    /// Converts a kana reading and segments the result into morphemes.
+    pub fn analyze_reading(&self, reading: &str) -> Result<Vec<Morpheme>> {
+        let result = self.morph(FELANG_REQ_CONV, FELANG_CMODE_HIRAGANAOUT | FELANG_CMODE_AUTOMATIC | FELANG_CMODE_NOINVISIBLECHAR, reading)?;
+        Ok(result.morphemes(Direction::Forward))
+    }
+
+    /// Converts a kana reading and splits the result into clauses
+    /// (bunsetsu), each with its reading and best conversion.
+    pub fn segment(&self, reading: &str) -> Result<Vec<Clause>> {
+        Ok(Clause::group(self.analyze_reading(reading)?))
+    }
+
+    fn morph(&self, request: u32, mode: u32, input: &str) -> Result<MorphResult> {
+        self.affinity.check()?;
+
+        let input: Vec<u16> = input.encode_utf16().collect();
+        let mut result: *mut MORRSLT = std::ptr::null_mut();
+        call!(self.language, GetJMorphResult(request, mode, input.len() as i32, windows_core::PCWSTR(input.as_ptr()), std::ptr::null_mut(), &mut result))?;
+        Ok(MorphResult(result))
+    }
+}
+
+impl Engine for FeLanguage<'_> {
+    fn kind(&self) -> EngineKind {
+        EngineKind::FeLanguage
+    }
+
+    /// Only prediction is probed, from the conversion mode caps. The rest
+    /// follow from how this engine uses `IFELanguage` and hold for every
+    /// installation: conversion and reverse conversion are part of the
+    /// interface, it has no way to learn a choice, and [`Engine::convert`]
+    /// yields the single best conversion.
+    fn capabilities(&self) -> Capabilities {
+        let caps = self.conversion_mode_caps().unwrap_or_else(|e| {
+            warn!("Failed to query IFELanguage conversion mode caps: {:?}", e);
+            0
+        });
+
+        Capabilities {
+            supports_reconversion: true,
+            supports_prediction: caps & FELANG_CMODE_PHRASEPREDICT != 0,
+            supports_reading: true,
+            supports_learning: false,
+            max_candidates_hint: Some(1),
+        }
+    }
+
+    fn convert(&self, reading: &str, max_candidates: Option<usize>) -> Result<Vec<Candidate>> {
+        if max_candidates == Some(0) {
+            return Ok(Vec::new());
+        }
+
+        let started = Instant::now();
+        let result = self.morph(FELANG_REQ_CONV, FELANG_CMODE_HIRAGANAOUT | FELANG_CMODE_AUTOMATIC | FELANG_CMODE_NOINVISIBLECHAR, reading).map(|result| {
+            let candidate = Candidate::from(result.output());
+            // Only a conversion that is a single word has a part of speech.
+            match result.morphemes(Direction::Forward).as_slice() {
+                [word] if let Some(annotation) = word.annotation() => vec![candidate.with_annotation(annotation)],
+                _ => vec![candidate],
+            }
+        });
+        metrics::record_conversion(EngineKind::FeLanguage, started.elapsed(), &result);
+        result
+    }
+
+    fn reading(&self, text: &str) -> Result<String> {
+        self.phonetic(text)
+    }
+}
+
+impl Drop for FeLanguage<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = call!(self.language, Close()) {
+            warn!("Failed to close IFELanguage: {:?}", e);
+        }
+    }
+}
+
+/// One word of a morphological analysis.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Morpheme {
+    /// The text as written.
+    pub surface: String,
+    /// The hiragana reading of `surface`.
+    pub reading: String,
+    /// MS-IME's part-of-speech number (`WDD::nPos`).
+    pub pos: u16,
+    /// Whether this morpheme starts a clause (bunsetsu).
+    pub clause_start: bool,
+    /// Whether the engine did not recognise the word.
+    pub unknown: bool,
+}
+
+impl Morpheme {
+    /// The kind of proper noun or symbol the word is, as MS-IME annotates
+    /// it in its candidate window.
+    pub fn annotation(&self) -> Option<&'static str> {
+        match self.pos as u32 {
+            JPOS_JINMEI => Some("人名"),
+            JPOS_JINMEI_SEI => Some("姓"),
+            JPOS_JINMEI_MEI => Some("名"),
+            JPOS_CHIMEI..=JPOS_CHIMEI_MURA => Some("地名"),
+            JPOS_CHIMEI_EKI => Some("駅名"),
+            JPOS_SHAMEI => Some("社名"),
+            JPOS_SOSHIKI => Some("組織名"),
+            JPOS_TANKANJI => Some("単漢字"),
+            JPOS_TANKANJI_KAO => Some("顔文字"),
+            _ => None,
+        }
+    }
+}
+
+/// A clause (bunsetsu) of a conversion, made of one or more morphemes.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Clause {
+    pub reading: String,
+    pub surface: String,
+    /// Offset of `reading` in the converted reading, in chars.
+    pub start: usize,
+}
+
+impl Clause {
+    /// Joins morphemes into clauses at each [`Morpheme::clause_start`].
+    pub fn group(morphemes: Vec<Morpheme>) -> Vec<Clause> {
+        let mut clauses: Vec<Clause> = Vec::new();
+        let mut start = 0;
+        for morpheme in morphemes {
+            let length = morpheme.reading.chars().count();
+            match clauses.last_mut() {
+                Some(clause) if !morpheme.clause_start => {
+                    clause.reading.push_str(&morpheme.reading);
+                    clause.surface.push_str(&morpheme.surface);
+                }
+                _ => clauses.push(Clause { reading: morpheme.reading, surface: morpheme.surface, start }),
+            }
+            start += length;
+        }
+        clauses
+    }
+}
+
+/// Which side of a `MORRSLT` holds the written form.
+#[derive(Clone, Copy)]
+enum Direction {
+    /// `FELANG_REQ_CONV`: the input is the reading, the output the surface.
+    Forward,
+    /// `FELANG_REQ_REV`: the input is the surface, the output the reading.
+    Reverse,
+}
+
+const WDD_PHRASE: u16 = 0x1;
+const WDD_UNKNOWN: u16 = 0x10;
+
+/// A `MORRSLT` allocated by the engine, freed with `CoTaskMemFree`.
+struct MorphResult(*mut MORRSLT);
+
+impl MorphResult {
+    // MORRSLT and WDD are packed, so records are copied out with
+    // `read_unaligned` instead of referencing fields.
+    fn record(&self) -> Option<MORRSLT> {
+        (!self.0.is_null()).then(|| unsafe { self.0.read_unaligned() })
+    }
+
+    fn output(&self) -> String {
+        let Some(result) = self.record() else {
+            return String::new();
+        };
+
+        let (output, len) = (result.pwchOutput, result.cchOutput);
+        String::from_utf16_lossy(unsafe { wide(output.0, len) })
+    }
+
+    fn morphemes(&self, direction: Direction) -> Vec<Morpheme> {
+        let Some(result) = self.record() else {
+            return Vec::new();
+        };
+
+        let output = unsafe { wide(result.pwchOutput.0, result.cchOutput) };
+        let input = unsafe { wide(result.Anonymous1.pwchRead.0, result.Anonymous2.cchRead) };
+        let (words, count) = (result.pWDD, result.cWDD);
+        if words.is_null() || count <= 0 {
+            return Vec::new();
+        }
+
+        (0..count as usize)
+            .map(|i| {
+                let word: WDD = unsafe { words.add(i).read_unaligned() };
+                let output = slice(output, word.wDispPos, word.cchDisp);
+                let input = slice(input, unsafe { word.Anonymous1.wReadPos }, unsafe { word.Anonymous2.cchRead });
+                let (surface, reading) = match direction {
+                    Direction::Forward => (output, input),
+                    Direction::Reverse => (input, output),
+                };
+                let flags = word._bitfield;
+
+                Morpheme {
+                    surface,
+                    reading,
+                    pos: word.nPos,
+                    clause_start: flags & WDD_PHRASE != 0,
+                    unknown: flags & WDD_UNKNOWN != 0,
+                }
+            })
+            .collect()
+    }
+}
+
+unsafe fn wide<'a>(text: *const u16, len: u16) -> &'a [u16] {
+    if text.is_null() {
+        return &[];
+    }
+
+    unsafe { std::slice::from_raw_parts(text, len as usize) }
+}
+
+fn slice(text: &[u16], start: u16, len: u16) -> String {
+    let start = (start as usize).min(text.len());
+    let end = (start + len as usize).min(text.len());
+    String::from_utf16_lossy(&text[start..end])
+}
+
+impl Drop for MorphResult {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { CoTaskMemFree(Some(self.0 as *const _)) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::UI::Input::Ime::{JPOS_CHIMEI_KEN, JPOS_MEISHI_FUTSU};
+
+    use super::*;
+
+    fn morpheme(pos: u32) -> Morpheme {
+        Morpheme { surface: String::new(), reading: String::new(), pos: pos as u16, clause_start: true, unknown: false }
+    }
+
+    #[test]
+    fn annotates_proper_nouns_and_symbols() {
+        let cases = [
+            (JPOS_JINMEI, Some("人名")),
+            (JPOS_JINMEI_SEI, Some("姓")),
+            (JPOS_JINMEI_MEI, Some("名")),
+            (JPOS_CHIMEI, Some("地名")),
+            (JPOS_CHIMEI_KEN, Some("地名")),
+            (JPOS_CHIMEI_MURA, Some("地名")),
+            (JPOS_CHIMEI_EKI, Some("駅名")),
+            (JPOS_SHAMEI, Some("社名")),
+            (JPOS_SOSHIKI, Some("組織名")),
+            (JPOS_TANKANJI, Some("単漢字")),
+            (JPOS_TANKANJI_KAO, Some("顔文字")),
+            (JPOS_MEISHI_FUTSU, None),
+        ];
+        for (pos, annotation) in cases {
+            assert_eq!(morpheme(pos).annotation(), annotation, "pos {pos}");
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{error::Result, felang::Morpheme, kana};
+
+/// What a [`Segment`] of text consists of.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SegmentKind {
+    /// Kanji, including `々` and `〆`; the reading comes from the engine.
+    Kanji,
+    /// Hiragana or katakana, passed through as its own reading.
+    Kana,
+    /// Anything else (ASCII, punctuation, symbols), passed through.
+    Other,
+}
+
+/// A run of text of one kind together with its reading.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub surface: String,
+    pub reading: String,
+    /// Offset of `surface` in the source text, in chars.
+    pub start: usize,
+    /// Offset of `reading` in [`Reading::text`], in chars.
+    pub reading_start: usize,
+}
+
+/// The reading of a whole text, aligned segment by segment.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Reading {
+    pub text: String,
+    pub segments: Vec<Segment>,
+}
+
+/// Whether `c` is read through the engine.
+pub fn is_kanji(c: char) -> bool {
+    matches!(c,
+        '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FFFF}' | '々' | '〆' | 'ヶ'
+    )
+}
+
+fn kind_of(c: char) -> SegmentKind {
+    if is_kanji(c) {
+        SegmentKind::Kanji
+    } else if kana::is_kana(c) {
+        SegmentKind::Kana
+    } else {
+        SegmentKind::Other
+    }
+}
+
+/// Splits `text` into runs of the same [`SegmentKind`].
+pub fn split(text: &str) -> Vec<(SegmentKind, String)> {
+    let mut runs: Vec<(SegmentKind, String)> = Vec::new();
+    for c in text.chars() {
+        let kind = kind_of(c);
+        match runs.last_mut() {
+            Some((last, run)) if *last == kind => run.push(c),
+            _ => runs.push((kind, c.to_string())),
+        }
+    }
+    runs
+}
+
+/// Builds the reading of mixed text, asking `lookup` only for kanji runs.
+///
+/// Each kanji run is looked up together with the hiragana following it, so
+/// that okurigana disambiguate the reading (`食べる`, not `食`); the
+/// okurigana is then stripped again. If the reading does not end with it,
+/// the run is looked up on its own.
+pub fn extract_reading(text: &str, mut lookup: impl FnMut(&str) -> Result<String>) -> Result<Reading> {
+    let runs = split(text);
+    let mut reading = Reading::default();
+    let mut start = 0;
+
+    for (i, (kind, surface)) in runs.iter().enumerate() {
+        let segment_reading = match kind {
+            SegmentKind::Kanji => {
+                let okurigana: String = runs
+                    .get(i + 1)
+                    .filter(|(kind, _)| *kind == SegmentKind::Kana)
+                    .map(|(_, run)| run.chars().take_while(|&c| kana::is_hiragana(c)).collect())
+                    .unwrap_or_default();
+
+                let with_okurigana = if okurigana.is_empty() {
+                    None
+                } else {
+                    lookup(&format!("{surface}{okurigana}"))
+                        .ok()
+                        .and_then(|reading| reading.strip_suffix(okurigana.as_str()).map(str::to_string))
+                        .filter(|reading| !reading.is_empty())
+                };
+
+                match with_okurigana {
+                    Some(reading) => reading,
+                    None => lookup(surface)?,
+                }
+            }
+            SegmentKind::Kana | SegmentKind::Other => surface.clone(),
+        };
+
+        reading.segments.push(Segment {
+            kind: *kind,
+            surface: surface.clone(),
+            reading: segment_reading.clone(),
+            start,
+            reading_start: reading.text.chars().count(),
+        });
+        reading.text.push_str(&segment_reading);
+        start += surface.chars().count();
+    }
+
+    Ok(reading)
+}
+
+/// A piece of text and, for kanji, the reading to print above it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Ruby {
+    pub base: String,
+    pub ruby: Option<String>,
+}
+
+impl Ruby {
+    fn plain(base: impl Into<String>) -> Self {
+        Self { base: base.into(), ruby: None }
+    }
+
+    fn annotated(base: impl Into<String>, ruby: impl Into<String>) -> Self {
+        Self { base: base.into(), ruby: Some(ruby.into()) }
+    }
+}
+
+/// Aligns `surface` with its full `reading`, splitting out okurigana and
+/// any other kana so that only kanji carry ruby: `食べる`/`たべる` gives
+/// `食[た]べる`, `取り扱い`/`とりあつかい` gives `取[と]り扱[あつか]い`.
+///
+/// When the kana in `surface` cannot be matched against `reading`, the
+/// whole surface is annotated with the whole reading.
+pub fn align(surface: &str, reading: &str) -> Vec<Ruby> {
+    let runs = split(surface);
+    if runs.iter().all(|(kind, _)| *kind != SegmentKind::Kanji) {
+        return vec![Ruby::plain(surface)];
+    }
+
+    let reading: Vec<char> = kana::to_hiragana(reading).chars().collect();
+    match match_runs(&runs, &reading) {
+        Some(readings) => {
+            let mut readings = readings.into_iter();
+            runs.into_iter()
+                .map(|(kind, run)| match kind {
+                    SegmentKind::Kanji => Ruby::annotated(run, readings.next().unwrap_or_default()),
+                    SegmentKind::Kana | SegmentKind::Other => Ruby::plain(run),
+                })
+                .collect()
+        }
+        None => vec![Ruby::annotated(surface, reading.into_iter().collect::<String>())],
+    }
+}
+
+/// Aligns each morpheme of an analysis; see [`align`].
+pub fn align_morphemes(morphemes: &[Morpheme]) -> Vec<Ruby> {
+    morphemes.iter().flat_map(|morpheme| align(&morpheme.surface, &morpheme.reading)).collect()
+}
+
+/// Turns the segments of [`extract_reading`] into ruby. Kanji segments
+/// already exclude their okurigana, so no further alignment is needed.
+pub fn align_reading(reading: &Reading) -> Vec<Ruby> {
+    reading
+        .segments
+        .iter()
+        .map(|segment| match segment.kind {
+            SegmentKind::Kanji => Ruby::annotated(segment.surface.as_str(), segment.reading.as_str()),
+            SegmentKind::Kana | SegmentKind::Other => Ruby::plain(segment.surface.as_str()),
+        })
+        .collect()
+}
+
+/// Matches `runs` against `reading`, where kana and other runs must appear
+/// literally and each kanji run consumes at least one character. Returns
+/// the reading of every kanji run, in order.
+fn match_runs(runs: &[(SegmentKind, String)], reading: &[char]) -> Option<Vec<String>> {
+    let Some(((kind, run), rest)) = runs.split_first() else {
+        return reading.is_empty().then(Vec::new);
+    };
+
+    if *kind != SegmentKind::Kanji {
+        let literal: Vec<char> = kana::to_hiragana(run).chars().collect();
+        return reading.strip_prefix(literal.as_slice()).and_then(|reading| match_runs(rest, reading));
+    }
+
+    // Shortest first, so the following literal anchors as early as possible.
+    let lengths = if rest.is_empty() { reading.len()..=reading.len() } else { 1..=reading.len() };
+    lengths.filter(|&len| len > 0).find_map(|len| {
+        let mut readings = match_runs(rest, &reading[len..])?;
+        readings.insert(0, reading[..len].iter().collect());
+        Some(readings)
+    })
+}
+
+/// Markup produced by [`render`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum RubyFormat {
+    /// `<ruby>漢字<rp>(</rp><rt>かんじ</rt><rp>)</rp></ruby>`, with text
+    /// HTML-escaped.
+    #[default]
+    Html,
+    /// Anki's `漢字[かんじ]`. A space is inserted before annotated text
+    /// that does not start the output, since Anki uses it to find where the
+    /// base begins.
+    Anki,
+    /// `漢字(かんじ)`
+    Parenthesized,
+}
+
+/// Renders aligned ruby as markup.
+pub fn render(rubies: &[Ruby], format: RubyFormat) -> String {
+    let mut output = String::new();
+    for ruby in rubies {
+        match (format, &ruby.ruby) {
+            (RubyFormat::Html, Some(reading)) => {
+                output.push_str("<ruby>");
+                push_escaped(&mut output, &ruby.base);
+                output.push_str("<rp>(</rp><rt>");
+                push_escaped(&mut output, reading);
+                output.push_str("</rt><rp>)</rp></ruby>");
+            }
+            (RubyFormat::Html, None) => push_escaped(&mut output, &ruby.base),
+            (RubyFormat::Anki, Some(reading)) => {
+                if !output.is_empty() && !output.ends_with(' ') {
+                    output.push(' ');
+                }
+                output.push_str(&format!("{}[{reading}]", ruby.base));
+            }
+            (RubyFormat::Parenthesized, Some(reading)) => output.push_str(&format!("{}({reading})", ruby.base)),
+            (RubyFormat::Anki | RubyFormat::Parenthesized, None) => output.push_str(&ruby.base),
+        }
+    }
+    output
+}
+
+fn push_escaped(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            _ => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TsfError;
+
+    /// Looks readings up in `readings`, failing for anything else.
+    fn dictionary<'a>(readings: &'a [(&str, &str)]) -> impl FnMut(&str) -> Result<String> + 'a {
+        |text| readings.iter().find(|(surface, _)| *surface == text).map(|(_, reading)| reading.to_string()).ok_or(TsfError::NoReading)
+    }
+
+    #[test]
+    fn splits_runs_by_kind() {
+        assert_eq!(
+            split("東京タワーへ行く!"),
+            [
+                (SegmentKind::Kanji, "東京".to_string()),
+                (SegmentKind::Kana, "タワーへ".to_string()),
+                (SegmentKind::Kanji, "行".to_string()),
+                (SegmentKind::Kana, "く".to_string()),
+                (SegmentKind::Other, "!".to_string()),
+            ]
+        );
+        assert!(is_kanji('々') && is_kanji('ヶ') && is_kanji('\u{20B9F}') && !is_kanji('ヵ'));
+    }
+
+    #[test]
+    fn extracts_readings_with_okurigana() {
+        let Ok(reading) = extract_reading("食べる本", dictionary(&[("食べる", "たべる"), ("本", "ほん")])) else {
+            panic!("lookup failed");
+        };
+        assert_eq!(reading.text, "たべるほん");
+        assert_eq!(
+            reading.segments,
+            [
+                Segment { kind: SegmentKind::Kanji, surface: "食".to_string(), reading: "た".to_string(), start: 0, reading_start: 0 },
+                Segment { kind: SegmentKind::Kana, surface: "べる".to_string(), reading: "べる".to_string(), start: 1, reading_start: 1 },
+                Segment { kind: SegmentKind::Kanji, surface: "本".to_string(), reading: "ほん".to_string(), start: 3, reading_start: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn looks_kanji_up_alone_when_okurigana_does_not_match() {
+        let reading = extract_reading("見るA", dictionary(&[("見る", "ミル"), ("見", "み")]));
+        assert!(matches!(reading, Ok(Reading { text, .. }) if text == "みるA"));
+        assert!(matches!(extract_reading("見る", dictionary(&[])), Err(TsfError::NoReading)));
+    }
+
+    #[test]
+    fn aligns_kanji_between_kana() {
+        assert_eq!(align("食べる", "たべる"), [Ruby::annotated("食", "た"), Ruby::plain("べる")]);
+        assert_eq!(align("取り扱い", "とりあつかい"), [Ruby::annotated("取", "と"), Ruby::plain("り"), Ruby::annotated("扱", "あつか"), Ruby::plain("い")]);
+        assert_eq!(align("お茶", "おちゃ"), [Ruby::plain("お"), Ruby::annotated("茶", "ちゃ")]);
+        assert_eq!(align("東京", "トウキョウ"), [Ruby::annotated("東京", "とうきょう")]);
+    }
+
+    #[test]
+    fn leaves_kana_alone_and_falls_back_to_the_whole_word() {
+        assert_eq!(align("ひらがな", "ひらがな"), [Ruby::plain("ひらがな")]);
+        assert_eq!(align("食べる", "のむ"), [Ruby::annotated("食べる", "のむ")]);
+        assert_eq!(align("食べる", "べる"), [Ruby::annotated("食べる", "べる")]);
+    }
+
+    #[test]
+    fn aligns_extracted_readings_by_segment() {
+        let reading = extract_reading("食べる本", dictionary(&[("食べる", "たべる"), ("本", "ほん")]));
+        let rubies = reading.map(|reading| align_reading(&reading)).unwrap_or_default();
+        assert_eq!(rubies, [Ruby::annotated("食", "た"), Ruby::plain("べる"), Ruby::annotated("本", "ほん")]);
+    }
+
+    #[test]
+    fn renders_every_format() {
+        let rubies = [Ruby::plain("「"), Ruby::annotated("食", "た"), Ruby::plain("べる"), Ruby::annotated("本", "ほん"), Ruby::plain("」")];
+        assert_eq!(render(&rubies, RubyFormat::Html), "「<ruby>食<rp>(</rp><rt>た</rt><rp>)</rp></ruby>べる<ruby>本<rp>(</rp><rt>ほん</rt><rp>)</rp></ruby>」");
+        assert_eq!(render(&rubies, RubyFormat::Anki), "「 食[た]べる 本[ほん]」");
+        assert_eq!(render(&rubies, RubyFormat::Parenthesized), "「食(た)べる本(ほん)」");
+    }
+
+    #[test]
+    fn anki_needs_no_space_at_the_start_or_after_one() {
+        assert_eq!(render(&[Ruby::annotated("漢字", "かんじ")], RubyFormat::Anki), "漢字[かんじ]");
+        assert_eq!(render(&[Ruby::plain("a "), Ruby::annotated("字", "じ")], RubyFormat::Anki), "a 字[じ]");
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(render(&[Ruby::plain("<a & \"b\">"), Ruby::annotated("字", "<じ>")], RubyFormat::Html), "&lt;a &amp; &quot;b&quot;&gt;<ruby>字<rp>(</rp><rt>&lt;じ&gt;</rt><rp>)</rp></ruby>");
+    }
+}
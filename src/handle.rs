@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use crate::{
+    cancel::CancellationToken,
+    error::Result,
+    events::{EventHub, EventReceiver},
+    service::{RequestOptions, TsfService},
+};
+
+/// A cloneable, `Send + Sync` handle to a TSF worker thread.
+///
+/// All clones talk to the same worker, which owns the `!Send` COM objects;
+/// the last clone to be dropped shuts the worker down.
+#[derive(Clone)]
+pub struct TsfHandle {
+    service: Arc<TsfService>,
+}
+
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<TsfHandle>;
+};
+
+impl TsfHandle {
+    pub fn spawn() -> Result<Self> {
+        Ok(Self::from_service(TsfService::spawn()?))
+    }
+
+    pub fn from_service(service: TsfService) -> Self {
+        Self { service: Arc::new(service) }
+    }
+
+    pub fn convert(&self, reading: &str) -> Result<Vec<String>> {
+        self.service.convert(reading)
+    }
+
+    pub fn convert_cancellable(&self, reading: &str, token: &CancellationToken) -> Result<Vec<String>> {
+        self.service.convert_cancellable(reading, token)
+    }
+
+    pub fn convert_with(&self, reading: &str, options: &RequestOptions) -> Result<Vec<String>> {
+        self.service.convert_with(reading, options)
+    }
+
+    pub fn set_text(&self, text: &str) -> Result<()> {
+        self.service.set_text(text)
+    }
+
+    pub fn set_text_cancellable(&self, text: &str, token: &CancellationToken) -> Result<()> {
+        self.service.set_text_cancellable(text, token)
+    }
+
+    pub fn set_text_with(&self, text: &str, options: &RequestOptions) -> Result<()> {
+        self.service.set_text_with(text, options)
+    }
+
+    pub fn events(&self) -> &EventHub {
+        self.service.events()
+    }
+
+    pub fn subscribe(&self) -> EventReceiver {
+        self.service.subscribe()
+    }
+
+    pub fn service(&self) -> &TsfService {
+        &self.service
+    }
+}
@@ -0,0 +1,39 @@
+//! Liveness checks for long-running services; see
+//! [`crate::service::TsfService::health`].
+
+use std::time::Duration;
+
+use crate::error::TsfError;
+
+/// Outcome of one probe.
+#[derive(Debug)]
+pub enum Check {
+    Passed { elapsed: Duration },
+    Failed(TsfError),
+    /// No answer within the deadline.
+    TimedOut,
+    /// Not attempted because an earlier probe did not pass.
+    Skipped,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        matches!(self, Check::Passed { .. })
+    }
+}
+
+#[derive(Debug)]
+pub struct HealthReport {
+    /// The worker thread has not exited.
+    pub worker_alive: bool,
+    /// A message posted to the worker's queue was handled.
+    pub message_pump: Check,
+    /// A synchronous read-only edit session completed.
+    pub edit_session: Check,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.worker_alive && self.message_pump.passed() && self.edit_session.passed()
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::Instant;
+
+use windows::Win32::{
+    Globalization::HIMC,
+    UI::{
+        Input::{
+            Ime::{ImmCreateContext, ImmDestroyContext, ImmGetConversionListW, ImmIsIME, CANDIDATELIST, GCL_CONVERSION, GCL_REVERSECONVERSION, GET_CONVERSION_LIST_FLAG},
+            KeyboardAndMouse::GetKeyboardLayout,
+        },
+        TextServices::HKL,
+    },
+};
+use windows_core::PCWSTR;
+
+use crate::{
+    affinity::ThreadAffinity,
+    engine::{Candidate, Capabilities, Engine, EngineKind},
+    error::{hresult, Result, TsfError},
+    metrics,
+    trace::{debug, warn},
+};
+
+/// Conversion through `ImmGetConversionListW` on a keyboard layout.
+///
+/// The last resort when neither TSF reconversion nor `IFELanguage` is
+/// available. Owns a private input context, destroyed when dropped.
+pub struct Imm32 {
+    layout: HKL,
+    context: HIMC,
+    affinity: ThreadAffinity,
+}
+
+impl Imm32 {
+    /// Uses the keyboard layout active on the current thread.
+    pub fn new() -> Result<Self> {
+        Self::with_layout(unsafe { GetKeyboardLayout(0) })
+    }
+
+    pub fn with_layout(layout: HKL) -> Result<Self> {
+        debug!("Opening IMM32 conversion on layout {:?}", layout);
+        let context = unsafe { ImmCreateContext() };
+        if context.is_invalid() {
+            return Err(hresult("ImmCreateContext")(windows_core::Error::from_win32()));
+        }
+
+        Ok(Self { layout, context, affinity: ThreadAffinity::current() })
+    }
+
+    pub fn layout(&self) -> HKL {
+        self.layout
+    }
+
+    /// Candidates for a kana reading (`GCL_CONVERSION`).
+    pub fn conversion_list(&self, reading: &str) -> Result<Vec<String>> {
+        self.list(reading, GCL_CONVERSION)
+    }
+
+    /// Readings for converted text (`GCL_REVERSECONVERSION`).
+    pub fn reverse_conversion_list(&self, text: &str) -> Result<Vec<String>> {
+        self.list(text, GCL_REVERSECONVERSION)
+    }
+
+    fn list(&self, source: &str, flag: GET_CONVERSION_LIST_FLAG) -> Result<Vec<String>> {
+        self.affinity.check()?;
+        if source.is_empty() {
+            return Err(TsfError::InvalidArgument("source text is empty"));
+        }
+
+        let source: Vec<u16> = source.encode_utf16().chain(Some(0)).collect();
+        let source = PCWSTR(source.as_ptr());
+
+        // The first call reports the buffer size in bytes.
+        let size = unsafe { ImmGetConversionListW(self.layout, self.context, source, std::ptr::null_mut(), 0, flag) };
+        if size == 0 {
+            return Err(hresult("ImmGetConversionListW")(windows_core::Error::from_win32()));
+        }
+
+        // u32 storage keeps the CANDIDATELIST header aligned.
+        let mut buffer = vec![0u32; (size as usize).div_ceil(4)];
+        let list = buffer.as_mut_ptr() as *mut CANDIDATELIST;
+        let written = unsafe { ImmGetConversionListW(self.layout, self.context, source, list, size, flag) };
+        if written == 0 {
+            return Err(hresult("ImmGetConversionListW")(windows_core::Error::from_win32()));
+        }
+
+        Ok(unsafe { read_candidates(list, written as usize) })
+    }
+}
+
+impl Engine for Imm32 {
+    fn kind(&self) -> EngineKind {
+        EngineKind::Imm32
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Plain keyboard layouts answer every conversion list request with
+        // nothing.
+        let ime = unsafe { ImmIsIME(self.layout) }.as_bool();
+        Capabilities { supports_reconversion: ime, supports_reading: ime, ..Capabilities::default() }
+    }
+
+    fn convert(&self, reading: &str, max_candidates: Option<usize>) -> Result<Vec<Candidate>> {
+        let started = Instant::now();
+        // The IME hands over the whole list at once.
+        let result = self.conversion_list(reading).map(|candidates| candidates.into_iter().take(max_candidates.unwrap_or(usize::MAX)).map(Candidate::from).collect());
+        metrics::record_conversion(EngineKind::Imm32, started.elapsed(), &result);
+        result
+    }
+
+    fn reading(&self, text: &str) -> Result<String> {
+        self.reverse_conversion_list(text)?.into_iter().next().ok_or(TsfError::NoReading)
+    }
+}
+
+impl Drop for Imm32 {
+    fn drop(&mut self) {
+        if !unsafe { ImmDestroyContext(self.context) }.as_bool() {
+            warn!("Failed to destroy IMM32 input context");
+        }
+    }
+}
+
+/// Reads the strings of a `CANDIDATELIST` occupying `size` bytes. Offsets
+/// are relative to the start of the list and each string is NUL-terminated.
+unsafe fn read_candidates(list: *const CANDIDATELIST, size: usize) -> Vec<String> {
+    let base = list as *const u8;
+    let table = std::mem::offset_of!(CANDIDATELIST, dwOffset);
+    if size < table {
+        return Vec::new();
+    }
+    // A corrupt count must not walk the offset table past the buffer.
+    let count = (unsafe { (*list).dwCount } as usize).min((size - table) / 4);
+    let offsets = unsafe { std::ptr::addr_of!((*list).dwOffset) } as *const u32;
+
+    (0..count)
+        .filter_map(|i| {
+            let offset = unsafe { *offsets.add(i) } as usize;
+            if offset >= size {
+                return None;
+            }
+
+            let start = unsafe { base.add(offset) } as *const u16;
+            let max = (size - offset) / 2;
+            let len = (0..max).take_while(|&n| unsafe { start.add(n).read_unaligned() } != 0).count();
+            let units: Vec<u16> = (0..len).map(|n| unsafe { start.add(n).read_unaligned() }).collect();
+            Some(String::from_utf16_lossy(&units))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidate_count_is_bounded_by_the_buffer() {
+        // Header, one offset and "あ\0"; the count claims far more entries.
+        let mut buffer = [0u32; 8];
+        buffer[0] = 32;
+        buffer[2] = 1000;
+        buffer[6] = 28;
+        buffer[7] = 0x3042;
+
+        let candidates = unsafe { read_candidates(buffer.as_ptr() as *const CANDIDATELIST, 32) };
+        assert_eq!(candidates, ["あ"]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Input scopes tell the input processor what kind of text the document
+//! holds, so that it can pick a fitting input mode and candidates, e.g.
+//! hiragana for a name's reading or full-width digits for a number.
+//!
+//! TSF reads them from the text store as the `GUID_PROP_INPUTSCOPE`
+//! attribute, whose value is an `ITfInputScope`. Scopes set on a range of
+//! the document take precedence over the document's own, so that a field
+//! mixing a name and a number can declare both.
+
+use std::ops::Range;
+
+use windows::Win32::{
+    Foundation::{E_NOTIMPL, E_OUTOFMEMORY, E_POINTER},
+    System::Com::CoTaskMemAlloc,
+    UI::TextServices::{self as ts, ITfInputScope, ITfInputScope_Impl},
+};
+use windows_core::{implement, BSTR};
+
+/// An input scope, as listed in `InputScope.h`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputScope {
+    Default,
+    Text,
+    Url,
+    EmailAddress,
+    PersonalName,
+    Surname,
+    GivenName,
+    PostalCode,
+    TelephoneNumber,
+    Number,
+    NumberFullWidth,
+    Digits,
+    AlphanumericHalfWidth,
+    AlphanumericFullWidth,
+    Hiragana,
+    KatakanaHalfWidth,
+    KatakanaFullWidth,
+    /// Readings of Japanese text.
+    Yomi,
+    Password,
+    Search,
+    /// Any other `IS_*` value.
+    Other(i32),
+}
+
+impl InputScope {
+    pub fn to_raw(self) -> ts::InputScope {
+        match self {
+            Self::Default => ts::IS_DEFAULT,
+            Self::Text => ts::IS_TEXT,
+            Self::Url => ts::IS_URL,
+            Self::EmailAddress => ts::IS_EMAIL_SMTPEMAILADDRESS,
+            Self::PersonalName => ts::IS_PERSONALNAME_FULLNAME,
+            Self::Surname => ts::IS_PERSONALNAME_SURNAME,
+            Self::GivenName => ts::IS_PERSONALNAME_GIVENNAME,
+            Self::PostalCode => ts::IS_ADDRESS_POSTALCODE,
+            Self::TelephoneNumber => ts::IS_TELEPHONE_FULLTELEPHONENUMBER,
+            Self::Number => ts::IS_NUMBER,
+            Self::NumberFullWidth => ts::IS_NUMBER_FULLWIDTH,
+            Self::Digits => ts::IS_DIGITS,
+            Self::AlphanumericHalfWidth => ts::IS_ALPHANUMERIC_HALFWIDTH,
+            Self::AlphanumericFullWidth => ts::IS_ALPHANUMERIC_FULLWIDTH,
+            Self::Hiragana => ts::IS_HIRAGANA,
+            Self::KatakanaHalfWidth => ts::IS_KATAKANA_HALFWIDTH,
+            Self::KatakanaFullWidth => ts::IS_KATAKANA_FULLWIDTH,
+            Self::Yomi => ts::IS_YOMI,
+            Self::Password => ts::IS_PASSWORD,
+            Self::Search => ts::IS_SEARCH,
+            Self::Other(value) => ts::InputScope(value),
+        }
+    }
+
+    pub fn from_raw(scope: ts::InputScope) -> Self {
+        match scope {
+            ts::IS_DEFAULT => Self::Default,
+            ts::IS_TEXT => Self::Text,
+            ts::IS_URL => Self::Url,
+            ts::IS_EMAIL_SMTPEMAILADDRESS => Self::EmailAddress,
+            ts::IS_PERSONALNAME_FULLNAME => Self::PersonalName,
+            ts::IS_PERSONALNAME_SURNAME => Self::Surname,
+            ts::IS_PERSONALNAME_GIVENNAME => Self::GivenName,
+            ts::IS_ADDRESS_POSTALCODE => Self::PostalCode,
+            ts::IS_TELEPHONE_FULLTELEPHONENUMBER => Self::TelephoneNumber,
+            ts::IS_NUMBER => Self::Number,
+            ts::IS_NUMBER_FULLWIDTH => Self::NumberFullWidth,
+            ts::IS_DIGITS => Self::Digits,
+            ts::IS_ALPHANUMERIC_HALFWIDTH => Self::AlphanumericHalfWidth,
+            ts::IS_ALPHANUMERIC_FULLWIDTH => Self::AlphanumericFullWidth,
+            ts::IS_HIRAGANA => Self::Hiragana,
+            ts::IS_KATAKANA_HALFWIDTH => Self::KatakanaHalfWidth,
+            ts::IS_KATAKANA_FULLWIDTH => Self::KatakanaFullWidth,
+            ts::IS_YOMI => Self::Yomi,
+            ts::IS_PASSWORD => Self::Password,
+            ts::IS_SEARCH => Self::Search,
+            ts::InputScope(value) => Self::Other(value),
+        }
+    }
+}
+
+/// The input scopes of a document and of ranges of it, in ACPs.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScopeMap {
+    document: Vec<InputScope>,
+    /// Sorted and disjoint.
+    ranges: Vec<(Range<i32>, Vec<InputScope>)>,
+}
+
+impl ScopeMap {
+    pub(crate) fn document(&self) -> &[InputScope] {
+        &self.document
+    }
+
+    pub(crate) fn set_document(&mut self, scopes: &[InputScope]) {
+        self.document = scopes.to_vec();
+    }
+
+    /// Sets the scopes of `range`, replacing those of any range it overlaps.
+    /// No scopes leave `range` with the document's.
+    pub(crate) fn set_range(&mut self, range: Range<i32>, scopes: &[InputScope]) {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 2);
+        for (existing, existing_scopes) in self.ranges.drain(..) {
+            if existing.end <= range.start || existing.start >= range.end {
+                ranges.push((existing, existing_scopes));
+                continue;
+            }
+            if existing.start < range.start {
+                ranges.push((existing.start..range.start, existing_scopes.clone()));
+            }
+            if existing.end > range.end {
+                ranges.push((range.end..existing.end, existing_scopes));
+            }
+        }
+        if !range.is_empty() && !scopes.is_empty() {
+            ranges.push((range, scopes.to_vec()));
+        }
+        ranges.sort_by_key(|(range, _)| range.start);
+        self.ranges = ranges;
+    }
+
+    pub(crate) fn clear_ranges(&mut self) {
+        self.ranges.clear();
+    }
+
+    /// The scopes of the character starting at `acp`.
+    pub(crate) fn at(&self, acp: i32) -> &[InputScope] {
+        self.ranges
+            .iter()
+            .find(|(range, _)| range.contains(&acp))
+            .map_or(&self.document, |(_, scopes)| scopes)
+    }
+
+    /// Whether the scopes before and after `acp` differ.
+    pub(crate) fn is_transition(&self, acp: i32) -> bool {
+        acp > 0 && self.at(acp - 1) != self.at(acp)
+    }
+
+    /// The smallest range of a document of `len` ACPs holding every
+    /// character whose scopes differ between `self` and `other`.
+    pub(crate) fn changed(&self, other: &ScopeMap, len: i32) -> Option<Range<i32>> {
+        let mut boundaries: Vec<i32> = [0, len]
+            .into_iter()
+            .chain(self.ranges.iter().chain(&other.ranges).flat_map(|(range, _)| [range.start, range.end]))
+            .filter(|acp| (0..=len).contains(acp))
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        if len == 0 {
+            return (self.at(0) != other.at(0)).then_some(0..0);
+        }
+        let changed: Vec<_> = boundaries.windows(2).filter(|span| self.at(span[0]) != other.at(span[0])).map(|span| span[0]..span[1]).collect();
+        Some(changed.first()?.start..changed.last()?.end)
+    }
+
+    /// The first transition after `start`, up to and including `halt`, or
+    /// the last one before `start`, down to `halt`, when searching
+    /// `backwards`.
+    pub(crate) fn find_transition(&self, start: i32, halt: i32, backwards: bool) -> Option<i32> {
+        let boundaries = self.ranges.iter().flat_map(|(range, _)| [range.start, range.end]).filter(|&acp| self.is_transition(acp));
+        if backwards {
+            boundaries.filter(|&acp| acp < start && acp >= halt).max()
+        } else {
+            boundaries.filter(|&acp| acp > start && acp <= halt).min()
+        }
+    }
+}
+
+/// The `ITfInputScope` handed to TSF for a list of scopes.
+#[implement(ITfInputScope)]
+pub(crate) struct InputScopes {
+    scopes: Vec<InputScope>,
+}
+
+impl InputScopes {
+    pub(crate) fn create(scopes: &[InputScope]) -> ITfInputScope {
+        Self { scopes: scopes.to_vec() }.into()
+    }
+}
+
+impl ITfInputScope_Impl for InputScopes {
+    fn GetInputScopes(&self, pprginputscopes: *mut *mut ts::InputScope, pccount: *mut u32) -> windows_core::Result<()> {
+        if pprginputscopes.is_null() || pccount.is_null() {
+            return Err(E_POINTER.into());
+        }
+
+        let scopes = unsafe { CoTaskMemAlloc(std::mem::size_of::<ts::InputScope>() * self.scopes.len().max(1)) } as *mut ts::InputScope;
+        if scopes.is_null() {
+            return Err(E_OUTOFMEMORY.into());
+        }
+        for (i, scope) in self.scopes.iter().enumerate() {
+            unsafe { scopes.add(i).write(scope.to_raw()) };
+        }
+
+        unsafe {
+            *pprginputscopes = scopes;
+            *pccount = self.scopes.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn GetPhrase(&self, _ppbstrphrases: *mut *mut BSTR, _pccount: *mut u32) -> windows_core::Result<()> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetRegularExpression(&self) -> windows_core::Result<BSTR> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetSRGS(&self) -> windows_core::Result<BSTR> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetXML(&self) -> windows_core::Result<BSTR> {
+        Err(E_NOTIMPL.into())
+    }
+}
@@ -0,0 +1,114 @@
+/// Distance between a hiragana code point and its katakana counterpart.
+const OFFSET: u32 = 0x60;
+
+const VOWELS: [(char, &str); 5] = [
+    ('あ', "あぁかがさざただなはばぱまやゃらわゎゕ"),
+    ('い', "いぃきぎしじちぢにひびぴみりゐ"),
+    ('う', "うぅくぐすずつづっぬふぶぷむゆゅるゔ"),
+    ('え', "えぇけげせぜてでねへべぺめれゑゖ"),
+    ('お', "おぉこごそぞとどのほぼぽもよょろを"),
+];
+
+/// Whether `c` is a hiragana letter or iteration mark.
+pub fn is_hiragana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{3096}' | 'ゝ' | 'ゞ')
+}
+
+/// Whether `c` is a katakana letter or iteration mark.
+pub fn is_katakana(c: char) -> bool {
+    matches!(c, '\u{30A1}'..='\u{30FA}' | 'ヽ' | 'ヾ')
+}
+
+/// Whether `c` is kana of either script, or the prolonged sound mark.
+pub fn is_kana(c: char) -> bool {
+    is_hiragana(c) || is_katakana(c) || c == 'ー'
+}
+
+pub fn hiragana_to_katakana(c: char) -> char {
+    if is_hiragana(c) {
+        char::from_u32(c as u32 + OFFSET).unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+/// Maps katakana to hiragana. `ヷ`–`ヺ` have no hiragana form and are kept.
+pub fn katakana_to_hiragana(c: char) -> char {
+    if is_katakana(c) && !matches!(c, 'ヷ'..='ヺ') {
+        char::from_u32(c as u32 - OFFSET).unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+/// Converts every hiragana character in `text` to katakana, including small
+/// kana and iteration marks. Everything else is left as is.
+pub fn to_katakana(text: &str) -> String {
+    text.chars().map(hiragana_to_katakana).collect()
+}
+
+/// Converts every katakana character in `text` to hiragana. The prolonged
+/// sound mark is kept; see [`expand_prolonged_sound`].
+pub fn to_hiragana(text: &str) -> String {
+    text.chars().map(katakana_to_hiragana).collect()
+}
+
+/// Replaces each `ー` with the vowel it lengthens, in the script of the
+/// preceding kana (`らーめん` → `らあめん`, `ラーメン` → `ラアメン`).
+/// Marks that do not follow a kana are kept.
+pub fn expand_prolonged_sound(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut previous = None;
+
+    for c in text.chars() {
+        let c = match (c, previous) {
+            ('ー', Some(previous)) => match vowel(previous) {
+                Some(vowel) if is_katakana(previous) => hiragana_to_katakana(vowel),
+                Some(vowel) => vowel,
+                None => c,
+            },
+            _ => c,
+        };
+        output.push(c);
+        previous = Some(c);
+    }
+
+    output
+}
+
+/// The vowel sound of a kana, as hiragana.
+fn vowel(c: char) -> Option<char> {
+    let c = katakana_to_hiragana(c);
+    VOWELS.iter().find(|(_, kana)| kana.contains(c)).map(|(vowel, _)| *vowel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_scripts() {
+        assert!(is_hiragana('ぁ') && is_hiragana('ゖ') && is_hiragana('ゞ'));
+        assert!(is_katakana('ァ') && is_katakana('ヺ') && is_katakana('ヾ'));
+        assert!(is_kana('ー') && !is_hiragana('ー') && !is_katakana('ー'));
+        assert!(!is_kana('漢') && !is_kana('a') && !is_kana('ｶ'));
+    }
+
+    #[test]
+    fn converts_between_scripts() {
+        assert_eq!(to_katakana("ひらがな、ゃゎゝ"), "ヒラガナ、ャヮヽ");
+        assert_eq!(to_hiragana("カタカナー、ヴァヾ"), "かたかなー、ゔぁゞ");
+        assert_eq!(to_hiragana("ヷヸヹヺ"), "ヷヸヹヺ");
+        assert_eq!(to_hiragana(&to_katakana("ぁゔゕゖ")), "ぁゔゕゖ");
+    }
+
+    #[test]
+    fn expands_prolonged_sounds_in_the_preceding_script() {
+        assert_eq!(expand_prolonged_sound("らーめん"), "らあめん");
+        assert_eq!(expand_prolonged_sound("ラーメン"), "ラアメン");
+        assert_eq!(expand_prolonged_sound("すーぱー"), "すうぱあ");
+        assert_eq!(expand_prolonged_sound("ふぇー"), "ふぇえ");
+        assert_eq!(expand_prolonged_sound("かーー"), "かああ");
+        assert_eq!(expand_prolonged_sound("ー漢ーんー"), "ー漢ーんー");
+    }
+}
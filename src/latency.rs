@@ -0,0 +1,199 @@
+//! Latency histograms for [`crate::service::TsfService`] requests.
+//!
+//! Each request is timed from submission to reply, so queueing behind other
+//! work on the worker counts. Percentiles are taken from log-linear buckets
+//! accurate to about 12%.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Sub-buckets per power of two.
+const SUB_BUCKETS: u64 = 8;
+const SUB_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+/// Durations of 2^40 µs (about 12 days) and more share the last bucket.
+const MAX_POWER: u32 = 40;
+const BUCKETS: usize = ((MAX_POWER - SUB_BITS + 2) as u64 * SUB_BUCKETS) as usize;
+
+/// A kind of service request.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Request {
+    Convert,
+    SetText,
+    Reading,
+    Segment,
+}
+
+/// Latency distribution of one kind of request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Latencies per kind of request since the service started or was last
+/// reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct LatencySnapshot {
+    pub convert: Percentiles,
+    pub set_text: Percentiles,
+    pub reading: Percentiles,
+    pub segment: Percentiles,
+}
+
+impl LatencySnapshot {
+    pub fn get(&self, request: Request) -> &Percentiles {
+        match request {
+            Request::Convert => &self.convert,
+            Request::SetText => &self.set_text,
+            Request::Reading => &self.reading,
+            Request::Segment => &self.segment,
+        }
+    }
+}
+
+struct Histogram {
+    buckets: Box<[AtomicU64]>,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self { buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(), max: AtomicU64::new(0) }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket(micros)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    fn percentiles(&self) -> Percentiles {
+        let counts: Vec<u64> = self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect();
+        let count = counts.iter().sum();
+        let max = self.max.load(Ordering::Relaxed);
+        let quantile = |q: f64| {
+            if count == 0 {
+                return Duration::ZERO;
+            }
+            let rank = ((q * count as f64).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (index, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    return Duration::from_micros(upper_bound(index).min(max));
+                }
+            }
+            Duration::from_micros(max)
+        };
+
+        Percentiles { count, p50: quantile(0.50), p95: quantile(0.95), p99: quantile(0.99), max: Duration::from_micros(max) }
+    }
+}
+
+fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    if micros >> MAX_POWER != 0 {
+        return BUCKETS - 1;
+    }
+    let power = 63 - micros.leading_zeros();
+    let sub = (micros >> (power - SUB_BITS)) & (SUB_BUCKETS - 1);
+    (((power - SUB_BITS + 1) as u64) * SUB_BUCKETS + sub) as usize
+}
+
+/// The largest duration, in microseconds, falling into bucket `index`.
+fn upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let power = (index / SUB_BUCKETS) as u32 + SUB_BITS - 1;
+    let sub = index % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub + 1) << (power - SUB_BITS)) - 1
+}
+
+/// One histogram per [`Request`].
+pub(crate) struct Latencies {
+    histograms: [Histogram; 4],
+}
+
+impl Latencies {
+    pub(crate) fn new() -> Self {
+        Self { histograms: [Histogram::new(), Histogram::new(), Histogram::new(), Histogram::new()] }
+    }
+
+    fn histogram(&self, request: Request) -> &Histogram {
+        &self.histograms[request as usize]
+    }
+
+    pub(crate) fn record(&self, request: Request, elapsed: Duration) {
+        self.histogram(request).record(elapsed);
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            convert: self.histogram(Request::Convert).percentiles(),
+            set_text: self.histogram(Request::SetText).percentiles(),
+            reading: self.histogram(Request::Reading).percentiles(),
+            segment: self.histogram(Request::Segment).percentiles(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for histogram in &self.histograms {
+            histogram.reset();
+        }
+    }
+}
+
+/// Measures durations, or reports every one as zero in test mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum Clock {
+    #[default]
+    Real,
+    #[cfg(feature = "test-utils")]
+    Frozen,
+}
+
+impl Clock {
+    pub(crate) fn elapsed(self, started: Instant) -> Duration {
+        match self {
+            Self::Real => started.elapsed(),
+            #[cfg(feature = "test-utils")]
+            Self::Frozen => Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_grow_with_the_duration() {
+        let buckets: Vec<usize> = [0, 7, 8, 15, 16, 1_000, 1_000_000].into_iter().map(bucket).collect();
+        assert!(buckets.is_sorted(), "{buckets:?}");
+        assert!(upper_bound(bucket(1_000)) >= 1_000);
+    }
+
+    #[test]
+    fn long_durations_share_the_last_bucket() {
+        assert_eq!(bucket(1 << 40), BUCKETS - 1);
+        assert_eq!(bucket((1 << 45) + 12_345), BUCKETS - 1);
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        assert!(bucket((1 << 40) - 1) < BUCKETS - 1);
+    }
+}
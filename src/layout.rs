@@ -0,0 +1,40 @@
+//! Where the document is on screen, for input processors placing their
+//! candidate and composition windows next to the text.
+//!
+//! The crate's document is not drawn, so the host describes a layout of its
+//! own through a [`LayoutProvider`] and reports when it moves; see
+//! [`crate::tsf::TSF::set_layout_provider`].
+
+use windows::Win32::{Foundation::RECT, UI::TextServices::{TsLayoutCode, TS_LC_CHANGE, TS_LC_CREATE, TS_LC_DESTROY}};
+
+/// The view cookie of the store's only view.
+pub const VIEW: u32 = 0;
+
+/// Screen geometry of the document, in screen coordinates.
+pub trait LayoutProvider: Send + Sync {
+    /// The rectangle bounding the text `start..end`, in ACPs, and whether it
+    /// is clipped, or `None` while the text is not laid out.
+    fn text_ext(&self, start: i32, end: i32) -> Option<(RECT, bool)>;
+
+    /// The rectangle of the whole view.
+    fn screen_ext(&self) -> Option<RECT>;
+}
+
+/// The kind of change reported through `OnLayoutChange`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum LayoutChangeKind {
+    Create,
+    /// The caret or the view moved, or the text was laid out again.
+    Change,
+    Destroy,
+}
+
+impl LayoutChangeKind {
+    pub fn to_raw(self) -> TsLayoutCode {
+        match self {
+            Self::Create => TS_LC_CREATE,
+            Self::Change => TS_LC_CHANGE,
+            Self::Destroy => TS_LC_DESTROY,
+        }
+    }
+}
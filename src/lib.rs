@@ -1,5 +1,66 @@
+pub mod acp;
+pub mod affinity;
+pub mod agent;
+pub mod agile;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "async")]
+pub mod async_tsf;
+pub mod diagnostics;
+pub mod dictionary;
+pub mod dump;
 mod edit_session;
+pub mod engine;
+#[cfg(feature = "test-utils")]
+pub mod fake_tip;
+pub mod environment;
+pub mod error;
+pub mod events;
+pub mod felang;
+pub mod furigana;
+pub mod handle;
+pub mod health;
+pub mod imm32;
+pub mod input_scope;
+pub mod kana;
+pub mod latency;
+pub mod layout;
+pub mod locking;
+pub mod metrics;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod normalize;
+pub mod notifications;
+pub mod numerals;
+pub mod owner_services;
 mod thread_mgr;
 pub mod tsf;
+pub mod cancel;
+pub mod clipboard;
 pub mod com;
-mod text_store;
\ No newline at end of file
+pub mod com_server;
+pub mod compat;
+mod context_owner;
+pub mod pool;
+mod profile;
+pub mod profiles;
+pub mod pump;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod region;
+mod registry;
+pub mod romaji;
+pub mod segmentation;
+pub mod service;
+mod sinks;
+pub mod store_trace;
+mod text_store;
+#[cfg(feature = "test-utils")]
+pub mod testing;
+#[cfg(feature = "test-utils")]
+pub mod test_mode;
+pub mod timeout;
+mod trace;
+pub mod width;
+pub mod window;
+pub use error::{Result, TsfError};
@@ -0,0 +1,145 @@
+//! The text store's lock, without the COM around it.
+//!
+//! [`LockState`] follows the rules of `ITextStoreACP::RequestLock`: a request
+//! made while the store is unlocked is granted at once; while it is locked,
+//! a `TS_LF_SYNC` request is refused and any other one queues until the
+//! holder releases. Locks are not upgraded in place: asking for
+//! `TS_LF_READWRITE` while `TS_LF_READ` is held queues like any other
+//! request.
+
+use std::collections::VecDeque;
+
+/// `TS_LF_SYNC`.
+pub const SYNC: u32 = 0x1;
+/// `TS_LF_READ`.
+pub const READ: u32 = 0x2;
+/// `TS_LF_READWRITE`.
+pub const READWRITE: u32 = 0x6;
+
+fn flag_check(value: u32, flag: u32) -> bool {
+    (value & flag) == flag
+}
+
+/// The store lock held, or held while a traced call ran.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "record", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "record", serde(rename_all = "lowercase"))]
+pub enum Lock {
+    #[default]
+    None,
+    Read,
+    ReadWrite,
+}
+
+impl Lock {
+    /// The lock a request for the `TS_LF_*` `flags` takes.
+    pub fn from_flags(flags: u32) -> Self {
+        if flag_check(flags, READWRITE) {
+            Lock::ReadWrite
+        } else if flag_check(flags, READ) {
+            Lock::Read
+        } else {
+            Lock::None
+        }
+    }
+}
+
+/// What became of a lock request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Request {
+    /// The lock is held for the caller, who must release it.
+    Granted,
+    /// The request waits behind the current holder (`TS_S_ASYNC`).
+    Queued,
+    /// A synchronous request found the store locked (`TS_E_SYNCHRONOUS`).
+    Refused,
+}
+
+/// The lock held on a store and the requests waiting for it, each carrying
+/// a `T` that says whom to grant it to.
+#[derive(Debug)]
+pub struct LockState<T> {
+    held: Lock,
+    flags: u32,
+    pending: VecDeque<(u32, T)>,
+}
+
+impl<T> Default for LockState<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> LockState<T> {
+    pub fn new() -> Self {
+        Self { held: Lock::None, flags: 0, pending: VecDeque::new() }
+    }
+
+    pub fn held(&self) -> Lock {
+        self.held
+    }
+
+    /// The flags the current lock was requested with.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Whether a lock covering every flag in `flags` is held.
+    pub fn is_locked(&self, flags: u32) -> bool {
+        self.held != Lock::None && flag_check(self.flags, flags)
+    }
+
+    /// Takes the lock if nobody holds it, without queueing.
+    pub fn acquire(&mut self, flags: u32) -> bool {
+        if self.held != Lock::None {
+            return false;
+        }
+
+        self.held = Lock::from_flags(flags);
+        self.flags = flags;
+        true
+    }
+
+    /// Handles `RequestLock(flags)`, queueing `waiter` when the request has
+    /// to wait.
+    pub fn request(&mut self, flags: u32, waiter: T) -> Request {
+        if self.acquire(flags) {
+            Request::Granted
+        } else if flag_check(flags, SYNC) {
+            Request::Refused
+        } else {
+            self.enqueue(flags, waiter);
+            Request::Queued
+        }
+    }
+
+    /// Queues a request behind the ones already waiting.
+    pub fn enqueue(&mut self, flags: u32, waiter: T) {
+        self.pending.push_back((flags, waiter));
+    }
+
+    /// Drops the current lock. Queued requests stay queued until
+    /// [`LockState::grant_next`] hands them the lock.
+    pub fn release(&mut self) {
+        self.held = Lock::None;
+        self.flags = 0;
+    }
+
+    /// Grants the oldest queued request if the store is unlocked, returning
+    /// its flags and waiter. The lock is then held for that waiter.
+    pub fn grant_next(&mut self) -> Option<(u32, T)> {
+        if self.held != Lock::None {
+            return None;
+        }
+
+        let (flags, waiter) = self.pending.pop_front()?;
+        self.held = Lock::from_flags(flags);
+        self.flags = flags;
+        Some((flags, waiter))
+    }
+
+    /// The number of queued requests.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
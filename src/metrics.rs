@@ -0,0 +1,143 @@
+//! Counters for hosts monitoring the component.
+//!
+//! Install one [`Metrics`] implementation per process with [`install`];
+//! until then every measurement is discarded. [`Counters`] is a ready-made
+//! implementation keeping totals in atomics.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use crate::{engine::EngineKind, events::TsfEvent, Result};
+
+/// Receives measurements from the conversion pipeline.
+///
+/// Methods are called on whichever thread did the work, often the TSF
+/// thread itself, so they must be cheap and must not block. Every method
+/// defaults to doing nothing.
+pub trait Metrics: Send + Sync {
+    /// `engine` converted a reading into `candidates` candidates.
+    fn conversion(&self, engine: EngineKind, candidates: usize, elapsed: Duration) {
+        let _ = (engine, candidates, elapsed);
+    }
+
+    /// `engine` failed to convert a reading.
+    fn conversion_failed(&self, engine: EngineKind) {
+        let _ = engine;
+    }
+
+    /// An [`crate::engine::EngineChain`] moved past `engine`, which failed
+    /// or had no answer.
+    fn engine_fallback(&self, engine: EngineKind) {
+        let _ = engine;
+    }
+
+    /// A lock was requested while the text store was already locked, and was
+    /// refused (`synchronous`) or queued.
+    fn lock_contended(&self, synchronous: bool) {
+        let _ = synchronous;
+    }
+
+    /// A sink raised `event`.
+    fn event(&self, event: &TsfEvent) {
+        let _ = event;
+    }
+}
+
+struct Discard;
+
+impl Metrics for Discard {}
+
+static METRICS: OnceLock<Arc<dyn Metrics>> = OnceLock::new();
+
+/// Sends the measurements of the whole process to `metrics`. Returns `false`
+/// when an implementation was installed already, which stays in place.
+pub fn install(metrics: Arc<dyn Metrics>) -> bool {
+    METRICS.set(metrics).is_ok()
+}
+
+pub(crate) fn metrics() -> &'static dyn Metrics {
+    match METRICS.get() {
+        Some(metrics) => metrics.as_ref(),
+        None => &Discard,
+    }
+}
+
+/// Reports the outcome of a conversion by `engine` that took `elapsed`.
+pub(crate) fn record_conversion<T>(engine: EngineKind, elapsed: Duration, result: &Result<Vec<T>>) {
+    match result {
+        Ok(candidates) => metrics().conversion(engine, candidates.len(), elapsed),
+        Err(_) => metrics().conversion_failed(engine),
+    }
+}
+
+/// Totals kept by [`Counters`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Snapshot {
+    pub conversions: u64,
+    pub failed_conversions: u64,
+    pub candidates: u64,
+    /// Time spent converting, summed over all conversions.
+    pub conversion_time: Duration,
+    pub fallbacks: u64,
+    pub contended_locks: u64,
+    pub events: u64,
+}
+
+/// A [`Metrics`] implementation summing everything up.
+#[derive(Debug, Default)]
+pub struct Counters {
+    conversions: AtomicU64,
+    failed_conversions: AtomicU64,
+    candidates: AtomicU64,
+    conversion_micros: AtomicU64,
+    fallbacks: AtomicU64,
+    contended_locks: AtomicU64,
+    events: AtomicU64,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            conversions: self.conversions.load(Ordering::Relaxed),
+            failed_conversions: self.failed_conversions.load(Ordering::Relaxed),
+            candidates: self.candidates.load(Ordering::Relaxed),
+            conversion_time: Duration::from_micros(self.conversion_micros.load(Ordering::Relaxed)),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+            contended_locks: self.contended_locks.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Metrics for Counters {
+    fn conversion(&self, _engine: EngineKind, candidates: usize, elapsed: Duration) {
+        self.conversions.fetch_add(1, Ordering::Relaxed);
+        self.candidates.fetch_add(candidates as u64, Ordering::Relaxed);
+        self.conversion_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn conversion_failed(&self, _engine: EngineKind) {
+        self.failed_conversions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn engine_fallback(&self, _engine: EngineKind) {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn lock_contended(&self, _synchronous: bool) {
+        self.contended_locks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn event(&self, _event: &TsfEvent) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+}
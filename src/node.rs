@@ -0,0 +1,53 @@
+//! Node.js bindings, built with the `node` feature.
+//!
+//! Every call returns a promise. The blocking wait for the [`TsfService`]
+//! worker happens on libuv's thread pool, never on the JavaScript thread.
+
+use std::sync::Arc;
+
+use napi::{bindgen_prelude::AsyncTask, Env, Error, Result, Status, Task};
+use napi_derive::napi;
+
+use crate::{error::TsfError, service::TsfService};
+
+fn to_js(e: TsfError) -> Error {
+    Error::new(Status::GenericFailure, e.to_string())
+}
+
+/// Conversion through the system IME.
+#[napi]
+pub struct Iatjc {
+    service: Arc<TsfService>,
+}
+
+#[napi]
+impl Iatjc {
+    #[napi(constructor)]
+    pub fn new() -> Result<Self> {
+        Ok(Self { service: Arc::new(TsfService::spawn().map_err(to_js)?) })
+    }
+
+    /// Converts a kana reading into candidates, best first.
+    #[napi(ts_return_type = "Promise<string[]>")]
+    pub fn convert(&self, reading: String) -> AsyncTask<Convert> {
+        AsyncTask::new(Convert { service: self.service.clone(), reading })
+    }
+}
+
+pub struct Convert {
+    service: Arc<TsfService>,
+    reading: String,
+}
+
+impl Task for Convert {
+    type Output = Vec<String>;
+    type JsValue = Vec<String>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        self.service.convert(&self.reading).map_err(to_js)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output)
+    }
+}
@@ -0,0 +1,83 @@
+use windows::Win32::{
+    Foundation::ERROR_INSUFFICIENT_BUFFER,
+    Globalization::{NormalizeString, NormalizationC, NormalizationKC, NORM_FORM},
+};
+
+use crate::error::{hresult, Result};
+
+/// Unicode normalization forms.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NormalizationForm {
+    Nfc,
+    /// Also folds compatibility characters such as half-width katakana and
+    /// full-width ASCII.
+    Nfkc,
+}
+
+impl NormalizationForm {
+    fn raw(self) -> NORM_FORM {
+        match self {
+            Self::Nfc => NormalizationC,
+            Self::Nfkc => NormalizationKC,
+        }
+    }
+}
+
+/// Normalization applied around a conversion.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Normalization {
+    /// Applied to the reading or text before it is stored.
+    pub input: Option<NormalizationForm>,
+    /// Applied to every candidate returned.
+    pub output: Option<NormalizationForm>,
+}
+
+impl Normalization {
+    /// Uses `form` for both directions.
+    pub fn both(form: NormalizationForm) -> Self {
+        Self { input: Some(form), output: Some(form) }
+    }
+
+    pub(crate) fn input(&self, text: String) -> Result<String> {
+        match self.input {
+            Some(form) => normalize(&text, form),
+            None => Ok(text),
+        }
+    }
+
+    pub(crate) fn output(&self, candidates: Vec<String>) -> Result<Vec<String>> {
+        match self.output {
+            Some(form) => candidates.iter().map(|candidate| normalize(candidate, form)).collect(),
+            None => Ok(candidates),
+        }
+    }
+}
+
+/// Normalizes `text` with `NormalizeString`.
+pub fn normalize(text: &str, form: NormalizationForm) -> Result<String> {
+    let source: Vec<u16> = text.encode_utf16().collect();
+    if source.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut capacity = unsafe { NormalizeString(form.raw(), &source, None) };
+    loop {
+        if capacity <= 0 {
+            return Err(hresult("NormalizeString")(windows_core::Error::from_win32()));
+        }
+
+        let mut buffer = vec![0u16; capacity as usize];
+        let written = unsafe { NormalizeString(form.raw(), &source, Some(&mut buffer)) };
+        if written > 0 {
+            buffer.truncate(written as usize);
+            return Ok(String::from_utf16_lossy(&buffer));
+        }
+
+        // A negative result is a better estimate of the required size.
+        let error = windows_core::Error::from_win32();
+        if error.code() != ERROR_INSUFFICIENT_BUFFER.to_hresult() {
+            return Err(hresult("NormalizeString")(error));
+        }
+        capacity = (-written).max(capacity * 2);
+    }
+}
@@ -0,0 +1,126 @@
+//! A bounded log of the latest sink notifications, sent by the text store
+//! to TSF and received from TSF by the crate's sinks.
+//!
+//! The log is always on and cheap enough to be: it keeps the last
+//! [`DEFAULT_CAPACITY`] entries unless resized. Reach it through
+//! [`crate::events::EventHub::notifications`].
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use windows_core::GUID;
+
+use crate::error::Recover;
+
+pub const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// From the text store to the advised `ITextStoreACPSink`.
+    Sent,
+    /// From TSF to one of the crate's sinks.
+    Received,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Notification {
+    TextChange { start: i32, old_end: i32, new_end: i32 },
+    SelectionChange,
+    StatusChange { flags: u32 },
+    AttrsChange { start: i32, end: i32, count: u32 },
+    LayoutChange { code: i32, view: u32 },
+    LockGranted { flags: u32 },
+    CompositionStarted,
+    CompositionUpdated,
+    CompositionEnded,
+    BeginUIElement { id: u32 },
+    UpdateUIElement { id: u32 },
+    EndUIElement { id: u32 },
+    ProfileActivated { profile: GUID, active: bool },
+    ThreadFocus { focused: bool },
+    CompartmentChange { compartment: GUID },
+}
+
+impl Notification {
+    pub fn direction(&self) -> Direction {
+        match self {
+            Self::TextChange { .. } | Self::SelectionChange | Self::StatusChange { .. } | Self::AttrsChange { .. } | Self::LayoutChange { .. } | Self::LockGranted { .. } => Direction::Sent,
+            _ => Direction::Received,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Entry {
+    /// Counts every notification logged, including those since evicted.
+    pub seq: u64,
+    pub at: Instant,
+    pub direction: Direction,
+    pub notification: Notification,
+}
+
+struct LogState {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    next: u64,
+}
+
+/// Cheap to clone; clones share the same log.
+#[derive(Clone)]
+pub struct NotificationLog {
+    state: Arc<Mutex<LogState>>,
+}
+
+impl Default for NotificationLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl NotificationLog {
+    pub fn new(capacity: usize) -> Self {
+        Self { state: Arc::new(Mutex::new(LogState { entries: VecDeque::with_capacity(capacity), capacity, next: 0 })) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LogState> {
+        self.state.lock().recover()
+    }
+
+    /// The logged notifications, oldest first.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.lock().entries.iter().copied().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+
+    /// Keeps at most `capacity` entries, dropping the oldest ones. 0 turns
+    /// the log off.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.lock();
+        state.capacity = capacity;
+        let excess = state.entries.len().saturating_sub(capacity);
+        state.entries.drain(..excess);
+    }
+
+    pub(crate) fn push(&self, notification: Notification) {
+        let mut state = self.lock();
+        let seq = state.next;
+        state.next += 1;
+        if state.capacity == 0 {
+            return;
+        }
+        if state.entries.len() == state.capacity {
+            state.entries.pop_front();
+        }
+        state.entries.push_back(Entry { seq, at: Instant::now(), direction: notification.direction(), notification });
+    }
+}
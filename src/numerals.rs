@@ -0,0 +1,231 @@
+/// How numbers are written.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum NumeralStyle {
+    /// `2024`
+    Arabic,
+    /// `２０２４`
+    FullwidthArabic,
+    /// Kanji digits in place-value order: `二〇二四`
+    Positional,
+    /// Kanji with unit characters: `二千二十四`
+    #[default]
+    Kanji,
+}
+
+const DIGITS: [char; 10] = ['〇', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+const SMALL_UNITS: [(char, u64); 3] = [('千', 1000), ('百', 100), ('十', 10)];
+const LARGE_UNITS: [(char, u64); 4] = [('京', 10u64.pow(16)), ('兆', 10u64.pow(12)), ('億', 10u64.pow(8)), ('万', 10u64.pow(4))];
+
+/// Counter words recognized after a number.
+pub const COUNTERS: &[&str] = &[
+    "個", "本", "枚", "人", "匹", "冊", "台", "回", "円", "年", "月", "日", "時", "分", "秒", "歳", "才", "件", "杯", "階", "番", "度", "名", "足",
+    "頭", "羽", "軒", "着", "通", "点", "つ", "ヶ月", "か月", "箇所", "週間", "時間", "年間",
+];
+
+/// Words made of a kanji numeral and a counter that are words of their own
+/// (`十分` "enough", `一番` "most", `一度` "once"), so the numeral is not
+/// rewritten.
+const LEXICALIZED: &[&str] = &["十分", "一番", "一度", "一杯", "一時", "一分", "一日", "一人", "一月", "一着", "二人", "二度"];
+
+/// Writes `number` in `style`.
+pub fn format_number(number: u64, style: NumeralStyle) -> String {
+    match style {
+        NumeralStyle::Arabic => number.to_string(),
+        NumeralStyle::FullwidthArabic => number.to_string().chars().map(|c| char::from_u32(c as u32 - '0' as u32 + '０' as u32).unwrap_or(c)).collect(),
+        NumeralStyle::Positional => number.to_string().chars().map(|c| DIGITS[c as usize - '0' as usize]).collect(),
+        NumeralStyle::Kanji => kanji(number),
+    }
+}
+
+fn kanji(number: u64) -> String {
+    if number == 0 {
+        return DIGITS[0].to_string();
+    }
+
+    let mut output = String::new();
+    let mut rest = number;
+    for (unit, value) in LARGE_UNITS {
+        let group = rest / value;
+        if group > 0 {
+            output.push_str(&kanji_group(group));
+            output.push(unit);
+        }
+        rest %= value;
+    }
+    output.push_str(&kanji_group(rest));
+    output
+}
+
+/// Writes a number below 10000 with `千百十`, omitting a leading `一`
+/// (`千`, not `一千`).
+fn kanji_group(mut number: u64) -> String {
+    let mut output = String::new();
+    for (unit, value) in SMALL_UNITS {
+        let digit = number / value;
+        if digit > 1 {
+            output.push(DIGITS[digit as usize]);
+        }
+        if digit > 0 {
+            output.push(unit);
+        }
+        number %= value;
+    }
+    if number > 0 {
+        output.push(DIGITS[number as usize]);
+    }
+    output
+}
+
+fn digit_value(c: char) -> Option<u64> {
+    match c {
+        '0'..='9' => Some(c as u64 - '0' as u64),
+        '０'..='９' => Some(c as u64 - '０' as u64),
+        '零' => Some(0),
+        _ => DIGITS.iter().position(|&d| d == c).map(|d| d as u64),
+    }
+}
+
+/// Parses a number at the start of `text`, written in any [`NumeralStyle`].
+/// Returns the value and the number of bytes consumed.
+pub fn parse_number(text: &str) -> Option<(u64, usize)> {
+    let (mut total, mut section, mut current) = (0u64, 0u64, 0u64);
+    let mut consumed = 0;
+
+    for (offset, c) in text.char_indices() {
+        if let Some(digit) = digit_value(c) {
+            current = current.checked_mul(10)?.checked_add(digit)?;
+        } else if let Some((_, value)) = SMALL_UNITS.iter().find(|(unit, _)| *unit == c) {
+            section = section.checked_add(current.max(1).checked_mul(*value)?)?;
+            current = 0;
+        } else if let Some((_, value)) = LARGE_UNITS.iter().find(|(unit, _)| *unit == c) {
+            if consumed == 0 {
+                break;
+            }
+            total = total.checked_add(section.checked_add(current)?.checked_mul(*value)?)?;
+            section = 0;
+            current = 0;
+        } else {
+            break;
+        }
+        consumed = offset + c.len_utf8();
+    }
+
+    (consumed > 0).then(|| Some((total.checked_add(section)?.checked_add(current)?, consumed))).flatten()
+}
+
+/// Rewrites every run of ASCII or full-width digits in `text` in `style`.
+pub fn convert_numbers(text: &str, style: NumeralStyle) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let is_digit = c.is_ascii_digit() || ('０'..='９').contains(&c);
+        if !is_digit {
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let run = rest.find(|c: char| !(c.is_ascii_digit() || ('０'..='９').contains(&c))).unwrap_or(rest.len());
+        // A run too long for u64 is kept as written rather than split.
+        match parse_number(&rest[..run]) {
+            Some((number, _)) => output.push_str(&format_number(number, style)),
+            None => output.push_str(&rest[..run]),
+        }
+        rest = &rest[run..];
+    }
+
+    output
+}
+
+/// A number followed by a counter word, e.g. `三本`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Counted<'a> {
+    pub number: u64,
+    pub counter: &'a str,
+}
+
+/// Recognizes candidates consisting of a number and one of [`COUNTERS`].
+pub fn split_counter(text: &str) -> Option<Counted<'_>> {
+    let (number, consumed) = parse_number(text)?;
+    let counter = &text[consumed..];
+    COUNTERS.contains(&counter).then_some(Counted { number, counter })
+}
+
+/// Rewrites number-and-counter candidates in `style` and drops the
+/// duplicates this creates, keeping the first occurrence. Other candidates,
+/// and dictionary words that merely look like a count such as `十分`, are
+/// kept as they are.
+pub fn normalize_counters(candidates: &[String], style: NumeralStyle) -> Vec<String> {
+    let mut output: Vec<String> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let counted = split_counter(candidate).filter(|_| !LEXICALIZED.contains(&candidate.as_str()));
+        let candidate = match counted {
+            Some(counted) => format!("{}{}", format_number(counted.number, style), counted.counter),
+            None => candidate.clone(),
+        };
+        if !output.contains(&candidate) {
+            output.push(candidate);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(candidates: &[&str]) -> Vec<String> {
+        candidates.iter().map(|candidate| candidate.to_string()).collect()
+    }
+
+    #[test]
+    fn formats_in_every_style() {
+        assert_eq!(format_number(2024, NumeralStyle::Arabic), "2024");
+        assert_eq!(format_number(2024, NumeralStyle::FullwidthArabic), "２０２４");
+        assert_eq!(format_number(2024, NumeralStyle::Positional), "二〇二四");
+        assert_eq!(format_number(2024, NumeralStyle::Kanji), "二千二十四");
+        assert_eq!(format_number(0, NumeralStyle::Kanji), "〇");
+        assert_eq!(format_number(1000, NumeralStyle::Kanji), "千");
+        assert_eq!(format_number(120_000_005, NumeralStyle::Kanji), "一億二千万五");
+    }
+
+    #[test]
+    fn parses_numbers_in_every_style() {
+        assert_eq!(parse_number("2024年"), Some((2024, 4)));
+        assert_eq!(parse_number("２０２４"), Some((2024, "２０２４".len())));
+        assert_eq!(parse_number("二〇二四"), Some((2024, "二〇二四".len())));
+        assert_eq!(parse_number("二千二十四本"), Some((2024, "二千二十四".len())));
+        assert_eq!(parse_number("一億二千万五"), Some((120_000_005, "一億二千万五".len())));
+        assert_eq!(parse_number("万"), None);
+        assert_eq!(parse_number("本"), None);
+        assert_eq!(parse_number("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn converts_digit_runs_only() {
+        assert_eq!(convert_numbers("第3章の12ページ", NumeralStyle::Kanji), "第三章の十二ページ");
+        assert_eq!(convert_numbers("１０個", NumeralStyle::Arabic), "10個");
+        assert_eq!(convert_numbers("三本", NumeralStyle::Arabic), "三本");
+        assert_eq!(convert_numbers("99999999999999999999円", NumeralStyle::Kanji), "99999999999999999999円");
+    }
+
+    #[test]
+    fn splits_numbers_from_counters() {
+        assert_eq!(split_counter("三本"), Some(Counted { number: 3, counter: "本" }));
+        assert_eq!(split_counter("12ヶ月"), Some(Counted { number: 12, counter: "ヶ月" }));
+        assert_eq!(split_counter("三本目"), None);
+        assert_eq!(split_counter("本"), None);
+    }
+
+    #[test]
+    fn normalizes_counts_and_drops_duplicates() {
+        let candidates = strings(&["3本", "三本", "３本", "参本"]);
+        assert_eq!(normalize_counters(&candidates, NumeralStyle::Kanji), ["三本", "参本"]);
+    }
+
+    #[test]
+    fn keeps_words_that_look_like_counts() {
+        let candidates = strings(&["十分", "10分", "一番", "一度", "1度"]);
+        assert_eq!(normalize_counters(&candidates, NumeralStyle::Arabic), ["十分", "10分", "一番", "一度", "1度"]);
+    }
+}
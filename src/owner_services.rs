@@ -0,0 +1,45 @@
+//! The context's `ITfContextOwnerServices`, through which a host tells TSF
+//! about changes it cannot report through the text store's sink, for
+//! [`crate::tsf::TSF::owner_services`].
+
+use windows::Win32::UI::TextServices::{ITfContextOwnerServices, ITfRangeACP};
+use windows_core::GUID;
+
+use crate::error::{call, Result};
+
+/// Notifications and helpers offered by a context to its owner. Only usable
+/// on the thread of the [`crate::tsf::TSF`] it came from.
+#[derive(Clone, Debug)]
+pub struct OwnerServices {
+    services: ITfContextOwnerServices,
+}
+
+impl OwnerServices {
+    pub(crate) fn new(services: ITfContextOwnerServices) -> Self {
+        Self { services }
+    }
+
+    /// The caret, the view or the text's layout moved.
+    pub fn layout_changed(&self) -> Result<()> {
+        call!(self.services, OnLayoutChange())
+    }
+
+    /// The document's `TS_SD_*` flags changed to `flags`.
+    pub fn status_changed(&self, flags: u32) -> Result<()> {
+        call!(self.services, OnStatusChange(flags))
+    }
+
+    /// The value of `attribute`, such as `GUID_PROP_INPUTSCOPE`, changed.
+    pub fn attribute_changed(&self, attribute: &GUID) -> Result<()> {
+        call!(self.services, OnAttributeChange(attribute))
+    }
+
+    /// A range of the context covering `start..end`, in ACPs.
+    pub fn create_range(&self, start: i32, end: i32) -> Result<ITfRangeACP> {
+        call!(self.services, CreateRange(start, end))
+    }
+
+    pub fn as_raw(&self) -> &ITfContextOwnerServices {
+        &self.services
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc, Arc,
+};
+
+use crate::trace::{debug, info};
+
+use crate::{cancel::CancellationToken, error::{Result, TsfError}, normalize::Normalization, service::{Command, Priority, Reply, RequestOptions, TsfService}};
+
+/// Counts a request as in flight until the reply has run or was dropped.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn start(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Worker {
+    service: TsfService,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// A fixed set of [`TsfService`] workers, each with its own STA thread and
+/// TSF pipeline. Requests go to the worker with the fewest in-flight items.
+pub struct TsfPool {
+    workers: Vec<Worker>,
+}
+
+impl TsfPool {
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "pool_spawn", level = "debug", err))]
+    pub fn spawn(size: usize) -> Result<Self> {
+        if size == 0 {
+            return Err(TsfError::InvalidArgument("pool size must be at least 1"));
+        }
+
+        let workers = (0..size)
+            .map(|_| {
+                Ok(Worker {
+                    service: TsfService::spawn()?,
+                    in_flight: Arc::new(AtomicUsize::new(0)),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        info!("Started TSF pool with {} workers", size);
+        Ok(Self { workers })
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn convert(&self, reading: &str) -> Result<Vec<String>> {
+        self.convert_cancellable(reading, &CancellationToken::new())
+    }
+
+    pub fn convert_cancellable(&self, reading: &str, token: &CancellationToken) -> Result<Vec<String>> {
+        self.convert_with(reading, &RequestOptions::with_token(token))
+    }
+
+    /// Converts on the least busy worker, as [`TsfService::convert_with`].
+    pub fn convert_with(&self, reading: &str, options: &RequestOptions) -> Result<Vec<String>> {
+        let worker = self.least_busy();
+        let _in_flight = InFlight::start(&worker.in_flight);
+        worker.service.convert_with(reading, options)
+    }
+
+    /// Converts every reading, spreading the work over all workers, and
+    /// returns the results in input order.
+    pub fn convert_batch<S: AsRef<str>>(&self, readings: &[S]) -> Vec<Result<Vec<String>>> {
+        self.convert_batch_cancellable(readings, &CancellationToken::new())
+    }
+
+    /// Like [`TsfPool::convert_batch`]; cancelling `token` skips every item
+    /// that has not started yet. Items run in the batch lane, behind any
+    /// interactive conversions.
+    pub fn convert_batch_cancellable<S: AsRef<str>>(&self, readings: &[S], token: &CancellationToken) -> Vec<Result<Vec<String>>> {
+        self.convert_batch_with(readings, &RequestOptions::with_token(token))
+    }
+
+    /// Like [`TsfPool::convert_batch`], with the token, normalization and
+    /// candidate limit of `options`. Items always run in the batch lane and without a timeout.
+    pub fn convert_batch_with<S: AsRef<str>>(&self, readings: &[S], options: &RequestOptions) -> Vec<Result<Vec<String>>> {
+        let token = options.token.clone().unwrap_or_default();
+        let (sender, receiver) = mpsc::channel();
+        let mut results: Vec<Option<Result<Vec<String>>>> = readings.iter().map(|_| None).collect();
+
+        for (index, reading) in readings.iter().enumerate() {
+            let sender = sender.clone();
+            let submitted = self.dispatch(reading.as_ref(), &token, options.normalization, options.max_candidates, Box::new(move |result| {
+                let _ = sender.send((index, result));
+            }));
+
+            if let Err(e) = submitted {
+                results[index] = Some(Err(e));
+            }
+        }
+        drop(sender);
+
+        for (index, result) in receiver {
+            results[index] = Some(result);
+        }
+
+        debug!("Converted batch of {} readings", readings.len());
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or(Err(TsfError::WorkerStopped)))
+            .collect()
+    }
+
+    fn least_busy(&self) -> &Worker {
+        self.workers
+            .iter()
+            .min_by_key(|worker| worker.in_flight.load(Ordering::SeqCst))
+            .expect("pool has at least one worker")
+    }
+
+    /// Queues a conversion in the batch lane of the least busy worker.
+    fn dispatch(&self, reading: &str, token: &CancellationToken, normalization: Normalization, max_candidates: Option<usize>, reply: Reply<Vec<String>>) -> Result<()> {
+        let worker = self.least_busy();
+        let in_flight = InFlight::start(&worker.in_flight);
+        let command = Command::Convert {
+            reading: reading.to_string(),
+            token: token.clone(),
+            normalization,
+            max_candidates,
+            reply: Box::new(move |result| {
+                drop(in_flight);
+                reply(result);
+            }),
+        };
+
+        worker.service.submit(Priority::Batch, command)
+    }
+}
@@ -0,0 +1,21 @@
+//! Profiler markers around the phases of a conversion.
+//!
+//! With the `profiling` feature `scope!` is the `profiling` crate's macro, so
+//! whichever backend the application enables in that crate (puffin, tracy,
+//! superluminal, optick) attributes time to the phase; without it the macro
+//! compiles to nothing.
+
+#[cfg(feature = "profiling")]
+pub(crate) use profiling::scope;
+
+#[cfg(not(feature = "profiling"))]
+mod noop {
+    macro_rules! scope {
+        ($($arg:tt)*) => {};
+    }
+
+    pub(crate) use scope;
+}
+
+#[cfg(not(feature = "profiling"))]
+pub(crate) use noop::scope;
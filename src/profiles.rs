@@ -0,0 +1,96 @@
+use windows::Win32::{
+    System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+    UI::TextServices::{
+        ITfInputProcessorProfileMgr, ITfInputProcessorProfiles, CLSID_TF_InputProcessorProfiles, GUID_TFCAT_TIP_KEYBOARD, HKL, TF_INPUTPROCESSORPROFILE,
+        TF_IPP_FLAG_ACTIVE, TF_IPP_FLAG_ENABLED, TF_PROFILETYPE_INPUTPROCESSOR,
+    },
+};
+use windows_core::{Interface, GUID};
+
+use crate::{
+    compat::Tip,
+    error::{call, Result},
+    trace::debug,
+};
+
+/// What backs an input profile.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ProfileKind {
+    /// A text input processor, identified by its CLSID and profile GUID.
+    InputProcessor,
+    /// A plain keyboard layout, identified by its HKL.
+    KeyboardLayout,
+}
+
+/// An installed input profile.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Profile {
+    pub kind: ProfileKind,
+    pub langid: u16,
+    /// `GUID::zeroed()` for keyboard layouts.
+    pub clsid: GUID,
+    pub profile: GUID,
+    pub hkl: HKL,
+    /// Empty for keyboard layouts.
+    pub description: String,
+    /// Recognized Japanese input processor, if any.
+    pub tip: Option<Tip>,
+    pub enabled: bool,
+    pub active: bool,
+    /// The default input processor for its language.
+    pub default: bool,
+}
+
+/// Lists every installed input profile, for all languages.
+pub fn installed() -> Result<Vec<Profile>> {
+    let manager: ITfInputProcessorProfileMgr = call!(CoCreateInstance(&CLSID_TF_InputProcessorProfiles, None, CLSCTX_INPROC_SERVER))?;
+    let profiles: ITfInputProcessorProfiles = call!(manager, cast())?;
+    let enumerator = call!(manager, EnumProfiles(0))?;
+
+    let mut installed = Vec::new();
+    loop {
+        let mut raw = [TF_INPUTPROCESSORPROFILE::default()];
+        let mut fetched = 0;
+        call!(enumerator, Next(&mut raw, &mut fetched))?;
+        if fetched == 0 {
+            break;
+        }
+
+        let raw = raw[0];
+        let kind = if raw.dwProfileType == TF_PROFILETYPE_INPUTPROCESSOR { ProfileKind::InputProcessor } else { ProfileKind::KeyboardLayout };
+        let (description, tip, default) = match kind {
+            ProfileKind::InputProcessor => {
+                let description = call!(profiles, GetLanguageProfileDescription(&raw.clsid, raw.langid, &raw.guidProfile))
+                    .map(|description| description.to_string())
+                    .unwrap_or_default();
+                let tip = Tip::detect(raw.clsid, &description);
+                (description, Some(tip).filter(|tip| !matches!(tip, Tip::Other(_))), is_default(&profiles, &raw))
+            }
+            ProfileKind::KeyboardLayout => (String::new(), None, false),
+        };
+
+        installed.push(Profile {
+            kind,
+            langid: raw.langid,
+            clsid: raw.clsid,
+            profile: raw.guidProfile,
+            hkl: raw.hkl,
+            description,
+            tip,
+            enabled: raw.dwFlags & TF_IPP_FLAG_ENABLED != 0,
+            active: raw.dwFlags & TF_IPP_FLAG_ACTIVE != 0,
+            default,
+        });
+    }
+
+    debug!("Found {} input profiles", installed.len());
+    Ok(installed)
+}
+
+fn is_default(profiles: &ITfInputProcessorProfiles, raw: &TF_INPUTPROCESSORPROFILE) -> bool {
+    let mut clsid = GUID::zeroed();
+    let mut profile = GUID::zeroed();
+    call!(profiles, GetDefaultLanguageProfile(raw.langid, &GUID_TFCAT_TIP_KEYBOARD, &mut clsid, &mut profile)).is_ok()
+        && clsid == raw.clsid
+        && profile == raw.guidProfile
+}
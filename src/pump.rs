@@ -0,0 +1,175 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::trace::debug;
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    System::Threading::GetCurrentThreadId,
+    UI::WindowsAndMessaging::{DispatchMessageW, PeekMessageW, PostQuitMessage, PostThreadMessageW, TranslateMessage, WaitMessage, MSG, PM_NOREMOVE, PM_REMOVE, WM_QUIT},
+};
+
+use crate::error::{call, Result};
+
+/// Forces the creation of the current thread's message queue so that other
+/// threads can post to it right away.
+pub fn ensure_message_queue() {
+    let mut msg = MSG::default();
+    unsafe {
+        let _ = PeekMessageW(&mut msg, HWND(0), 0, 0, PM_NOREMOVE);
+    }
+}
+
+/// Posts WM_QUIT to the current thread's queue.
+pub fn post_quit(exit_code: i32) {
+    unsafe { PostQuitMessage(exit_code) };
+}
+
+/// Stops a message loop running on another (or the same) thread.
+#[derive(Clone, Debug)]
+pub struct QuitSignal {
+    thread_id: u32,
+    requested: Arc<AtomicBool>,
+}
+
+impl QuitSignal {
+    /// Creates a signal bound to the calling thread, which is expected to run
+    /// the message loop.
+    pub fn for_current_thread() -> Self {
+        ensure_message_queue();
+        Self {
+            thread_id: unsafe { GetCurrentThreadId() },
+            requested: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn thread_id(&self) -> u32 {
+        self.thread_id
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Asks the loop to exit by posting WM_QUIT to its thread.
+    pub fn quit(&self) -> Result<()> {
+        self.requested.store(true, Ordering::SeqCst);
+        call!(PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)))?;
+        Ok(())
+    }
+
+    /// Posts an arbitrary thread message to the loop's thread.
+    pub fn post(&self, message: u32, wparam: usize, lparam: isize) -> Result<()> {
+        call!(PostThreadMessageW(self.thread_id, message, WPARAM(wparam), LPARAM(lparam)))?;
+        Ok(())
+    }
+}
+
+/// What the loop should do after an idle callback returns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IdleAction {
+    /// Call the idle callback again as soon as the queue is empty.
+    Continue,
+    /// Block until the next message arrives.
+    Wait,
+}
+
+type MessageHook<'a> = Box<dyn FnMut(&MSG) -> bool + 'a>;
+type IdleHook<'a> = Box<dyn FnMut() -> IdleAction + 'a>;
+
+/// A peek-based message loop with optional message and idle hooks.
+pub struct MessageLoop<'a> {
+    quit: QuitSignal,
+    on_message: Option<MessageHook<'a>>,
+    on_idle: Option<IdleHook<'a>>,
+}
+
+impl<'a> MessageLoop<'a> {
+    pub fn new(quit: QuitSignal) -> Self {
+        Self { quit, on_message: None, on_idle: None }
+    }
+
+    /// Sees every message before it is dispatched. Returning `true` marks the
+    /// message as handled and skips translation and dispatch.
+    pub fn on_message<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&MSG) -> bool + 'a,
+    {
+        self.on_message = Some(Box::new(hook));
+        self
+    }
+
+    /// Runs whenever the queue has been drained.
+    pub fn on_idle<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut() -> IdleAction + 'a,
+    {
+        self.on_idle = Some(Box::new(hook));
+        self
+    }
+
+    /// Pumps messages until WM_QUIT arrives and returns its exit code.
+    pub fn run(mut self) -> Result<i32> {
+        debug!("Entering message loop on thread {}", self.quit.thread_id());
+        let mut msg = MSG::default();
+
+        loop {
+            while unsafe { PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE) }.as_bool() {
+                if msg.message == WM_QUIT {
+                    debug!("WM_QUIT received, leaving message loop");
+                    return Ok(msg.wParam.0 as i32);
+                }
+
+                if let Some(hook) = &mut self.on_message
+                    && hook(&msg)
+                {
+                    continue;
+                }
+
+                unsafe {
+                    let _ = TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+
+            if self.quit.is_requested() {
+                debug!("Quit requested, leaving message loop");
+                return Ok(0);
+            }
+
+            let action = match &mut self.on_idle {
+                Some(hook) => hook(),
+                None => IdleAction::Wait,
+            };
+
+            if action == IdleAction::Wait {
+                call!(WaitMessage())?;
+            }
+        }
+    }
+}
+
+/// Pumps messages on the current thread until `quit` is signalled.
+pub fn run_message_loop(quit: &QuitSignal) -> Result<i32> {
+    MessageLoop::new(quit.clone()).run()
+}
+
+/// Dispatches every message already queued on the current thread without
+/// waiting for more. Returns `false` if WM_QUIT was among them; it is posted
+/// again so that the enclosing loop still sees it.
+pub fn pump_pending() -> bool {
+    let mut msg = MSG::default();
+    while unsafe { PeekMessageW(&mut msg, HWND(0), 0, 0, PM_REMOVE) }.as_bool() {
+        if msg.message == WM_QUIT {
+            post_quit(msg.wParam.0 as i32);
+            return false;
+        }
+
+        unsafe {
+            let _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+    true
+}
@@ -0,0 +1,74 @@
+//! Python bindings, built with the `python` feature (see `pyproject.toml`).
+//!
+//! Conversions run on a [`TsfService`] worker, and the GIL is released while
+//! waiting for it, so other Python threads keep running.
+
+use pyo3::{exceptions::PyRuntimeError, prelude::*};
+
+use crate::{error::TsfError, felang, service::TsfService};
+
+fn to_py(e: TsfError) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// A clause of a segmented reading.
+#[pyclass(module = "iatjc", get_all, frozen)]
+pub struct Clause {
+    reading: String,
+    surface: String,
+    start: usize,
+}
+
+impl From<felang::Clause> for Clause {
+    fn from(clause: felang::Clause) -> Self {
+        Self { reading: clause.reading, surface: clause.surface, start: clause.start }
+    }
+}
+
+#[pymethods]
+impl Clause {
+    fn __repr__(&self) -> String {
+        format!("Clause(reading={:?}, surface={:?}, start={})", self.reading, self.surface, self.start)
+    }
+}
+
+/// Conversion through the system IME.
+#[pyclass(module = "iatjc", frozen)]
+pub struct Iatjc {
+    service: TsfService,
+}
+
+#[pymethods]
+impl Iatjc {
+    #[new]
+    fn new(py: Python<'_>) -> PyResult<Self> {
+        let service = py.allow_threads(TsfService::spawn).map_err(to_py)?;
+        Ok(Self { service })
+    }
+
+    /// Converts a kana reading into candidates, best first.
+    #[pyo3(signature = (reading, count = None))]
+    fn convert(&self, py: Python<'_>, reading: &str, count: Option<usize>) -> PyResult<Vec<String>> {
+        let mut candidates = py.allow_threads(|| self.service.convert(reading)).map_err(to_py)?;
+        candidates.truncate(count.unwrap_or(usize::MAX));
+        Ok(candidates)
+    }
+
+    /// Splits a kana reading into clauses.
+    fn segment(&self, py: Python<'_>, reading: &str) -> PyResult<Vec<Clause>> {
+        let clauses = py.allow_threads(|| self.service.segment(reading)).map_err(to_py)?;
+        Ok(clauses.into_iter().map(Clause::from).collect())
+    }
+
+    /// The kana reading of kanji-kana text.
+    fn reading(&self, py: Python<'_>, text: &str) -> PyResult<String> {
+        py.allow_threads(|| self.service.reading(text)).map_err(to_py)
+    }
+}
+
+#[pymodule]
+fn iatjc(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Iatjc>()?;
+    module.add_class::<Clause>()?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! Regions split a document into parts that input processors treat as
+//! separate, e.g. a form's fields kept in one text store. A composition
+//! never spans two regions.
+//!
+//! The store holds regions in its text, separated by `TS_CHAR_REGION`, which
+//! is how `GetText` reports them to TSF. A document without that character
+//! is a single region.
+
+use std::ops::Range;
+
+use windows::Win32::UI::TextServices::{TS_CHAR_REGION, TS_RT_PLAIN, TS_RUNINFO};
+
+/// The UTF-16 unit separating two regions.
+pub const BOUNDARY: u16 = TS_CHAR_REGION as u16;
+
+/// The document text holding `regions`, in order.
+pub fn join(regions: &[&str]) -> String {
+    regions.join("\u{0}")
+}
+
+/// The ranges of the regions of `text`, in ACPs, without their boundaries.
+pub fn ranges(text: &[u16]) -> Vec<Range<i32>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (acp, &unit) in text.iter().enumerate() {
+        if unit == BOUNDARY {
+            ranges.push(start..acp as i32);
+            start = acp as i32 + 1;
+        }
+    }
+    ranges.push(start..text.len() as i32);
+    ranges
+}
+
+/// The plain text runs of `text[start..end]` for `GetText`, split so that
+/// each boundary is a run of its own and no run spans two regions.
+pub(crate) fn runs(text: &[u16], start: usize, end: usize) -> Vec<TS_RUNINFO> {
+    let mut runs = Vec::new();
+    let mut run_start = start;
+    for (acp, &unit) in text.iter().enumerate().take(end).skip(start) {
+        if unit == BOUNDARY {
+            if acp > run_start {
+                runs.push(TS_RUNINFO { uCount: (acp - run_start) as u32, r#type: TS_RT_PLAIN });
+            }
+            runs.push(TS_RUNINFO { uCount: 1, r#type: TS_RT_PLAIN });
+            run_start = acp + 1;
+        }
+    }
+    if end > run_start {
+        runs.push(TS_RUNINFO { uCount: (end - run_start) as u32, r#type: TS_RT_PLAIN });
+    }
+    runs
+}
@@ -0,0 +1,69 @@
+//! String values under `HKEY_CURRENT_USER`, which is all the crate writes.
+
+use windows::Win32::{
+    Foundation::ERROR_FILE_NOT_FOUND,
+    System::Registry::{RegCloseKey, RegCreateKeyExW, RegDeleteKeyValueW, RegDeleteTreeW, RegGetValueW, RegSetValueExW, HKEY, HKEY_CURRENT_USER, KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_SZ, RRF_RT_REG_SZ},
+};
+use windows_core::{HSTRING, PCWSTR};
+
+use crate::error::{hresult, Result};
+
+/// Writes the string `name` (the default value when `None`) of `HKCU\<path>`,
+/// creating the key.
+pub(crate) fn set_string(path: &str, name: Option<&str>, value: &str) -> Result<()> {
+    let mut key = HKEY::default();
+    unsafe { RegCreateKeyExW(HKEY_CURRENT_USER, &HSTRING::from(path), 0, None, REG_OPTION_NON_VOLATILE, KEY_WRITE, None, &mut key, None) }
+        .ok()
+        .map_err(hresult(format!("RegCreateKeyExW({path})")))?;
+
+    let name = name.map(HSTRING::from);
+    let data: Vec<u8> = value.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+    let result = unsafe { RegSetValueExW(key, name.as_ref().map_or(PCWSTR::null(), |name| PCWSTR(name.as_ptr())), 0, REG_SZ, Some(&data)) }
+        .ok()
+        .map_err(hresult(format!("RegSetValueExW({path})")));
+    let _ = unsafe { RegCloseKey(key) };
+    result
+}
+
+/// Reads the string `name` of `HKCU\<path>`, or `None` when it is missing
+/// or not a string.
+pub(crate) fn get_string(path: &str, name: Option<&str>) -> Option<String> {
+    let path = HSTRING::from(path);
+    let name = name.map(HSTRING::from);
+    let name = name.as_ref().map_or(PCWSTR::null(), |name| PCWSTR(name.as_ptr()));
+
+    let mut size = 0u32;
+    let status = unsafe { RegGetValueW(HKEY_CURRENT_USER, &path, name, RRF_RT_REG_SZ, None, None, Some(&mut size)) };
+    if status.is_err() {
+        return None;
+    }
+
+    let mut data = vec![0u16; (size as usize).div_ceil(2)];
+    let status = unsafe { RegGetValueW(HKEY_CURRENT_USER, &path, name, RRF_RT_REG_SZ, None, Some(data.as_mut_ptr() as *mut _), Some(&mut size)) };
+    if status.is_err() {
+        return None;
+    }
+
+    let length = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+    Some(String::from_utf16_lossy(&data[..length]))
+}
+
+/// Deletes the value `name` of `HKCU\<path>`. A missing value is not an
+/// error.
+pub(crate) fn delete_value(path: &str, name: &str) -> Result<()> {
+    let status = unsafe { RegDeleteKeyValueW(HKEY_CURRENT_USER, &HSTRING::from(path), &HSTRING::from(name)) };
+    if status == ERROR_FILE_NOT_FOUND {
+        return Ok(());
+    }
+    status.ok().map_err(hresult(format!("RegDeleteKeyValueW({path}, {name})")))
+}
+
+/// Deletes `HKCU\<path>` and everything below it. A missing key is not an
+/// error.
+pub(crate) fn delete_tree(path: &str) -> Result<()> {
+    let status = unsafe { RegDeleteTreeW(HKEY_CURRENT_USER, &HSTRING::from(path)) };
+    if status == ERROR_FILE_NOT_FOUND {
+        return Ok(());
+    }
+    status.ok().map_err(hresult(format!("RegDeleteTreeW({path})")))
+}
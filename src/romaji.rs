@@ -0,0 +1,224 @@
+/// Romanization systems accepted by [`to_hiragana_with`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Romanization {
+    /// Hepburn: `shi`, `chi`, `tsu`, `fu`, `ji`, `sha`, `dzu`, and `m`
+    /// before labials (`shimbun`).
+    Hepburn,
+    /// Kunrei-shiki and Nihon-shiki: `si`, `ti`, `tu`, `hu`, `zi`, `sya`,
+    /// `di`, `du`.
+    Kunrei,
+    /// Either spelling.
+    #[default]
+    Any,
+}
+
+impl Romanization {
+    fn hepburn(self) -> bool {
+        self != Self::Kunrei
+    }
+
+    fn kunrei(self) -> bool {
+        self != Self::Hepburn
+    }
+}
+
+/// Longest key in the tables below.
+const MAX_KEY: usize = 4;
+
+const COMMON: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("sa", "さ"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("za", "ざ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("ta", "た"), ("te", "て"), ("to", "と"),
+    ("da", "だ"), ("de", "で"), ("do", "ど"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("ha", "は"), ("hi", "ひ"), ("he", "へ"), ("ho", "ほ"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wi", "うぃ"), ("we", "うぇ"), ("wo", "を"),
+    ("ye", "いぇ"),
+    ("va", "ゔぁ"), ("vi", "ゔぃ"), ("vu", "ゔ"), ("ve", "ゔぇ"), ("vo", "ゔぉ"),
+    ("thi", "てぃ"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("xa", "ぁ"), ("xi", "ぃ"), ("xu", "ぅ"), ("xe", "ぇ"), ("xo", "ぉ"),
+    ("la", "ぁ"), ("li", "ぃ"), ("lu", "ぅ"), ("le", "ぇ"), ("lo", "ぉ"),
+    ("xya", "ゃ"), ("xyu", "ゅ"), ("xyo", "ょ"), ("xwa", "ゎ"),
+    ("lya", "ゃ"), ("lyu", "ゅ"), ("lyo", "ょ"), ("lwa", "ゎ"),
+    ("xtu", "っ"), ("ltu", "っ"), ("xtsu", "っ"), ("ltsu", "っ"),
+    ("-", "ー"),
+];
+
+const HEPBURN: &[(&str, &str)] = &[
+    ("shi", "し"), ("chi", "ち"), ("tsu", "つ"), ("fu", "ふ"), ("ji", "じ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("tsa", "つぁ"), ("tsi", "つぃ"), ("tse", "つぇ"), ("tso", "つぉ"),
+    ("dzu", "づ"),
+];
+
+const KUNREI: &[(&str, &str)] = &[
+    ("si", "し"), ("ti", "ち"), ("tu", "つ"), ("hu", "ふ"), ("zi", "じ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"),
+    ("di", "ぢ"), ("du", "づ"), ("dya", "ぢゃ"), ("dyu", "ぢゅ"), ("dyo", "ぢょ"),
+];
+
+/// Converts romaji to hiragana, accepting Hepburn and Kunrei spellings.
+///
+/// Characters that do not form a syllable are passed through unchanged.
+pub fn to_hiragana(romaji: &str) -> String {
+    to_hiragana_with(romaji, Romanization::Any)
+}
+
+pub fn to_hiragana_with(romaji: &str, system: Romanization) -> String {
+    let original: Vec<char> = romaji.chars().collect();
+    let chars: Vec<char> = original.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let mut output = String::with_capacity(romaji.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c == 'n' {
+            match next {
+                // "n'" and "nn" spell ん explicitly, but "nna" is ん + な.
+                Some('\'') => {
+                    output.push('ん');
+                    i += 2;
+                    continue;
+                }
+                Some('n') if !chars.get(i + 2).is_some_and(|&c| is_vowel(c) || c == 'y') => {
+                    output.push('ん');
+                    i += 2;
+                    continue;
+                }
+                Some(next) if is_vowel(next) || next == 'y' => {}
+                _ => {
+                    output.push('ん');
+                    i += 1;
+                    continue;
+                }
+            }
+        }
+
+        if c == 'm' && system.hepburn() && matches!(next, Some('b' | 'm' | 'p')) {
+            output.push('ん');
+            i += 1;
+            continue;
+        }
+
+        // Doubled consonants, and Hepburn's "tch", spell the sokuon.
+        if next == Some(c) && is_consonant(c) || system.hepburn() && c == 't' && next == Some('c') && chars.get(i + 2) == Some(&'h') {
+            output.push('っ');
+            i += 1;
+            continue;
+        }
+
+        match lookup(&chars[i..], system) {
+            Some((len, kana)) => {
+                output.push_str(kana);
+                i += len;
+            }
+            None => {
+                output.push(original[i]);
+                i += 1;
+            }
+        }
+    }
+
+    output
+}
+
+/// Finds the longest syllable at the start of `chars`.
+fn lookup(chars: &[char], system: Romanization) -> Option<(usize, &'static str)> {
+    let mut tables = vec![COMMON];
+    if system.hepburn() {
+        tables.push(HEPBURN);
+    }
+    if system.kunrei() {
+        tables.push(KUNREI);
+    }
+
+    (1..=MAX_KEY.min(chars.len())).rev().find_map(|len| {
+        let key: String = chars[..len].iter().collect();
+        tables.iter().flat_map(|table| table.iter()).find(|(romaji, _)| *romaji == key).map(|(_, kana)| (len, *kana))
+    })
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+fn is_consonant(c: char) -> bool {
+    c.is_ascii_lowercase() && !is_vowel(c) && c != 'n'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_either_spelling() {
+        assert_eq!(to_hiragana("shinbun"), "しんぶん");
+        assert_eq!(to_hiragana("sinbun"), "しんぶん");
+        assert_eq!(to_hiragana("Tokyo"), "ときょ");
+        assert_eq!(to_hiragana("kyouto"), "きょうと");
+    }
+
+    #[test]
+    fn systems_only_accept_their_own_spellings() {
+        assert_eq!(to_hiragana_with("shi", Romanization::Kunrei), "sひ");
+        assert_eq!(to_hiragana_with("si", Romanization::Hepburn), "sい");
+        assert_eq!(to_hiragana_with("shimbun", Romanization::Hepburn), "しんぶん");
+        assert_eq!(to_hiragana_with("shimbun", Romanization::Kunrei), "sひmぶん");
+    }
+
+    #[test]
+    fn spells_the_moraic_nasal() {
+        assert_eq!(to_hiragana("kan'i"), "かんい");
+        assert_eq!(to_hiragana("kanni"), "かんに");
+        assert_eq!(to_hiragana("konnnichiha"), "こんにちは");
+        assert_eq!(to_hiragana("hon"), "ほん");
+        assert_eq!(to_hiragana("hannya"), "はんにゃ");
+    }
+
+    #[test]
+    fn doubles_consonants_into_the_sokuon() {
+        assert_eq!(to_hiragana("kitte"), "きって");
+        assert_eq!(to_hiragana("matcha"), "まっちゃ");
+        assert_eq!(to_hiragana("vvu"), "っゔ");
+    }
+
+    #[test]
+    fn converts_foreign_sounds() {
+        assert_eq!(to_hiragana("wi we ye"), "うぃ うぇ いぇ");
+        assert_eq!(to_hiragana("tsatsitsetso"), "つぁつぃつぇつぉ");
+        assert_eq!(to_hiragana("thi"), "てぃ");
+        assert_eq!(to_hiragana("vavivuvevo"), "ゔぁゔぃゔゔぇゔぉ");
+        assert_eq!(to_hiragana("tsudzuku"), "つづく");
+        assert_eq!(to_hiragana_with("tuduku", Romanization::Kunrei), "つづく");
+    }
+
+    #[test]
+    fn passes_other_characters_through() {
+        assert_eq!(to_hiragana("a-ru"), "あーる");
+        assert_eq!(to_hiragana("q!"), "q!");
+        assert_eq!(to_hiragana("かna"), "かな");
+    }
+}
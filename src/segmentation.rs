@@ -0,0 +1,123 @@
+//! A reading split into clauses (bunsetsu) whose boundaries can be moved,
+//! like Shift+Left and Shift+Right in the IME, for
+//! [`crate::tsf::TSF::adjust_clause`].
+
+use std::ops::Range;
+
+use crate::{
+    error::{Result, TsfError},
+    felang::Clause,
+};
+
+/// The clauses of a reading, as offsets into it in chars.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Segmentation {
+    reading: Vec<char>,
+    /// The end of each clause; the last one is the reading's length.
+    ends: Vec<usize>,
+}
+
+impl Segmentation {
+    /// `reading` as a single clause.
+    pub fn new(reading: &str) -> Self {
+        let reading: Vec<char> = reading.chars().collect();
+        let ends = vec![reading.len()];
+        Self { reading, ends }
+    }
+
+    /// The clauses of the reading made of `readings`, in order. Empty
+    /// readings are left out.
+    pub fn from_readings(readings: &[&str]) -> Self {
+        let mut segmentation = Self { reading: Vec::new(), ends: Vec::new() };
+        for reading in readings.iter().filter(|reading| !reading.is_empty()) {
+            segmentation.reading.extend(reading.chars());
+            segmentation.ends.push(segmentation.reading.len());
+        }
+        if segmentation.ends.is_empty() {
+            segmentation.ends.push(0);
+        }
+        segmentation
+    }
+
+    /// The clauses `IFELanguage` split a conversion into, see
+    /// [`crate::felang::FeLanguage::segment`].
+    pub fn from_clauses(clauses: &[Clause]) -> Self {
+        Self::from_readings(&clauses.iter().map(|clause| clause.reading.as_str()).collect::<Vec<_>>())
+    }
+
+    pub fn reading(&self) -> String {
+        self.reading.iter().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reading.is_empty()
+    }
+
+    /// The span of clause `index` in the reading, in chars.
+    pub fn span(&self, index: usize) -> Option<Range<usize>> {
+        let end = *self.ends.get(index)?;
+        let start = if index == 0 { 0 } else { self.ends[index - 1] };
+        Some(start..end)
+    }
+
+    /// The reading of clause `index`.
+    pub fn clause(&self, index: usize) -> Option<String> {
+        self.span(index).map(|span| self.reading[span].iter().collect())
+    }
+
+    /// The readings of every clause, in order.
+    pub fn readings(&self) -> Vec<String> {
+        (0..self.len()).filter_map(|index| self.clause(index)).collect()
+    }
+
+    /// Moves the end of clause `index` by `delta` chars: a positive delta
+    /// takes them from the clauses after it, which disappear once empty, and
+    /// a negative one hands them to the next clause, starting one after the
+    /// last. A clause keeps at least one char and never grows past the end
+    /// of the reading.
+    ///
+    /// Returns the clauses whose reading changed, which need converting
+    /// again; empty when the boundary could not move.
+    pub fn move_boundary(&mut self, index: usize, delta: isize) -> Result<Range<usize>> {
+        let span = self.span(index).ok_or(TsfError::InvalidArgument("clause index is out of range"))?;
+        let end = span.end.saturating_add_signed(delta).clamp(span.start + 1, self.reading.len().max(span.start + 1));
+        if end == span.end || end > self.reading.len() {
+            return Ok(index..index);
+        }
+
+        if end > span.end {
+            self.ends.retain(|&other| other <= span.start || other > end);
+            self.ends.insert(index, end);
+        } else if index + 1 == self.len() {
+            self.ends.insert(index, end);
+        } else {
+            self.ends[index] = end;
+        }
+        Ok(index..(index + 2).min(self.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clause_boundaries_stay_within_the_reading() {
+        let mut segmentation = Segmentation::from_readings(&["きょうは", "いしゃに"]);
+
+        assert_eq!(segmentation.move_boundary(0, -10).ok(), Some(0..2));
+        assert_eq!(segmentation.readings(), ["き", "ょうはいしゃに"]);
+        assert_eq!(segmentation.move_boundary(0, -1).ok(), Some(0..0));
+
+        assert_eq!(segmentation.move_boundary(0, 10).ok(), Some(0..1));
+        assert_eq!(segmentation.readings(), ["きょうはいしゃに"]);
+
+        assert_eq!(segmentation.move_boundary(0, -2).ok(), Some(0..2));
+        assert_eq!(segmentation.readings(), ["きょうはいし", "ゃに"]);
+        assert!(segmentation.move_boundary(2, 1).is_err());
+    }
+}
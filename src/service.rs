@@ -0,0 +1,490 @@
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::trace::{debug, error, info, warn};
+use windows::Win32::UI::WindowsAndMessaging::WM_APP;
+
+use crate::{cancel::{CancellationToken, Cancelled}, com::Com, diagnostics::Diagnostics, engine::EngineKind, error::{Result, TsfError}, felang::{Clause, FeLanguage}, events::{EventHub, EventReceiver}, health::{Check, HealthReport}, input_scope::InputScope, latency::{Clock, Latencies, LatencySnapshot, Request}, normalize::Normalization, pump::{MessageLoop, QuitSignal}, timeout::{Operation, Timeout, TimeoutPolicy}, tsf::TSF};
+
+/// Posted to the worker thread whenever a command has been queued.
+const WM_SERVICE_COMMAND: u32 = WM_APP + 1;
+
+/// Called on the worker thread with the outcome of a command.
+pub(crate) type Reply<T> = Box<dyn FnOnce(Result<T>) + Send>;
+
+pub(crate) enum Command {
+    Convert { reading: String, token: CancellationToken, normalization: Normalization, max_candidates: Option<usize>, reply: Reply<Vec<String>> },
+    SetText { text: String, token: CancellationToken, normalization: Normalization, reply: Reply<()> },
+    Reading { text: String, token: CancellationToken, reply: Reply<String> },
+    Segment { reading: String, token: CancellationToken, reply: Reply<Vec<Clause>> },
+    Ping { reply: Reply<()> },
+    ProbeEditSession { token: CancellationToken, reply: Reply<()> },
+}
+
+/// Scheduling lane for a queued request. The worker always drains pending
+/// interactive requests before taking the next batch item, so a single
+/// keystroke-driven conversion never waits behind a bulk job.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Batch,
+}
+
+/// Settings for a [`TsfService`] worker.
+#[derive(Clone, Debug, Default)]
+pub struct ServiceOptions {
+    pub timeouts: TimeoutPolicy,
+    /// Window for coalescing text store notifications; see
+    /// [`TSF::set_notification_batching`].
+    pub notification_batching: Option<Duration>,
+    /// Logging of text store calls on the worker.
+    pub diagnostics: Diagnostics,
+    /// Input scopes of the worker's document; see [`TSF::set_input_scopes`].
+    pub input_scopes: Vec<InputScope>,
+    /// Whether the worker's document is transitory; see [`TSF::transitory`].
+    pub transitory: bool,
+    /// Default limit on the candidates enumerated per conversion; see
+    /// [`TSF::convert_with_limit`].
+    pub max_candidates: Option<usize>,
+    /// Runs the worker in a test mode, answering from its script.
+    #[cfg(feature = "test-utils")]
+    pub test_mode: Option<crate::test_mode::TestMode>,
+}
+
+impl ServiceOptions {
+    fn clock(&self) -> Clock {
+        #[cfg(feature = "test-utils")]
+        if self.test_mode.is_some() {
+            return Clock::Frozen;
+        }
+        Clock::Real
+    }
+}
+
+/// Per-call settings overriding the service defaults.
+#[derive(Clone, Debug, Default)]
+pub struct RequestOptions {
+    pub token: Option<CancellationToken>,
+    pub timeout: Option<Duration>,
+    pub priority: Priority,
+    /// Normalization of the stored text and of the returned candidates.
+    pub normalization: Normalization,
+    /// Overrides [`ServiceOptions::max_candidates`].
+    pub max_candidates: Option<usize>,
+}
+
+impl RequestOptions {
+    pub fn with_token(token: &CancellationToken) -> Self {
+        Self { token: Some(token.clone()), ..Default::default() }
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self { timeout: Some(timeout), ..Default::default() }
+    }
+
+    pub fn with_priority(priority: Priority) -> Self {
+        Self { priority, ..Default::default() }
+    }
+
+    pub fn with_normalization(normalization: Normalization) -> Self {
+        Self { normalization, ..Default::default() }
+    }
+
+    pub fn with_max_candidates(max_candidates: usize) -> Self {
+        Self { max_candidates: Some(max_candidates), ..Default::default() }
+    }
+
+    /// Resolves the effective token and deadline for `operation`.
+    pub(crate) fn resolve(&self, policy: &TimeoutPolicy, operation: Operation) -> (CancellationToken, Option<Duration>) {
+        let token = self.token.clone().unwrap_or_default();
+        (token, self.timeout.or(policy.for_operation(operation)))
+    }
+}
+
+/// Owns a dedicated STA thread running COM, TSF and a message pump.
+///
+/// Every method blocks the caller until the worker has processed the request,
+/// so the service can be used from any thread without touching COM.
+pub struct TsfService {
+    sender: Sender<(Priority, Command)>,
+    quit: QuitSignal,
+    events: EventHub,
+    options: ServiceOptions,
+    latencies: Latencies,
+    /// Times requests; frozen in test mode.
+    clock: Clock,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TsfService {
+    pub fn spawn() -> Result<Self> {
+        Self::spawn_with(ServiceOptions::default())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "service_spawn", level = "debug", err))]
+    pub fn spawn_with(options: ServiceOptions) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+
+        let handle = thread::Builder::new()
+            .name("iatjc-tsf".to_string())
+            .spawn({
+                let options = options.clone();
+                move || run(receiver, ready_sender, options)
+            })
+            .map_err(TsfError::WorkerSpawn)?;
+
+        let (quit, events) = match ready_receiver.recv() {
+            Ok(Ok(ready)) => ready,
+            Ok(Err(e)) => {
+                error!("TSF worker failed to start: {:?}", e);
+                let _ = handle.join();
+                return Err(e);
+            }
+            Err(_) => {
+                error!("TSF worker exited during startup");
+                let _ = handle.join();
+                return Err(TsfError::WorkerStopped);
+            }
+        };
+
+        info!("TSF worker started on thread {}", quit.thread_id());
+        let clock = options.clock();
+        Ok(Self { sender, quit, events, options, latencies: Latencies::new(), clock, handle: Some(handle) })
+    }
+
+    pub fn options(&self) -> &ServiceOptions {
+        &self.options
+    }
+
+    pub fn convert(&self, reading: &str) -> Result<Vec<String>> {
+        self.convert_with(reading, &RequestOptions::default())
+    }
+
+    pub fn convert_cancellable(&self, reading: &str, token: &CancellationToken) -> Result<Vec<String>> {
+        self.convert_with(reading, &RequestOptions::with_token(token))
+    }
+
+    pub fn convert_with(&self, reading: &str, options: &RequestOptions) -> Result<Vec<String>> {
+        self.blocking(Request::Convert, Operation::Conversion, options, |token, reply| Command::Convert {
+            reading: reading.to_string(),
+            token,
+            normalization: options.normalization,
+            max_candidates: options.max_candidates,
+            reply,
+        })
+    }
+
+    pub fn set_text(&self, text: &str) -> Result<()> {
+        self.set_text_with(text, &RequestOptions::default())
+    }
+
+    pub fn set_text_cancellable(&self, text: &str, token: &CancellationToken) -> Result<()> {
+        self.set_text_with(text, &RequestOptions::with_token(token))
+    }
+
+    pub fn set_text_with(&self, text: &str, options: &RequestOptions) -> Result<()> {
+        self.blocking(Request::SetText, Operation::SetText, options, |token, reply| Command::SetText {
+            text: text.to_string(),
+            token,
+            normalization: options.normalization,
+            reply,
+        })
+    }
+
+    /// The reading of `text`, from the TSF document or, when the input
+    /// processor does not record one, from `IFELanguage`.
+    pub fn reading(&self, text: &str) -> Result<String> {
+        self.reading_with(text, &RequestOptions::default())
+    }
+
+    pub fn reading_with(&self, text: &str, options: &RequestOptions) -> Result<String> {
+        self.blocking(Request::Reading, Operation::Conversion, options, |token, reply| Command::Reading { text: text.to_string(), token, reply })
+    }
+
+    /// Splits `reading` into clauses with `IFELanguage`; see
+    /// [`FeLanguage::segment`].
+    pub fn segment(&self, reading: &str) -> Result<Vec<Clause>> {
+        self.segment_with(reading, &RequestOptions::default())
+    }
+
+    pub fn segment_with(&self, reading: &str, options: &RequestOptions) -> Result<Vec<Clause>> {
+        self.blocking(Request::Segment, Operation::Conversion, options, |token, reply| Command::Segment { reading: reading.to_string(), token, reply })
+    }
+
+    /// Events raised on the worker thread.
+    pub fn events(&self) -> &EventHub {
+        &self.events
+    }
+
+    pub fn subscribe(&self) -> EventReceiver {
+        self.events.subscribe()
+    }
+
+    /// Latency percentiles per kind of request, measured from submission to
+    /// reply. Requests that timed out count with their deadline.
+    pub fn latency(&self) -> LatencySnapshot {
+        self.latencies.snapshot()
+    }
+
+    /// Clears the latencies, e.g. once the worker has warmed up.
+    pub fn reset_latency(&self) {
+        self.latencies.reset();
+    }
+
+    /// Checks that the worker thread is alive, that its message pump answers
+    /// a posted message and that a trivial edit session completes, each
+    /// within `deadline`. Meant for supervisors; never fails itself.
+    pub fn health(&self, deadline: Duration) -> HealthReport {
+        let worker_alive = self.handle.as_ref().is_some_and(|handle| !handle.is_finished());
+        let options = RequestOptions::with_timeout(deadline);
+        let probe = |command: fn(CancellationToken, Reply<()>) -> Command| {
+            let started = Instant::now();
+            match self.wait(Operation::Conversion, &options, command) {
+                Ok(()) => Check::Passed { elapsed: self.clock.elapsed(started) },
+                Err(TsfError::Timeout(_)) => Check::TimedOut,
+                Err(e) => Check::Failed(e),
+            }
+        };
+
+        let message_pump = if worker_alive { probe(|_, reply| Command::Ping { reply }) } else { Check::Skipped };
+        let edit_session = if message_pump.passed() { probe(|token, reply| Command::ProbeEditSession { token, reply }) } else { Check::Skipped };
+
+        let report = HealthReport { worker_alive, message_pump, edit_session };
+        if !report.is_healthy() {
+            warn!("TSF worker is unhealthy: {:?}", report);
+        }
+        report
+    }
+
+    /// Runs [`Self::wait`], recording how long the caller waited.
+    fn blocking<T, F>(&self, request: Request, operation: Operation, options: &RequestOptions, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(CancellationToken, Reply<T>) -> Command,
+    {
+        let started = Instant::now();
+        let result = self.wait(operation, options, command);
+        if !matches!(result, Err(TsfError::WorkerStopped)) {
+            self.latencies.record(request, self.clock.elapsed(started));
+        }
+        result
+    }
+
+    /// Submits a command and waits for its reply. When the deadline passes
+    /// the request is cancelled so the worker abandons it at the next safe
+    /// point, and a [`Timeout`] error is returned.
+    fn wait<T, F>(&self, operation: Operation, options: &RequestOptions, command: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(CancellationToken, Reply<T>) -> Command,
+    {
+        let (token, timeout) = options.resolve(&self.options.timeouts, operation);
+        let (sender, receiver) = mpsc::channel();
+        self.submit(options.priority, command(token.clone(), Box::new(move |result| {
+            let _ = sender.send(result);
+        })))?;
+
+        let Some(after) = timeout else {
+            return receiver.recv().map_err(|_| TsfError::WorkerStopped)?;
+        };
+
+        match receiver.recv_timeout(after) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("{:?} timed out after {:?}", operation, after);
+                token.cancel();
+                Err(Timeout { operation, after }.into())
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(TsfError::WorkerStopped),
+        }
+    }
+
+    pub(crate) fn submit(&self, priority: Priority, command: Command) -> Result<()> {
+        if self.sender.send((priority, command)).is_err() {
+            error!("TSF worker is not running");
+            return Err(TsfError::WorkerStopped);
+        }
+
+        self.quit.post(WM_SERVICE_COMMAND, 0, 0)
+    }
+}
+
+impl Drop for TsfService {
+    fn drop(&mut self) {
+        debug!("Stopping TSF worker");
+        if let Err(e) = self.quit.quit() {
+            warn!("Failed to post WM_QUIT to TSF worker: {:?}", e);
+        }
+
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            error!("TSF worker panicked");
+        }
+    }
+}
+
+fn run(receiver: Receiver<(Priority, Command)>, ready: Sender<Result<(QuitSignal, EventHub)>>, options: ServiceOptions) {
+    let com = match Com::new() {
+        Ok(com) => com,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+
+    let mut tsf = if options.transitory { TSF::transitory(&com) } else { TSF::new(&com) };
+    tsf.set_diagnostics(options.diagnostics);
+    tsf.set_max_candidates(options.max_candidates);
+    #[cfg(feature = "test-utils")]
+    tsf.set_test_mode(options.test_mode.as_ref());
+    if let Err(e) = tsf.initialize() {
+        let _ = ready.send(Err(e));
+        return;
+    }
+
+    if let Err(e) = tsf.set_notification_batching(options.notification_batching).and_then(|()| tsf.set_input_scopes(&options.input_scopes)) {
+        let _ = ready.send(Err(e));
+        return;
+    }
+
+    let segmenter = Segmenter::open(&com, &options);
+
+    let quit = QuitSignal::for_current_thread();
+    if ready.send(Ok((quit.clone(), tsf.events().clone()))).is_err() {
+        return;
+    }
+
+    let mut lanes = Lanes::default();
+    let result = MessageLoop::new(quit)
+        .on_message(|msg| {
+            if msg.message != WM_SERVICE_COMMAND {
+                return false;
+            }
+
+            // Refill between items so interactive requests submitted while a
+            // batch is running overtake the rest of the batch.
+            loop {
+                lanes.fill(&receiver);
+                let Some(command) = lanes.next() else {
+                    break;
+                };
+                handle_command(&tsf, &segmenter, command);
+            }
+            true
+        })
+        .run();
+    if let Err(e) = result {
+        error!("TSF worker message loop failed: {:?}", e);
+    }
+
+    if let Err(e) = tsf.uninitialize() {
+        warn!("Failed to uninitialize TSF worker: {:?}", e);
+    }
+    debug!("TSF worker stopped");
+}
+
+#[derive(Default)]
+struct Lanes {
+    interactive: VecDeque<Command>,
+    batch: VecDeque<Command>,
+}
+
+impl Lanes {
+    fn fill(&mut self, receiver: &Receiver<(Priority, Command)>) {
+        while let Ok((priority, command)) = receiver.try_recv() {
+            match priority {
+                Priority::Interactive => self.interactive.push_back(command),
+                Priority::Batch => self.batch.push_back(command),
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<Command> {
+        self.interactive.pop_front().or_else(|| self.batch.pop_front())
+    }
+}
+
+/// How the worker splits readings into clauses, and where it turns for
+/// readings TSF does not have.
+enum Segmenter<'com> {
+    FeLanguage(FeLanguage<'com>),
+    #[cfg(feature = "test-utils")]
+    Script(crate::test_mode::TestMode),
+    Unavailable,
+}
+
+impl<'com> Segmenter<'com> {
+    fn open(com: &'com Com, options: &ServiceOptions) -> Self {
+        #[cfg(feature = "test-utils")]
+        if let Some(test_mode) = &options.test_mode {
+            return Self::Script(test_mode.clone());
+        }
+        #[cfg(not(feature = "test-utils"))]
+        let _ = options;
+
+        // Only needed for readings and segmentation; the worker runs without it.
+        match FeLanguage::new(com) {
+            Ok(felang) => Self::FeLanguage(felang),
+            Err(e) => {
+                warn!("IFELanguage is not available on the TSF worker: {:?}", e);
+                Self::Unavailable
+            }
+        }
+    }
+
+    fn felang(&self) -> Option<&FeLanguage<'com>> {
+        match self {
+            Self::FeLanguage(felang) => Some(felang),
+            _ => None,
+        }
+    }
+
+    fn segment(&self, reading: &str) -> Result<Vec<Clause>> {
+        match self {
+            Self::FeLanguage(felang) => felang.segment(reading),
+            #[cfg(feature = "test-utils")]
+            Self::Script(test_mode) => test_mode.segment(reading).ok_or(TsfError::NotConvertible),
+            Self::Unavailable => Err(TsfError::EngineUnavailable(EngineKind::FeLanguage)),
+        }
+    }
+}
+
+fn handle_command(tsf: &TSF, segmenter: &Segmenter, command: Command) {
+    match command {
+        Command::Convert { reading, token, normalization, max_candidates, reply } => reply(unless_cancelled(&token, || {
+            let reading = normalization.input(reading)?;
+            normalization.output(tsf.convert_with_limit(&reading, &token, max_candidates)?)
+        })),
+        Command::SetText { text, token, normalization, reply } => {
+            reply(unless_cancelled(&token, || tsf.set_text(&normalization.input(text)?)))
+        }
+        Command::Reading { text, token, reply } => reply(unless_cancelled(&token, || match (tsf.reading(&text), segmenter.felang()) {
+            (Ok(reading), _) => Ok(reading),
+            (Err(e), Some(felang)) => {
+                debug!("TSF has no reading, asking IFELanguage: {:?}", e);
+                felang.phonetic(&text)
+            }
+            (Err(e), None) => Err(e),
+        })),
+        Command::Segment { reading, token, reply } => reply(unless_cancelled(&token, || segmenter.segment(&reading))),
+        Command::Ping { reply } => reply(Ok(())),
+        Command::ProbeEditSession { token, reply } => reply(unless_cancelled(&token, || tsf.probe_edit_session())),
+    }
+}
+
+/// Requests cancelled while still queued are answered without running.
+fn unless_cancelled<T>(token: &CancellationToken, run: impl FnOnce() -> Result<T>) -> Result<T> {
+    if token.is_cancelled() {
+        debug!("Skipping cancelled request");
+        return Err(Cancelled.into());
+    }
+
+    run()
+}
@@ -0,0 +1,166 @@
+use std::{cell::RefCell, collections::HashSet};
+
+use crate::trace::{debug, warn};
+use windows::Win32::{
+    Foundation::{BOOL, TRUE},
+    UI::TextServices::{ITfCandidateListUIElement, ITfCompartmentEventSink, ITfCompartmentEventSink_Impl, ITfCompartmentMgr, ITfInputProcessorProfileActivationSink, ITfInputProcessorProfileActivationSink_Impl, ITfThreadFocusSink, ITfThreadFocusSink_Impl, ITfUIElementMgr, ITfUIElementSink, ITfUIElementSink_Impl, GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION, GUID_COMPARTMENT_KEYBOARD_INPUTMODE_SENTENCE, GUID_COMPARTMENT_KEYBOARD_OPENCLOSE, HKL, TF_IPSINK_FLAG_ACTIVE},
+};
+use windows_core::{implement, Interface, GUID};
+
+use crate::{error::{call, com_entry, Result}, events::{EventHub, TsfEvent}, notifications::Notification};
+
+/// Receives thread manager notifications and turns them into [`TsfEvent`]s.
+#[implement(ITfUIElementSink, ITfInputProcessorProfileActivationSink, ITfThreadFocusSink)]
+pub struct ThreadMgrEventSink {
+    ui_element_mgr: Option<ITfUIElementMgr>,
+    candidate_elements: RefCell<HashSet<u32>>,
+    events: EventHub
+}
+
+impl ThreadMgrEventSink {
+    pub fn new(ui_element_mgr: Option<ITfUIElementMgr>, events: EventHub) -> Self {
+        Self {
+            ui_element_mgr,
+            candidate_elements: RefCell::new(HashSet::new()),
+            events
+        }
+    }
+
+    fn candidate_list(&self, id: u32) -> Option<ITfCandidateListUIElement> {
+        let element = unsafe { self.ui_element_mgr.as_ref()?.GetUIElement(id).ok()? };
+        element.cast().ok()
+    }
+
+    fn publish_candidates(&self, id: u32) {
+        let Some(candidate_list) = self.candidate_list(id) else {
+            return;
+        };
+        self.candidate_elements.borrow_mut().insert(id);
+
+        let result = unsafe {
+            candidate_list.GetCount().and_then(|count| {
+                let candidates = (0..count)
+                    .map(|index| candidate_list.GetString(index).map(|s| s.to_string()))
+                    .collect::<windows_core::Result<Vec<_>>>()?;
+                let selection = candidate_list.GetSelection()?;
+                Ok((candidates, selection))
+            })
+        };
+
+        match result {
+            Ok((candidates, selection)) => self.events.emit(TsfEvent::CandidatesUpdated { candidates, selection }),
+            Err(e) => warn!("Failed to read candidate list element {}: {:?}", id, e)
+        }
+    }
+}
+
+impl ITfUIElementSink_Impl for ThreadMgrEventSink {
+    fn BeginUIElement(&self, dwuielementid: u32, pbshow: *mut BOOL) -> windows_core::Result<()> {
+        com_entry("ITfUIElementSink::BeginUIElement", || {
+            debug!("UI element {} began", dwuielementid);
+            self.events.notifications().push(Notification::BeginUIElement { id: dwuielementid });
+            if !pbshow.is_null() {
+                unsafe {
+                    *pbshow = TRUE;
+                }
+            }
+
+            self.publish_candidates(dwuielementid);
+            Ok(())
+        })
+    }
+
+    fn UpdateUIElement(&self, dwuielementid: u32) -> windows_core::Result<()> {
+        com_entry("ITfUIElementSink::UpdateUIElement", || {
+            self.events.notifications().push(Notification::UpdateUIElement { id: dwuielementid });
+            self.publish_candidates(dwuielementid);
+            Ok(())
+        })
+    }
+
+    fn EndUIElement(&self, dwuielementid: u32) -> windows_core::Result<()> {
+        com_entry("ITfUIElementSink::EndUIElement", || {
+            debug!("UI element {} ended", dwuielementid);
+            self.events.notifications().push(Notification::EndUIElement { id: dwuielementid });
+            if self.candidate_elements.borrow_mut().remove(&dwuielementid) {
+                self.events.emit(TsfEvent::CandidatesClosed);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl ITfInputProcessorProfileActivationSink_Impl for ThreadMgrEventSink {
+    fn OnActivated(&self, _dwprofiletype: u32, langid: u16, clsid: *const GUID, _catid: *const GUID, guidprofile: *const GUID, _hkl: HKL, dwflags: u32) -> windows_core::Result<()> {
+        com_entry("ITfInputProcessorProfileActivationSink::OnActivated", || {
+            let clsid = unsafe { clsid.as_ref().copied().unwrap_or_default() };
+            let profile = unsafe { guidprofile.as_ref().copied().unwrap_or_default() };
+            let active = (dwflags & TF_IPSINK_FLAG_ACTIVE) != 0;
+
+            debug!("Profile {:?} of {:?} activated: {}", profile, clsid, active);
+            self.events.notifications().push(Notification::ProfileActivated { profile, active });
+            self.events.emit(TsfEvent::ProfileActivated { clsid, profile, langid, active });
+            Ok(())
+        })
+    }
+}
+
+impl ITfThreadFocusSink_Impl for ThreadMgrEventSink {
+    fn OnSetThreadFocus(&self) -> windows_core::Result<()> {
+        com_entry("ITfThreadFocusSink::OnSetThreadFocus", || {
+            debug!("Thread focus set");
+            self.events.notifications().push(Notification::ThreadFocus { focused: true });
+            self.events.emit(TsfEvent::ThreadFocusChanged { focused: true });
+            Ok(())
+        })
+    }
+
+    fn OnKillThreadFocus(&self) -> windows_core::Result<()> {
+        com_entry("ITfThreadFocusSink::OnKillThreadFocus", || {
+            debug!("Thread focus killed");
+            self.events.notifications().push(Notification::ThreadFocus { focused: false });
+            self.events.emit(TsfEvent::ThreadFocusChanged { focused: false });
+            Ok(())
+        })
+    }
+}
+
+/// Thread manager compartments watched by [`CompartmentEventSink`].
+pub const COMPARTMENTS: [GUID; 3] = [GUID_COMPARTMENT_KEYBOARD_OPENCLOSE, GUID_COMPARTMENT_KEYBOARD_INPUTMODE_CONVERSION, GUID_COMPARTMENT_KEYBOARD_INPUTMODE_SENTENCE];
+
+/// Reports compartment changes as [`TsfEvent::CompartmentChanged`].
+#[implement(ITfCompartmentEventSink)]
+pub struct CompartmentEventSink {
+    compartment_mgr: ITfCompartmentMgr,
+    events: EventHub
+}
+
+impl CompartmentEventSink {
+    pub fn new(compartment_mgr: ITfCompartmentMgr, events: EventHub) -> Self {
+        Self { compartment_mgr, events }
+    }
+
+    fn value(&self, compartment: &GUID) -> Result<Option<i32>> {
+        let compartment = call!(self.compartment_mgr, GetCompartment(compartment))?;
+        let value = call!(compartment, GetValue())?;
+        Ok(i32::try_from(&value).ok())
+    }
+}
+
+impl ITfCompartmentEventSink_Impl for CompartmentEventSink {
+    fn OnChange(&self, rguid: *const GUID) -> windows_core::Result<()> {
+        com_entry("ITfCompartmentEventSink::OnChange", || {
+            let compartment = unsafe { rguid.as_ref().copied().unwrap_or_default() };
+            self.events.notifications().push(Notification::CompartmentChange { compartment });
+            let value = self.value(&compartment).unwrap_or_else(|e| {
+                warn!("Failed to read compartment {:?}: {:?}", compartment, e);
+                None
+            });
+
+            debug!("Compartment {:?} changed to {:?}", compartment, value);
+            self.events.emit(TsfEvent::CompartmentChanged { compartment, value });
+            Ok(())
+        })
+    }
+}
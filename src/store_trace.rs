@@ -0,0 +1,327 @@
+//! A structured trace of the `ITextStoreACP` calls TSF makes into the store,
+//! and a replayer that re-executes such a trace against a store.
+//!
+//! With the `record` feature, [`Recorder`] writes one JSON object per call
+//! (method, arguments, lock state, result) to a JSONL file and
+//! [`read_records`] loads it back, so a trace captured in the field becomes
+//! a test case. Document text never enters the trace; calls that carry text
+//! record its length and are replayed with placeholder text.
+
+use std::{cell::RefCell, rc::Rc};
+
+use windows::Win32::{
+    Foundation::{BOOL, POINT, RECT, S_OK},
+    UI::TextServices::{ITextStoreACP, ITextStoreACPSink, ITextStoreACPSink_Impl, TEXT_STORE_LOCK_FLAGS, TEXT_STORE_TEXT_CHANGE_FLAGS, TS_AE_END, TS_ATTRVAL, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_RUNINFO, TS_SELECTIONSTYLE, TS_SELECTION_ACP, TS_TEXTCHANGE, TsLayoutCode},
+};
+use windows_core::{implement, IUnknown, Interface, HRESULT};
+
+use crate::{
+    error::{call, Result},
+    locking::Lock,
+    text_store::TfTextStore,
+};
+
+/// One `ITextStoreACP` call and its scalar arguments.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "record", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "record", serde(tag = "method", rename_all_fields = "camelCase"))]
+pub enum StoreCall {
+    AdviseSink { mask: u32 },
+    UnadviseSink,
+    RequestLock { flags: u32 },
+    GetStatus,
+    QueryInsert { start: i32, end: i32, cch: u32 },
+    GetSelection { index: u32, count: u32 },
+    SetSelection { start: i32, end: i32 },
+    GetText { start: i32, end: i32, max: u32 },
+    /// `cch` placeholder characters stand in for the text.
+    SetText { flags: u32, start: i32, end: i32, cch: u32 },
+    GetFormattedText { start: i32, end: i32 },
+    GetEmbedded { pos: i32 },
+    QueryInsertEmbedded,
+    InsertEmbedded { flags: u32, start: i32, end: i32 },
+    /// `cch` placeholder characters stand in for the text.
+    InsertTextAtSelection { flags: u32, cch: u32 },
+    InsertEmbeddedAtSelection { flags: u32 },
+    /// `attrs` is the number of attributes filtered on; they are replayed
+    /// without a filter.
+    RequestSupportedAttrs { flags: u32, attrs: u32 },
+    RequestAttrsAtPosition { pos: i32, attrs: u32, flags: u32 },
+    RequestAttrsTransitioningAtPosition { pos: i32, attrs: u32, flags: u32 },
+    FindNextAttrTransition { start: i32, halt: i32, attrs: u32, flags: u32 },
+    RetrieveRequestedAttrs { count: u32 },
+    GetEndACP,
+    GetActiveView,
+    GetACPFromPoint { view: u32, x: i32, y: i32, flags: u32 },
+    GetTextExt { view: u32, start: i32, end: i32 },
+    GetScreenExt { view: u32 },
+    GetWnd { view: u32 },
+}
+
+impl StoreCall {
+    /// The interface and method name, e.g. `ITextStoreACP::GetText`.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::AdviseSink { .. } => "ITextStoreACP::AdviseSink",
+            Self::UnadviseSink => "ITextStoreACP::UnadviseSink",
+            Self::RequestLock { .. } => "ITextStoreACP::RequestLock",
+            Self::GetStatus => "ITextStoreACP::GetStatus",
+            Self::QueryInsert { .. } => "ITextStoreACP::QueryInsert",
+            Self::GetSelection { .. } => "ITextStoreACP::GetSelection",
+            Self::SetSelection { .. } => "ITextStoreACP::SetSelection",
+            Self::GetText { .. } => "ITextStoreACP::GetText",
+            Self::SetText { .. } => "ITextStoreACP::SetText",
+            Self::GetFormattedText { .. } => "ITextStoreACP::GetFormattedText",
+            Self::GetEmbedded { .. } => "ITextStoreACP::GetEmbedded",
+            Self::QueryInsertEmbedded => "ITextStoreACP::QueryInsertEmbedded",
+            Self::InsertEmbedded { .. } => "ITextStoreACP::InsertEmbedded",
+            Self::InsertTextAtSelection { .. } => "ITextStoreACP::InsertTextAtSelection",
+            Self::InsertEmbeddedAtSelection { .. } => "ITextStoreACP::InsertEmbeddedAtSelection",
+            Self::RequestSupportedAttrs { .. } => "ITextStoreACP::RequestSupportedAttrs",
+            Self::RequestAttrsAtPosition { .. } => "ITextStoreACP::RequestAttrsAtPosition",
+            Self::RequestAttrsTransitioningAtPosition { .. } => "ITextStoreACP::RequestAttrsTransitioningAtPosition",
+            Self::FindNextAttrTransition { .. } => "ITextStoreACP::FindNextAttrTransition",
+            Self::RetrieveRequestedAttrs { .. } => "ITextStoreACP::RetrieveRequestedAttrs",
+            Self::GetEndACP => "ITextStoreACP::GetEndACP",
+            Self::GetActiveView => "ITextStoreACP::GetActiveView",
+            Self::GetACPFromPoint { .. } => "ITextStoreACP::GetACPFromPoint",
+            Self::GetTextExt { .. } => "ITextStoreACP::GetTextExt",
+            Self::GetScreenExt { .. } => "ITextStoreACP::GetScreenExt",
+            Self::GetWnd { .. } => "ITextStoreACP::GetWnd",
+        }
+    }
+}
+
+/// A traced call.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "record", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record {
+    /// Position in the trace, starting at 0.
+    pub seq: u64,
+    #[cfg_attr(feature = "record", serde(flatten))]
+    pub call: StoreCall,
+    pub lock: Lock,
+    /// The HRESULT returned; for `RequestLock` the one reported through
+    /// `phrSession`.
+    pub result: i32,
+}
+
+/// Writes a [`Record`] per store call as JSON lines.
+#[cfg(feature = "record")]
+#[derive(Clone)]
+pub struct Recorder {
+    output: std::sync::Arc<std::sync::Mutex<Box<dyn std::io::Write + Send>>>,
+    next: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+#[cfg(feature = "record")]
+impl Recorder {
+    pub fn new(output: impl std::io::Write + Send + 'static) -> Self {
+        Self { output: std::sync::Arc::new(std::sync::Mutex::new(Box::new(output))), next: Default::default() }
+    }
+
+    /// Records into a new file at `path`, replacing any existing one.
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Ok(Self::new(std::io::BufWriter::new(std::fs::File::create(path)?)))
+    }
+
+    pub(crate) fn record(&self, call: StoreCall, lock: Lock, result: HRESULT) {
+        let seq = self.next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let record = Record { seq, call, lock, result: result.0 };
+        let mut output = crate::error::Recover::recover(self.output.lock());
+        let written = serde_json::to_writer(&mut *output, &record).map_err(std::io::Error::from).and_then(|()| output.write_all(b"\n")).and_then(|()| output.flush());
+        if let Err(e) = written {
+            crate::trace::warn!("Failed to record {}: {:?}", call.method(), e);
+        }
+    }
+}
+
+/// Reads a trace written by [`Recorder`]. Blank lines are skipped.
+#[cfg(feature = "record")]
+pub fn read_records(input: impl std::io::BufRead) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line).map_err(std::io::Error::from)?);
+    }
+    Ok(records)
+}
+
+/// A replayed call that returned something else than it did when recorded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Divergence {
+    pub seq: u64,
+    pub call: StoreCall,
+    pub expected: HRESULT,
+    pub actual: HRESULT,
+}
+
+/// Creates a standalone store holding `text`, selected, for replaying into.
+pub fn store(text: &str) -> Result<ITextStoreACP> {
+    let store = TfTextStore::new();
+    store.set_string(text)?;
+    Ok(store.into())
+}
+
+/// Re-executes `records` against `store` and returns the calls whose result
+/// changed.
+///
+/// Each call runs under the lock recorded with it, which the replayer
+/// requests synchronously through its own advise sink; `store` must not have
+/// another sink advised, such as one from a [`crate::tsf::TSF`]. Sink and
+/// lock management calls are not replayed, nor are those involving embedded
+/// objects.
+pub fn replay(store: &ITextStoreACP, records: &[Record]) -> Result<Vec<Divergence>> {
+    let pending: Pending = Rc::new(RefCell::new(None));
+    let sink: ITextStoreACPSink = ReplaySink { pending: pending.clone() }.into();
+    let unknown: IUnknown = sink.cast().map_err(crate::error::hresult("ITextStoreACPSink::cast"))?;
+    call!(store, AdviseSink(&ITextStoreACPSink::IID, &unknown, 0))?;
+
+    let mut divergences = Vec::new();
+    for record in records {
+        let Some(actual) = run_locked(store, &pending, record.call, record.lock) else {
+            continue;
+        };
+        if actual.0 != record.result {
+            divergences.push(Divergence { seq: record.seq, call: record.call, expected: HRESULT(record.result), actual });
+        }
+    }
+
+    call!(store, UnadviseSink(&unknown))?;
+    Ok(divergences)
+}
+
+type Pending = Rc<RefCell<Option<Box<dyn FnOnce()>>>>;
+
+/// Runs `call` under `lock`, or returns `None` when it is not replayed.
+fn run_locked(store: &ITextStoreACP, pending: &Pending, call: StoreCall, lock: Lock) -> Option<HRESULT> {
+    let flags = match lock {
+        Lock::None => return execute(store, call),
+        Lock::Read => TS_LF_READ.0,
+        Lock::ReadWrite => TS_LF_READWRITE.0,
+    };
+
+    let result = Rc::new(RefCell::new(None));
+    let (output, target) = (result.clone(), store.clone());
+    *pending.borrow_mut() = Some(Box::new(move || *output.borrow_mut() = Some(execute(&target, call))));
+
+    // Without the lock the recorded call cannot be reproduced at all, which
+    // is reported as the lock request's failure.
+    let granted = match call!(store, RequestLock(flags | TS_LF_SYNC)) {
+        Ok(session) if session.is_ok() => result.borrow_mut().take(),
+        Ok(session) => Some(Some(session)),
+        Err(e) => Some(e.hresult()),
+    };
+    pending.borrow_mut().take();
+    granted.flatten()
+}
+
+fn execute(store: &ITextStoreACP, call: StoreCall) -> Option<HRESULT> {
+    let placeholder = |cch: u32| vec![u16::from(b'x'); cch as usize];
+    let result = unsafe {
+        match call {
+            StoreCall::AdviseSink { .. }
+            | StoreCall::UnadviseSink
+            | StoreCall::RequestLock { .. }
+            | StoreCall::GetEmbedded { .. }
+            | StoreCall::QueryInsertEmbedded
+            | StoreCall::InsertEmbedded { .. }
+            | StoreCall::InsertEmbeddedAtSelection { .. } => return None,
+            StoreCall::GetStatus => store.GetStatus().map(drop),
+            StoreCall::QueryInsert { start, end, cch } => {
+                let (mut result_start, mut result_end) = (0, 0);
+                store.QueryInsert(start, end, cch, &mut result_start, &mut result_end)
+            }
+            StoreCall::GetSelection { index, count } => {
+                let mut selection = vec![TS_SELECTION_ACP::default(); count as usize];
+                let mut fetched = 0;
+                store.GetSelection(index, &mut selection, &mut fetched)
+            }
+            StoreCall::SetSelection { start, end } => {
+                let style = TS_SELECTIONSTYLE { ase: TS_AE_END, fInterimChar: BOOL(0) };
+                store.SetSelection(&[TS_SELECTION_ACP { acpStart: start, acpEnd: end, style }])
+            }
+            StoreCall::GetText { start, end, max } => {
+                let mut text = vec![0u16; max as usize];
+                let mut runs = [TS_RUNINFO::default(); 1];
+                let (mut copied, mut run_count, mut next) = (0, 0, 0);
+                store.GetText(start, end, &mut text, &mut copied, &mut runs, &mut run_count, &mut next)
+            }
+            StoreCall::SetText { flags, start, end, cch } => store.SetText(flags, start, end, &placeholder(cch)).map(drop),
+            StoreCall::GetFormattedText { start, end } => store.GetFormattedText(start, end).map(drop),
+            StoreCall::InsertTextAtSelection { flags, cch } => {
+                let (mut start, mut end, mut change) = (0, 0, TS_TEXTCHANGE::default());
+                store.InsertTextAtSelection(flags, &placeholder(cch), &mut start, &mut end, &mut change)
+            }
+            StoreCall::RequestSupportedAttrs { flags, .. } => store.RequestSupportedAttrs(flags, &[]),
+            StoreCall::RequestAttrsAtPosition { pos, flags, .. } => store.RequestAttrsAtPosition(pos, &[], flags),
+            StoreCall::RequestAttrsTransitioningAtPosition { pos, flags, .. } => store.RequestAttrsTransitioningAtPosition(pos, &[], flags),
+            StoreCall::FindNextAttrTransition { start, halt, flags, .. } => {
+                let (mut next, mut found, mut offset) = (0, BOOL(0), 0);
+                store.FindNextAttrTransition(start, halt, &[], flags, &mut next, &mut found, &mut offset)
+            }
+            StoreCall::RetrieveRequestedAttrs { count } => {
+                let mut values: Vec<TS_ATTRVAL> = (0..count).map(|_| TS_ATTRVAL::default()).collect();
+                let mut fetched = 0;
+                store.RetrieveRequestedAttrs(&mut values, &mut fetched)
+            }
+            StoreCall::GetEndACP => store.GetEndACP().map(drop),
+            StoreCall::GetActiveView => store.GetActiveView().map(drop),
+            StoreCall::GetACPFromPoint { view, x, y, flags } => store.GetACPFromPoint(view, &POINT { x, y }, flags).map(drop),
+            StoreCall::GetTextExt { view, start, end } => {
+                let (mut rect, mut clipped) = (RECT::default(), BOOL(0));
+                store.GetTextExt(view, start, end, &mut rect, &mut clipped)
+            }
+            StoreCall::GetScreenExt { view } => store.GetScreenExt(view).map(drop),
+            StoreCall::GetWnd { view } => store.GetWnd(view).map(drop),
+        }
+    };
+    Some(result.map_or_else(|e| e.code(), |()| S_OK))
+}
+
+/// Runs the pending call when the replayer's lock is granted.
+#[implement(ITextStoreACPSink)]
+struct ReplaySink {
+    pending: Pending,
+}
+
+impl ITextStoreACPSink_Impl for ReplaySink {
+    fn OnTextChange(&self, _dwflags: TEXT_STORE_TEXT_CHANGE_FLAGS, _pchange: *const TS_TEXTCHANGE) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnSelectionChange(&self) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnLayoutChange(&self, _lcode: TsLayoutCode, _vcview: u32) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnStatusChange(&self, _dwflags: u32) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnAttrsChange(&self, _acpstart: i32, _acpend: i32, _cattrs: u32, _paattrs: *const windows_core::GUID) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnLockGranted(&self, _dwlockflags: TEXT_STORE_LOCK_FLAGS) -> windows_core::Result<()> {
+        let pending = self.pending.borrow_mut().take();
+        if let Some(pending) = pending {
+            pending();
+        }
+        Ok(())
+    }
+
+    fn OnStartEditTransaction(&self) -> windows_core::Result<()> {
+        Ok(())
+    }
+
+    fn OnEndEditTransaction(&self) -> windows_core::Result<()> {
+        Ok(())
+    }
+}
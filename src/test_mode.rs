@@ -0,0 +1,41 @@
+//! A deterministic mode for applications testing their own code against
+//! this crate on machines without a Japanese IME. Enabled by the
+//! `test-utils` feature.
+//!
+//! A [`TestMode`] only applies to the instances it is handed to, so tests
+//! running in parallel do not see each other's scripts: a
+//! [`crate::tsf::TSF`] given one with [`crate::tsf::TSF::set_test_mode`]
+//! converts through a [`FakeTip`](crate::fake_tip::FakeTip) and takes
+//! readings from the script, an
+//! [`crate::engine::EngineChain::with_test_mode`] holds
+//! [`FakeEngine`](crate::fake_tip::FakeEngine)s, and a
+//! [`crate::service::TsfService`] spawned with
+//! [`crate::service::ServiceOptions::test_mode`] also splits a reading into
+//! one scripted clause instead of asking `IFELanguage`. The durations these
+//! instances measure, in conversion metrics, service latencies and health
+//! checks, are all zero.
+
+use crate::{fake_tip::Script, felang::Clause};
+
+/// Scripted engines and frozen clocks for the instances it is applied to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TestMode {
+    script: Script,
+}
+
+impl TestMode {
+    pub fn new(script: Script) -> Self {
+        Self { script }
+    }
+
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// `reading` as a single clause surfacing as its first scripted
+    /// candidate.
+    pub(crate) fn segment(&self, reading: &str) -> Option<Vec<Clause>> {
+        let surface = self.script.candidates(reading)?.first()?;
+        Some(vec![Clause { reading: reading.to_string(), surface: surface.clone(), start: 0 }])
+    }
+}
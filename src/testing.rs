@@ -0,0 +1,475 @@
+//! Support for testing the text store without an input processor, for this
+//! crate's tests and for applications testing their own integration code.
+//! Enabled by the `test-utils` feature.
+//!
+//! [`MockSink`] stands in for the `ITextStoreACPSink` TSF would advise: it
+//! records every notification and runs scripted actions, or fails, when a
+//! lock is granted or the text changes. [`StoreState`] builds a standalone store to advise it on,
+//! and [`FakeEngine`] and [`FakeTip`] answer conversions from a [`Script`].
+//!
+//! [`Harness`] goes the other way: it brings up the real pipeline, with the
+//! installed input processor, for end-to-end tests.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    ops::Range,
+    rc::Rc,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use windows::Win32::{
+    Foundation::{BOOL, E_UNEXPECTED, S_OK},
+    System::Com::CoTaskMemFree,
+    UI::TextServices::{
+        ITextStoreACP, ITextStoreACPSink, ITfInputScope, ITextStoreACPSink_Impl, TEXT_STORE_LOCK_FLAGS, TEXT_STORE_TEXT_CHANGE_FLAGS, TS_AE_END, TS_DEFAULT_SELECTION, TS_LF_SYNC, TS_RUNINFO, TS_SELECTIONSTYLE, TS_SELECTION_ACP, TS_TEXTCHANGE, TsLayoutCode,
+    },
+};
+use windows_core::{implement, AsImpl, IUnknown, Interface, GUID, HRESULT, VARIANT};
+
+use crate::{
+    com::Com,
+    error::{call, hresult, Result},
+    events::EventHub,
+    input_scope::InputScope,
+    layout::LayoutProvider,
+    pump,
+    text_store::TfTextStore,
+    tsf::TSF,
+    window::{HiddenWindow, WindowKind},
+};
+
+pub use crate::fake_tip::{FakeEngine, FakeTip, Finalized, Script};
+
+/// `MAKELANGID(LANG_JAPANESE, SUBLANG_DEFAULT)`.
+const LANGID_JAPANESE: u16 = 0x0411;
+
+/// A notification received by a [`MockSink`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Received {
+    TextChange { flags: u32, start: i32, old_end: i32, new_end: i32 },
+    SelectionChange,
+    LayoutChange { code: i32, view: u32 },
+    StatusChange { flags: u32 },
+    AttrsChange { start: i32, end: i32, count: u32 },
+    LockGranted { flags: u32 },
+    StartEditTransaction,
+    EndEditTransaction,
+}
+
+/// A scripted call into the store from the sink.
+type Action = Box<dyn FnOnce(&ITextStoreACP)>;
+
+/// What the sink does the next time a lock is granted.
+enum LockAction {
+    Run(Action),
+    Fail(HRESULT),
+}
+
+#[derive(Default)]
+struct MockState {
+    store: Option<ITextStoreACP>,
+    received: Vec<Received>,
+    script: VecDeque<LockAction>,
+    text_change_script: VecDeque<Action>,
+}
+
+/// An `ITextStoreACPSink` advised on a store for the lifetime of this value.
+///
+/// Without a script, granted locks are recorded and released right away.
+pub struct MockSink {
+    state: Rc<RefCell<MockState>>,
+    sink: ITextStoreACPSink,
+}
+
+impl MockSink {
+    /// Advises a new sink on `store` for the `TS_AS_*` notifications in
+    /// `mask`.
+    pub fn advise(store: &ITextStoreACP, mask: u32) -> Result<Self> {
+        let state = Rc::new(RefCell::new(MockState { store: Some(store.clone()), ..Default::default() }));
+        let sink: ITextStoreACPSink = MockSinkImpl { state: state.clone() }.into();
+        let unknown: IUnknown = sink.cast().map_err(hresult("ITextStoreACPSink::cast"))?;
+        call!(store, AdviseSink(&ITextStoreACPSink::IID, &unknown, mask))?;
+        Ok(Self { state, sink })
+    }
+
+    /// Advises the same sink again with a new `TS_AS_*` mask.
+    pub fn set_mask(&self, mask: u32) -> Result<()> {
+        let Some(store) = self.state.borrow().store.clone() else {
+            return Ok(());
+        };
+        let unknown: IUnknown = self.sink.cast().map_err(hresult("ITextStoreACPSink::cast"))?;
+        call!(store, AdviseSink(&ITextStoreACPSink::IID, &unknown, mask))
+    }
+
+    /// The notifications received so far, oldest first.
+    pub fn received(&self) -> Vec<Received> {
+        self.state.borrow().received.clone()
+    }
+
+    /// Returns and forgets the notifications received so far.
+    pub fn take_received(&self) -> Vec<Received> {
+        std::mem::take(&mut self.state.borrow_mut().received)
+    }
+
+    /// Runs `action` inside the next lock granted, after queued actions.
+    pub fn on_lock(&self, action: impl FnOnce(&ITextStoreACP) + 'static) {
+        self.state.borrow_mut().script.push_back(LockAction::Run(Box::new(action)));
+    }
+
+    /// Runs `action` from the next `OnTextChange`, after queued actions, like
+    /// an input processor reacting to an edit made by the application.
+    pub fn on_text_change(&self, action: impl FnOnce(&ITextStoreACP) + 'static) {
+        self.state.borrow_mut().text_change_script.push_back(Box::new(action));
+    }
+
+    /// Makes `OnLockGranted` fail with `hr` for the next lock granted, after
+    /// queued actions.
+    pub fn fail_lock(&self, hr: HRESULT) {
+        self.state.borrow_mut().script.push_back(LockAction::Fail(hr));
+    }
+
+    /// Scripted actions that have not run yet.
+    pub fn pending(&self) -> usize {
+        let state = self.state.borrow();
+        state.script.len() + state.text_change_script.len()
+    }
+
+    /// Requests a synchronous lock of `flags` on `store` and runs `body`
+    /// under it, after queued actions. Fails with the error `RequestLock`
+    /// returned, or with `E_UNEXPECTED` when the lock was not granted.
+    pub fn under_lock<T: 'static>(&self, store: &ITextStoreACP, flags: u32, body: impl FnOnce(&ITextStoreACP) -> T + 'static) -> std::result::Result<T, HRESULT> {
+        let output = Rc::new(RefCell::new(None));
+        let slot = output.clone();
+        self.on_lock(move |store| *slot.borrow_mut() = Some(body(store)));
+        let hr = unsafe { store.RequestLock(flags | TS_LF_SYNC) }.map_err(|e| e.code())?;
+        if hr != S_OK {
+            return Err(hr);
+        }
+        output.borrow_mut().take().ok_or(E_UNEXPECTED)
+    }
+}
+
+impl Drop for MockSink {
+    fn drop(&mut self) {
+        let store = self.state.borrow_mut().store.take();
+        if let Some(store) = store {
+            let _ = unsafe { store.UnadviseSink(&self.sink) };
+        }
+    }
+}
+
+/// The initial state of a standalone store made by [`StoreState::build`].
+#[derive(Clone, Default)]
+pub struct StoreState {
+    pub text: String,
+    /// Selected range in ACPs, clamped to the text. All of it when `None`.
+    pub selection: Option<(i32, i32)>,
+    /// Window for coalescing sink notifications; see
+    /// [`TSF::set_notification_batching`].
+    pub notification_batching: Option<Duration>,
+    /// Receives the store's events, like [`TSF::events`] does.
+    pub events: Option<EventHub>,
+    pub input_scopes: Vec<InputScope>,
+    /// Input scopes of ranges of the text, in ACPs.
+    pub range_input_scopes: Vec<(Range<i32>, Vec<InputScope>)>,
+    /// Creates a [`TfTextStore::transitory`] store.
+    pub transitory: bool,
+    /// `TS_SD_*` flags; read-only and loading when `None`.
+    pub status: Option<u32>,
+}
+
+impl StoreState {
+    pub fn with_text(text: &str) -> Self {
+        Self { text: text.to_string(), ..Default::default() }
+    }
+
+    /// Creates a store in this state on the current thread, which must have
+    /// initialized COM.
+    pub fn build(&self) -> Result<ITextStoreACP> {
+        let store = if self.transitory { TfTextStore::transitory() } else { TfTextStore::new() };
+        store.set_string(&self.text)?;
+        if let Some((start, end)) = self.selection {
+            store.select(start, end);
+        }
+        if let Some(status) = self.status {
+            store.set_status(status)?;
+        }
+        store.set_notification_batching(self.notification_batching);
+        if let Some(events) = &self.events {
+            store.set_events(events.clone());
+        }
+        store.set_input_scopes(&self.input_scopes)?;
+        for (range, scopes) in &self.range_input_scopes {
+            store.set_range_input_scopes(range.start, range.end, scopes)?;
+        }
+        Ok(store.into())
+    }
+}
+
+/// Creates a store holding `text` with a [`MockSink`] advised for `mask`,
+/// on the current thread, which must have initialized COM.
+pub fn advised(text: &str, mask: u32) -> Result<(ITextStoreACP, MockSink)> {
+    let store = StoreState::with_text(text).build()?;
+    let sink = MockSink::advise(&store, mask)?;
+    Ok((store, sink))
+}
+
+/// Replaces the text of `store` and selects it, notifying the advised sink
+/// like an edit made through [`crate::tsf::TSF::set_text`]. Returns `false`
+/// while the store is locked.
+///
+/// # Safety
+///
+/// `store` must be one of the crate's stores, created by
+/// [`crate::store_trace::store`] or [`StoreState::build`], or returned by
+/// [`crate::tsf::TSF::text_store`].
+pub unsafe fn set_text(store: &ITextStoreACP, text: &str) -> Result<bool> {
+    let store: &TfTextStore = unsafe { store.as_impl() };
+    Ok(store.set_string(text)?)
+}
+
+/// Clears `TS_SD_LOADING` on `store`, like [`crate::tsf::TSF::finish_loading`].
+///
+/// # Safety
+///
+/// As for [`set_text`].
+pub unsafe fn finish_loading(store: &ITextStoreACP) -> Result<bool> {
+    let store: &TfTextStore = unsafe { store.as_impl() };
+    Ok(store.finish_loading()?)
+}
+
+/// Declares the input scopes of `store`'s document, like
+/// [`crate::tsf::TSF::set_input_scopes`]. Returns `false` while the store is
+/// locked.
+///
+/// # Safety
+///
+/// As for [`set_text`].
+pub unsafe fn set_input_scopes(store: &ITextStoreACP, scopes: &[InputScope]) -> Result<bool> {
+    let store: &TfTextStore = unsafe { store.as_impl() };
+    Ok(store.set_input_scopes(scopes)?)
+}
+
+/// Declares the input scopes of `range` of `store`, like
+/// [`crate::tsf::TSF::set_range_input_scopes`]. Returns `false` while the
+/// store is locked.
+///
+/// # Safety
+///
+/// As for [`set_text`].
+pub unsafe fn set_range_input_scopes(store: &ITextStoreACP, range: Range<i32>, scopes: &[InputScope]) -> Result<bool> {
+    let store: &TfTextStore = unsafe { store.as_impl() };
+    Ok(store.set_range_input_scopes(range.start, range.end, scopes)?)
+}
+
+/// Answers layout queries on `store` from `provider`, like
+/// [`crate::tsf::TSF::set_layout_provider`].
+///
+/// # Safety
+///
+/// As for [`set_text`].
+pub unsafe fn set_layout_provider(store: &ITextStoreACP, provider: Option<Arc<dyn LayoutProvider>>) -> Result<()> {
+    let store: &TfTextStore = unsafe { store.as_impl() };
+    Ok(store.set_layout_provider(provider)?)
+}
+
+/// `GetText(start, end)` on `store` into a buffer of `max` units, returning
+/// the text and the next ACP. Must be called under a read lock.
+pub fn get_text(store: &ITextStoreACP, start: i32, end: i32, max: usize) -> std::result::Result<(String, i32), HRESULT> {
+    let mut text = vec![0u16; max];
+    let mut runs = [TS_RUNINFO::default(); 1];
+    let (mut copied, mut run_count, mut next) = (0, 0, 0);
+    unsafe { store.GetText(start, end, &mut text, &mut copied, &mut runs, &mut run_count, &mut next) }.map_err(|e| e.code())?;
+    Ok((String::from_utf16_lossy(&text[..copied as usize]), next))
+}
+
+/// The whole text of `store`. Must be called under a read lock.
+pub fn read_text(store: &ITextStoreACP) -> std::result::Result<String, HRESULT> {
+    let end = unsafe { store.GetEndACP() }.map_err(|e| e.code())?;
+    get_text(store, 0, -1, end.max(0) as usize).map(|(text, _)| text)
+}
+
+/// The default selection of `store`, in ACPs. Must be called under a read
+/// lock.
+pub fn selection(store: &ITextStoreACP) -> std::result::Result<(i32, i32), HRESULT> {
+    let mut selection = [TS_SELECTION_ACP::default(); 1];
+    let mut fetched = 0;
+    unsafe { store.GetSelection(TS_DEFAULT_SELECTION, &mut selection, &mut fetched) }.map_err(|e| e.code())?;
+    Ok((selection[0].acpStart, selection[0].acpEnd))
+}
+
+/// Selects `start..end` of `store`, with the active end at `end`. Must be
+/// called under a read/write lock.
+pub fn set_selection(store: &ITextStoreACP, start: i32, end: i32) -> std::result::Result<(), HRESULT> {
+    let style = TS_SELECTIONSTYLE { ase: TS_AE_END, fInterimChar: BOOL(0) };
+    unsafe { store.SetSelection(&[TS_SELECTION_ACP { acpStart: start, acpEnd: end, style }]) }.map_err(|e| e.code())
+}
+
+/// The input scopes in `value`, an attribute value reported for
+/// `GUID_PROP_INPUTSCOPE` by a store or a context owner. Empty when it holds
+/// none.
+pub fn input_scopes(value: &VARIANT) -> Result<Vec<InputScope>> {
+    let Ok(unknown) = IUnknown::try_from(value) else {
+        return Ok(Vec::new());
+    };
+    let scopes: ITfInputScope = unknown.cast().map_err(hresult("ITfInputScope::cast"))?;
+    let (mut raw, mut count) = (std::ptr::null_mut(), 0);
+    call!(scopes, GetInputScopes(&mut raw, &mut count))?;
+    if raw.is_null() {
+        return Ok(Vec::new());
+    }
+    let reported = unsafe { std::slice::from_raw_parts(raw, count as usize) }.iter().copied().map(InputScope::from_raw).collect();
+    unsafe { CoTaskMemFree(Some(raw as *const _)) };
+    Ok(reported)
+}
+
+/// An initialized [`TSF`] next to a hidden top-level window standing in for
+/// the application's, on a thread whose messages are pumped by the test.
+///
+/// Tests that need a Japanese input processor should check
+/// [`Harness::japanese_ime`] and be marked `#[ignore]` so that they only run
+/// when asked for on machines that have one.
+pub struct Harness<'com> {
+    tsf: TSF<'com>,
+    _window: HiddenWindow,
+}
+
+impl<'com> Harness<'com> {
+    pub fn new(com: &'com Com) -> Result<Self> {
+        pump::ensure_message_queue();
+        let window = HiddenWindow::new(WindowKind::Hidden)?;
+        let mut tsf = TSF::new(com);
+        tsf.initialize()?;
+        let harness = Self { tsf, _window: window };
+        harness.pump();
+        Ok(harness)
+    }
+
+    pub fn tsf(&self) -> &TSF<'com> {
+        &self.tsf
+    }
+
+    pub fn tsf_mut(&mut self) -> &mut TSF<'com> {
+        &mut self.tsf
+    }
+
+    /// Whether the active profile is a Japanese input processor.
+    pub fn japanese_ime(&self) -> bool {
+        self.tsf.profile().is_some_and(|profile| profile.langid == LANGID_JAPANESE)
+    }
+
+    /// Dispatches the messages queued so far. Returns `false` once WM_QUIT
+    /// has been posted.
+    pub fn pump(&self) -> bool {
+        pump::pump_pending()
+    }
+
+    /// Pumps until `done` returns `true` or `timeout` passes, returning
+    /// whether it did.
+    pub fn pump_until(&self, timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !self.pump() {
+                return done();
+            }
+            if done() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Converts `reading` and lets the input processor handle whatever it
+    /// posted meanwhile.
+    pub fn convert(&self, reading: &str) -> Result<Vec<String>> {
+        let candidates = self.tsf.convert(reading)?;
+        self.pump();
+        Ok(candidates)
+    }
+}
+
+impl Drop for Harness<'_> {
+    fn drop(&mut self) {
+        let _ = self.tsf.uninitialize();
+        self.pump();
+    }
+}
+
+#[implement(ITextStoreACPSink)]
+struct MockSinkImpl {
+    state: Rc<RefCell<MockState>>,
+}
+
+impl MockSinkImpl {
+    fn receive(&self, received: Received) {
+        self.state.borrow_mut().received.push(received);
+    }
+}
+
+impl ITextStoreACPSink_Impl for MockSinkImpl {
+    fn OnTextChange(&self, dwflags: TEXT_STORE_TEXT_CHANGE_FLAGS, pchange: *const TS_TEXTCHANGE) -> windows_core::Result<()> {
+        let change = unsafe { pchange.as_ref().copied().unwrap_or_default() };
+        self.receive(Received::TextChange { flags: dwflags.0, start: change.acpStart, old_end: change.acpOldEnd, new_end: change.acpNewEnd });
+
+        let (action, store) = {
+            let mut state = self.state.borrow_mut();
+            (state.text_change_script.pop_front(), state.store.clone())
+        };
+        if let (Some(action), Some(store)) = (action, store) {
+            action(&store);
+        }
+        Ok(())
+    }
+
+    fn OnSelectionChange(&self) -> windows_core::Result<()> {
+        self.receive(Received::SelectionChange);
+        Ok(())
+    }
+
+    fn OnLayoutChange(&self, lcode: TsLayoutCode, vcview: u32) -> windows_core::Result<()> {
+        self.receive(Received::LayoutChange { code: lcode.0, view: vcview });
+        Ok(())
+    }
+
+    fn OnStatusChange(&self, dwflags: u32) -> windows_core::Result<()> {
+        self.receive(Received::StatusChange { flags: dwflags });
+        Ok(())
+    }
+
+    fn OnAttrsChange(&self, acpstart: i32, acpend: i32, cattrs: u32, _paattrs: *const GUID) -> windows_core::Result<()> {
+        self.receive(Received::AttrsChange { start: acpstart, end: acpend, count: cattrs });
+        Ok(())
+    }
+
+    fn OnLockGranted(&self, dwlockflags: TEXT_STORE_LOCK_FLAGS) -> windows_core::Result<()> {
+        self.receive(Received::LockGranted { flags: dwlockflags.0 });
+
+        // Nothing may stay borrowed while the action re-enters the store.
+        let (action, store) = {
+            let mut state = self.state.borrow_mut();
+            (state.script.pop_front(), state.store.clone())
+        };
+        match (action, store) {
+            (Some(LockAction::Run(action)), Some(store)) => action(&store),
+            (Some(LockAction::Fail(hr)), _) => return Err(hr.into()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn OnStartEditTransaction(&self) -> windows_core::Result<()> {
+        self.receive(Received::StartEditTransaction);
+        Ok(())
+    }
+
+    fn OnEndEditTransaction(&self) -> windows_core::Result<()> {
+        self.receive(Received::EndEditTransaction);
+        Ok(())
+    }
+}
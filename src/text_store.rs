@@ -1,382 +1,1164 @@
-use std::sync::{atomic::{AtomicI32, Ordering}, Mutex, RwLock};
+use std::{future::Future, mem::ManuallyDrop, ops::Range, pin::Pin, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, RwLock}, task::{Context, Poll, Waker}, time::{Duration, Instant}};
 
-use windows::{Win32::{Foundation::{HWND, POINT, RECT, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL, E_UNEXPECTED, S_OK, BOOL}, System::{Com::{IDataObject, FORMATETC}, Ole::CONNECT_E_ADVISELIMIT}, UI::TextServices::{ITextStoreACP, ITextStoreACPSink, ITextStoreACP_Impl, TEXT_STORE_LOCK_FLAGS, TS_AS_TEXT_CHANGE, TS_ATTRVAL, TS_E_NOLOCK, TS_E_SYNCHRONOUS, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_RT_PLAIN, TS_SD_LOADING, TS_SD_READONLY, TS_SELECTION_ACP, TS_SS_REGIONS, TS_STATUS, TS_ST_NONE, TS_TEXTCHANGE}}};
-use windows_core::{implement, IUnknown, IUnknownImpl, Interface, HRESULT};
+use windows::{Win32::{Foundation::{HWND, POINT, RECT, E_INVALIDARG, E_NOTIMPL, E_UNEXPECTED, S_OK, BOOL}, System::{Com::{IDataObject, FORMATETC}, Ole::{CONNECT_E_ADVISELIMIT, CONNECT_E_NOCONNECTION}}, UI::TextServices::{ITextStoreACP, ITextStoreACPSink, ITextStoreACP_Impl, ITfCompositionView, ITfContextOwnerCompositionSink, ITfContextOwnerCompositionSink_Impl, ITfRange, GUID_PROP_INPUTSCOPE, TEXT_STORE_LOCK_FLAGS, TS_AE_END, TS_AS_ATTR_CHANGE, TS_AS_LAYOUT_CHANGE, TS_AS_SEL_CHANGE, TS_AS_STATUS_CHANGE, TS_AS_TEXT_CHANGE, TS_ATTR_FIND_BACKWARDS, TS_ATTR_FIND_WANT_END, TS_ATTR_FIND_WANT_VALUE, TS_ATTRVAL, TS_DEFAULT_SELECTION, TS_E_INVALIDPOS, TS_E_NOLAYOUT, TS_E_NOLOCK, TS_E_NOSELECTION, TS_E_SYNCHRONOUS, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_S_ASYNC, TS_SD_LOADING, TS_SD_READONLY, TS_SELECTIONSTYLE, TS_SELECTION_ACP, TS_SS_REGIONS, TS_SS_TRANSITORY, TS_STATUS, TS_ST_NONE, TS_TEXTCHANGE}, UI::WindowsAndMessaging::{KillTimer, SetTimer}}};
+use windows_core::{implement, IUnknown, Interface, GUID, HRESULT, VARIANT};
+
+use crate::{acp, affinity::{ThreadAffinity, WrongThread}, diagnostics::{Diagnostics, SlowCall}, dump::StoreDump, error::{com_entry, Recover}, events::{EventHub, TsfEvent}, input_scope::{InputScope, InputScopes, ScopeMap}, layout::{self, LayoutChangeKind, LayoutProvider}, locking::{Lock, LockState, Request}, metrics::metrics, notifications::Notification, region, store_trace::StoreCall};
+
+/// Timer id used on the store window to flush batched notifications.
+pub(crate) const NOTIFY_TIMER_ID: usize = 0x1a7c;
+
+/// Attributes the store reports through the `ITextStoreACP` attribute calls.
+const SUPPORTED_ATTRS: &[GUID] = &[GUID_PROP_INPUTSCOPE];
 
 fn flag_check(value: u32, flag: u32) -> bool {
     (value & flag) == flag
 }
 
+fn attr_filter<'a>(count: u32, attrs: *const GUID) -> &'a [GUID] {
+    if attrs.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(attrs, count as usize) }
+    }
+}
+
 struct AdviceSink {
     text_store_sink: Option<ITextStoreACPSink>,
+    /// The sink's identity `IUnknown`, compared to tell advising callers
+    /// apart.
+    identity: Option<IUnknown>,
     mask: u32
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-enum LockType {
-    None,
-    Read,
-    ReadWrite,
+/// Sink notifications held back while a batching window is open.
+#[derive(Default)]
+struct NotifyBatch {
+    window: Option<Duration>,
+    pending: Option<(TS_TEXTCHANGE, Instant)>
 }
 
-impl From<u32> for LockType {
-    fn from(flags: u32) -> Self {
-        if flag_check(flags, TS_LF_READWRITE.0) {
-            LockType::ReadWrite
-        } else if flag_check(flags, TS_LF_READ.0) {
-            LockType::Read
-        } else {
-            LockType::None
+/// Folds `next` into `prev` so that the result describes both edits in the
+/// coordinates of the text before `prev` and after `next`.
+fn merge_text_change(prev: TS_TEXTCHANGE, next: TS_TEXTCHANGE) -> TS_TEXTCHANGE {
+    let prev_delta = prev.acpNewEnd - prev.acpOldEnd;
+    let next_delta = next.acpNewEnd - next.acpOldEnd;
+
+    TS_TEXTCHANGE {
+        acpStart: prev.acpStart.min(next.acpStart),
+        acpOldEnd: prev.acpOldEnd.max(next.acpOldEnd - prev_delta),
+        acpNewEnd: next.acpNewEnd.max(prev.acpNewEnd + next_delta)
+    }
+}
+
+#[derive(Default)]
+struct WaiterState {
+    granted: bool,
+    cancelled: bool,
+    waker: Option<Waker>
+}
+
+/// A Rust-side caller waiting in the pending-lock queue.
+#[derive(Default)]
+struct LockWaiter {
+    state: Mutex<WaiterState>
+}
+
+impl LockWaiter {
+    /// Hands the lock to the waiter. Returns `false` if it gave up already.
+    fn grant(&self) -> bool {
+        let mut state = self.state.lock().recover();
+        if state.cancelled {
+            return false;
+        }
+
+        state.granted = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
         }
+        true
     }
 }
 
-#[implement(ITextStoreACP)]
+/// Whom a queued lock request is granted to.
+enum PendingLock {
+    Sink,
+    Waiter(Arc<LockWaiter>)
+}
+
+#[implement(ITextStoreACP, ITfContextOwnerCompositionSink)]
 pub struct TfTextStore {
-    ref_count: AtomicI32,
     advice_sink: Mutex<AdviceSink>,
-    input_text: RwLock<String>,
-    lock_state: RwLock<(LockType, u32)>
+    input_text: RwLock<Vec<u16>>,
+    selection: RwLock<(i32, i32)>,
+    window: RwLock<HWND>,
+    events: RwLock<EventHub>,
+    lock_state: Mutex<LockState<PendingLock>>,
+    processing_pending: AtomicBool,
+    notify_batch: Mutex<NotifyBatch>,
+    diagnostics: RwLock<Diagnostics>,
+    input_scopes: RwLock<ScopeMap>,
+    /// Attributes queued by the `Request*Attrs*` calls for
+    /// `RetrieveRequestedAttrs`, with the values to report. No scopes
+    /// report an empty value.
+    requested_attrs: Mutex<Vec<(GUID, Vec<InputScope>)>>,
+    /// Reported as `TS_SS_TRANSITORY`; see [`TfTextStore::transitory`].
+    transitory: bool,
+    /// `TS_SD_*` flags reported by `GetStatus`.
+    status: RwLock<u32>,
+    layout: RwLock<Option<Arc<dyn LayoutProvider>>>,
+    #[cfg(feature = "record")]
+    recorder: RwLock<Option<crate::store_trace::Recorder>>,
+    affinity: ThreadAffinity
 }
 
 impl TfTextStore {
     pub fn new() -> Self {
         Self {
-            ref_count: AtomicI32::new(1),
             advice_sink: Mutex::new(AdviceSink {
                 text_store_sink: None,
+                identity: None,
                 mask: 0
             }),
-            input_text: RwLock::new(String::new()),
-            lock_state: RwLock::new((LockType::None, 0))
+            input_text: RwLock::new(Vec::new()),
+            selection: RwLock::new((0, 0)),
+            window: RwLock::new(HWND(0)),
+            events: RwLock::new(EventHub::new()),
+            lock_state: Mutex::new(LockState::new()),
+            processing_pending: AtomicBool::new(false),
+            notify_batch: Mutex::new(NotifyBatch::default()),
+            diagnostics: RwLock::new(Diagnostics::default()),
+            input_scopes: RwLock::new(ScopeMap::default()),
+            requested_attrs: Mutex::new(Vec::new()),
+            transitory: false,
+            status: RwLock::new(TS_SD_READONLY | TS_SD_LOADING),
+            layout: RwLock::new(None),
+            #[cfg(feature = "record")]
+            recorder: RwLock::new(None),
+            affinity: ThreadAffinity::current()
         }
     }
 
-    pub fn is_locked(&self, flags: u32) -> bool {
-        let lock_state = self.lock_state.read().unwrap();
-        lock_state.0 != LockType::None && flag_check(lock_state.1, flags)
+    /// A store for a short-lived document, such as one holding only the text
+    /// being reconverted. Input processors keep less state for it, e.g. no
+    /// learning from or reconversion of text outside a composition.
+    pub fn transitory() -> Self {
+        Self { transitory: true, ..Self::new() }
     }
 
-    pub fn try_lock(&self, flags: u32) -> Result<LockGuard, ()> {
-        let mut lock_state = self.lock_state.write().unwrap();
+    pub fn is_transitory(&self) -> bool {
+        self.transitory
+    }
 
-        if lock_state.0 == LockType::None {
-            let lock_type = LockType::from(flags);
-            *lock_state = (lock_type, flags);
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, LockState<PendingLock>> {
+        self.lock_state.lock().recover()
+    }
 
+    pub fn is_locked(&self, flags: u32) -> bool {
+        self.lock_state().is_locked(flags)
+    }
+
+    pub fn try_lock(&self, flags: u32) -> Result<LockGuard<'_>, ()> {
+        if self.acquire(flags) {
             Ok(LockGuard { text_store: self })
         } else {
             Err(())
         }
     }
 
-    pub fn set_string(&self, text: &str) -> bool {
-        if let Ok(_lock) = self.try_lock(TS_LF_READWRITE.0) {
-            let old_len = self.input_text.read().unwrap().len() as i32;
+    /// Waits for the lock in the same queue as asynchronous TSF requests.
+    ///
+    /// The future resolves once every earlier request has been granted and
+    /// released. Dropping it before completion gives up its place.
+    pub fn lock_async(&self, flags: u32) -> LockFuture<'_> {
+        let waiter = Arc::new(LockWaiter::default());
+
+        let mut lock_state = self.lock_state();
+        if lock_state.acquire(flags) {
+            waiter.grant();
+        } else {
+            lock_state.enqueue(flags, PendingLock::Waiter(waiter.clone()));
+        }
+        drop(lock_state);
+
+        LockFuture { text_store: self, waiter, done: false }
+    }
+
+    fn acquire(&self, flags: u32) -> bool {
+        self.lock_state().acquire(flags)
+    }
+
+    fn release(&self) {
+        self.lock_state().release();
+        self.process_pending();
+    }
+
+    /// Grants queued lock requests in order until one of them keeps holding
+    /// the lock or the queue is empty.
+    fn process_pending(&self) {
+        if self.processing_pending.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        loop {
+            let next = self.lock_state().grant_next();
+            let Some((flags, next)) = next else {
+                break;
+            };
+
+            match next {
+                PendingLock::Sink => {
+                    let (sink, _) = self.sink();
+                    if let Some(sink) = sink {
+                        self.log(Notification::LockGranted { flags });
+                        self.diagnostics().timed(SlowCall::LockGranted, || unsafe { sink.OnLockGranted(TEXT_STORE_LOCK_FLAGS(flags)) }).ok();
+                    }
+                    self.lock_state().release();
+                }
+                PendingLock::Waiter(waiter) => {
+                    if waiter.grant() {
+                        break;
+                    }
+                    self.lock_state().release();
+                }
+            }
+        }
+
+        self.processing_pending.store(false, Ordering::SeqCst);
+    }
+
+    /// Replaces the whole document and selects it, notifying the advised sink
+    /// once the store lock has been released again.
+    pub fn set_string(&self, text: &str) -> Result<bool, WrongThread> {
+        self.affinity.check()?;
 
-            let mut input_text = self.input_text.write().unwrap();
-            *input_text = text.to_string();
+        let text_change = if let Ok(_lock) = self.try_lock(TS_LF_READWRITE.0) {
+            let mut input_text = self.input_text.write().recover();
+            let old_len = input_text.len() as i32;
+            *input_text = text.encode_utf16().collect();
             let new_len = input_text.len() as i32;
 
-            let text_change = TS_TEXTCHANGE {
+            *self.selection.write().recover() = (0, new_len);
+            self.scopes().clear_ranges();
+
+            TS_TEXTCHANGE {
                 acpStart: 0,
                 acpOldEnd: old_len,
                 acpNewEnd: new_len
-            };
+            }
+        } else {
+            return Ok(false);
+        };
 
-            drop(input_text);
+        self.emit(TsfEvent::TextChanged {
+            start: text_change.acpStart,
+            old_end: text_change.acpOldEnd,
+            new_end: text_change.acpNewEnd
+        });
 
-            let advice_sink = self.advice_sink.lock().unwrap();
-            if flag_check(advice_sink.mask, TS_AS_TEXT_CHANGE) {
-                if let Some(sink) = &advice_sink.text_store_sink {
-                    unsafe {
-                        sink.OnTextChange(TS_ST_NONE, &text_change).ok();
-                    }
+        self.queue_notification(text_change);
+        Ok(true)
+    }
+
+    /// The ranges of the document's regions, in ACPs.
+    pub fn regions(&self) -> Vec<Range<i32>> {
+        region::ranges(&self.input_text.read().recover())
+    }
+
+    /// Selects `start..end`, clamped to the document, without notifying the
+    /// advised sink.
+    #[cfg(feature = "test-utils")]
+    pub(crate) fn select(&self, start: i32, end: i32) {
+        let text = self.input_text.read().recover();
+        let len = text.len() as i32;
+        let start = start.clamp(0, len);
+        let selection = acp::snap_range_to_code_points(&text, start, end.clamp(start, len));
+        *self.selection.write().recover() = selection;
+    }
+
+    /// Coalesces sink notifications raised within `window` of the first
+    /// pending edit into a single OnTextChange/OnSelectionChange pair.
+    /// `None` notifies after every edit and flushes anything pending.
+    pub fn set_notification_batching(&self, window: Option<Duration>) {
+        self.notify_batch.lock().recover().window = window;
+        if window.is_none() {
+            self.flush_notifications();
+        }
+    }
+
+    /// Delivers any batched notification right away.
+    pub fn flush_notifications(&self) {
+        let pending = self.notify_batch.lock().recover().pending.take();
+        if let Some((text_change, _)) = pending {
+            self.stop_notify_timer();
+            self.notify(&text_change);
+        }
+    }
+
+    fn queue_notification(&self, text_change: TS_TEXTCHANGE) {
+        let mut batch = self.notify_batch.lock().recover();
+        let Some(window) = batch.window else {
+            drop(batch);
+            self.notify(&text_change);
+            return;
+        };
+
+        match batch.pending {
+            Some((pending, deadline)) => {
+                batch.pending = Some((merge_text_change(pending, text_change), deadline));
+                if Instant::now() >= deadline {
+                    drop(batch);
+                    self.flush_notifications();
                 }
             }
+            None => {
+                batch.pending = Some((text_change, Instant::now() + window));
+                drop(batch);
+                self.start_notify_timer(window);
+            }
+        }
+    }
 
-            true
-        } else {
-            false
+    fn start_notify_timer(&self, window: Duration) {
+        let hwnd = *self.window.read().recover();
+        if hwnd.0 != 0 {
+            unsafe {
+                SetTimer(hwnd, NOTIFY_TIMER_ID, window.as_millis().clamp(1, u32::MAX as u128) as u32, None);
+            }
         }
     }
 
-    pub fn cast_iunknown(&self) -> windows_core::Result<IUnknown> {
-        unsafe {
-            self.cast()
+    fn stop_notify_timer(&self) {
+        let hwnd = *self.window.read().recover();
+        if hwnd.0 != 0 {
+            unsafe {
+                let _ = KillTimer(hwnd, NOTIFY_TIMER_ID);
+            }
         }
     }
-}
 
-pub struct LockGuard<'a> {
-    text_store: &'a TfTextStore
-}
+    fn notify(&self, text_change: &TS_TEXTCHANGE) {
+        let (sink, mask) = self.sink();
+        if let Some(sink) = sink {
+            if flag_check(mask, TS_AS_TEXT_CHANGE) {
+                self.log(Notification::TextChange { start: text_change.acpStart, old_end: text_change.acpOldEnd, new_end: text_change.acpNewEnd });
+                unsafe {
+                    sink.OnTextChange(TS_ST_NONE, text_change).ok();
+                }
+            }
+            if flag_check(mask, TS_AS_SEL_CHANGE) {
+                self.log(Notification::SelectionChange);
+                unsafe {
+                    sink.OnSelectionChange().ok();
+                }
+            }
+        }
+    }
 
-impl <'a> Drop for LockGuard<'a> {
-    fn drop(&mut self) {
-        let mut lock_state = self.text_store.lock_state.write().unwrap();
-        *lock_state = (LockType::None, 0);
+    /// The `TS_SD_*` flags reported by `GetStatus`. Stores start read-only
+    /// and loading.
+    pub fn status(&self) -> u32 {
+        *self.status.read().recover()
     }
-}
 
-impl IUnknownImpl for TfTextStore {
-    type Impl = Self;
+    /// Replaces the `TS_SD_*` flags, sending OnStatusChange to the advised
+    /// sink if they changed. Returns whether they did.
+    pub fn set_status(&self, flags: u32) -> Result<bool, WrongThread> {
+        self.affinity.check()?;
 
-    fn AddRef(&self) -> u32 {
-        self.ref_count.fetch_add(1, Ordering::SeqCst) as u32
+        let previous = std::mem::replace(&mut *self.status.write().recover(), flags);
+        if previous == flags {
+            return Ok(false);
+        }
+
+        let (sink, mask) = self.sink();
+        if let Some(sink) = sink && flag_check(mask, TS_AS_STATUS_CHANGE) {
+            self.log(Notification::StatusChange { flags });
+            unsafe {
+                sink.OnStatusChange(flags).ok();
+            }
+        }
+        Ok(true)
+    }
+
+    /// Clears `TS_SD_LOADING` once the initial content is in place, telling
+    /// the input processor that the document is ready. Returns `false` if it
+    /// had already finished loading.
+    pub fn finish_loading(&self) -> Result<bool, WrongThread> {
+        self.set_status(self.status() & !TS_SD_LOADING)
     }
 
-    unsafe fn Release(&self) -> u32 {
-        let count = self.ref_count.fetch_sub(1, Ordering::SeqCst) - 1;
-        count as u32
+    fn scopes(&self) -> std::sync::RwLockWriteGuard<'_, ScopeMap> {
+        self.input_scopes.write().recover()
     }
 
-    unsafe fn GetTrustLevel(&self, value: *mut i32) -> windows_core::HRESULT {
-        if !value.is_null() {
+    /// Declares the input scopes of the whole document, notifying the
+    /// advised sink of the characters whose scopes changed. Returns `false`
+    /// while the store is locked.
+    pub fn set_input_scopes(&self, scopes: &[InputScope]) -> Result<bool, WrongThread> {
+        self.affinity.check()?;
+
+        let previous = {
+            let Ok(_lock) = self.try_lock(TS_LF_READWRITE.0) else {
+                return Ok(false);
+            };
+            let mut map = self.scopes();
+            let previous = map.clone();
+            map.set_document(scopes);
+            previous
+        };
+        self.notify_scopes_changed(&previous);
+        Ok(true)
+    }
+
+    pub fn input_scopes(&self) -> Vec<InputScope> {
+        self.scopes().document().to_vec()
+    }
+
+    /// Declares the input scopes of `start..end`, overriding the document's.
+    /// They last until the document is replaced. Returns `false` while the
+    /// store is locked.
+    pub fn set_range_input_scopes(&self, start: i32, end: i32, scopes: &[InputScope]) -> Result<bool, WrongThread> {
+        self.affinity.check()?;
+
+        let previous = {
+            let Ok(_lock) = self.try_lock(TS_LF_READWRITE.0) else {
+                return Ok(false);
+            };
+            let (start, end) = {
+                let text = self.input_text.read().recover();
+                let start = acp::clamp(&text, start);
+                acp::snap_range_to_code_points(&text, start, acp::clamp(&text, end).max(start))
+            };
+            let mut map = self.scopes();
+            let previous = map.clone();
+            map.set_range(start..end, scopes);
+            previous
+        };
+        self.notify_scopes_changed(&previous);
+        Ok(true)
+    }
+
+    /// Sends OnAttrsChange for `GUID_PROP_INPUTSCOPE` over the characters
+    /// whose scopes differ from `previous`, if any.
+    fn notify_scopes_changed(&self, previous: &ScopeMap) {
+        let len = self.input_text.read().recover().len() as i32;
+        let Some(changed) = self.scopes().changed(previous, len) else {
+            return;
+        };
+
+        let (sink, mask) = self.sink();
+        if let Some(sink) = sink && flag_check(mask, TS_AS_ATTR_CHANGE) {
+            let attrs = [GUID_PROP_INPUTSCOPE];
+            self.log(Notification::AttrsChange { start: changed.start, end: changed.end, count: attrs.len() as u32 });
             unsafe {
-                *value = 0;
+                sink.OnAttrsChange(changed.start, changed.end, &attrs).ok();
             }
+        }
+    }
 
-             S_OK
-        } else {
-            E_INVALIDARG
+    /// The input scopes of the character at `acp`.
+    pub fn input_scopes_at(&self, acp: i32) -> Vec<InputScope> {
+        self.scopes().at(acp).to_vec()
+    }
+
+    /// Replaces the queue read by `RetrieveRequestedAttrs` with the supported
+    /// attributes among `filter`, or all of them for an empty filter, and
+    /// the values `value` gives them. Attributes without one are skipped.
+    fn request_attrs(&self, filter: &[GUID], value: impl Fn(&GUID) -> Option<Vec<InputScope>>) {
+        let filter = if filter.is_empty() { SUPPORTED_ATTRS } else { filter };
+        let mut requested = self.requested_attrs.lock().recover();
+        requested.clear();
+        requested.extend(filter.iter().filter(|id| SUPPORTED_ATTRS.contains(id)).filter_map(|id| Some((*id, value(id)?))));
+    }
+
+    /// Answers `GetTextExt` and `GetScreenExt` from `provider`, notifying the
+    /// advised sink that the view's layout was created, replaced or, for
+    /// `None`, destroyed.
+    pub fn set_layout_provider(&self, provider: Option<Arc<dyn LayoutProvider>>) -> Result<(), WrongThread> {
+        self.affinity.check()?;
+
+        let installed = provider.is_some();
+        let previous = std::mem::replace(&mut *self.layout.write().recover(), provider);
+        match (previous.is_some(), installed) {
+            (false, true) => self.notify_layout_changed(layout::VIEW, LayoutChangeKind::Create),
+            (true, true) => self.notify_layout_changed(layout::VIEW, LayoutChangeKind::Change),
+            (true, false) => self.notify_layout_changed(layout::VIEW, LayoutChangeKind::Destroy),
+            (false, false) => Ok(()),
         }
     }
 
-    unsafe fn QueryInterface(&self, iid: *const windows_core::GUID, interface: *mut *mut std::ffi::c_void) -> HRESULT {
-        let iid = unsafe { &*iid };
+    fn layout(&self) -> Option<Arc<dyn LayoutProvider>> {
+        self.layout.read().recover().clone()
+    }
+
+    pub fn has_layout(&self) -> bool {
+        self.layout.read().recover().is_some()
+    }
+
+    /// Sends OnLayoutChange for `view` to the advised sink.
+    pub fn notify_layout_changed(&self, view: u32, kind: LayoutChangeKind) -> Result<(), WrongThread> {
+        self.affinity.check()?;
 
-        if iid == &<IUnknown as Interface>::IID || iid == &<ITextStoreACP as Interface>::IID {
+        let (sink, mask) = self.sink();
+        if let Some(sink) = sink && flag_check(mask, TS_AS_LAYOUT_CHANGE) {
+            let code = kind.to_raw();
+            self.log(Notification::LayoutChange { code: code.0, view });
             unsafe {
-                *interface = self as *const _ as *mut std::ffi::c_void;
+                sink.OnLayoutChange(code, view).ok();
             }
-            self.AddRef();
+        }
+        Ok(())
+    }
 
-            S_OK
-        } else {
-            unsafe {
-                *interface = std::ptr::null_mut();
+    /// Sets the window reported to TSF through `GetWnd`.
+    pub fn set_window(&self, hwnd: HWND) {
+        *self.window.write().recover() = hwnd;
+    }
+
+    /// Sets what is logged about incoming `ITextStoreACP` calls.
+    pub fn set_diagnostics(&self, diagnostics: Diagnostics) {
+        *self.diagnostics.write().recover() = diagnostics;
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        *self.diagnostics.read().recover()
+    }
+
+    /// Records every incoming `ITextStoreACP` call to `recorder`, or stops
+    /// recording.
+    #[cfg(feature = "record")]
+    pub fn set_recorder(&self, recorder: Option<crate::store_trace::Recorder>) {
+        *self.recorder.write().recover() = recorder;
+    }
+
+    /// Runs an `ITextStoreACP` method body, logging the call and its outcome
+    /// when enabled in [`Diagnostics`]. With the `tracing` feature the body
+    /// runs in a span carrying the arguments, the lock held and the result.
+    fn entry<T>(&self, call: StoreCall, body: impl FnOnce() -> windows_core::Result<T>) -> windows_core::Result<T> {
+        self.entry_with(call, body, |_| S_OK)
+    }
+
+    /// Like [`Self::entry`], with `succeeded` telling the HRESULT to report
+    /// for a successful call.
+    fn entry_with<T>(&self, call: StoreCall, body: impl FnOnce() -> windows_core::Result<T>, succeeded: impl FnOnce(&T) -> HRESULT) -> windows_core::Result<T> {
+        let method = call.method();
+        let lock = self.lock();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("text_store_call", method, call = ?call, lock = ?lock, result = tracing::field::Empty).entered();
+
+        let result = com_entry(method, body);
+        let hr = result.as_ref().map_or_else(|e| e.code(), succeeded);
+        #[cfg(feature = "tracing")]
+        span.record("result", tracing::field::debug(hr));
+
+        let diagnostics = self.diagnostics();
+        if diagnostics.log_store_calls {
+            match &result {
+                Ok(_) => diagnostics.log(format_args!("{} -> S_OK", method)),
+                Err(e) => diagnostics.log(format_args!("{} -> {}", method, e.code())),
             }
+        }
+
+        #[cfg(feature = "record")]
+        if let Some(recorder) = &*self.recorder.read().recover() {
+            recorder.record(call, lock, hr);
+        }
+        #[cfg(not(any(feature = "tracing", feature = "record")))]
+        let _ = (lock, hr);
+
+        result
+    }
+
+    fn lock(&self) -> Lock {
+        self.lock_state().held()
+    }
+
+    /// Sets the hub that receives text and composition events.
+    pub fn set_events(&self, events: EventHub) {
+        *self.events.write().recover() = events;
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf16_lossy(&self.input_text.read().recover())
+    }
+
+    pub(crate) fn dump(&self) -> StoreDump {
+        let (sink, sink_mask) = self.sink();
+        StoreDump {
+            sink_advised: sink.is_some(),
+            sink_mask,
+            text_len: self.input_text.read().recover().len(),
+            selection: *self.selection.read().recover(),
+            lock: self.lock(),
+            pending_locks: self.lock_state().pending(),
+        }
+    }
+
+    pub fn cast_iunknown(&self) -> windows_core::Result<IUnknown> {
+        unsafe {
+            self.cast()
+        }
+    }
+
+    fn emit(&self, event: TsfEvent) {
+        self.events.read().recover().emit(event);
+    }
+
+    fn log(&self, notification: Notification) {
+        self.events.read().recover().notifications().push(notification);
+    }
+
+    /// Clones the advised sink out of the mutex so that callbacks into TSF can
+    /// re-enter the store without deadlocking.
+    fn sink(&self) -> (Option<ITextStoreACPSink>, u32) {
+        let advice_sink = self.advice_sink.lock().recover();
+        (advice_sink.text_store_sink.clone(), advice_sink.mask)
+    }
+
+    fn resolve_range(&self, acpstart: i32, acpend: i32) -> windows_core::Result<(usize, usize)> {
+        let len = self.input_text.read().recover().len() as i32;
+        let end = if acpend == -1 { len } else { acpend };
 
-            E_NOINTERFACE
+        if acpstart < 0 || acpstart > end || end > len {
+            return Err(TS_E_INVALIDPOS.into());
         }
+
+        Ok((acpstart as usize, end as usize))
     }
+}
 
-    fn get_impl(&self) -> &Self::Impl {
-        self
+pub struct LockGuard<'a> {
+    text_store: &'a TfTextStore
+}
+
+impl <'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        self.text_store.release();
     }
 }
 
-impl ITextStoreACP_Impl for TfTextStore {
-    fn AdviseSink(&self, riid: *const windows_core::GUID, punk: Option<&windows_core::IUnknown>, mask: u32) -> windows_core::Result<()> {
-        let punk = match punk {
-            Some(punk) => punk,
-            None => return Err(E_INVALIDARG.into())
-        };
+/// Resolves to a [`LockGuard`] once the store grants the requested lock.
+pub struct LockFuture<'a> {
+    text_store: &'a TfTextStore,
+    waiter: Arc<LockWaiter>,
+    done: bool
+}
 
-        let mut advice_sink = self.advice_sink.lock().unwrap();
+impl <'a> Future for LockFuture<'a> {
+    type Output = LockGuard<'a>;
 
-        if let Some(existing_sink) = &advice_sink.text_store_sink {
-            advice_sink.mask = mask;
-            
-            Ok(())
-        } else if advice_sink.text_store_sink.is_some() {
-            Err(CONNECT_E_ADVISELIMIT.into())
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<LockGuard<'a>> {
+        let mut state = self.waiter.state.lock().recover();
+        if state.granted {
+            drop(state);
+            self.done = true;
+            return Poll::Ready(LockGuard { text_store: self.text_store });
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl <'a> Drop for LockFuture<'a> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let mut state = self.waiter.state.lock().recover();
+        if state.granted {
+            drop(state);
+            self.text_store.release();
         } else {
-            let mut sink: Option<ITextStoreACPSink> = None;
-            let hr = unsafe { punk.query(&<ITextStoreACPSink as Interface>::IID, &mut sink as *mut _ as *mut _) };
+            state.cancelled = true;
+        }
+    }
+}
 
-            if hr.is_ok() {
-                advice_sink.text_store_sink = sink;
-                advice_sink.mask = mask;
+impl ITextStoreACP_Impl for TfTextStore {
+    fn AdviseSink(&self, riid: *const windows_core::GUID, punk: Option<&windows_core::IUnknown>, mask: u32) -> windows_core::Result<()> {
+        self.entry(StoreCall::AdviseSink { mask }, || {
+            self.affinity.check()?;
 
-                return Ok(());
+            if riid.is_null() || unsafe { *riid } != ITextStoreACPSink::IID {
+                return Err(E_INVALIDARG.into());
             }
+            let Some(punk) = punk else {
+                return Err(E_INVALIDARG.into());
+            };
+            let identity: IUnknown = punk.cast()?;
 
-            Err(hr.into())
-        }
+            let mut advice_sink = self.advice_sink.lock().recover();
+            match &advice_sink.identity {
+                Some(existing) if *existing == identity => {
+                    advice_sink.mask = mask;
+                    Ok(())
+                }
+                Some(_) => Err(CONNECT_E_ADVISELIMIT.into()),
+                None => {
+                    advice_sink.text_store_sink = Some(punk.cast()?);
+                    advice_sink.identity = Some(identity);
+                    advice_sink.mask = mask;
+                    Ok(())
+                }
+            }
+        })
     }
 
     fn UnadviseSink(&self, punk: Option<&windows_core::IUnknown>) -> windows_core::Result<()> {
-        let mut advice_sink = self.advice_sink.lock().unwrap();
+        self.entry(StoreCall::UnadviseSink, || {
+            self.affinity.check()?;
 
-        if let Some(_existing_sink) = &advice_sink.text_store_sink {
-            advice_sink.text_store_sink = None;
-            advice_sink.mask = 0;
+            let Some(punk) = punk else {
+                return Err(E_INVALIDARG.into());
+            };
+            let identity: IUnknown = punk.cast()?;
 
+            let mut advice_sink = self.advice_sink.lock().recover();
+            if advice_sink.identity.as_ref() != Some(&identity) {
+                return Err(CONNECT_E_NOCONNECTION.into());
+            }
+            *advice_sink = AdviceSink { text_store_sink: None, identity: None, mask: 0 };
             Ok(())
-        } else {
-            Err(E_INVALIDARG.into())
-        }
+        })
     }
 
     fn RequestLock(&self, dwlockflags: u32) -> windows_core::Result<windows_core::HRESULT> {
-        let advice_sink = self.advice_sink.lock().unwrap();
+        self.entry_with(StoreCall::RequestLock { flags: dwlockflags }, || {
+            self.affinity.check()?;
 
-        if advice_sink.text_store_sink.is_none() {
-            return Ok(E_UNEXPECTED);
-        }
+            let (text_store_sink, _) = self.sink();
 
-        let is_currently_locked = {
-            let lock_state = self.lock_state.read().unwrap();
-            lock_state.0 != LockType::None
-        };
+            if text_store_sink.is_none() {
+                return Ok(E_UNEXPECTED);
+            }
 
-        if is_currently_locked {
-            if flag_check(dwlockflags, TS_LF_SYNC) {
-                return Ok(TS_E_SYNCHRONOUS)
-            } else {
-                return Ok(E_NOTIMPL)
+            let request = self.lock_state().request(dwlockflags, PendingLock::Sink);
+            if request != Request::Granted {
+                metrics().lock_contended(flag_check(dwlockflags, TS_LF_SYNC));
             }
-        } else {
-            if let Ok(_guard) = self.try_lock(dwlockflags) {
-                if let Some(sink) = &advice_sink.text_store_sink {
-                    let hr = unsafe { sink.OnLockGranted(TEXT_STORE_LOCK_FLAGS(dwlockflags)) };
 
-                    return match hr {
-                        Ok(_) => Ok(S_OK),
-                        Err(e) => Err(e)
-                    };
+            match request {
+                Request::Refused => Ok(TS_E_SYNCHRONOUS),
+                Request::Queued => Ok(TS_S_ASYNC),
+                Request::Granted => {
+                    let _guard = LockGuard { text_store: self };
+                    if let Some(sink) = &text_store_sink {
+                        self.log(Notification::LockGranted { flags: dwlockflags });
+                        self.diagnostics().timed(SlowCall::LockGranted, || unsafe { sink.OnLockGranted(TEXT_STORE_LOCK_FLAGS(dwlockflags)) })?;
+                    }
+
+                    Ok(S_OK)
                 }
             }
-
-            Ok(S_OK)
-        }
+        }, |session| *session)
     }
 
     fn GetStatus(&self) -> windows_core::Result<windows::Win32::UI::TextServices::TS_STATUS> {
-        let status = TS_STATUS {
-            dwDynamicFlags: TS_SD_READONLY | TS_SD_LOADING,
-            dwStaticFlags: TS_SS_REGIONS
-        };
+        self.entry(StoreCall::GetStatus, || {
+            self.affinity.check()?;
 
-        Ok(status)
+            let status = TS_STATUS {
+                dwDynamicFlags: self.status(),
+                dwStaticFlags: if self.transitory { TS_SS_REGIONS | TS_SS_TRANSITORY } else { TS_SS_REGIONS }
+            };
+
+            Ok(status)
+        })
     }
 
     fn GetText(&self, acpstart: i32, acpend: i32, pchplain: windows_core::PWSTR, cchplainreq: u32, pcchplainret: *mut u32, prgruninfo: *mut windows::Win32::UI::TextServices::TS_RUNINFO, cruninforeq: u32, pcruninforet: *mut u32, pacpnext: *mut i32) -> windows_core::Result<()> {
-        if !self.is_locked(TS_LF_READ.0) {
-            return Err(TS_E_NOLOCK.into());
-        }
+        self.entry(StoreCall::GetText { start: acpstart, end: acpend, max: cchplainreq }, || {
+            self.affinity.check()?;
 
-        let input_text = self.input_text.read().unwrap();
-        let text_len = input_text.len();
-        let copy_len = std::cmp::min(text_len as u32, cchplainreq);
+            if !self.is_locked(TS_LF_READ.0) {
+                return Err(TS_E_NOLOCK.into());
+            }
 
-        if copy_len > 0 && !pchplain.is_null() {
-            let src_slice = input_text.as_bytes();
-            let dest_slice = unsafe { std::slice::from_raw_parts_mut(pchplain.0 as *mut u8, copy_len as usize) };
-            dest_slice.copy_from_slice(&src_slice[0..copy_len as usize]);
-        }
+            let (start, end) = self.resolve_range(acpstart, acpend)?;
+            let input_text = self.input_text.read().recover();
+            let mut copy_len = std::cmp::min(end - start, cchplainreq as usize);
 
-        if !pcchplainret.is_null() {
-            unsafe {
-                *pcchplainret = copy_len;
+            // Runs are split at region boundaries; stop at the last one the
+            // caller has room for.
+            let mut runs = region::runs(&input_text, start, start + copy_len);
+            if prgruninfo.is_null() || cruninforeq == 0 {
+                runs.clear();
+            } else if runs.len() > cruninforeq as usize {
+                runs.truncate(cruninforeq as usize);
+                copy_len = runs.iter().map(|run| run.uCount as usize).sum();
             }
-        }
 
-        if !prgruninfo.is_null() && cruninforeq > 0 {
-            unsafe {
-                (*prgruninfo).r#type = TS_RT_PLAIN;
-                (*prgruninfo).uCount = text_len as u32;
+            if copy_len > 0 && !pchplain.is_null() {
+                let dest_slice = unsafe { std::slice::from_raw_parts_mut(pchplain.0, copy_len) };
+                dest_slice.copy_from_slice(&input_text[start..start + copy_len]);
             }
-        }
 
-        if !pcruninforet.is_null() {
-            unsafe {
-                *pcruninforet = 1;
+            let diagnostics = self.diagnostics();
+            if diagnostics.log_store_calls {
+                let text = String::from_utf16_lossy(&input_text[start..start + copy_len]);
+                diagnostics.log(format_args!("GetText({}..{}) returned {}", start, start + copy_len, diagnostics.text(&text)));
             }
-        }
 
-        if !pacpnext.is_null() {
-            unsafe {
-                *pacpnext = acpstart + text_len as i32;
+            if !pcchplainret.is_null() {
+                unsafe {
+                    *pcchplainret = copy_len as u32;
+                }
             }
-        }
 
-        Ok(())
+            if !runs.is_empty() {
+                unsafe { std::slice::from_raw_parts_mut(prgruninfo, runs.len()) }.copy_from_slice(&runs);
+            }
+
+            if !pcruninforet.is_null() {
+                unsafe {
+                    *pcruninforet = runs.len() as u32;
+                }
+            }
+
+            if !pacpnext.is_null() {
+                unsafe {
+                    *pacpnext = (start + copy_len) as i32;
+                }
+            }
+
+            Ok(())
+        })
     }
 
-    fn QueryInsert(&self, _acpteststart: i32, _acptestend: i32, _cch: u32, _pacpresultstart: *mut i32, _pacpresultend: *mut i32) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn QueryInsert(&self, acpteststart: i32, acptestend: i32, cch: u32, _pacpresultstart: *mut i32, _pacpresultend: *mut i32) -> windows_core::Result<()> {
+        self.entry(StoreCall::QueryInsert { start: acpteststart, end: acptestend, cch }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn GetSelection(&self, _ulindex: u32, _ulcount: u32, _pselection: *mut TS_SELECTION_ACP, _pcfetched: *mut u32) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetSelection(&self, ulindex: u32, ulcount: u32, pselection: *mut TS_SELECTION_ACP, pcfetched: *mut u32) -> windows_core::Result<()> {
+        self.entry(StoreCall::GetSelection { index: ulindex, count: ulcount }, || {
+            self.affinity.check()?;
+
+            if !self.is_locked(TS_LF_READ.0) {
+                return Err(TS_E_NOLOCK.into());
+            }
+
+            if pselection.is_null() || pcfetched.is_null() {
+                return Err(E_INVALIDARG.into());
+            }
+
+            if ulindex != 0 && ulindex != TS_DEFAULT_SELECTION {
+                return Err(TS_E_NOSELECTION.into());
+            }
+
+            let fetched = if ulcount > 0 {
+                let (acp_start, acp_end) = *self.selection.read().recover();
+                unsafe {
+                    *pselection = TS_SELECTION_ACP {
+                        acpStart: acp_start,
+                        acpEnd: acp_end,
+                        style: TS_SELECTIONSTYLE {
+                            ase: TS_AE_END,
+                            fInterimChar: BOOL(0)
+                        }
+                    };
+                }
+                1
+            } else {
+                0
+            };
+
+            unsafe {
+                *pcfetched = fetched;
+            }
+
+            Ok(())
+        })
     }
     
-    fn SetSelection(&self, _ulcount: u32, _pselection: *const TS_SELECTION_ACP) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn SetSelection(&self, ulcount: u32, pselection: *const TS_SELECTION_ACP) -> windows_core::Result<()> {
+        let (start, end) = if pselection.is_null() { (0, 0) } else { unsafe { ((*pselection).acpStart, (*pselection).acpEnd) } };
+        self.entry(StoreCall::SetSelection { start, end }, || {
+            self.affinity.check()?;
+
+            if !self.is_locked(TS_LF_READWRITE.0) {
+                return Err(TS_E_NOLOCK.into());
+            }
+
+            if ulcount == 0 || pselection.is_null() {
+                return Err(E_INVALIDARG.into());
+            }
+
+            let selection = unsafe { *pselection };
+            let (start, end) = self.resolve_range(selection.acpStart, selection.acpEnd)?;
+            let (start, end) = {
+                let text = self.input_text.read().recover();
+                acp::snap_range_to_code_points(&text, start as i32, end as i32)
+            };
+            *self.selection.write().recover() = (start, end);
+
+            Ok(())
+        })
     }
     
-    fn SetText(&self, _dwflags: u32, _acpstart: i32, _acpend: i32, _pchtext: &windows_core::PCWSTR, _cch: u32) -> windows_core::Result<TS_TEXTCHANGE> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn SetText(&self, dwflags: u32, acpstart: i32, acpend: i32, _pchtext: &windows_core::PCWSTR, cch: u32) -> windows_core::Result<TS_TEXTCHANGE> {
+        self.entry(StoreCall::SetText { flags: dwflags, start: acpstart, end: acpend, cch }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn GetFormattedText(&self, _acpstart: i32, _acpend: i32) -> windows_core::Result<IDataObject> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetFormattedText(&self, acpstart: i32, acpend: i32) -> windows_core::Result<IDataObject> {
+        self.entry(StoreCall::GetFormattedText { start: acpstart, end: acpend }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn GetEmbedded(&self, _acppos: i32, _rguidservice: *const windows_core::GUID, _riid: *const windows_core::GUID) -> windows_core::Result<windows_core::IUnknown> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetEmbedded(&self, acppos: i32, _rguidservice: *const windows_core::GUID, _riid: *const windows_core::GUID) -> windows_core::Result<windows_core::IUnknown> {
+        self.entry(StoreCall::GetEmbedded { pos: acppos }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
     fn QueryInsertEmbedded(&self, _pguidservice: *const windows_core::GUID, _pformatetc: *const FORMATETC) -> windows_core::Result<BOOL> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+        self.entry(StoreCall::QueryInsertEmbedded, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn InsertEmbedded(&self, _dwflags: u32, _acpstart: i32, _acpend: i32, _pdataobject: Option<&IDataObject>) -> windows_core::Result<TS_TEXTCHANGE> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn InsertEmbedded(&self, dwflags: u32, acpstart: i32, acpend: i32, _pdataobject: Option<&IDataObject>) -> windows_core::Result<TS_TEXTCHANGE> {
+        self.entry(StoreCall::InsertEmbedded { flags: dwflags, start: acpstart, end: acpend }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn InsertTextAtSelection(&self, _dwflags: u32, _pchtext: &windows_core::PCWSTR, _cch: u32, _pacpstart: *mut i32, _pacpend: *mut i32, _pchange: *mut TS_TEXTCHANGE) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn InsertTextAtSelection(&self, dwflags: u32, _pchtext: &windows_core::PCWSTR, cch: u32, _pacpstart: *mut i32, _pacpend: *mut i32, _pchange: *mut TS_TEXTCHANGE) -> windows_core::Result<()> {
+        self.entry(StoreCall::InsertTextAtSelection { flags: dwflags, cch }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn InsertEmbeddedAtSelection(&self, _dwflags: u32, _pdataobject: Option<&IDataObject>, _pacpstart: *mut i32, _pacpend: *mut i32, _pchange: *mut TS_TEXTCHANGE) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn InsertEmbeddedAtSelection(&self, dwflags: u32, _pdataobject: Option<&IDataObject>, _pacpstart: *mut i32, _pacpend: *mut i32, _pchange: *mut TS_TEXTCHANGE) -> windows_core::Result<()> {
+        self.entry(StoreCall::InsertEmbeddedAtSelection { flags: dwflags }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn RequestSupportedAttrs(&self, _dwflags: u32, _cfilterattrs: u32, _pafilterattrs: *const windows_core::GUID) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn RequestSupportedAttrs(&self, dwflags: u32, cfilterattrs: u32, pafilterattrs: *const windows_core::GUID) -> windows_core::Result<()> {
+        self.entry(StoreCall::RequestSupportedAttrs { flags: dwflags, attrs: cfilterattrs }, || {
+            self.affinity.check()?;
+
+            let scopes = if flag_check(dwflags, TS_ATTR_FIND_WANT_VALUE) { self.input_scopes() } else { Vec::new() };
+            self.request_attrs(attr_filter(cfilterattrs, pafilterattrs), |_| Some(scopes.clone()));
+            Ok(())
+        })
     }
     
-    fn RequestAttrsAtPosition(&self, _acppos: i32, _cfilterattrs: u32, _pafilterattrs: *const windows_core::GUID, _dwflags: u32) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn RequestAttrsAtPosition(&self, acppos: i32, cfilterattrs: u32, pafilterattrs: *const windows_core::GUID, dwflags: u32) -> windows_core::Result<()> {
+        self.entry(StoreCall::RequestAttrsAtPosition { pos: acppos, attrs: cfilterattrs, flags: dwflags }, || {
+            self.affinity.check()?;
+
+            self.resolve_range(acppos, acppos)?;
+            let scopes = self.input_scopes_at(acppos);
+            self.request_attrs(attr_filter(cfilterattrs, pafilterattrs), |_| Some(scopes.clone()));
+            Ok(())
+        })
     }
     
-    fn RequestAttrsTransitioningAtPosition(&self, _acppos: i32, _cfilterattrs: u32, _pafilterattrs: *const windows_core::GUID, _dwflags: u32) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn RequestAttrsTransitioningAtPosition(&self, acppos: i32, cfilterattrs: u32, pafilterattrs: *const windows_core::GUID, dwflags: u32) -> windows_core::Result<()> {
+        self.entry(StoreCall::RequestAttrsTransitioningAtPosition { pos: acppos, attrs: cfilterattrs, flags: dwflags }, || {
+            self.affinity.check()?;
+
+            self.resolve_range(acppos, acppos)?;
+            let scopes = self.scopes();
+            let transition = scopes.is_transition(acppos).then(|| {
+                match (flag_check(dwflags, TS_ATTR_FIND_WANT_VALUE), flag_check(dwflags, TS_ATTR_FIND_WANT_END)) {
+                    (false, _) => Vec::new(),
+                    (true, false) => scopes.at(acppos).to_vec(),
+                    (true, true) => scopes.at(acppos - 1).to_vec(),
+                }
+            });
+            drop(scopes);
+            self.request_attrs(attr_filter(cfilterattrs, pafilterattrs), |_| transition.clone());
+            Ok(())
+        })
     }
     
-    fn FindNextAttrTransition(&self, _acpstart: i32, _acphalt: i32, _cfilterattrs: u32, _pafilterattrs: *const windows_core::GUID, _dwflags: u32, _pacpnext: *mut i32, _pffound: *mut BOOL, _plfoundoffset: *mut i32) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn FindNextAttrTransition(&self, acpstart: i32, acphalt: i32, cfilterattrs: u32, pafilterattrs: *const windows_core::GUID, dwflags: u32, pacpnext: *mut i32, pffound: *mut BOOL, plfoundoffset: *mut i32) -> windows_core::Result<()> {
+        self.entry(StoreCall::FindNextAttrTransition { start: acpstart, halt: acphalt, attrs: cfilterattrs, flags: dwflags }, || {
+            self.affinity.check()?;
+
+            if pacpnext.is_null() || pffound.is_null() || plfoundoffset.is_null() {
+                return Err(E_INVALIDARG.into());
+            }
+            let backwards = flag_check(dwflags, TS_ATTR_FIND_BACKWARDS);
+            let (start, halt) = if backwards {
+                let (halt, start) = self.resolve_range(acphalt, acpstart)?;
+                (start as i32, halt as i32)
+            } else {
+                let (start, halt) = self.resolve_range(acpstart, acphalt)?;
+                (start as i32, halt as i32)
+            };
+
+            let filter = attr_filter(cfilterattrs, pafilterattrs);
+            let wanted = filter.is_empty() || filter.contains(&GUID_PROP_INPUTSCOPE);
+            let found = wanted.then(|| self.scopes().find_transition(start, halt, backwards)).flatten();
+
+            unsafe {
+                *pacpnext = found.unwrap_or(halt);
+                *pffound = BOOL(found.is_some() as i32);
+                *plfoundoffset = found.map_or(0, |found| found - start);
+            }
+            Ok(())
+        })
     }
     
-    fn RetrieveRequestedAttrs(&self, _ulcount: u32, _paattrvals: *mut TS_ATTRVAL, _pcfetched: *mut u32) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn RetrieveRequestedAttrs(&self, ulcount: u32, paattrvals: *mut TS_ATTRVAL, pcfetched: *mut u32) -> windows_core::Result<()> {
+        self.entry(StoreCall::RetrieveRequestedAttrs { count: ulcount }, || {
+            self.affinity.check()?;
+
+            if paattrvals.is_null() || pcfetched.is_null() {
+                return Err(E_INVALIDARG.into());
+            }
+
+            let retrieved: Vec<_> = {
+                let mut requested = self.requested_attrs.lock().recover();
+                let count = requested.len().min(ulcount as usize);
+                requested.drain(..count).collect()
+            };
+            for (i, (id, scopes)) in retrieved.iter().enumerate() {
+                let value = if scopes.is_empty() { VARIANT::default() } else { VARIANT::from(IUnknown::from(InputScopes::create(scopes))) };
+                unsafe {
+                    paattrvals.add(i).write(TS_ATTRVAL { idAttr: *id, dwOverlapId: 0, varValue: ManuallyDrop::new(value) });
+                }
+            }
+
+            unsafe {
+                *pcfetched = retrieved.len() as u32;
+            }
+            Ok(())
+        })
     }
     
     fn GetEndACP(&self) -> windows_core::Result<i32> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+        self.entry(StoreCall::GetEndACP, || {
+            self.affinity.check()?;
+
+            if !self.is_locked(TS_LF_READ.0) {
+                return Err(TS_E_NOLOCK.into());
+            }
+
+            Ok(self.input_text.read().recover().len() as i32)
+        })
     }
     
     fn GetActiveView(&self) -> windows_core::Result<u32> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+        self.entry(StoreCall::GetActiveView, || {
+            self.affinity.check()?;
+
+            Ok(layout::VIEW)
+        })
     }
     
-    fn GetACPFromPoint(&self, _vcview: u32, _ptscreen: *const POINT, _dwflags: u32) -> windows_core::Result<i32> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetACPFromPoint(&self, vcview: u32, ptscreen: *const POINT, dwflags: u32) -> windows_core::Result<i32> {
+        let POINT { x, y } = if ptscreen.is_null() { POINT::default() } else { unsafe { *ptscreen } };
+        self.entry(StoreCall::GetACPFromPoint { view: vcview, x, y, flags: dwflags }, || {
+            self.affinity.check()?;
+
+            Err(windows_core::Error::from(E_NOTIMPL))
+        })
     }
     
-    fn GetTextExt(&self, _vcview: u32, _acpstart: i32, _acpend: i32, _prc: *mut RECT, _pfclipped: *mut BOOL) -> windows_core::Result<()> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetTextExt(&self, vcview: u32, acpstart: i32, acpend: i32, prc: *mut RECT, pfclipped: *mut BOOL) -> windows_core::Result<()> {
+        self.entry(StoreCall::GetTextExt { view: vcview, start: acpstart, end: acpend }, || {
+            self.affinity.check()?;
+
+            if !self.is_locked(TS_LF_READ.0) {
+                return Err(TS_E_NOLOCK.into());
+            }
+            if vcview != layout::VIEW || prc.is_null() || pfclipped.is_null() {
+                return Err(E_INVALIDARG.into());
+            }
+            let (start, end) = self.resolve_range(acpstart, acpend)?;
+            let Some(layout) = self.layout() else {
+                return Err(E_NOTIMPL.into());
+            };
+
+            let (rect, clipped) = layout.text_ext(start as i32, end as i32).ok_or(windows_core::Error::from(TS_E_NOLAYOUT))?;
+            unsafe {
+                *prc = rect;
+                *pfclipped = clipped.into();
+            }
+            Ok(())
+        })
     }
     
-    fn GetScreenExt(&self, _vcview: u32) -> windows_core::Result<RECT> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetScreenExt(&self, vcview: u32) -> windows_core::Result<RECT> {
+        self.entry(StoreCall::GetScreenExt { view: vcview }, || {
+            self.affinity.check()?;
+
+            if vcview != layout::VIEW {
+                return Err(E_INVALIDARG.into());
+            }
+            let Some(layout) = self.layout() else {
+                return Err(E_NOTIMPL.into());
+            };
+
+            layout.screen_ext().ok_or(TS_E_NOLAYOUT.into())
+        })
     }
     
-    fn GetWnd(&self, _vcview: u32) -> windows_core::Result<HWND> {
-        Err(windows_core::Error::from(E_NOTIMPL))
+    fn GetWnd(&self, vcview: u32) -> windows_core::Result<HWND> {
+        self.entry(StoreCall::GetWnd { view: vcview }, || {
+            self.affinity.check()?;
+
+            Ok(*self.window.read().recover())
+        })
+    }
+}
+
+impl ITfContextOwnerCompositionSink_Impl for TfTextStore {
+    fn OnStartComposition(&self, _pcomposition: Option<&ITfCompositionView>) -> windows_core::Result<BOOL> {
+        com_entry("ITfContextOwnerCompositionSink::OnStartComposition", || {
+            self.affinity.check()?;
+
+            self.log(Notification::CompositionStarted);
+            self.emit(TsfEvent::CompositionStarted);
+            Ok(BOOL(1))
+        })
+    }
+
+    fn OnUpdateComposition(&self, _pcomposition: Option<&ITfCompositionView>, _prangenew: Option<&ITfRange>) -> windows_core::Result<()> {
+        com_entry("ITfContextOwnerCompositionSink::OnUpdateComposition", || {
+            self.affinity.check()?;
+
+            self.log(Notification::CompositionUpdated);
+            self.emit(TsfEvent::CompositionUpdated);
+            Ok(())
+        })
+    }
+
+    fn OnEndComposition(&self, _pcomposition: Option<&ITfCompositionView>) -> windows_core::Result<()> {
+        com_entry("ITfContextOwnerCompositionSink::OnEndComposition", || {
+            self.affinity.check()?;
+
+            self.log(Notification::CompositionEnded);
+            self.emit(TsfEvent::CompositionEnded);
+            Ok(())
+        })
     }
 }
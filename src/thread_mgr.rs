@@ -1,10 +1,11 @@
-use anyhow::Result;
-use tracing::{debug, error, info};
+use crate::trace::{debug, error, info};
 use windows::Win32::{
     System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
     UI::TextServices::{CLSID_TF_ThreadMgr, ITfDocumentMgr, ITfFunctionProvider, ITfThreadMgr2},
 };
 
+use crate::error::{call, rewrap, Result, TsfError};
+
 pub struct ThreadMgr {
     pub thread_mgr: ITfThreadMgr2,
 }
@@ -12,45 +13,33 @@ pub struct ThreadMgr {
 impl ThreadMgr {
     pub fn new() -> Result<Self> {
         debug!("Creating new ThreadMgr");
-        let thread_mgr =
-            unsafe { CoCreateInstance(&CLSID_TF_ThreadMgr, None, CLSCTX_INPROC_SERVER)? };
+        let thread_mgr = call!(CoCreateInstance(&CLSID_TF_ThreadMgr, None, CLSCTX_INPROC_SERVER)).map_err(rewrap(TsfError::ThreadMgrActivate))?;
         info!("ThreadMgr created successfully");
         Ok(ThreadMgr { thread_mgr })
     }
 
-    pub fn activate_ex(&self, flags: u32) -> Result<u32> {
-        debug!("Activating ThreadMgr with flags: {}", flags);
-        let mut client_id = 0;
-        unsafe {
-            self.thread_mgr
-                .ActivateEx(&mut client_id as *mut _ as *const _ as *mut _, flags)?
-        };
-        info!("ThreadMgr activated with client_id: {}", client_id);
-        Ok(client_id)
-    }
-
     pub fn get_function_provider(&self, clsid: &windows_core::GUID) -> Result<ITfFunctionProvider> {
         debug!("Getting function provider for CLSID: {:?}", clsid);
-        match unsafe { self.thread_mgr.GetFunctionProvider(clsid) } {
+        match call!(self.thread_mgr, GetFunctionProvider(clsid)) {
             Ok(provider) => {
                 info!("Function provider obtained successfully");
                 Ok(provider)
             }
             Err(e) => {
                 error!("Failed to get function provider: {:?}", e);
-                Err(e.into())
+                Err(rewrap(TsfError::NoFunctionProvider)(e))
             }
         }
     }
 
     pub fn activate(&self) -> Result<u32> {
-        let client_id = unsafe { self.thread_mgr.Activate()? };
+        let client_id = call!(self.thread_mgr, Activate()).map_err(rewrap(TsfError::ThreadMgrActivate))?;
 
         Ok(client_id)
     }
 
     pub fn create_document_manager(&self) -> Result<ITfDocumentMgr> {
-        let document_mgr = unsafe { self.thread_mgr.CreateDocumentMgr()? };
+        let document_mgr = call!(self.thread_mgr, CreateDocumentMgr())?;
 
         Ok(document_mgr)
     }
@@ -0,0 +1,42 @@
+use std::{fmt, time::Duration};
+
+/// Operations covered by a [`TimeoutPolicy`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Operation {
+    Conversion,
+    SetText,
+}
+
+/// Returned when an operation did not finish within its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout {
+    pub operation: Operation,
+    pub after: Duration,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} timed out after {:?}", self.operation, self.after)
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Deadlines applied by the service facades. Per-operation values take
+/// precedence over `default`; `None` everywhere means wait forever.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeoutPolicy {
+    pub default: Option<Duration>,
+    pub conversion: Option<Duration>,
+    pub set_text: Option<Duration>,
+}
+
+impl TimeoutPolicy {
+    pub fn for_operation(&self, operation: Operation) -> Option<Duration> {
+        let specific = match operation {
+            Operation::Conversion => self.conversion,
+            Operation::SetText => self.set_text,
+        };
+        specific.or(self.default)
+    }
+}
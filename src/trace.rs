@@ -0,0 +1,27 @@
+//! Logging macros used throughout the crate.
+//!
+//! With the `tracing` feature these are the `tracing` macros; without it they
+//! compile to nothing while still type-checking their arguments.
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error, info, trace, warn};
+
+#[cfg(not(feature = "tracing"))]
+mod noop {
+    macro_rules! noop {
+        ($($arg:tt)*) => {{
+            if false {
+                let _ = ::std::format_args!($($arg)*);
+            }
+        }};
+    }
+
+    pub(crate) use noop as debug;
+    pub(crate) use noop as error;
+    pub(crate) use noop as info;
+    pub(crate) use noop as trace;
+    pub(crate) use noop as warn;
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use noop::{debug, error, info, trace, warn};
@@ -1,27 +1,95 @@
-use std::{ops::Deref, rc::Rc};
+use std::{cell::RefCell, mem::ManuallyDrop, ops::Range, rc::Rc, sync::Arc, time::{Duration, Instant}};
 
-use anyhow::Result;
+use windows::Win32::{Foundation::{BOOL, E_POINTER, E_UNEXPECTED}, System::Com::CoTaskMemFree, UI::TextServices::{ITextStoreACP, ITfContextOwner, ITfContextOwnerServices, ITfInputScope, TF_AE_END, TF_E_LOCKED, TF_ES_READWRITE, TF_SELECTION, TF_SELECTIONSTYLE, GUID_PROP_INPUTSCOPE, ITfContext, ITfThreadMgr2, ITfInputProcessorProfileActivationSink, ITfSource, ITfCompartmentEventSink, ITfCompartmentMgr, ITfThreadFocusSink, ITfThreadMgr, ITfUIElementMgr, ITfUIElementSink, ITfDocumentMgr, ITfEditSession, ITfCandidateList, ITfFnReconversion, ITfFnSearchCandidateProvider, ITfFunctionProvider, ITfRange, GUID_PROP_READING, GUID_SYSTEM_FUNCTIONPROVIDER, CAND_FINALIZED, TF_ANCHOR_END, TF_POPF_ALL, TF_ES_READ, TF_ES_SYNC}};
+use windows_core::{AsImpl, Interface, BSTR, GUID, IUnknown};
+use crate::{profile::scope, trace::{debug, error, info, warn}};
 
-use windows::Win32::UI::TextServices::{ITfContext, ITfDocumentMgr, ITfFnReconversion, ITfFunctionProvider, GUID_SYSTEM_FUNCTIONPROVIDER};
-use windows_core::{IUnknown, Interface};
-use tracing::{debug, error, info, instrument, warn, span, Level};
+use crate::{affinity::ThreadAffinity, cancel::CancellationToken, com::Com, compat::{self, ActiveProfile, Provider, Quirks}, context_owner::ContextOwner, diagnostics::{Diagnostics, SlowCall}, dump::{ContextStack, DebugDump, Focus}, engine::EngineKind, error::{call, hresult, Result, TsfError}, events::EventHub, input_scope::InputScope, latency::Clock, layout::{self, LayoutChangeKind, LayoutProvider}, metrics, owner_services::OwnerServices, region, segmentation::Segmentation, sinks::{self, CompartmentEventSink, ThreadMgrEventSink}, agile::{AgileTsf, GlobalInterface}, edit_session::EditSession, text_store::TfTextStore, thread_mgr::ThreadMgr, window::{HiddenWindow, WindowKind}};
 
-use crate::{text_store::TfTextStore, thread_mgr::ThreadMgr};
+/// Optional parts of the pipeline that may fail to come up without making
+/// the instance unusable.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Component {
+    /// UI element and profile activation sinks; no candidate or profile events.
+    EventSinks,
+    /// The system reconversion function; `convert` fails.
+    Reconversion,
+    /// `SetFocus` on the document manager.
+    Focus,
+    /// `AssociateFocus` with the owned window.
+    FocusAssociation,
+    /// Keyboard compartment sinks; no compartment events.
+    CompartmentSinks,
+}
+
+/// Initialization status of a [`TSF`] instance.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TsfState {
+    /// Not initialized, or uninitialized again. No interfaces are held.
+    Uninitialized,
+    /// The thread manager is active and the context pushed, but the document
+    /// does not have focus.
+    Activated,
+    /// Fully initialized and focused.
+    Focused,
+    /// Initialized, but some optional components are unavailable.
+    Degraded { missing: Vec<Component> },
+}
 
-pub struct TSF {
+/// The TSF pipeline for one document.
+///
+/// Borrows the [`Com`] guard of its thread so that COM cannot be
+/// uninitialized while any of the interfaces below are still alive.
+pub struct TSF<'com> {
     client_id: u32,
     thread_mgr: Option<ThreadMgr>,
     doc_mgr: Option<ITfDocumentMgr>,
-    text_store: Option<Rc<TfTextStore>>,
+    text_store: Option<ITextStoreACP>,
     context: Option<ITfContext>,
     edit_cookie: u32,
     func_prov: Option<ITfFunctionProvider>,
-    reconvert: Option<ITfFnReconversion>
+    reconvert: Option<ITfFnReconversion>,
+    /// The input processor's predictions, for `predict`.
+    search: Option<ITfFnSearchCandidateProvider>,
+    /// Replaces the input processor's provider; see `set_function_provider`.
+    provider_override: Option<ITfFunctionProvider>,
+    /// Replaces the detected keyboard profile; see `set_active_profile`.
+    #[cfg(feature = "test-utils")]
+    profile_override: Option<ActiveProfile>,
+    /// The scripted input processor set with `set_fake_tip`, which also
+    /// answers readings.
+    #[cfg(feature = "test-utils")]
+    fake_tip: Option<crate::fake_tip::FakeTip>,
+    /// Times conversions; frozen in test mode.
+    clock: Clock,
+    /// Whether the document is created transitory; see [`TSF::transitory`].
+    transitory: bool,
+    /// `TS_SD_*` flags new stores start with; see `set_initial_status`.
+    initial_status: Option<u32>,
+    /// Whether the document is hosted through a context owner instead of a
+    /// text store; see [`TSF::with_context_owner`].
+    owns_context: bool,
+    /// The context owner and its cookie on the context's `ITfSource`.
+    context_owner: Option<(ITfContextOwner, u32)>,
+    /// Default limit on the candidates enumerated per conversion.
+    max_candidates: Option<usize>,
+    window: Option<HiddenWindow>,
+    events: EventHub,
+    sink_cookies: Vec<u32>,
+    compartment_cookies: Vec<(ITfSource, u32)>,
+    state: TsfState,
+    profile: Option<ActiveProfile>,
+    quirks: Quirks,
+    diagnostics: Diagnostics,
+    #[cfg(feature = "record")]
+    recorder: Option<crate::store_trace::Recorder>,
+    affinity: ThreadAffinity,
+    _com: &'com Com
 }
 
-impl TSF {
-    #[instrument(name = "tsf_new", level = "debug", skip_all)]
-    pub fn new() -> Self {
+impl<'com> TSF<'com> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_new", level = "debug", skip_all))]
+    pub fn new(com: &'com Com) -> Self {
         info!("Creating new TSF instance");
         Self {
             client_id: 0,
@@ -31,139 +99,1079 @@ impl TSF {
             context: None,
             edit_cookie: 0,
             func_prov: None,
-            reconvert: None
+            reconvert: None,
+            search: None,
+            provider_override: None,
+            #[cfg(feature = "test-utils")]
+            profile_override: None,
+            #[cfg(feature = "test-utils")]
+            fake_tip: None,
+            clock: Clock::Real,
+            transitory: false,
+            initial_status: None,
+            owns_context: false,
+            context_owner: None,
+            max_candidates: None,
+            window: None,
+            events: EventHub::new(),
+            sink_cookies: Vec::new(),
+            compartment_cookies: Vec::new(),
+            state: TsfState::Uninitialized,
+            profile: None,
+            quirks: Quirks::default(),
+            diagnostics: Diagnostics::default(),
+            #[cfg(feature = "record")]
+            recorder: None,
+            affinity: ThreadAffinity::current(),
+            _com: com
         }
     }
 
-    #[instrument(name = "tsf_initialize", level = "debug", skip_all, err)]
+    /// Like [`TSF::new`], but the document is transitory: TSF expects it to
+    /// live only for one operation, such as a single reconversion, and it is
+    /// not associated with the owned window's focus. Some input processors
+    /// keep less state for such documents and convert more predictably.
+    pub fn transitory(com: &'com Com) -> Self {
+        let mut tsf = Self::new(com);
+        tsf.transitory = true;
+        tsf
+    }
+
+    /// Like [`TSF::new`], but TSF keeps the document's text itself and the
+    /// crate only acts as the context's `ITfContextOwner`. Lighter than a
+    /// text store, and enough for converting short strings; calls that need
+    /// the crate's store, such as [`TSF::text_store`] or
+    /// [`TSF::set_range_input_scopes`], fail with [`TsfError::NoTextStore`].
+    pub fn with_context_owner(com: &'com Com) -> Self {
+        let mut tsf = Self::new(com);
+        tsf.owns_context = true;
+        tsf
+    }
+
+    pub fn is_transitory(&self) -> bool {
+        self.transitory
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_initialize", level = "debug", skip_all, err))]
     pub fn initialize(&mut self) -> Result<()> {
-        let span = span!(Level::INFO, "initialize_tsf");
-        let _enter = span.enter();
+        self.affinity.check()?;
+
+        #[cfg(feature = "tracing")]
+        let _enter = tracing::info_span!("initialize_tsf").entered();
         
+        if self.state != TsfState::Uninitialized {
+            debug!("TSF is already initialized");
+            return Ok(());
+        }
+
         info!("Initializing TSF");
-        
+
+        let mut missing = Vec::new();
+        if let Err(e) = self.build(&mut missing) {
+            error!("TSF initialization failed, rolling back: {:?}", e);
+            debug!("Pipeline at failure:\n{}", self.debug_dump());
+            if let Err(e) = self.uninitialize() {
+                warn!("Failed to roll back partial initialization: {:?}", e);
+            }
+            return Err(e);
+        }
+
+        self.state = if missing.is_empty() {
+            TsfState::Focused
+        } else if missing == [Component::Focus] {
+            TsfState::Activated
+        } else {
+            TsfState::Degraded { missing }
+        };
+
+        info!("TSF initialized: {:?}", self.state);
+        Ok(())
+    }
+
+    /// Current initialization status.
+    pub fn state(&self) -> &TsfState {
+        &self.state
+    }
+
+    /// Brings up every part of the pipeline. Failures of required parts are
+    /// returned; optional ones are recorded in `missing`.
+    fn build(&mut self, missing: &mut Vec<Component>) -> Result<()> {
+        debug!("Creating message-only window");
+        let hwnd = self.window.insert(HiddenWindow::new(WindowKind::MessageOnly)?).hwnd();
+        debug!("Message-only window created successfully");
+
         debug!("Creating thread manager");
         self.thread_mgr = Some(ThreadMgr::new()?);
-        let thread_mgr = self.thread_mgr.as_ref().unwrap();
+        let thread_mgr = self.thread_mgr.as_ref().ok_or(TsfError::NotInitialized)?;
         debug!("Thread manager created successfully");
         
         debug!("Creating document manager");
         let doc_mgr = thread_mgr.create_document_manager()?;
-        self.doc_mgr = Some(doc_mgr);
+        self.doc_mgr = Some(doc_mgr.clone());
         debug!("Document manager created successfully");
 
         debug!("Activating thread manager");
         self.client_id = thread_mgr.activate()?;
         debug!("Thread manager activated with client_id: {}", self.client_id);
 
-        debug!("Creating text store");
-        self.text_store = Some(Rc::new(TfTextStore::new()));
-        let text_store = self.text_store.as_ref().unwrap();
-        debug!("Text store created successfully");
+        debug!("Advising thread manager event sinks");
+        match self.advise_sinks() {
+            Ok(cookies) => {
+                self.sink_cookies = cookies;
+                debug!("Thread manager event sinks advised successfully");
+            },
+            Err(e) => {
+                warn!("Failed to advise thread manager event sinks: {:?}", e);
+                missing.push(Component::EventSinks);
+            }
+        }
 
-        let doc_mgr = self.doc_mgr.as_ref().unwrap();
+        debug!("Advising compartment event sinks");
+        match self.advise_compartment_sinks() {
+            Ok(cookies) => {
+                self.compartment_cookies = cookies;
+                debug!("Compartment event sinks advised successfully");
+            },
+            Err(e) => {
+                warn!("Failed to advise compartment event sinks: {:?}", e);
+                missing.push(Component::CompartmentSinks);
+            }
+        }
+
+        let document: IUnknown = if self.owns_context {
+            debug!("Creating context owner");
+            let owner: ITfContextOwner = ContextOwner::new(hwnd, self.events.clone(), self.transitory).into();
+            self.context_owner = Some((owner.clone(), 0));
+            call!(owner, cast())?
+        } else {
+            debug!("Creating text store");
+            let store = if self.transitory { TfTextStore::transitory() } else { TfTextStore::new() };
+            if let Some(status) = self.initial_status {
+                store.set_status(status)?;
+            }
+            store.set_window(hwnd);
+            store.set_events(self.events.clone());
+            store.set_diagnostics(self.diagnostics);
+            #[cfg(feature = "record")]
+            store.set_recorder(self.recorder.clone());
+            let text_store: ITextStoreACP = store.into();
+            if let Some(window) = &self.window {
+                window.set_text_store(&text_store);
+            }
+            self.text_store = Some(text_store.clone());
+            debug!("Text store created successfully");
+            call!(text_store, cast())?
+        };
 
         debug!("Creating context with client_id: {}", self.client_id);
         let (context, edit_cookie) = unsafe {
             let mut context = None;
             let mut edit_cookie = 0;
-            debug!("Casting text store to IUnknown");
-            let text_store = text_store.deref() as *const _ as *mut IUnknown;
             debug!("Creating context");
-            let result = doc_mgr.CreateContext(self.client_id, 0, Some(&*text_store), &mut context, &mut edit_cookie);
-            if result.is_err() {
-                error!("Failed to create context: {:?}", result);
-                return Err(anyhow::anyhow!("Failed to create context: {:?}", result));
+            if let Err(e) = doc_mgr.CreateContext(self.client_id, 0, &document, &mut context, &mut edit_cookie) {
+                error!("Failed to create context: {:?}", e);
+                return Err(TsfError::ContextCreate(e));
             }
 
-            (context.unwrap(), edit_cookie)
+            match context {
+                Some(context) => (context, edit_cookie),
+                None => return Err(TsfError::ContextCreate(E_POINTER.into()))
+            }
         };
         debug!("Context created successfully with edit_cookie: {}", edit_cookie);
 
-        self.context = Some(context);
+        self.context = Some(context.clone());
         self.edit_cookie = edit_cookie;
 
         debug!("Pushing context to document manager");
-        unsafe {
-            match doc_mgr.Push(self.context.as_ref().unwrap()) {
-                Ok(_) => debug!("Context pushed successfully"),
-                Err(e) => {
-                    error!("Failed to push context: {:?}", e);
-                    return Err(e.into());
-                }
+        match call!(doc_mgr, Push(&context)) {
+            Ok(_) => debug!("Context pushed successfully"),
+            Err(e) => {
+                error!("Failed to push context: {:?}", e);
+                return Err(e);
             }
         }
 
-        debug!("Getting function provider");
-        let func_prov = match thread_mgr.get_function_provider(&GUID_SYSTEM_FUNCTIONPROVIDER) {
-            Ok(fp) => {
-                debug!("Function provider retrieved successfully");
-                fp
-            },
-            Err(e) => {
-                error!("Failed to get function provider: {:?}", e);
-                return Err(e);
+        if let Some((owner, cookie)) = &mut self.context_owner {
+            debug!("Advising context owner");
+            let source: ITfSource = call!(context, cast())?;
+            *cookie = call!(source, AdviseSink(&ITfContextOwner::IID, &*owner))?;
+        } else if self.initial_status.is_none() {
+            debug!("Finishing loading of the empty document");
+            self.store()?.finish_loading()?;
+        }
+
+        debug!("Detecting active input processor");
+        match self.detect_profile() {
+            Ok(profile) => {
+                self.quirks = profile.as_ref().map(ActiveProfile::quirks).unwrap_or_default();
+                self.profile = profile;
             }
-        };
-        self.func_prov = Some(func_prov);
-
-        if let Some(func_prov) = &self.func_prov {
-            debug!("Getting reconversion function");
-            let reconvert: ITfFnReconversion = unsafe {
-                match func_prov.GetFunction(&windows_core::GUID::zeroed(), &ITfFnReconversion::IID) {
-                    Ok(func) => {
-                        match func.cast() {
-                            Ok(reconv) => {
-                                debug!("Reconversion function retrieved and cast successfully");
-                                reconv
-                            },
-                            Err(e) => {
-                                error!("Failed to cast function to ITfFnReconversion: {:?}", e);
-                                return Err(e.into());
-                            }
-                        }
+            Err(e) => warn!("Failed to detect active input processor: {:?}", e)
+        }
+
+        if let Some(func_prov) = self.provider_override.clone() {
+            debug!("Using the function provider set on this instance");
+            self.quirks = Quirks::default();
+            self.search = search_function(&func_prov);
+            if let Some(reconv) = reconversion_function(&func_prov) {
+                self.func_prov = Some(func_prov);
+                self.reconvert = Some(reconv);
+            }
+        } else {
+            for &provider in &self.quirks.providers {
+                let clsid = match (provider, &self.profile) {
+                    (Provider::System, _) => GUID_SYSTEM_FUNCTIONPROVIDER,
+                    (Provider::Tip, Some(profile)) => profile.clsid,
+                    (Provider::Tip, None) => continue,
+                };
+
+                debug!("Getting {:?} function provider", provider);
+                let func_prov = match thread_mgr.get_function_provider(&clsid) {
+                    Ok(fp) => {
+                        debug!("Function provider retrieved successfully");
+                        fp
                     },
                     Err(e) => {
-                        error!("Failed to get reconversion function: {:?}", e);
-                        return Err(e.into());
+                        warn!("Failed to get function provider: {:?}", e);
+                        continue;
                     }
+                };
+
+                if let Some(reconv) = reconversion_function(&func_prov) {
+                    self.func_prov = Some(func_prov);
+                    self.reconvert = Some(reconv);
+                    break;
                 }
-            };
+            }
 
-            self.reconvert = Some(reconvert);
+            // Predictions come from the TIP's own provider, which the
+            // reconversion order above may never reach.
+            if let Some(profile) = &self.profile {
+                match thread_mgr.get_function_provider(&profile.clsid) {
+                    Ok(func_prov) => self.search = search_function(&func_prov),
+                    Err(e) => warn!("Failed to get the input processor's function provider: {:?}", e),
+                }
+            }
+        }
+        if self.reconvert.is_none() {
+            missing.push(Component::Reconversion);
         }
 
         debug!("Setting focus to document manager");
-        unsafe {
-            match thread_mgr.thread_mgr.SetFocus(Some(self.doc_mgr.as_ref().unwrap())) {
-                Ok(_) => debug!("Focus set successfully"),
+        if let Err(e) = call!(thread_mgr.thread_mgr, SetFocus(self.doc_mgr.as_ref())) {
+            warn!("Failed to set focus: {:?}", e);
+            missing.push(Component::Focus);
+            return Ok(());
+        }
+        debug!("Focus set successfully");
+
+        if self.transitory {
+            debug!("Not associating focus with a transitory document");
+            return Ok(());
+        }
+
+        debug!("Associating focus with message-only window");
+        let associated = call!(thread_mgr.thread_mgr, cast::<ITfThreadMgr>())
+            .and_then(|thread_mgr| call!(thread_mgr, AssociateFocus(hwnd, self.doc_mgr.as_ref())));
+        match associated {
+            Ok(_) => debug!("Focus associated successfully"),
+            Err(e) => {
+                warn!("Failed to associate focus: {:?}", e);
+                missing.push(Component::FocusAssociation);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts through `provider` instead of the active input processor's
+    /// function provider, e.g. a `FakeTip` from the `test-utils` feature, with default
+    /// quirks. Takes effect at the next `initialize`.
+    pub fn set_function_provider(&mut self, provider: Option<ITfFunctionProvider>) {
+        self.provider_override = provider;
+    }
+
+    /// Treats `profile` as the active keyboard profile instead of detecting
+    /// it, e.g. to look up a fake TIP installed with
+    /// [`crate::fake_tip::FakeTip::install`] under its CLSID. Takes effect at
+    /// the next `initialize`.
+    #[cfg(feature = "test-utils")]
+    pub fn set_active_profile(&mut self, profile: Option<ActiveProfile>) {
+        self.profile_override = profile;
+    }
+
+    /// Converts through `tip` with [`TSF::set_function_provider`], and takes
+    /// readings from its script. Takes effect at the next `initialize`.
+    #[cfg(feature = "test-utils")]
+    pub fn set_fake_tip(&mut self, tip: Option<crate::fake_tip::FakeTip>) {
+        self.set_function_provider(tip.as_ref().map(crate::fake_tip::FakeTip::function_provider));
+        self.fake_tip = tip;
+    }
+
+    /// Answers conversions and readings from the script of `test_mode`, and
+    /// measures every conversion as taking no time, or stops doing so for
+    /// `None`. Takes effect at the next `initialize`.
+    #[cfg(feature = "test-utils")]
+    pub fn set_test_mode(&mut self, test_mode: Option<&crate::test_mode::TestMode>) {
+        self.set_fake_tip(test_mode.map(|test_mode| crate::fake_tip::FakeTip::new(test_mode.script().clone())));
+        self.clock = if test_mode.is_some() { Clock::Frozen } else { Clock::Real };
+    }
+
+    /// Starts new documents with the `TS_SD_*` flags `status` instead of
+    /// finishing loading them as soon as their context is pushed. A status
+    /// with `TS_SD_LOADING` lasts until [`TSF::finish_loading`]. Takes effect
+    /// at the next `initialize`.
+    pub fn set_initial_status(&mut self, status: Option<u32>) {
+        self.initial_status = status;
+    }
+
+    /// Clears `TS_SD_LOADING` once the initial content has been set, telling
+    /// the input processor that the document is ready.
+    pub fn finish_loading(&self) -> Result<()> {
+        self.affinity.check()?;
+        self.store()?.finish_loading()?;
+        Ok(())
+    }
+
+    /// Controls logging of text store calls and redaction of document text.
+    /// Applies to the current store and to any store created later.
+    pub fn set_diagnostics(&mut self, diagnostics: Diagnostics) {
+        self.diagnostics = diagnostics;
+        if let Ok(store) = self.store() {
+            store.set_diagnostics(diagnostics);
+        }
+    }
+
+    /// Records every call TSF makes into the text store to `recorder`, for
+    /// [`crate::store_trace::replay`]. Applies to the current store and to
+    /// any store created later.
+    #[cfg(feature = "record")]
+    pub fn set_recorder(&mut self, recorder: Option<crate::store_trace::Recorder>) {
+        self.recorder = recorder.clone();
+        if let Ok(store) = self.store() {
+            store.set_recorder(recorder);
+        }
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Whether a reconversion function was obtained during initialization.
+    pub fn has_reconversion(&self) -> bool {
+        self.reconvert.is_some()
+    }
+
+    fn detect_profile(&self) -> Result<Option<ActiveProfile>> {
+        #[cfg(feature = "test-utils")]
+        if let Some(profile) = &self.profile_override {
+            return Ok(Some(profile.clone()));
+        }
+        compat::active_profile()
+    }
+
+    /// The keyboard input processor that was active at initialization.
+    pub fn profile(&self) -> Option<&ActiveProfile> {
+        self.profile.as_ref()
+    }
+
+    /// Adjustments made for the active input processor, and the
+    /// capabilities it is expected to offer.
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    /// Hub receiving composition, candidate, profile and text events.
+    pub fn events(&self) -> &EventHub {
+        &self.events
+    }
+
+    fn advise_sinks(&self) -> Result<Vec<u32>> {
+        let thread_mgr = match &self.thread_mgr {
+            Some(thread_mgr) => &thread_mgr.thread_mgr,
+            None => return Err(TsfError::NotInitialized)
+        };
+
+        let source = call!(thread_mgr, cast::<ITfSource>())?;
+        let ui_element_mgr: Option<ITfUIElementMgr> = thread_mgr.cast().ok();
+        let ui_element_sink: ITfUIElementSink = ThreadMgrEventSink::new(ui_element_mgr, self.events.clone()).into();
+        let profile_sink = call!(ui_element_sink, cast::<ITfInputProcessorProfileActivationSink>())?;
+        let focus_sink = call!(ui_element_sink, cast::<ITfThreadFocusSink>())?;
+
+        let mut cookies = vec![call!(source, AdviseSink(&ITfUIElementSink::IID, &ui_element_sink))?];
+        let sinks: [(&GUID, IUnknown); 2] = [(&ITfInputProcessorProfileActivationSink::IID, profile_sink.into()), (&ITfThreadFocusSink::IID, focus_sink.into())];
+        for (iid, sink) in sinks {
+            match call!(source, AdviseSink(iid, &sink)) {
+                Ok(cookie) => cookies.push(cookie),
                 Err(e) => {
-                    error!("Failed to set focus: {:?}", e);
-                    return Err(e.into());
+                    for &cookie in &cookies {
+                        let _ = call!(source, UnadviseSink(cookie));
+                    }
+                    return Err(e);
                 }
             }
         }
-        
-        info!("TSF initialized successfully");
+
+        Ok(cookies)
+    }
+
+    /// Watches the keyboard compartments in [`sinks::COMPARTMENTS`]. Each
+    /// compartment is its own source, so cookies are kept with it.
+    fn advise_compartment_sinks(&self) -> Result<Vec<(ITfSource, u32)>> {
+        let thread_mgr = match &self.thread_mgr {
+            Some(thread_mgr) => &thread_mgr.thread_mgr,
+            None => return Err(TsfError::NotInitialized)
+        };
+
+        let compartment_mgr = call!(thread_mgr, cast::<ITfCompartmentMgr>())?;
+        let sink: ITfCompartmentEventSink = CompartmentEventSink::new(compartment_mgr.clone(), self.events.clone()).into();
+
+        let mut cookies: Vec<(ITfSource, u32)> = Vec::new();
+        for compartment in &sinks::COMPARTMENTS {
+            let advised = call!(compartment_mgr, GetCompartment(compartment))
+                .and_then(|compartment| call!(compartment, cast::<ITfSource>()))
+                .and_then(|source| Ok((source.clone(), call!(source, AdviseSink(&ITfCompartmentEventSink::IID, &sink))?)));
+            match advised {
+                Ok(cookie) => cookies.push(cookie),
+                Err(e) => {
+                    for (source, cookie) in &cookies {
+                        let _ = call!(source, UnadviseSink(*cookie));
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(cookies)
+    }
+
+    /// Describes the pipeline: activation, focus, the context stack, advised
+    /// sinks, the text store and the active profile. Never fails; parts that
+    /// cannot be queried, e.g. from another thread, are left out.
+    pub fn debug_dump(&self) -> DebugDump {
+        let on_thread = self.affinity.check().is_ok();
+        let thread_mgr = self.thread_mgr().filter(|_| on_thread);
+
+        let active_flags = thread_mgr.and_then(|thread_mgr| call!(thread_mgr, GetActiveFlags()).ok());
+        // A null focus or context comes back as an error.
+        let focus = thread_mgr.map(|thread_mgr| match call!(thread_mgr, GetFocus()) {
+            Err(_) => Focus::None,
+            Ok(focus) if Some(&focus) == self.doc_mgr.as_ref() => Focus::Ours,
+            Ok(_) => Focus::Other,
+        });
+        let contexts = self.doc_mgr.as_ref().filter(|_| on_thread).map(|doc_mgr| {
+            let top = call!(doc_mgr, GetTop()).ok();
+            let base = call!(doc_mgr, GetBase()).ok();
+            let depth = match (&top, &base) {
+                (None, _) => 0,
+                (Some(top), Some(base)) if top == base => 1,
+                _ => 2,
+            };
+            ContextStack { depth, ours_on_top: top.is_some() && top.as_ref() == self.context.as_ref() }
+        });
+
+        DebugDump {
+            state: self.state.clone(),
+            client_id: self.client_id(),
+            active_flags,
+            focus,
+            contexts,
+            thread_mgr_sinks: self.sink_cookies.len(),
+            compartment_sinks: self.compartment_cookies.len(),
+            store: self.text_store.as_ref().map(|store| unsafe { store.as_impl() }.dump()),
+            profile: self.profile.clone(),
+        }
+    }
+
+    /// The window owned by this instance, available once initialized.
+    pub fn window(&self) -> Option<&HiddenWindow> {
+        self.window.as_ref()
+    }
+
+    // The interfaces below are available between `initialize` and
+    // `uninitialize`. Clones keep the COM objects alive past that point, but
+    // the thread manager is deactivated by then, so they must not be used to
+    // drive TSF afterwards. All of them belong to this thread; use
+    // `agile` to reach them from elsewhere.
+
+    /// The client id returned by `ITfThreadMgr::Activate`.
+    pub fn client_id(&self) -> Option<u32> {
+        self.thread_mgr.as_ref().map(|_| self.client_id)
+    }
+
+    /// The activated thread manager.
+    pub fn thread_mgr(&self) -> Option<&ITfThreadMgr2> {
+        self.thread_mgr.as_ref().map(|thread_mgr| &thread_mgr.thread_mgr)
+    }
+
+    /// The document manager holding this instance's context, e.g. for
+    /// `AssociateFocus` calls made by other code in the process.
+    pub fn document_mgr(&self) -> Option<&ITfDocumentMgr> {
+        self.doc_mgr.as_ref()
+    }
+
+    /// The context pushed onto [`TSF::document_mgr`].
+    pub fn context(&self) -> Option<&ITfContext> {
+        self.context.as_ref()
+    }
+
+    /// The edit cookie returned alongside [`TSF::context`].
+    pub fn edit_cookie(&self) -> Option<u32> {
+        self.context.as_ref().map(|_| self.edit_cookie)
+    }
+
+    /// The crate's text store backing [`TSF::context`].
+    pub fn text_store(&self) -> Option<&ITextStoreACP> {
+        self.text_store.as_ref()
+    }
+
+    /// The crate's `ITfContextOwner` for a document made by
+    /// [`TSF::with_context_owner`].
+    pub fn context_owner(&self) -> Option<&ITfContextOwner> {
+        self.context_owner.as_ref().map(|(owner, _)| owner)
+    }
+
+    /// Replaces the document text, held by the text store or, for a context
+    /// owner's document, by TSF.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_set_text", level = "debug", skip_all, err))]
+    pub fn set_text(&self, text: &str) -> Result<()> {
+        self.affinity.check()?;
+
+        if self.context_owner.is_some() {
+            return self.set_owned_text(text);
+        }
+        let text_store = self.store()?;
+        if !text_store.set_string(text)? {
+            error!("Text store is locked");
+            return Err(TsfError::StoreLocked);
+        }
+
+        debug!("Text store updated with {} characters", text.chars().count());
+        Ok(())
+    }
+
+    /// Replaces the document with `regions`, which the input processor
+    /// treats as separate texts; see [`crate::region`].
+    pub fn set_regions(&self, regions: &[&str]) -> Result<()> {
+        self.set_text(&region::join(regions))
+    }
+
+    /// The ranges of the document's regions, in ACPs.
+    pub fn regions(&self) -> Result<Vec<Range<i32>>> {
+        self.affinity.check()?;
+        Ok(self.store()?.regions())
+    }
+
+    /// Tells input processors where the document is on screen through
+    /// `provider`, or that it is no longer shown for `None`.
+    pub fn set_layout_provider(&self, provider: Option<Arc<dyn LayoutProvider>>) -> Result<()> {
+        self.affinity.check()?;
+        if let Some(owner) = self.owner() {
+            owner.set_layout_provider(provider)?;
+            return self.owner_services()?.layout_changed();
+        }
+        self.store()?.set_layout_provider(provider)?;
+        Ok(())
+    }
+
+    /// Reports that the caret or the view moved, so that candidate windows
+    /// follow. Does nothing without a layout provider.
+    pub fn layout_changed(&self) -> Result<()> {
+        self.affinity.check()?;
+        if let Some(owner) = self.owner() {
+            if owner.has_layout() {
+                self.owner_services()?.layout_changed()?;
+            }
+            return Ok(());
+        }
+        let store = self.store()?;
+        if store.has_layout() {
+            store.notify_layout_changed(layout::VIEW, LayoutChangeKind::Change)?;
+        }
+        Ok(())
+    }
+
+    /// Batches text store notifications to the TIP over `window`; see
+    /// [`TfTextStore::set_notification_batching`].
+    pub fn set_notification_batching(&self, window: Option<Duration>) -> Result<()> {
+        self.affinity.check()?;
+        self.store()?.set_notification_batching(window);
+        Ok(())
+    }
+
+    /// Declares the input scopes of the document, steering the modes and
+    /// candidates the input processor offers for it.
+    pub fn set_input_scopes(&self, scopes: &[InputScope]) -> Result<()> {
+        self.affinity.check()?;
+        if let Some(owner) = self.owner() {
+            if owner.set_input_scopes(scopes)? {
+                self.owner_services()?.attribute_changed(&GUID_PROP_INPUTSCOPE)?;
+            }
+            return Ok(());
+        }
+        if !self.store()?.set_input_scopes(scopes)? {
+            return Err(TsfError::StoreLocked);
+        }
+        Ok(())
+    }
+
+    /// Declares the input scopes of `start..end`, in ACPs, overriding those of
+    /// the document for a field that mixes kinds of text. They last until
+    /// the text is replaced.
+    pub fn set_range_input_scopes(&self, start: i32, end: i32, scopes: &[InputScope]) -> Result<()> {
+        self.affinity.check()?;
+        if !self.store()?.set_range_input_scopes(start, end, scopes)? {
+            return Err(TsfError::StoreLocked);
+        }
         Ok(())
     }
 
-    #[instrument(name = "tsf_uninitialize", level = "debug", skip_all)]
-    pub fn uninitialize(&mut self) {
+    /// The input scopes TSF sees at `acp`, read back through the context's
+    /// `GUID_PROP_INPUTSCOPE` property.
+    pub fn input_scopes_at(&self, acp: i32) -> Result<Vec<InputScope>> {
+        self.affinity.check()?;
+        let context = self.context.as_ref().ok_or(TsfError::NotInitialized)?;
+        let property = call!(context, GetProperty(&GUID_PROP_INPUTSCOPE))?;
+
+        let scopes = Rc::new(RefCell::new(Vec::new()));
+        let session: ITfEditSession = {
+            let (context, scopes) = (context.clone(), scopes.clone());
+            EditSession::new(move |ec| {
+                let range = call!(context, GetStart(ec))?;
+                let mut shifted = 0;
+                call!(range, ShiftEnd(ec, acp + 1, &mut shifted, std::ptr::null()))?;
+                call!(range, ShiftStart(ec, acp, &mut shifted, std::ptr::null()))?;
+                let value = call!(property, GetValue(ec, &range))?;
+                let Ok(unknown) = IUnknown::try_from(&value) else {
+                    return Ok(());
+                };
+                let input_scope = call!(unknown, cast::<ITfInputScope>())?;
+                let (mut raw, mut count) = (std::ptr::null_mut(), 0);
+                call!(input_scope, GetInputScopes(&mut raw, &mut count))?;
+                if !raw.is_null() {
+                    *scopes.borrow_mut() = unsafe { std::slice::from_raw_parts(raw, count as usize) }.iter().copied().map(InputScope::from_raw).collect();
+                    unsafe { CoTaskMemFree(Some(raw as *const _)) };
+                }
+                Ok(())
+            }).into()
+        };
+
+        call!(context, RequestEditSession(self.client_id, &session, TF_ES_SYNC | TF_ES_READ))?
+            .ok()
+            .map_err(hresult("ITfContext::RequestEditSession"))?;
+        Ok(scopes.take())
+    }
+
+    /// Delivers batched text store notifications immediately.
+    pub fn flush_notifications(&self) -> Result<()> {
+        self.affinity.check()?;
+        if self.context_owner.is_some() {
+            return Ok(());
+        }
+        self.store()?.flush_notifications();
+        Ok(())
+    }
+
+    /// Stores `reading` in the document and returns the reconversion
+    /// candidates offered by the active input processor.
+    pub fn convert(&self, reading: &str) -> Result<Vec<String>> {
+        self.convert_cancellable(reading, &CancellationToken::new())
+    }
+
+    /// Like [`TSF::convert`], but gives up with [`crate::cancel::Cancelled`]
+    /// at the next step boundary once `token` is cancelled.
+    pub fn convert_cancellable(&self, reading: &str, token: &CancellationToken) -> Result<Vec<String>> {
+        self.convert_with_limit(reading, token, None)
+    }
+
+    /// Like [`TSF::convert_cancellable`], but enumerates at most
+    /// `max_candidates`, or the limit set with [`TSF::set_max_candidates`]
+    /// for `None`. The input processor is not asked for the rest, which
+    /// bounds the time spent on readings with huge candidate lists.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_convert", level = "debug", skip_all, err))]
+    pub fn convert_with_limit(&self, reading: &str, token: &CancellationToken, max_candidates: Option<usize>) -> Result<Vec<String>> {
+        debug!("Converting {}", self.diagnostics.text(reading));
+        let started = Instant::now();
+        let result = self.candidates(reading, token, max_candidates.or(self.max_candidates));
+        metrics::record_conversion(EngineKind::Tsf, self.clock.elapsed(started), &result);
+        result
+    }
+
+    /// Limits how many candidates conversions enumerate by default; `None`
+    /// enumerates all of them.
+    pub fn set_max_candidates(&mut self, max_candidates: Option<usize>) {
+        self.max_candidates = max_candidates;
+    }
+
+    pub fn max_candidates(&self) -> Option<usize> {
+        self.max_candidates
+    }
+
+    fn candidates(&self, reading: &str, token: &CancellationToken, max_candidates: Option<usize>) -> Result<Vec<String>> {
+        let candidate_list = self.candidate_list(reading, token)?;
+        self.enumerate(&candidate_list, reading, token, max_candidates)
+    }
+
+    /// The strings of up to `max_candidates` of `candidate_list`, offered
+    /// for `reading`.
+    fn enumerate(&self, candidate_list: &ITfCandidateList, reading: &str, token: &CancellationToken, max_candidates: Option<usize>) -> Result<Vec<String>> {
+        let max_candidates = max_candidates.unwrap_or(usize::MAX);
+
+        scope!("candidate_enumeration");
+        let candidates = self.diagnostics.timed(SlowCall::Candidates, || -> Result<Vec<String>> {
+            let count = call!(candidate_list, GetCandidateNum())?;
+
+            let mut candidates = Vec::with_capacity((count as usize).min(max_candidates));
+            let mut echoed = !self.quirks.candidates_echo_reading;
+            for index in 0..count {
+                if candidates.len() >= max_candidates {
+                    debug!("Stopping at {} of {} candidates", candidates.len(), count);
+                    break;
+                }
+                token.check()?;
+                let candidate = call!(candidate_list, GetCandidate(index))?;
+                let candidate = call!(candidate, GetString())?.to_string();
+                // Only the echo goes; the reading may also be offered as a
+                // candidate of its own.
+                if !echoed && candidate == reading {
+                    echoed = true;
+                    continue;
+                }
+                candidates.push(candidate);
+            }
+            Ok(candidates)
+        })?;
+
+        debug!("Retrieved {} candidates", candidates.len());
+        Ok(candidates)
+    }
+
+    /// Reports `candidate` as the one chosen for `reading`, so that input
+    /// processors which learn from reconversion rank it higher next time.
+    pub fn commit(&self, reading: &str, candidate: &str) -> Result<()> {
+        debug!("Committing {} for {}", self.diagnostics.text(candidate), self.diagnostics.text(reading));
+        let candidate_list = self.candidate_list(reading, &CancellationToken::new())?;
+        let count = call!(candidate_list, GetCandidateNum())?;
+
+        for index in 0..count {
+            let offered = call!(candidate_list, GetCandidate(index))?;
+            if call!(offered, GetString())? == candidate {
+                call!(candidate_list, SetResult(index, CAND_FINALIZED))?;
+                return Ok(());
+            }
+        }
+
+        warn!("Candidate is not offered for the reading");
+        Err(TsfError::InvalidArgument("candidate is not offered for the reading"))
+    }
+
+    /// Predictions completing `prefix`, a reading being typed, in the order
+    /// the input processor suggests them. Needs an input processor offering
+    /// `ITfFnSearchCandidateProvider`, otherwise [`TsfError::NoPrediction`]
+    /// is returned. At most [`TSF::max_candidates`] are enumerated.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_predict", level = "debug", skip_all, err))]
+    pub fn predict(&self, prefix: &str) -> Result<Vec<String>> {
+        self.affinity.check()?;
+        debug!("Predicting {}", self.diagnostics.text(prefix));
+
+        let Some(search) = &self.search else {
+            warn!("Search candidate provider is not available");
+            return Err(TsfError::NoPrediction);
+        };
+        let candidate_list = call!(search, GetSearchCandidates(&BSTR::from(prefix), &BSTR::new()))?;
+        let predictions = self.enumerate(&candidate_list, prefix, &CancellationToken::new(), self.max_candidates)?;
+        debug!("Retrieved {} predictions", predictions.len());
+        Ok(predictions)
+    }
+
+    /// Whether [`TSF::predict`] is available.
+    pub fn has_prediction(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Converts each of `clauses` of `segmentation` on its own.
+    pub fn convert_clauses(&self, segmentation: &Segmentation, clauses: Range<usize>) -> Result<Vec<Vec<String>>> {
+        if clauses.end > segmentation.len() {
+            return Err(TsfError::InvalidArgument("clause index is out of range"));
+        }
+        clauses.filter_map(|index| segmentation.clause(index)).map(|reading| self.convert(&reading)).collect()
+    }
+
+    /// Moves the end of clause `index` by `delta` chars, as with
+    /// [`Segmentation::move_boundary`], and converts the clauses that
+    /// changed again. Returns them with their candidates.
+    pub fn adjust_clause(&self, segmentation: &mut Segmentation, index: usize, delta: isize) -> Result<(Range<usize>, Vec<Vec<String>>)> {
+        let changed = segmentation.move_boundary(index, delta)?;
+        debug!("Moved the end of clause {} by {}, reconverting clauses {:?}", index, delta, changed);
+        let candidates = self.convert_clauses(segmentation, changed.clone())?;
+        Ok((changed, candidates))
+    }
+
+    /// Stores `reading` in the document and asks the reconversion function
+    /// for its candidate list.
+    fn candidate_list(&self, reading: &str, token: &CancellationToken) -> Result<ITfCandidateList> {
+        token.check()?;
+        {
+            scope!("store_write");
+            self.set_text(reading)?;
+            self.flush_notifications()?;
+        }
+
+        let reconvert = match &self.reconvert {
+            Some(reconvert) => reconvert,
+            None => {
+                error!("Reconversion function is not available");
+                return Err(TsfError::NoReconversion);
+            }
+        };
+
+        token.check()?;
+        let range = self.document_range()?;
+
+        token.check()?;
+        scope!("reconversion");
+        self.diagnostics.timed(SlowCall::Reconversion, || {
+            let range = if self.quirks.query_range {
+                debug!("Querying reconversion range");
+                let mut new_range = None;
+                let mut convertable = BOOL(0);
+                call!(reconvert, QueryRange(&range, &mut new_range, &mut convertable))?;
+                if !convertable.as_bool() {
+                    warn!("Range is not convertable");
+                    return Err(TsfError::NotConvertible);
+                }
+                new_range.unwrap_or(range)
+            } else {
+                range
+            };
+
+            token.check()?;
+            debug!("Getting reconversion candidates");
+            call!(reconvert, GetReconversion(&range))
+        })
+    }
+
+    /// Stores `text` in the document and returns its reading as recorded in
+    /// `GUID_PROP_READING`. Only input processors that annotate readings
+    /// populate the property; otherwise [`TsfError::NoReading`] is returned.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_reading", level = "debug", skip_all, err))]
+    pub fn reading(&self, text: &str) -> Result<String> {
+        debug!("Reading {}", self.diagnostics.text(text));
+        #[cfg(feature = "test-utils")]
+        if let Some(fake_tip) = &self.fake_tip {
+            return fake_tip.reading(text);
+        }
+        if !self.quirks.reading_property {
+            warn!("Active input processor does not record readings");
+            return Err(TsfError::NoReading);
+        }
+
+        self.set_text(text)?;
+        self.flush_notifications()?;
+
+        let range = self.document_range()?;
+        let context = self.context.as_ref().ok_or(TsfError::NotInitialized)?;
+        let property = call!(context, GetProperty(&GUID_PROP_READING))?;
+
+        let reading = Rc::new(RefCell::new(None));
+        let session: ITfEditSession = {
+            let reading = reading.clone();
+            EditSession::new(move |ec| {
+                *reading.borrow_mut() = Some(call!(property, GetValue(ec, &range)).map(|value| BSTR::try_from(&value).ok()));
+                Ok(())
+            }).into()
+        };
+
+        call!(context, RequestEditSession(self.client_id, &session, TF_ES_SYNC | TF_ES_READ))?
+            .ok()
+            .map_err(hresult("ITfContext::RequestEditSession"))?;
+
+        let reading = reading.borrow_mut().take().transpose()?.flatten().map(|reading| reading.to_string()).unwrap_or_default();
+        if reading.is_empty() {
+            warn!("No reading property on the document");
+            return Err(TsfError::NoReading);
+        }
+
+        Ok(reading)
+    }
+
+    fn store(&self) -> Result<&TfTextStore> {
+        match &self.text_store {
+            Some(text_store) => Ok(unsafe { text_store.as_impl() }),
+            None if self.context_owner.is_some() => Err(TsfError::NoTextStore),
+            None => {
+                error!("TSF is not initialized");
+                Err(TsfError::NotInitialized)
+            }
+        }
+    }
+
+    fn owner(&self) -> Option<&ContextOwner> {
+        self.context_owner.as_ref().map(|(owner, _)| unsafe { owner.as_impl() })
+    }
+
+    /// The context's owner services, for notifying TSF directly of layout,
+    /// status and attribute changes. Works for text store and context owner
+    /// documents alike.
+    pub fn owner_services(&self) -> Result<OwnerServices> {
+        self.affinity.check()?;
+        let context = self.context.as_ref().ok_or(TsfError::NotInitialized)?;
+        Ok(OwnerServices::new(call!(context, cast::<ITfContextOwnerServices>())?))
+    }
+
+    /// Replaces the text TSF keeps for a context owner's document and
+    /// selects it, in a synchronous read/write edit session.
+    fn set_owned_text(&self, text: &str) -> Result<()> {
+        let context = self.context.as_ref().ok_or(TsfError::NotInitialized)?;
+        let session: ITfEditSession = {
+            let context = context.clone();
+            let text: Vec<u16> = text.encode_utf16().collect();
+            EditSession::new(move |ec| {
+                let range = call!(context, GetStart(ec))?;
+                let end = call!(context, GetEnd(ec))?;
+                call!(range, ShiftEndToRange(ec, &end, TF_ANCHOR_END))?;
+                call!(range, SetText(ec, 0, &text))?;
+                let selection = TF_SELECTION {
+                    range: ManuallyDrop::new(Some(range)),
+                    style: TF_SELECTIONSTYLE { ase: TF_AE_END, fInterimChar: BOOL(0) },
+                };
+                let selected = call!(context, SetSelection(ec, std::slice::from_ref(&selection)));
+                drop(ManuallyDrop::into_inner(selection.range));
+                Ok(selected?)
+            }).into()
+        };
+
+        let hr = call!(context, RequestEditSession(self.client_id, &session, TF_ES_SYNC | TF_ES_READWRITE))?;
+        if hr == TF_E_LOCKED {
+            error!("Context is locked");
+            return Err(TsfError::StoreLocked);
+        }
+        hr.ok().map_err(hresult("ITfContext::RequestEditSession"))
+    }
+
+    /// Runs a trivial synchronous edit session, to check that the context
+    /// still grants them.
+    pub(crate) fn probe_edit_session(&self) -> Result<()> {
+        self.affinity.check()?;
+        self.document_range().map(drop)
+    }
+
+    /// Returns a range covering the whole document, obtained in a synchronous
+    /// read-only edit session.
+    fn document_range(&self) -> Result<ITfRange> {
+        scope!("edit_session");
+        let context = match &self.context {
+            Some(context) => context.clone(),
+            None => {
+                error!("TSF is not initialized");
+                return Err(TsfError::NotInitialized);
+            }
+        };
+
+        let range = Rc::new(RefCell::new(None));
+        let session: ITfEditSession = {
+            let context = context.clone();
+            let range = range.clone();
+            EditSession::new(move |ec| {
+                let start = call!(context, GetStart(ec))?;
+                let end = call!(context, GetEnd(ec))?;
+                call!(start, ShiftEndToRange(ec, &end, TF_ANCHOR_END))?;
+                *range.borrow_mut() = Some(start);
+                Ok(())
+            }).into()
+        };
+
+        debug!("Requesting read-only edit session");
+        call!(context, RequestEditSession(self.client_id, &session, TF_ES_SYNC | TF_ES_READ))?
+            .ok()
+            .map_err(hresult("ITfContext::RequestEditSession"))?;
+
+        let range = range.borrow_mut().take();
+        range.ok_or_else(|| hresult("ITfContext::RequestEditSession")(E_UNEXPECTED.into()))
+    }
+
+    /// Registers the live interfaces in the Global Interface Table so that
+    /// other threads can reach this pipeline through the returned handle.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_agile", level = "debug", skip_all, err))]
+    pub fn agile(&self) -> Result<AgileTsf> {
+        self.affinity.check()?;
+
+        let (thread_mgr, doc_mgr, context) = match (&self.thread_mgr, &self.doc_mgr, &self.context) {
+            (Some(thread_mgr), Some(doc_mgr), Some(context)) => (thread_mgr, doc_mgr, context),
+            _ => {
+                error!("TSF is not initialized");
+                return Err(TsfError::NotInitialized);
+            }
+        };
+
+        debug!("Registering TSF interfaces in the global interface table");
+        Ok(AgileTsf {
+            thread_mgr: GlobalInterface::register(&thread_mgr.thread_mgr)?,
+            doc_mgr: GlobalInterface::register(doc_mgr)?,
+            context: GlobalInterface::register(context)?,
+            reconvert: self.reconvert.as_ref().map(GlobalInterface::register).transpose()?,
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "tsf_uninitialize", level = "debug", skip_all, err))]
+    pub fn uninitialize(&mut self) -> Result<()> {
+        self.affinity.check()?;
+
         info!("Uninitializing TSF");
         
+        if let (Some(thread_mgr), Some(window), false) = (&self.thread_mgr, &self.window, self.transitory) {
+            debug!("Clearing focus association");
+            if let Ok(thread_mgr) = call!(thread_mgr.thread_mgr, cast::<ITfThreadMgr>())
+                && let Err(e) = call!(thread_mgr, AssociateFocus(window.hwnd(), None))
+            {
+                warn!("Failed to clear focus association: {:?}", e);
+            }
+        }
+
         if let Some(thread_mgr) = &self.thread_mgr {
-            debug!("Deactivating thread manager");
-            unsafe {
-                match thread_mgr.thread_mgr.Deactivate() {
-                    Ok(_) => debug!("Thread manager deactivated successfully"),
-                    Err(e) => warn!("Failed to deactivate thread manager: {:?}", e)
+            debug!("Unadvising thread manager event sinks");
+            if let Ok(source) = call!(thread_mgr.thread_mgr, cast::<ITfSource>()) {
+                for cookie in self.sink_cookies.drain(..) {
+                    if let Err(e) = call!(source, UnadviseSink(cookie)) {
+                        warn!("Failed to unadvise sink {}: {:?}", cookie, e);
+                    }
                 }
             }
         }
 
+        debug!("Unadvising compartment event sinks");
+        for (source, cookie) in self.compartment_cookies.drain(..) {
+            if let Err(e) = call!(source, UnadviseSink(cookie)) {
+                warn!("Failed to unadvise compartment sink {}: {:?}", cookie, e);
+            }
+        }
+
+        if let (Some((_, cookie)), Some(context)) = (self.context_owner.take(), &self.context) && cookie != 0 {
+            debug!("Unadvising context owner");
+            if let Err(e) = call!(context, cast::<ITfSource>()).and_then(|source| call!(source, UnadviseSink(cookie))) {
+                warn!("Failed to unadvise context owner: {:?}", e);
+            }
+        }
+
+        if let Some(doc_mgr) = &self.doc_mgr {
+            debug!("Popping contexts from document manager");
+            if let Err(e) = call!(doc_mgr, Pop(TF_POPF_ALL)) {
+                warn!("Failed to pop contexts: {:?}", e);
+            }
+        }
+
+        if let Some(thread_mgr) = &self.thread_mgr {
+            debug!("Deactivating thread manager");
+            match call!(thread_mgr.thread_mgr, Deactivate()) {
+                Ok(_) => debug!("Thread manager deactivated successfully"),
+                Err(e) => warn!("Failed to deactivate thread manager: {:?}", e)
+            }
+        }
+
         debug!("Clearing resources");
         self.reconvert = None;
+        self.search = None;
         debug!("Reconversion function cleared");
         
         self.func_prov = None;
@@ -183,7 +1191,58 @@ impl TSF {
         
         self.thread_mgr = None;
         debug!("Thread manager cleared");
+
+        self.window = None;
+        debug!("Window destroyed");
+
+        self.client_id = 0;
+        self.profile = None;
+        self.quirks = Quirks::default();
+        self.state = TsfState::Uninitialized;
         
         info!("TSF uninitialized successfully");
+        Ok(())
+    }
+}
+
+impl Drop for TSF<'_> {
+    fn drop(&mut self) {
+        if self.thread_mgr.is_some() && let Err(e) = self.uninitialize() {
+            warn!("Failed to uninitialize TSF on drop: {:?}", e);
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Asks `func_prov` for its reconversion function.
+fn reconversion_function(func_prov: &ITfFunctionProvider) -> Option<ITfFnReconversion> {
+    debug!("Getting reconversion function");
+    match call!(func_prov, GetFunction(&windows_core::GUID::zeroed(), &ITfFnReconversion::IID)) {
+        Ok(func) => match call!(func, cast::<ITfFnReconversion>()) {
+            Ok(reconv) => {
+                debug!("Reconversion function retrieved and cast successfully");
+                Some(reconv)
+            },
+            Err(e) => {
+                warn!("Failed to cast function to ITfFnReconversion: {:?}", e);
+                None
+            }
+        },
+        Err(e) => {
+            warn!("Failed to get reconversion function: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Asks `func_prov` for its search candidate provider, which offers
+/// predictions.
+fn search_function(func_prov: &ITfFunctionProvider) -> Option<ITfFnSearchCandidateProvider> {
+    debug!("Getting search candidate provider");
+    match call!(func_prov, GetFunction(&windows_core::GUID::zeroed(), &ITfFnSearchCandidateProvider::IID)) {
+        Ok(func) => call!(func, cast::<ITfFnSearchCandidateProvider>()).ok(),
+        Err(e) => {
+            debug!("No search candidate provider: {:?}", e);
+            None
+        }
+    }
+}
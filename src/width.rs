@@ -0,0 +1,204 @@
+/// Character classes affected by a width conversion.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct WidthClasses {
+    /// ASCII letters and digits.
+    pub alphanumeric: bool,
+    /// ASCII symbols and the CJK punctuation with half-width forms
+    /// (`。「」、・`).
+    pub punctuation: bool,
+    /// U+0020 and the ideographic space U+3000.
+    pub space: bool,
+    /// Katakana, including the voiced sound marks.
+    pub katakana: bool,
+}
+
+impl WidthClasses {
+    pub const ALL: Self = Self { alphanumeric: true, punctuation: true, space: true, katakana: true };
+    pub const ASCII: Self = Self { alphanumeric: true, punctuation: true, space: true, katakana: false };
+    pub const KATAKANA: Self = Self { alphanumeric: false, punctuation: false, space: false, katakana: true };
+}
+
+impl Default for WidthClasses {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Half-width katakana U+FF66..=U+FF9D and their full-width forms, in order.
+const HALF_KATAKANA: &str = "ｦｧｨｩｪｫｬｭｮｯｰｱｲｳｴｵｶｷｸｹｺｻｼｽｾｿﾀﾁﾂﾃﾄﾅﾆﾇﾈﾉﾊﾋﾌﾍﾎﾏﾐﾑﾒﾓﾔﾕﾖﾗﾘﾙﾚﾛﾜﾝ";
+const FULL_KATAKANA: &str = "ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン";
+const HALF_PUNCTUATION: &str = "｡｢｣､･";
+const FULL_PUNCTUATION: &str = "。「」、・";
+
+/// Katakana taking a dakuten or handakuten, and their composed forms.
+const VOICEABLE: &str = "ウカキクケコサシスセソタチツテトハヒフヘホ";
+const VOICED: &str = "ヴガギグゲゴザジズゼゾダヂヅデドバビブベボ";
+const SEMI_VOICEABLE: &str = "ハヒフヘホ";
+const SEMI_VOICED: &str = "パピプペポ";
+
+const HALF_DAKUTEN: char = 'ﾞ';
+const HALF_HANDAKUTEN: char = 'ﾟ';
+
+/// Converts the selected classes in `text` to full-width forms. Half-width
+/// katakana followed by a voiced sound mark is composed (`ｶﾞ` → `ガ`).
+pub fn to_fullwidth(text: &str, classes: WidthClasses) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if classes.katakana
+            && let Some(full) = swap(c, HALF_KATAKANA, FULL_KATAKANA)
+        {
+            let composed = match chars.peek() {
+                Some(&HALF_DAKUTEN) => swap(full, VOICEABLE, VOICED),
+                Some(&HALF_HANDAKUTEN) => swap(full, SEMI_VOICEABLE, SEMI_VOICED),
+                _ => None,
+            };
+            match composed {
+                Some(composed) => {
+                    chars.next();
+                    output.push(composed);
+                }
+                None => output.push(full),
+            }
+            continue;
+        }
+
+        let full = match c {
+            ' ' if classes.space => '\u{3000}',
+            '!'..='~' if class_of(c, classes) => char::from_u32(c as u32 - 0x21 + 0xFF01).unwrap_or(c),
+            HALF_DAKUTEN if classes.katakana => '゛',
+            HALF_HANDAKUTEN if classes.katakana => '゜',
+            _ if classes.punctuation => swap(c, HALF_PUNCTUATION, FULL_PUNCTUATION).unwrap_or(c),
+            _ => c,
+        };
+        output.push(full);
+    }
+
+    output
+}
+
+/// Converts the selected classes in `text` to half-width forms. Voiced
+/// katakana is decomposed (`ガ` → `ｶﾞ`); characters without a half-width
+/// form are kept.
+pub fn to_halfwidth(text: &str, classes: WidthClasses) -> String {
+    let mut output = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if classes.katakana {
+            if let Some(half) = swap(c, FULL_KATAKANA, HALF_KATAKANA) {
+                output.push(half);
+                continue;
+            }
+
+            let decomposed = swap(c, VOICED, VOICEABLE)
+                .map(|base| (base, HALF_DAKUTEN))
+                .or_else(|| swap(c, SEMI_VOICED, SEMI_VOICEABLE).map(|base| (base, HALF_HANDAKUTEN)));
+            if let Some((base, mark)) = decomposed
+                && let Some(half) = swap(base, FULL_KATAKANA, HALF_KATAKANA)
+            {
+                output.push(half);
+                output.push(mark);
+                continue;
+            }
+
+            match c {
+                '゛' => {
+                    output.push(HALF_DAKUTEN);
+                    continue;
+                }
+                '゜' => {
+                    output.push(HALF_HANDAKUTEN);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        let half = match c {
+            '\u{3000}' if classes.space => ' ',
+            '\u{FF01}'..='\u{FF5E}' => {
+                let ascii = char::from_u32(c as u32 - 0xFF01 + 0x21).unwrap_or(c);
+                if class_of(ascii, classes) { ascii } else { c }
+            }
+            _ if classes.punctuation => swap(c, FULL_PUNCTUATION, HALF_PUNCTUATION).unwrap_or(c),
+            _ => c,
+        };
+        output.push(half);
+    }
+
+    output
+}
+
+/// Half-width ASCII and space with full-width katakana, the form most
+/// matching code expects.
+pub fn canonical(text: &str) -> String {
+    to_fullwidth(&to_halfwidth(text, WidthClasses::ASCII), WidthClasses::KATAKANA)
+}
+
+/// MS-IME's note for text written in half-width katakana (`[半]`) or in
+/// full-width ASCII forms (`[全]`), the variants it offers among candidates.
+pub fn annotation(text: &str) -> Option<&'static str> {
+    if text.chars().any(|c| ('\u{FF61}'..='\u{FF9F}').contains(&c)) {
+        Some("[半]")
+    } else if text.chars().any(|c| ('\u{FF01}'..='\u{FF5E}').contains(&c)) {
+        Some("[全]")
+    } else {
+        None
+    }
+}
+
+/// Whether the ASCII character `c` belongs to an enabled class.
+fn class_of(c: char, classes: WidthClasses) -> bool {
+    if c.is_ascii_alphanumeric() {
+        classes.alphanumeric
+    } else {
+        classes.punctuation
+    }
+}
+
+/// Maps `c` from `from` to the character at the same position in `to`.
+fn swap(c: char, from: &str, to: &str) -> Option<char> {
+    from.chars().position(|f| f == c).and_then(|i| to.chars().nth(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widens_the_selected_classes() {
+        assert_eq!(to_fullwidth("ABC 123!", WidthClasses::ALL), "ＡＢＣ\u{3000}１２３！");
+        assert_eq!(to_fullwidth("ABC 123!", WidthClasses { punctuation: false, space: false, ..WidthClasses::ALL }), "ＡＢＣ １２３!");
+        assert_eq!(to_fullwidth("ｶﾀｶﾅ｡", WidthClasses::KATAKANA), "カタカナ｡");
+        assert_eq!(to_fullwidth("｢ｶﾀｶﾅ｣", WidthClasses::ALL), "「カタカナ」");
+    }
+
+    #[test]
+    fn composes_voiced_katakana() {
+        assert_eq!(to_fullwidth("ｶﾞｯﾂﾎﾟｰｽﾞ", WidthClasses::KATAKANA), "ガッツポーズ");
+        assert_eq!(to_fullwidth("ｳﾞｧ", WidthClasses::KATAKANA), "ヴァ");
+        assert_eq!(to_fullwidth("ｱﾞﾏﾟ", WidthClasses::KATAKANA), "ア゛マ゜");
+    }
+
+    #[test]
+    fn narrows_the_selected_classes() {
+        assert_eq!(to_halfwidth("ＡＢＣ\u{3000}１２３！", WidthClasses::ALL), "ABC 123!");
+        assert_eq!(to_halfwidth("ＡＢＣ！", WidthClasses { alphanumeric: false, ..WidthClasses::ALL }), "ＡＢＣ!");
+        assert_eq!(to_halfwidth("ガッツポーズ。", WidthClasses::KATAKANA), "ｶﾞｯﾂﾎﾟｰｽﾞ。");
+        assert_eq!(to_halfwidth("ヴァヰ漢字", WidthClasses::ALL), "ｳﾞｧヰ漢字");
+    }
+
+    #[test]
+    fn canonical_form_is_narrow_ascii_and_wide_katakana() {
+        assert_eq!(canonical("ＡＢＣ　ｶﾞｲﾄﾞ"), "ABC ガイド");
+        assert_eq!(canonical(&canonical("ＡＢＣ　ｶﾞｲﾄﾞ")), "ABC ガイド");
+    }
+
+    #[test]
+    fn annotates_width_variants() {
+        assert_eq!(annotation("ｶﾀｶﾅ"), Some("[半]"));
+        assert_eq!(annotation("ＡＢＣ"), Some("[全]"));
+        assert_eq!(annotation("カタカナ ABC"), None);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{cell::RefCell, collections::HashMap, sync::OnceLock};
+
+use crate::trace::{debug, error, warn};
+use windows::Win32::{
+    Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM},
+    System::LibraryLoader::GetModuleHandleW,
+    UI::{
+        TextServices::ITextStoreACP,
+        WindowsAndMessaging::{CreateWindowExW, DefWindowProcW, DestroyWindow, RegisterClassExW, HWND_MESSAGE, WINDOW_EX_STYLE, WM_TIMER, WNDCLASSEXW, WS_OVERLAPPED},
+    },
+};
+use windows_core::{w, AsImpl, PCWSTR};
+
+use crate::{
+    error::{call, hresult, Result},
+    text_store::{TfTextStore, NOTIFY_TIMER_ID},
+};
+
+const CLASS_NAME: PCWSTR = w!("iatjc_hidden_window");
+
+static CLASS_ATOM: OnceLock<u16> = OnceLock::new();
+
+thread_local! {
+    /// The text store whose notification timer runs on each window of this
+    /// thread; see [`HiddenWindow::set_text_store`].
+    static TEXT_STORES: RefCell<HashMap<isize, ITextStoreACP>> = RefCell::new(HashMap::new());
+}
+
+extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if msg == WM_TIMER && wparam.0 == NOTIFY_TIMER_ID {
+        // Cloned out so that the store can run sinks without the map borrowed.
+        let store = TEXT_STORES.with(|stores| stores.borrow().get(&hwnd.0).cloned());
+        if let Some(store) = store {
+            let store: &TfTextStore = unsafe { store.as_impl() };
+            store.flush_notifications();
+            return LRESULT(0);
+        }
+    }
+    unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+}
+
+fn register_class(instance: HINSTANCE) -> Result<()> {
+    let atom = *CLASS_ATOM.get_or_init(|| {
+        let class = WNDCLASSEXW {
+            cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance,
+            lpszClassName: CLASS_NAME,
+            ..Default::default()
+        };
+        unsafe { RegisterClassExW(&class) }
+    });
+
+    if atom == 0 {
+        error!("Failed to register hidden window class");
+        return Err(hresult("RegisterClassExW")(windows_core::Error::from_win32()));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WindowKind {
+    /// Parented to HWND_MESSAGE; receives messages but is never shown.
+    MessageOnly,
+    /// A top-level window that is created but never made visible.
+    Hidden,
+}
+
+/// A window owned by the crate, destroyed when dropped.
+///
+/// Gives the text store something to return from `GetWnd`, a target for
+/// `AssociateFocus`, and a destination for messages driving the pump.
+pub struct HiddenWindow {
+    hwnd: HWND,
+    kind: WindowKind,
+}
+
+impl HiddenWindow {
+    pub fn new(kind: WindowKind) -> Result<Self> {
+        debug!("Creating {:?} window", kind);
+        let instance: HINSTANCE = call!(GetModuleHandleW(None))?.into();
+        register_class(instance)?;
+
+        let parent = match kind {
+            WindowKind::MessageOnly => HWND_MESSAGE,
+            WindowKind::Hidden => HWND(0),
+        };
+
+        let hwnd = unsafe {
+            CreateWindowExW(WINDOW_EX_STYLE(0), CLASS_NAME, w!("iatjc"), WS_OVERLAPPED, 0, 0, 0, 0, parent, None, instance, None)
+        };
+
+        if hwnd.0 == 0 {
+            error!("Failed to create {:?} window", kind);
+            return Err(hresult("CreateWindowExW")(windows_core::Error::from_win32()));
+        }
+
+        debug!("Created {:?} window: {:?}", kind, hwnd);
+        Ok(Self { hwnd, kind })
+    }
+
+    pub fn hwnd(&self) -> HWND {
+        self.hwnd
+    }
+
+    pub fn kind(&self) -> WindowKind {
+        self.kind
+    }
+
+    /// Flushes the batched notifications of `store` when its notification
+    /// timer fires on this window, for as long as the window lives.
+    pub(crate) fn set_text_store(&self, store: &ITextStoreACP) {
+        TEXT_STORES.with(|stores| stores.borrow_mut().insert(self.hwnd.0, store.clone()));
+    }
+}
+
+impl Drop for HiddenWindow {
+    fn drop(&mut self) {
+        debug!("Destroying window: {:?}", self.hwnd);
+        TEXT_STORES.with(|stores| stores.borrow_mut().remove(&self.hwnd.0));
+        if let Err(e) = unsafe { DestroyWindow(self.hwnd) } {
+            warn!("Failed to destroy window {:?}: {:?}", self.hwnd, e);
+        }
+    }
+}
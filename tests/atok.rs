@@ -0,0 +1,49 @@
+#![cfg(windows)]
+
+use iatjc_rs::{
+    com::Com,
+    compat::{self, Quirks, Tip},
+    tsf::TSF,
+};
+use windows_core::GUID;
+
+const UNREGISTERED_CLSID: GUID = GUID::from_u128(0x5ad4f6d2_8b37_4e6f_8f5a_3c7a0e4f9d11);
+
+#[test]
+fn atok_is_detected_by_description() {
+    assert_eq!(Tip::detect(UNREGISTERED_CLSID, "ATOK 2017"), Tip::Atok);
+    assert_eq!(Tip::detect(UNREGISTERED_CLSID, "  ATOK for Windows"), Tip::Atok);
+    assert_eq!(Tip::detect(UNREGISTERED_CLSID, "Microsoft IME"), Tip::Other(UNREGISTERED_CLSID));
+}
+
+#[test]
+fn known_clsid_wins_over_description() {
+    let ms_ime = GUID::from_u128(0x03b5835f_f03c_411b_9ce2_aa23e1171e36);
+    assert_eq!(Tip::detect(ms_ime, "ATOK"), Tip::MsIme);
+}
+
+#[test]
+fn atok_quirks() {
+    let quirks = Quirks::for_tip(Tip::Atok);
+    assert!(quirks.candidates_echo_reading);
+    assert!(!quirks.reading_property);
+    assert!(quirks.query_range);
+    assert_eq!(quirks.providers, Quirks::default().providers);
+}
+
+/// Needs ATOK as the active input processor.
+#[test]
+#[ignore]
+fn atok_conversion_drops_reading() {
+    let com = Com::new().unwrap();
+    let tip = compat::active_profile().unwrap().map(|profile| profile.tip);
+    assert_eq!(tip, Some(Tip::Atok), "ATOK is not the active input processor");
+
+    let mut tsf = TSF::new(&com);
+    tsf.initialize().unwrap();
+    assert_eq!(tsf.quirks(), &Quirks::for_tip(Tip::Atok));
+
+    let candidates = tsf.convert("へんかん").unwrap();
+    assert!(!candidates.is_empty());
+    assert_ne!(candidates[0], "へんかん");
+}
@@ -0,0 +1,296 @@
+#![cfg(all(windows, feature = "test-utils"))]
+
+//! The documented `ITextStoreACP` requirements, checked against the crate's
+//! store one by one. The test fails, naming each requirement and why, when
+//! one outside [`KNOWN_FAILURES`] breaks, or when a known failure starts
+//! passing and should be taken off the list.
+
+use std::{cell::RefCell, panic::AssertUnwindSafe, rc::Rc};
+
+use iatjc_rs::{
+    com::Com,
+    store_trace,
+    testing::{self, get_text, set_selection, MockSink, Received, StoreState},
+};
+use windows::Win32::{
+    Foundation::{E_FAIL, E_INVALIDARG, S_OK},
+    System::Ole::{CONNECT_E_ADVISELIMIT, CONNECT_E_NOCONNECTION},
+    UI::TextServices::{ITextStoreACP, TS_AS_SEL_CHANGE, TS_AS_TEXT_CHANGE, TS_DEFAULT_SELECTION, TS_E_INVALIDPOS, TS_E_NOLOCK, TS_E_READONLY, TS_E_SYNCHRONOUS, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_SD_READONLY, TS_SELECTION_ACP, TS_S_ASYNC},
+};
+use windows_core::{IUnknown, Interface, HRESULT};
+
+const ALL: u32 = TS_AS_TEXT_CHANGE | TS_AS_SEL_CHANGE;
+
+struct Requirement {
+    id: &'static str,
+    text: &'static str,
+    check: fn() -> Result<(), String>,
+}
+
+const REQUIREMENTS: &[Requirement] = &[
+    Requirement { id: "lock/get-text", text: "GetText fails with TS_E_NOLOCK without a read lock", check: get_text_needs_lock },
+    Requirement { id: "lock/get-selection", text: "GetSelection fails with TS_E_NOLOCK without a read lock", check: get_selection_needs_lock },
+    Requirement { id: "lock/get-end-acp", text: "GetEndACP fails with TS_E_NOLOCK without a read lock", check: get_end_acp_needs_lock },
+    Requirement { id: "lock/set-selection", text: "SetSelection fails with TS_E_NOLOCK under a read-only lock", check: set_selection_needs_write_lock },
+    Requirement { id: "lock/get-status", text: "GetStatus needs no lock", check: get_status_needs_no_lock },
+    Requirement { id: "lock/sync-while-locked", text: "A synchronous request made while locked returns TS_E_SYNCHRONOUS", check: sync_request_while_locked },
+    Requirement { id: "lock/async-while-locked", text: "An asynchronous request made while locked returns TS_S_ASYNC and is granted after the release", check: async_request_while_locked },
+    Requirement { id: "lock/upgrade", text: "A read/write request made under a read lock is granted read/write after the release", check: async_upgrade },
+    Requirement { id: "lock/granted-flags", text: "OnLockGranted receives TS_LF_READ or TS_LF_READWRITE, without TS_LF_SYNC", check: granted_flags },
+    Requirement { id: "lock/sink-failure", text: "The lock is released when OnLockGranted fails", check: sink_failure_releases },
+    Requirement { id: "sink/advise-limit", text: "Advising a second sink fails with CONNECT_E_ADVISELIMIT", check: advise_limit },
+    Requirement { id: "sink/advise-after-unadvise", text: "A new sink can be advised once the previous one is unadvised", check: advise_after_unadvise },
+    Requirement { id: "sink/readvise", text: "Advising the same sink again replaces its mask", check: readvise_replaces_mask },
+    Requirement { id: "sink/advise-iid", text: "AdviseSink fails with E_INVALIDARG for an interface other than ITextStoreACPSink", check: advise_unsupported_iid },
+    Requirement { id: "sink/unadvise-unknown", text: "UnadviseSink fails with CONNECT_E_NOCONNECTION for a sink that is not advised", check: unadvise_unknown },
+    Requirement { id: "notify/order", text: "An application edit raises OnTextChange, then OnSelectionChange", check: notification_order },
+    Requirement { id: "notify/range", text: "OnTextChange reports the start, old end and new end of the edit", check: notification_range },
+    Requirement { id: "notify/mask", text: "Only the notifications in the advised mask are sent", check: notification_mask },
+    Requirement { id: "notify/not-locked", text: "The application does not edit the document while a lock is held", check: no_edits_under_lock },
+    Requirement { id: "text/end-acp", text: "GetEndACP returns the length of the document", check: end_acp },
+    Requirement { id: "text/to-end", text: "GetText with an end of -1 reads to the end of the document", check: get_text_to_end },
+    Requirement { id: "text/next", text: "GetText reports where a truncated read stopped", check: get_text_next },
+    Requirement { id: "text/invalid-range", text: "GetText fails with TS_E_INVALIDPOS for a range outside the document", check: get_text_invalid },
+    Requirement { id: "selection/default", text: "GetSelection(TS_DEFAULT_SELECTION) returns exactly one selection", check: default_selection },
+    Requirement { id: "readonly/set-text", text: "SetText fails with TS_E_READONLY on a read-only document", check: set_text_read_only },
+    Requirement { id: "readonly/insert", text: "InsertTextAtSelection fails with TS_E_READONLY on a read-only document", check: insert_read_only },
+    Requirement { id: "query-insert", text: "QueryInsert returns where text would be inserted", check: query_insert },
+];
+
+/// Requirements the store does not meet yet.
+const KNOWN_FAILURES: &[&str] = &[
+    "lock/granted-flags",
+    "readonly/set-text",
+    "readonly/insert",
+    "query-insert",
+];
+
+#[test]
+fn store_conformance() {
+    let _com = Com::new().unwrap();
+    let (mut regressed, mut fixed) = (Vec::new(), Vec::new());
+
+    for requirement in REQUIREMENTS {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(requirement.check)).unwrap_or_else(|_| Err("panicked".to_string()));
+        match (result, KNOWN_FAILURES.contains(&requirement.id)) {
+            (Err(reason), false) => regressed.push(format!("{}: {} ({reason})", requirement.id, requirement.text)),
+            (Ok(()), true) => fixed.push(requirement.id),
+            _ => {}
+        }
+    }
+
+    assert!(regressed.is_empty(), "requirements no longer met:\n{}", regressed.join("\n"));
+    assert!(fixed.is_empty(), "requirements now met, take them off KNOWN_FAILURES: {fixed:?}");
+}
+
+#[test]
+fn known_failures_are_requirements() {
+    for id in KNOWN_FAILURES {
+        assert!(REQUIREMENTS.iter().any(|requirement| requirement.id == *id), "unknown requirement {id}");
+    }
+}
+
+fn ensure_eq<T: PartialEq + std::fmt::Debug>(what: &str, actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("{what} was {actual:?}, expected {expected:?}"))
+    }
+}
+
+/// Reports a lock that [`MockSink::under_lock`] could not get.
+fn refused(hr: HRESULT) -> String {
+    format!("lock was not granted ({hr:?})")
+}
+
+fn selections(store: &ITextStoreACP) -> Result<u32, HRESULT> {
+    let mut selection = [TS_SELECTION_ACP::default(); 2];
+    let mut fetched = 0;
+    unsafe { store.GetSelection(TS_DEFAULT_SELECTION, &mut selection, &mut fetched) }.map_err(|e| e.code())?;
+    Ok(fetched)
+}
+
+/// An object that is not a text store sink.
+fn foreign_object() -> IUnknown {
+    store_trace::store("").unwrap().cast().unwrap()
+}
+
+fn get_text_needs_lock() -> Result<(), String> {
+    let (store, _sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetText", get_text(&store, 0, -1, 16), Err(TS_E_NOLOCK))
+}
+
+fn get_selection_needs_lock() -> Result<(), String> {
+    let (store, _sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetSelection", selections(&store), Err(TS_E_NOLOCK))
+}
+
+fn get_end_acp_needs_lock() -> Result<(), String> {
+    let (store, _sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetEndACP", unsafe { store.GetEndACP() }.map_err(|e| e.code()), Err(TS_E_NOLOCK))
+}
+
+fn set_selection_needs_write_lock() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("SetSelection", sink.under_lock(&store, TS_LF_READ.0, |store| set_selection(store, 0, 1)).map_err(refused)?, Err(TS_E_NOLOCK))
+}
+
+fn get_status_needs_no_lock() -> Result<(), String> {
+    let (store, _sink) = testing::advised("へんかん", ALL).unwrap();
+    unsafe { store.GetStatus() }.map(|_| ()).map_err(|e| format!("GetStatus failed with {:?}", e.code()))
+}
+
+fn sync_request_while_locked() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    let nested = sink.under_lock(&store, TS_LF_READWRITE.0, |store| unsafe { store.RequestLock(TS_LF_READ.0 | TS_LF_SYNC) }.map_err(|e| e.code())).map_err(refused)?;
+    ensure_eq("nested RequestLock", nested, Ok(TS_E_SYNCHRONOUS))
+}
+
+fn async_request_while_locked() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    let nested = sink.under_lock(&store, TS_LF_READWRITE.0, |store| unsafe { store.RequestLock(TS_LF_READ.0) }.map_err(|e| e.code())).map_err(refused)?;
+    ensure_eq("nested RequestLock", nested, Ok(TS_S_ASYNC))?;
+    ensure_eq("locks granted", sink.received().get(1).copied(), Some(Received::LockGranted { flags: TS_LF_READ.0 }))
+}
+
+fn async_upgrade() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    let upgraded = Rc::new(RefCell::new(None));
+    let slot = upgraded.clone();
+    sink.on_lock(|store| {
+        let _ = unsafe { store.RequestLock(TS_LF_READWRITE.0) };
+    });
+    sink.on_lock(move |store| *slot.borrow_mut() = Some(set_selection(store, 0, 1)));
+    ensure_eq("RequestLock", unsafe { store.RequestLock(TS_LF_READ.0 | TS_LF_SYNC) }.map_err(|e| e.code()), Ok(S_OK))?;
+    ensure_eq("SetSelection in the upgraded lock", upgraded.borrow_mut().take(), Some(Ok(())))
+}
+
+fn granted_flags() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    sink.under_lock(&store, TS_LF_READ.0, |_| ()).map_err(refused)?;
+    ensure_eq("notifications", sink.received(), vec![Received::LockGranted { flags: TS_LF_READ.0 }])
+}
+
+fn sink_failure_releases() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    sink.fail_lock(E_FAIL);
+    let _ = unsafe { store.RequestLock(TS_LF_READ.0 | TS_LF_SYNC) };
+    ensure_eq("GetText", sink.under_lock(&store, TS_LF_READ.0, |store| get_text(store, 0, -1, 16)).map_err(refused)?, Ok(("へんかん".to_string(), 4)))
+}
+
+fn advise_limit() -> Result<(), String> {
+    let (store, _sink) = testing::advised("へんかん", ALL).unwrap();
+    let second = MockSink::advise(&store, ALL).map(|_| ()).map_err(|e| e.hresult());
+    ensure_eq("second AdviseSink", second, Err(Some(CONNECT_E_ADVISELIMIT)))
+}
+
+fn advise_after_unadvise() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    drop(sink);
+    let second = MockSink::advise(&store, TS_AS_TEXT_CHANGE).map_err(|e| format!("AdviseSink failed: {e:?}"))?;
+    unsafe { testing::set_text(&store, "かな") }.map_err(|e| format!("set_text failed: {e:?}"))?;
+    ensure_eq("notifications", second.received(), vec![Received::TextChange { flags: 0, start: 0, old_end: 4, new_end: 2 }])
+}
+
+fn readvise_replaces_mask() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    sink.set_mask(TS_AS_SEL_CHANGE).map_err(|e| format!("AdviseSink failed: {e:?}"))?;
+    unsafe { testing::set_text(&store, "かな") }.map_err(|e| format!("set_text failed: {e:?}"))?;
+    ensure_eq("notifications", sink.received(), vec![Received::SelectionChange])
+}
+
+fn advise_unsupported_iid() -> Result<(), String> {
+    let store = StoreState::with_text("へんかん").build().unwrap();
+    let advised = unsafe { store.AdviseSink(&IUnknown::IID, &foreign_object(), ALL) }.map_err(|e| e.code());
+    ensure_eq("AdviseSink", advised, Err(E_INVALIDARG))
+}
+
+fn unadvise_unknown() -> Result<(), String> {
+    let (store, _sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("UnadviseSink", unsafe { store.UnadviseSink(&foreign_object()) }.map_err(|e| e.code()), Err(CONNECT_E_NOCONNECTION))
+}
+
+fn notification_order() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    unsafe { testing::set_text(&store, "かな") }.map_err(|e| format!("set_text failed: {e:?}"))?;
+    let kinds: Vec<_> = sink.received().into_iter().map(|received| matches!(received, Received::TextChange { .. })).collect();
+    ensure_eq("text change first", kinds, vec![true, false])
+}
+
+fn notification_range() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    unsafe { testing::set_text(&store, "かな") }.map_err(|e| format!("set_text failed: {e:?}"))?;
+    ensure_eq("first notification", sink.received().first().copied(), Some(Received::TextChange { flags: 0, start: 0, old_end: 4, new_end: 2 }))
+}
+
+fn notification_mask() -> Result<(), String> {
+    let store = StoreState::with_text("へんかん").build().unwrap();
+    let sink = MockSink::advise(&store, TS_AS_TEXT_CHANGE).unwrap();
+    unsafe { testing::set_text(&store, "かな") }.map_err(|e| format!("set_text failed: {e:?}"))?;
+    ensure_eq("notifications", sink.received().len(), 1)
+}
+
+fn no_edits_under_lock() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    let edited = sink.under_lock(&store, TS_LF_READ.0, |store| unsafe { testing::set_text(store, "かな") }.ok()).map_err(refused)?;
+    ensure_eq("edit under lock", edited, Some(false))?;
+    ensure_eq("notifications", sink.received().len(), 1)
+}
+
+fn end_acp() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetEndACP", sink.under_lock(&store, TS_LF_READ.0, |store| unsafe { store.GetEndACP() }.map_err(|e| e.code())).map_err(refused)?, Ok(4))
+}
+
+fn get_text_to_end() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetText", sink.under_lock(&store, TS_LF_READ.0, |store| get_text(store, 1, -1, 16)).map_err(refused)?, Ok(("んかん".to_string(), 4)))
+}
+
+fn get_text_next() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetText", sink.under_lock(&store, TS_LF_READ.0, |store| get_text(store, 0, -1, 3)).map_err(refused)?, Ok(("へんか".to_string(), 3)))
+}
+
+fn get_text_invalid() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetText", sink.under_lock(&store, TS_LF_READ.0, |store| get_text(store, 2, 9, 16)).map_err(refused)?, Err(TS_E_INVALIDPOS))
+}
+
+fn default_selection() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    ensure_eq("GetSelection", sink.under_lock(&store, TS_LF_READ.0, selections).map_err(refused)?, Ok(1))
+}
+
+fn read_only(store: &ITextStoreACP) -> Result<(), String> {
+    let status = unsafe { store.GetStatus() }.map_err(|e| format!("GetStatus failed with {:?}", e.code()))?;
+    ensure_eq("TS_SD_READONLY", status.dwDynamicFlags & TS_SD_READONLY, TS_SD_READONLY)
+}
+
+fn set_text_read_only() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    read_only(&store)?;
+    let text: Vec<u16> = "かな".encode_utf16().collect();
+    let result = sink.under_lock(&store, TS_LF_READWRITE.0, move |store| unsafe { store.SetText(0, 0, 4, &text) }.map(|_| ()).map_err(|e| e.code())).map_err(refused)?;
+    ensure_eq("SetText", result, Err(TS_E_READONLY))
+}
+
+fn insert_read_only() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    read_only(&store)?;
+    let text: Vec<u16> = "かな".encode_utf16().collect();
+    let result = sink.under_lock(&store, TS_LF_READWRITE.0, move |store| {
+        let (mut start, mut end, mut change) = (0, 0, Default::default());
+        unsafe { store.InsertTextAtSelection(0, &text, &mut start, &mut end, &mut change) }.map_err(|e| e.code())
+    }).map_err(refused)?;
+    ensure_eq("InsertTextAtSelection", result, Err(TS_E_READONLY))
+}
+
+fn query_insert() -> Result<(), String> {
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    let result = sink.under_lock(&store, TS_LF_READ.0, |store| {
+        let (mut start, mut end) = (-1, -1);
+        unsafe { store.QueryInsert(1, 2, 1, &mut start, &mut end) }.map(|()| (start, end)).map_err(|e| e.code())
+    }).map_err(refused)?;
+    ensure_eq("QueryInsert", result, Ok((1, 2)))
+}
@@ -0,0 +1,89 @@
+#![cfg(all(windows, feature = "test-utils"))]
+
+use std::{cell::RefCell, rc::Rc};
+
+use iatjc_rs::{
+    com::Com,
+    events::TsfEvent,
+    input_scope::InputScope,
+    testing,
+    tsf::TSF,
+    TsfError,
+};
+use windows::Win32::{
+    Foundation::{BOOL, S_OK},
+    UI::TextServices::{ITfContextOwnerCompositionSink, ITfEditSession, ITfEditSession_Impl, GUID_PROP_INPUTSCOPE, GUID_PROP_READING, TF_ES_READWRITE, TF_ES_SYNC},
+};
+use windows_core::{implement, Interface};
+
+fn owned(com: &Com) -> TSF<'_> {
+    let mut tsf = TSF::with_context_owner(com);
+    tsf.initialize().unwrap();
+    tsf
+}
+
+/// Runs a callback in an edit session.
+#[implement(ITfEditSession)]
+struct Session(Box<dyn Fn()>);
+
+impl ITfEditSession_Impl for Session {
+    fn DoEditSession(&self, _ec: u32) -> windows_core::Result<()> {
+        (self.0)();
+        Ok(())
+    }
+}
+
+#[test]
+fn status_has_no_flags() {
+    let com = Com::new().unwrap();
+    let tsf = owned(&com);
+
+    let status = unsafe { tsf.context_owner().unwrap().GetStatus() }.unwrap();
+    assert_eq!((status.dwDynamicFlags, status.dwStaticFlags), (0, 0));
+}
+
+#[test]
+fn input_scopes_are_reported_as_an_attribute() {
+    let com = Com::new().unwrap();
+    let tsf = owned(&com);
+    let owner = tsf.context_owner().unwrap();
+
+    let scopes = || testing::input_scopes(&unsafe { owner.GetAttribute(&GUID_PROP_INPUTSCOPE) }.unwrap()).unwrap();
+    assert_eq!(scopes(), []);
+    tsf.set_input_scopes(&[InputScope::Hiragana, InputScope::Url]).unwrap();
+    assert_eq!(scopes(), [InputScope::Hiragana, InputScope::Url]);
+    assert!(unsafe { owner.GetAttribute(&GUID_PROP_READING) }.unwrap().is_empty());
+}
+
+#[test]
+fn compositions_raise_events() {
+    let com = Com::new().unwrap();
+    let tsf = owned(&com);
+    let receiver = tsf.events().subscribe();
+    let sink: ITfContextOwnerCompositionSink = tsf.context_owner().unwrap().cast().unwrap();
+
+    assert_eq!(unsafe { sink.OnStartComposition(None) }.unwrap(), BOOL(1));
+    unsafe { sink.OnUpdateComposition(None, None) }.unwrap();
+    unsafe { sink.OnEndComposition(None) }.unwrap();
+    assert_eq!(
+        std::iter::from_fn(|| receiver.try_recv()).collect::<Vec<_>>(),
+        [TsfEvent::CompositionStarted, TsfEvent::CompositionUpdated, TsfEvent::CompositionEnded]
+    );
+}
+
+#[test]
+fn text_is_refused_while_the_context_is_locked() {
+    // The session outlives this frame as far as the borrow checker knows.
+    let com: &'static Com = Box::leak(Box::new(Com::new().unwrap()));
+    let tsf = Rc::new(owned(com));
+
+    let nested = Rc::new(RefCell::new(None));
+    let session: ITfEditSession = {
+        let (tsf, nested) = (tsf.clone(), nested.clone());
+        Session(Box::new(move || *nested.borrow_mut() = Some(tsf.set_text("かな")))).into()
+    };
+    let hr = unsafe { tsf.context().unwrap().RequestEditSession(tsf.client_id().unwrap(), &session, TF_ES_SYNC | TF_ES_READWRITE) }.unwrap();
+    assert_eq!(hr, S_OK);
+    assert!(matches!(nested.borrow_mut().take(), Some(Err(TsfError::StoreLocked))));
+    tsf.set_text("かな").unwrap();
+}
@@ -0,0 +1,121 @@
+#![cfg(all(windows, feature = "test-utils"))]
+
+use iatjc_rs::{
+    com::Com,
+    compat::{ActiveProfile, Tip},
+    engine::{Engine, EngineKind},
+    fake_tip::{FakeEngine, FakeTip, Finalized, Script, CLSID_FAKE_TIP},
+    segmentation::Segmentation,
+    tsf::TSF,
+    TsfError,
+};
+
+fn tip() -> FakeTip {
+    FakeTip::new(Script::new().with("へんかん", &["変換", "返還", "偏簡"]).with("かな", &["かな", "仮名"]))
+}
+
+fn tsf<'com>(com: &'com Com, tip: &FakeTip) -> TSF<'com> {
+    let mut tsf = TSF::new(com);
+    tsf.set_function_provider(Some(tip.function_provider()));
+    tsf.initialize().unwrap();
+    tsf
+}
+
+#[test]
+fn converts_scripted_readings() {
+    let com = Com::new().unwrap();
+    let tip = tip();
+    let tsf = tsf(&com, &tip);
+
+    assert!(tsf.has_reconversion());
+    assert_eq!(tsf.convert("へんかん").unwrap(), ["変換", "返還", "偏簡"]);
+    assert_eq!(tsf.convert("かな").unwrap(), ["かな", "仮名"]);
+}
+
+#[test]
+fn unscripted_reading_is_not_convertible() {
+    let com = Com::new().unwrap();
+    let tip = tip();
+    let tsf = tsf(&com, &tip);
+
+    assert!(matches!(tsf.convert("にほん"), Err(TsfError::NotConvertible)));
+}
+
+#[test]
+fn commit_finalizes_the_candidate() {
+    let com = Com::new().unwrap();
+    let tip = tip();
+    let tsf = tsf(&com, &tip);
+
+    tsf.commit("へんかん", "返還").unwrap();
+    assert_eq!(tip.finalized(), [Finalized { reading: "へんかん".to_string(), candidate: "返還".to_string() }]);
+    assert!(tsf.commit("へんかん", "変化").is_err());
+    assert_eq!(tip.finalized().len(), 1);
+}
+
+#[test]
+fn script_changes_apply_to_later_conversions() {
+    let com = Com::new().unwrap();
+    let tip = tip();
+    let tsf = tsf(&com, &tip);
+
+    tip.set_script(Script::new().with("へんかん", &["変換"]));
+    assert_eq!(tsf.convert("へんかん").unwrap(), ["変換"]);
+}
+
+#[test]
+fn width_variants_are_annotated() {
+    let engine = FakeEngine::new(EngineKind::Tsf, Script::new().with("かな", &["カナ", "ｶﾅ", "ＫＡＮＡ"]));
+
+    let annotations: Vec<_> = engine.convert("かな", None).unwrap().into_iter().map(|candidate| candidate.annotation).collect();
+    assert_eq!(annotations, [None, Some("[半]".to_owned()), Some("[全]".to_owned())]);
+}
+
+#[test]
+fn moving_a_clause_boundary_reconverts_both_clauses() {
+    let com = Com::new().unwrap();
+    let tip = FakeTip::new(
+        Script::new()
+            .with("きょうは", &["今日は"])
+            .with("いしゃに", &["医者に"])
+            .with("きょう", &["今日"])
+            .with("はいしゃに", &["歯医者に"]),
+    );
+    let tsf = tsf(&com, &tip);
+    let mut segmentation = Segmentation::from_readings(&["きょうは", "いしゃに"]);
+
+    let (changed, candidates) = tsf.adjust_clause(&mut segmentation, 0, -1).unwrap();
+    assert_eq!(changed, 0..2);
+    assert_eq!(segmentation.readings(), ["きょう", "はいしゃに"]);
+    assert_eq!(candidates, [vec!["今日"], vec!["歯医者に"]]);
+
+    let (changed, _) = tsf.adjust_clause(&mut segmentation, 0, 1).unwrap();
+    assert_eq!(changed, 0..2);
+    assert_eq!(segmentation.readings(), ["きょうは", "いしゃに"]);
+}
+
+#[test]
+fn predicts_from_a_typed_prefix() {
+    let com = Com::new().unwrap();
+    let tip = FakeTip::new(Script::new().with("へんかん", &["変換", "返還"]).with("へん", &["変", "編"]).with("かな", &["仮名"]));
+    let tsf = tsf(&com, &tip);
+
+    assert!(tsf.has_prediction());
+    assert_eq!(tsf.predict("へん").unwrap(), ["変", "編", "変換", "返還"]);
+    assert!(tsf.predict("あ").unwrap().is_empty());
+}
+
+#[test]
+fn predicts_through_the_active_tips_own_provider() {
+    let com = Com::new().unwrap();
+    let tip = FakeTip::new(Script::new().with("へん", &["変", "編"]));
+    let _installed = tip.install().unwrap();
+
+    // MS-IME only looks for reconversion through the system provider.
+    let mut tsf = TSF::new(&com);
+    tsf.set_active_profile(Some(ActiveProfile { tip: Tip::MsIme, clsid: CLSID_FAKE_TIP, profile: CLSID_FAKE_TIP, langid: 0x0411 }));
+    tsf.initialize().unwrap();
+
+    assert!(tsf.has_prediction());
+    assert_eq!(tsf.predict("へん").unwrap(), ["変", "編"]);
+}
@@ -0,0 +1,91 @@
+#![cfg(all(windows, feature = "cli", feature = "test-utils"))]
+
+//! Runs the CLI against the scripted engine and compares its output with
+//! the files in `tests/golden`. Set `UPDATE_GOLDEN=1` to rewrite them.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+fn golden(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name)
+}
+
+fn run(args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_iatjc"))
+        .arg("--fake-tip")
+        .arg(golden("script.json"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn check(name: &str, args: &[&str], stdin: &str) {
+    let actual = run(args, stdin);
+    let path = golden(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+    assert_eq!(actual, expected, "output differs from {}", path.display());
+}
+
+#[test]
+fn convert_text() {
+    check("convert.txt", &["convert", "--engine", "tsf", "へんかん"], "");
+}
+
+#[test]
+fn convert_json() {
+    check("convert.json", &["--format", "json", "convert", "--engine", "tsf", "へんかん"], "");
+}
+
+#[test]
+fn convert_tsv() {
+    check("convert.tsv", &["--format", "tsv", "convert", "--engine", "tsf", "--stdin"], "へんかん\n\nにほん\nせん\n");
+}
+
+#[test]
+fn convert_csv() {
+    check("convert.csv", &["--format", "csv", "convert", "--engine", "tsf", "--stdin"], "へんかん\n\nにほん\nせん\n");
+}
+
+#[test]
+fn convert_stdin_text() {
+    check("convert_stdin.txt", &["convert", "--engine", "tsf", "--stdin"], "へんかん\n\nにほん\nせん\n");
+}
+
+#[test]
+fn convert_stdin_json() {
+    check("convert_stdin.json", &["--format", "json", "convert", "--engine", "tsf", "--stdin"], "へんかん\n\nにほん\nせん\n");
+}
+
+#[test]
+fn furigana_plain() {
+    check("furigana.txt", &["furigana", "漢字を書く"], "");
+}
+
+#[test]
+fn furigana_html() {
+    check("furigana_html.txt", &["--format", "ruby-html", "furigana", "漢字を書く"], "");
+}
+
+#[test]
+fn furigana_anki() {
+    check("furigana_anki.txt", &["--format", "anki", "furigana", "漢字を書く"], "");
+}
+
+#[test]
+fn furigana_json() {
+    check("furigana.json", &["--format", "json", "furigana", "漢字を書く"], "");
+}
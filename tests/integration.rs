@@ -0,0 +1,71 @@
+#![cfg(all(windows, feature = "test-utils"))]
+
+use std::time::Duration;
+
+use iatjc_rs::{com::Com, testing::Harness, tsf::TsfState};
+
+fn harness(com: &Com) -> Harness<'_> {
+    let harness = Harness::new(com).unwrap();
+    assert!(harness.japanese_ime(), "the active input processor is not Japanese");
+    harness
+}
+
+#[test]
+fn harness_brings_up_the_pipeline() {
+    let com = Com::new().unwrap();
+    let harness = Harness::new(&com).unwrap();
+
+    assert_ne!(*harness.tsf().state(), TsfState::Uninitialized);
+    assert!(harness.pump());
+    assert!(harness.pump_until(Duration::from_millis(50), || true));
+    assert!(!harness.pump_until(Duration::from_millis(50), || false));
+}
+
+#[test]
+fn owner_services_reach_the_store_backed_context() {
+    let com = Com::new().unwrap();
+    let harness = Harness::new(&com).unwrap();
+    harness.tsf().set_text("へんかん").unwrap();
+
+    let services = harness.tsf().owner_services().unwrap();
+    services.layout_changed().unwrap();
+    services.status_changed(0).unwrap();
+
+    let range = services.create_range(1, 3).unwrap();
+    let (mut start, mut length) = (0, 0);
+    unsafe { range.GetExtent(&mut start, &mut length) }.unwrap();
+    assert_eq!((start, length), (1, 2));
+}
+
+#[test]
+#[ignore = "needs a Japanese IME"]
+fn converts_a_reading() {
+    let com = Com::new().unwrap();
+    let harness = harness(&com);
+
+    let candidates = harness.convert("へんかん").unwrap();
+    assert!(candidates.iter().any(|candidate| candidate == "変換"), "{candidates:?}");
+}
+
+#[test]
+#[ignore = "needs a Japanese IME"]
+fn converts_repeatedly() {
+    let com = Com::new().unwrap();
+    let harness = harness(&com);
+
+    for (reading, expected) in [("にほん", "日本"), ("かんじ", "漢字"), ("にほん", "日本")] {
+        let candidates = harness.convert(reading).unwrap();
+        assert!(candidates.iter().any(|candidate| candidate == expected), "{reading}: {candidates:?}");
+    }
+}
+
+#[test]
+#[ignore = "needs a Japanese IME"]
+fn committed_candidate_is_still_offered() {
+    let com = Com::new().unwrap();
+    let harness = harness(&com);
+
+    harness.tsf().commit("へんかん", "変換").unwrap();
+    assert!(harness.pump());
+    assert!(harness.convert("へんかん").unwrap().iter().any(|candidate| candidate == "変換"));
+}
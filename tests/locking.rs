@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+
+use iatjc_rs::locking::{Lock, LockState, Request, READ, READWRITE, SYNC};
+use proptest::prelude::*;
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Request { flags: u32 },
+    Acquire { flags: u32 },
+    Release,
+    GrantNext,
+}
+
+fn lock_flags() -> impl Strategy<Value = u32> {
+    prop_oneof![Just(READ), Just(READWRITE)]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (lock_flags(), any::<bool>()).prop_map(|(flags, sync)| Op::Request { flags: if sync { flags | SYNC } else { flags } }),
+        1 => lock_flags().prop_map(|flags| Op::Acquire { flags }),
+        2 => Just(Op::Release),
+        2 => Just(Op::GrantNext),
+    ]
+}
+
+/// What the state machine should be doing, kept the simple way.
+#[derive(Default)]
+struct Model {
+    holder: Option<u32>,
+    queue: VecDeque<(u32, usize)>,
+}
+
+fn check_holder(state: &LockState<usize>, holder: Option<u32>) {
+    let flags = holder.unwrap_or(0);
+    assert_eq!(state.held(), Lock::from_flags(flags));
+    assert_eq!(state.flags(), flags);
+    assert_eq!(state.is_locked(READ), holder.is_some());
+    assert_eq!(state.is_locked(READWRITE), flags & READWRITE == READWRITE);
+}
+
+proptest! {
+    #[test]
+    fn lock_state_follows_request_lock_rules(ops in prop::collection::vec(op(), 0..64)) {
+        let mut state = LockState::new();
+        let mut model = Model::default();
+
+        for (id, op) in ops.into_iter().enumerate() {
+            match op {
+                Op::Request { flags } => {
+                    let request = state.request(flags, id);
+                    let expected = match model.holder {
+                        None => {
+                            model.holder = Some(flags);
+                            Request::Granted
+                        }
+                        Some(_) if flags & SYNC != 0 => Request::Refused,
+                        Some(_) => {
+                            model.queue.push_back((flags, id));
+                            Request::Queued
+                        }
+                    };
+                    prop_assert_eq!(request, expected);
+                }
+                Op::Acquire { flags } => {
+                    let acquired = state.acquire(flags);
+                    prop_assert_eq!(acquired, model.holder.is_none());
+                    if acquired {
+                        model.holder = Some(flags);
+                    }
+                }
+                Op::Release => {
+                    state.release();
+                    model.holder = None;
+                }
+                Op::GrantNext => {
+                    let granted = state.grant_next();
+                    let expected = match model.holder {
+                        Some(_) => None,
+                        None => model.queue.pop_front(),
+                    };
+                    prop_assert_eq!(granted, expected);
+                    if let Some((flags, _)) = granted {
+                        model.holder = Some(flags);
+                    }
+                }
+            }
+
+            check_holder(&state, model.holder);
+            prop_assert_eq!(state.pending(), model.queue.len());
+        }
+
+        // Every queued request is granted exactly once, in order.
+        let mut granted = Vec::new();
+        loop {
+            state.release();
+            match state.grant_next() {
+                Some((_, id)) => granted.push(id),
+                None => break,
+            }
+        }
+        prop_assert_eq!(granted, model.queue.iter().map(|&(_, id)| id).collect::<Vec<_>>());
+        prop_assert_eq!(state.held(), Lock::None);
+    }
+}
+
+#[test]
+fn read_lock_is_not_upgraded_in_place() {
+    let mut state = LockState::new();
+
+    assert_eq!(state.request(READ, 0), Request::Granted);
+    assert_eq!(state.request(READWRITE | SYNC, 1), Request::Refused);
+    assert_eq!(state.request(READWRITE, 2), Request::Queued);
+    assert_eq!(state.held(), Lock::Read);
+    assert_eq!(state.grant_next(), None);
+
+    state.release();
+    assert_eq!(state.grant_next(), Some((READWRITE, 2)));
+    assert_eq!(state.held(), Lock::ReadWrite);
+}
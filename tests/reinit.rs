@@ -0,0 +1,64 @@
+#![cfg(windows)]
+
+use iatjc_rs::{com::Com, tsf::{TsfState, TSF}};
+use windows_core::Interface;
+
+#[test]
+fn initialize_uninitialize_cycles() {
+    let com = Com::new().unwrap();
+    let mut tsf = TSF::new(&com);
+
+    for _ in 0..3 {
+        tsf.initialize().unwrap();
+        assert_ne!(*tsf.state(), TsfState::Uninitialized);
+        assert!(tsf.client_id().is_some());
+        assert!(tsf.context().is_some());
+        assert!(tsf.text_store().is_some());
+        tsf.set_text("へんかん").unwrap();
+
+        tsf.uninitialize().unwrap();
+        assert_eq!(*tsf.state(), TsfState::Uninitialized);
+        assert!(tsf.client_id().is_none());
+        assert!(tsf.context().is_none());
+        assert!(tsf.text_store().is_none());
+    }
+}
+
+#[test]
+fn reinitialize_recreates_store_and_context() {
+    let com = Com::new().unwrap();
+    let mut tsf = TSF::new(&com);
+
+    tsf.initialize().unwrap();
+    let store = tsf.text_store().unwrap().clone();
+    let context = tsf.context().unwrap().clone();
+    tsf.uninitialize().unwrap();
+
+    tsf.initialize().unwrap();
+    assert_ne!(tsf.text_store().unwrap().as_raw(), store.as_raw());
+    assert_ne!(tsf.context().unwrap().as_raw(), context.as_raw());
+}
+
+#[test]
+fn initialize_twice_is_a_no_op() {
+    let com = Com::new().unwrap();
+    let mut tsf = TSF::new(&com);
+
+    tsf.initialize().unwrap();
+    let context = tsf.context().unwrap().as_raw();
+    let state = tsf.state().clone();
+
+    tsf.initialize().unwrap();
+    assert_eq!(tsf.context().unwrap().as_raw(), context);
+    assert_eq!(*tsf.state(), state);
+}
+
+#[test]
+fn uninitialize_without_initialize() {
+    let com = Com::new().unwrap();
+    let mut tsf = TSF::new(&com);
+
+    tsf.uninitialize().unwrap();
+    tsf.uninitialize().unwrap();
+    assert_eq!(*tsf.state(), TsfState::Uninitialized);
+}
@@ -0,0 +1,57 @@
+#![cfg(all(windows, feature = "record"))]
+
+use std::{fs::File, io::BufReader};
+
+use iatjc_rs::{com::Com, store_trace::{self, read_records, Recorder, StoreCall}, tsf::TSF};
+use windows::Win32::{Foundation::S_OK, UI::TextServices::TS_E_NOLOCK};
+
+const TRACE: &str = r#"
+{"seq":0,"method":"GetText","start":0,"end":-1,"max":16,"lock":"none","result":-2147220991}
+{"seq":1,"method":"GetText","start":0,"end":-1,"max":16,"lock":"read","result":0}
+{"seq":2,"method":"GetEndACP","lock":"read","result":0}
+{"seq":3,"method":"SetSelection","start":0,"end":2,"lock":"readwrite","result":0}
+{"seq":4,"method":"SetSelection","start":0,"end":2,"lock":"read","result":-2147220991}
+"#;
+
+#[test]
+fn recorded_trace_replays_without_divergence() {
+    let _com = Com::new().unwrap();
+    let records = read_records(TRACE.as_bytes()).unwrap();
+    assert_eq!(records.len(), 5);
+    assert_eq!(records[0].result, TS_E_NOLOCK.0);
+    assert_eq!(records[1].result, S_OK.0);
+
+    let store = store_trace::store("へんかん").unwrap();
+    assert!(store_trace::replay(&store, &records).unwrap().is_empty());
+}
+
+#[test]
+fn replay_reports_divergent_results() {
+    let _com = Com::new().unwrap();
+    let mut records = read_records(TRACE.as_bytes()).unwrap();
+    records[1].result = TS_E_NOLOCK.0;
+
+    let store = store_trace::store("へんかん").unwrap();
+    let divergences = store_trace::replay(&store, &records).unwrap();
+    assert_eq!(divergences.len(), 1);
+    assert_eq!(divergences[0].seq, 1);
+    assert_eq!(divergences[0].actual, S_OK);
+}
+
+#[test]
+fn recorder_traces_tsf_calls() {
+    let path = std::env::temp_dir().join(format!("iatjc-replay-{}.jsonl", std::process::id()));
+    let com = Com::new().unwrap();
+    let mut tsf = TSF::new(&com);
+    tsf.set_recorder(Some(Recorder::create(&path).unwrap()));
+    tsf.initialize().unwrap();
+    tsf.set_text("へんかん").unwrap();
+    tsf.uninitialize().unwrap();
+    drop(tsf);
+
+    let records = read_records(BufReader::new(File::open(&path).unwrap())).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!records.is_empty());
+    assert!(records.windows(2).all(|pair| pair[0].seq + 1 == pair[1].seq));
+    assert!(records.iter().any(|record| matches!(record.call, StoreCall::RequestLock { .. })));
+}
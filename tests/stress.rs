@@ -0,0 +1,226 @@
+#![cfg(all(windows, feature = "stress"))]
+
+//! Long-running stress tests, enabled by the `stress` feature. They run for
+//! `IATJC_STRESS_SECS` seconds each (60 by default) and print the seed they
+//! used; set `IATJC_STRESS_SEED` to repeat a run.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use iatjc_rs::{
+    com::Com,
+    events::{EventHub, EventQueueConfig, EventReceiver, OverflowPolicy, TsfEvent},
+    service::{RequestOptions, TsfService},
+    testing::{self, MockSink, Received, StoreState},
+    TsfError,
+};
+use windows::Win32::{
+    Foundation::S_OK,
+    UI::TextServices::{TS_AS_SEL_CHANGE, TS_AS_TEXT_CHANGE, TS_E_SYNCHRONOUS, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_S_ASYNC},
+};
+
+/// How long a single step may take before the run counts as deadlocked.
+const STALL: Duration = Duration::from_secs(10);
+const THREADS: usize = 8;
+const KANA: &[char] = &['か', 'な', 'へ', 'ん', 'に', 'ほ', 'ア', 'イ', '漢', '字', '\u{1F600}', 'a'];
+
+fn duration() -> Duration {
+    let secs = std::env::var("IATJC_STRESS_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(60);
+    Duration::from_secs(secs)
+}
+
+fn seed() -> u64 {
+    let seed = std::env::var("IATJC_STRESS_SEED").ok().and_then(|s| s.parse().ok()).unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64
+    }).max(1);
+    println!("IATJC_STRESS_SEED={seed}");
+    seed
+}
+
+/// xorshift64, enough to pick operations reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn text(&mut self) -> String {
+        (0..self.below(12)).map(|_| KANA[self.below(KANA.len())]).collect()
+    }
+}
+
+/// Runs `body` on its own thread, failing if it stops reporting progress for
+/// longer than [`STALL`].
+fn watchdog(name: &str, body: impl FnOnce(&dyn Fn()) + Send + 'static) {
+    let (progress, ticks) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || body(&|| {
+            let _ = progress.send(());
+        }))
+        .unwrap();
+
+    loop {
+        match ticks.recv_timeout(STALL) {
+            Ok(()) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => panic!("{name} made no progress for {STALL:?}; deadlocked"),
+        }
+    }
+    if let Err(panic) = handle.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+/// Counts the text changes queued on `receiver`, without waiting.
+fn drain_text_changes(receiver: &EventReceiver) -> usize {
+    std::iter::from_fn(|| receiver.try_recv()).filter(|event| matches!(event, TsfEvent::TextChanged { .. })).count()
+}
+
+/// Interleaves application edits with lock requests from a scripted input
+/// processor on the store's thread: synchronous reads, edits attempted under
+/// a lock, nested synchronous and asynchronous requests, and reads requested
+/// by the sink itself from the notification of an edit. The document
+/// must always match the edits accepted, and each accepted edit must notify
+/// the sink and the event hub exactly once.
+#[test]
+fn store_under_interleaved_edits_and_locks() {
+    let mut rng = Rng(seed());
+    watchdog("store", move |tick| {
+        let _com = Com::new().unwrap();
+        let events = EventHub::new();
+        let receiver = events.subscribe_with(EventQueueConfig { capacity: 1024, policy: OverflowPolicy::DropOldest });
+        let store = StoreState { events: Some(events), ..Default::default() }.build().unwrap();
+        let sink = MockSink::advise(&store, TS_AS_TEXT_CHANGE | TS_AS_SEL_CHANGE).unwrap();
+        let _ = drain_text_changes(&receiver);
+        let mut expected = String::new();
+
+        let deadline = Instant::now() + duration();
+        while Instant::now() < deadline {
+            sink.take_received();
+            match rng.below(5) {
+                0 => {
+                    let text = rng.text();
+                    let old_end = expected.encode_utf16().count() as i32;
+                    assert!(unsafe { testing::set_text(&store, &text) }.unwrap());
+                    let new_end = text.encode_utf16().count() as i32;
+                    expected = text;
+                    assert_eq!(sink.received(), [Received::TextChange { flags: 0, start: 0, old_end, new_end }, Received::SelectionChange]);
+                    assert_eq!(drain_text_changes(&receiver), 1, "lost or duplicated event");
+                }
+                1 => {
+                    assert_eq!(sink.under_lock(&store, TS_LF_READ.0, testing::read_text).unwrap(), Ok(expected.clone()));
+                }
+                2 => {
+                    // The application edits while the input processor holds
+                    // the lock, and the processor queues another request.
+                    let text = rng.text();
+                    let (outer, queued) = (Rc::new(RefCell::new(None)), Rc::new(RefCell::new(None)));
+                    let (outer_slot, queued_slot) = (outer.clone(), queued.clone());
+                    sink.on_lock(move |store| {
+                        let edited = unsafe { testing::set_text(store, &text) }.unwrap();
+                        let nested = unsafe { store.RequestLock(TS_LF_READ.0) }.unwrap();
+                        *outer_slot.borrow_mut() = Some((edited, nested));
+                    });
+                    sink.on_lock(move |store| *queued_slot.borrow_mut() = testing::read_text(store).ok());
+                    assert_eq!(unsafe { store.RequestLock(TS_LF_READWRITE.0 | TS_LF_SYNC) }.unwrap(), S_OK);
+                    assert_eq!(outer.borrow_mut().take(), Some((false, TS_S_ASYNC)));
+                    assert_eq!(queued.borrow_mut().take().as_ref(), Some(&expected), "queued lock was not granted");
+                    assert_eq!(sink.pending(), 0);
+                    assert_eq!(drain_text_changes(&receiver), 0, "refused edit raised an event");
+                }
+                3 => {
+                    // The input processor reads the edit from OnTextChange,
+                    // while the store is still notifying.
+                    let text = rng.text();
+                    let (requested, seen) = (Rc::new(RefCell::new(None)), Rc::new(RefCell::new(None)));
+                    let (requested_slot, seen_slot) = (requested.clone(), seen.clone());
+                    sink.on_lock(move |store| *seen_slot.borrow_mut() = testing::read_text(store).ok());
+                    sink.on_text_change(move |store| *requested_slot.borrow_mut() = unsafe { store.RequestLock(TS_LF_READ.0 | TS_LF_SYNC) }.ok());
+                    assert!(unsafe { testing::set_text(&store, &text) }.unwrap());
+                    expected = text;
+                    assert_eq!(requested.borrow_mut().take(), Some(S_OK));
+                    assert_eq!(seen.borrow_mut().take().as_ref(), Some(&expected));
+                    assert!(matches!(sink.received()[..], [Received::TextChange { .. }, Received::LockGranted { .. }, Received::SelectionChange]), "{:?}", sink.received());
+                    assert_eq!(sink.pending(), 0);
+                    assert_eq!(drain_text_changes(&receiver), 1, "lost or duplicated event");
+                }
+                _ => {
+                    let nested = Rc::new(RefCell::new(None));
+                    let slot = nested.clone();
+                    sink.on_lock(move |store| *slot.borrow_mut() = Some(unsafe { store.RequestLock(TS_LF_READ.0 | TS_LF_SYNC) }.unwrap()));
+                    assert_eq!(unsafe { store.RequestLock(TS_LF_READWRITE.0 | TS_LF_SYNC) }.unwrap(), S_OK);
+                    assert_eq!(nested.borrow_mut().take(), Some(TS_E_SYNCHRONOUS));
+                    assert_eq!(sink.received().len(), 1);
+                }
+            }
+            tick();
+        }
+        assert_eq!(receiver.dropped(), 0);
+    });
+}
+
+/// Several threads set text through one service at once while another
+/// checks its health. Every call must finish within [`STALL`], and each
+/// accepted edit must raise exactly one event.
+#[test]
+fn service_under_concurrent_callers() {
+    let seed = seed();
+    watchdog("service", move |tick| {
+        let service = Arc::new(TsfService::spawn().unwrap());
+        let receiver = service.events().subscribe_with(EventQueueConfig { capacity: 1 << 16, policy: OverflowPolicy::DropOldest });
+        let accepted = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let deadline = Instant::now() + duration();
+
+        let callers: Vec<_> = (0..THREADS as u64)
+            .map(|i| {
+                let (service, accepted, stop) = (service.clone(), accepted.clone(), stop.clone());
+                thread::spawn(move || {
+                    let mut rng = Rng(seed.wrapping_add(i).max(1));
+                    let options = RequestOptions::with_timeout(STALL);
+                    while !stop.load(Ordering::Relaxed) {
+                        match service.set_text_with(&rng.text(), &options) {
+                            Ok(()) => {
+                                accepted.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(TsfError::StoreLocked) => {}
+                            Err(e) => panic!("set_text failed: {e:?}"),
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut received = 0;
+        while Instant::now() < deadline {
+            received += drain_text_changes(&receiver);
+            assert!(service.health(STALL).is_healthy());
+            tick();
+            thread::sleep(Duration::from_millis(50));
+        }
+        stop.store(true, Ordering::Relaxed);
+        for caller in callers {
+            caller.join().unwrap();
+            tick();
+        }
+
+        // Replies are sent after the events, so all of them are queued now.
+        received += drain_text_changes(&receiver);
+        assert_eq!(receiver.dropped(), 0);
+        assert_eq!(received as u64, accepted.load(Ordering::Relaxed), "lost or duplicated events");
+    });
+}
@@ -0,0 +1,83 @@
+#![cfg(all(windows, feature = "test-utils"))]
+
+use std::time::Duration;
+
+use iatjc_rs::{
+    cancel::CancellationToken,
+    com::Com,
+    engine::{EngineChain, EngineKind, FALLBACK_ORDER},
+    fake_tip::Script,
+    felang::Clause,
+    service::{RequestOptions, ServiceOptions, TsfService},
+    test_mode::TestMode,
+    tsf::TSF,
+    TsfError,
+};
+
+fn test_mode() -> TestMode {
+    TestMode::new(Script::new().with("へんかん", &["変換", "返還"]))
+}
+
+#[test]
+fn tsf_answers_from_the_script() {
+    let com = Com::new().unwrap();
+    let mut tsf = TSF::new(&com);
+    tsf.set_test_mode(Some(&test_mode()));
+    tsf.initialize().unwrap();
+
+    assert_eq!(tsf.convert("へんかん").unwrap(), ["変換", "返還"]);
+    assert_eq!(tsf.convert_with_limit("へんかん", &CancellationToken::new(), Some(1)).unwrap(), ["変換"]);
+    assert_eq!(tsf.reading("返還").unwrap(), "へんかん");
+    assert!(matches!(tsf.reading("日本"), Err(TsfError::NoReading)));
+}
+
+#[test]
+fn context_owner_documents_answer_from_the_script() {
+    let com = Com::new().unwrap();
+    let mut owned = TSF::with_context_owner(&com);
+    owned.set_test_mode(Some(&test_mode()));
+    owned.initialize().unwrap();
+
+    assert!(owned.text_store().is_none());
+    assert_eq!(owned.convert("へんかん").unwrap(), ["変換", "返還"]);
+    assert!(matches!(owned.regions(), Err(TsfError::NoTextStore)));
+}
+
+#[test]
+fn engine_chains_answer_from_the_script() {
+    let chain = EngineChain::with_test_mode(&test_mode(), &FALLBACK_ORDER).unwrap();
+
+    let answered = chain.convert("へんかん", None).unwrap();
+    assert_eq!(answered.engine, EngineKind::Tsf);
+    assert_eq!(answered.value.into_iter().map(|candidate| candidate.text).collect::<Vec<_>>(), ["変換", "返還"]);
+}
+
+#[test]
+fn services_answer_from_the_script_with_frozen_clocks() {
+    let service = TsfService::spawn_with(ServiceOptions { test_mode: Some(test_mode()), ..Default::default() }).unwrap();
+
+    assert_eq!(service.convert("へんかん").unwrap(), ["変換", "返還"]);
+    assert_eq!(service.segment("へんかん").unwrap(), [Clause { reading: "へんかん".to_string(), surface: "変換".to_string(), start: 0 }]);
+    assert!(matches!(service.segment("にほん"), Err(TsfError::NotConvertible)));
+
+    let latency = service.latency();
+    assert_eq!(latency.convert.count, 1);
+    assert_eq!(latency.convert.max, Duration::ZERO);
+    assert!(service.health(Duration::from_secs(5)).is_healthy());
+    assert_eq!(service.convert_with("へんかん", &RequestOptions::with_max_candidates(1)).unwrap(), ["変換"]);
+}
+
+#[test]
+fn test_modes_do_not_leak_into_other_instances() {
+    let com = Com::new().unwrap();
+    let mut scripted = TSF::new(&com);
+    scripted.set_test_mode(Some(&test_mode()));
+    scripted.initialize().unwrap();
+
+    let mut tsf = TSF::new(&com);
+    tsf.set_test_mode(Some(&TestMode::new(Script::new().with("かな", &["仮名"]))));
+    tsf.initialize().unwrap();
+
+    assert!(matches!(tsf.convert("へんかん"), Err(TsfError::NotConvertible)));
+    assert_eq!(scripted.convert("へんかん").unwrap(), ["変換", "返還"]);
+}
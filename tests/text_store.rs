@@ -0,0 +1,262 @@
+#![cfg(all(windows, feature = "test-utils"))]
+
+use std::{cell::RefCell, mem::ManuallyDrop, rc::Rc, sync::Arc};
+
+use iatjc_rs::{
+    com::Com,
+    input_scope::InputScope,
+    layout::{self, LayoutProvider},
+    region,
+    testing::{self, get_text, selection, set_selection, MockSink, Received, StoreState},
+};
+use windows::Win32::{
+    Foundation::{BOOL, E_NOTIMPL, RECT, S_OK},
+    UI::TextServices::{ITextStoreACP, GUID_PROP_INPUTSCOPE, TS_AS_ATTR_CHANGE, TS_AS_LAYOUT_CHANGE, TS_AS_SEL_CHANGE, TS_AS_STATUS_CHANGE, TS_AS_TEXT_CHANGE, TS_ATTR_FIND_BACKWARDS, TS_ATTRVAL, TS_E_INVALIDPOS, TS_E_NOLOCK, TS_LC_CREATE, TS_LC_DESTROY, TS_LF_READ, TS_LF_READWRITE, TS_LF_SYNC, TS_RUNINFO, TS_SD_LOADING, TS_SD_READONLY, TS_SS_REGIONS, TS_SS_TRANSITORY},
+};
+
+const ALL: u32 = TS_AS_TEXT_CHANGE | TS_AS_SEL_CHANGE | TS_AS_LAYOUT_CHANGE | TS_AS_ATTR_CHANGE | TS_AS_STATUS_CHANGE;
+
+#[test]
+fn synchronous_lock_is_granted_to_the_sink() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+
+    let text = sink.under_lock(&store, TS_LF_READ.0, |store| get_text(store, 0, -1, 16)).unwrap();
+    assert_eq!(text, Ok(("へんかん".to_string(), 4)));
+    assert!(matches!(sink.received()[..], [Received::LockGranted { flags }] if flags & TS_LF_READ.0 == TS_LF_READ.0));
+}
+
+#[test]
+fn edits_notify_text_and_selection_changes() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+
+    assert!(unsafe { testing::set_text(&store, "かな") }.unwrap());
+    assert_eq!(sink.take_received(), [Received::TextChange { flags: 0, start: 0, old_end: 4, new_end: 2 }, Received::SelectionChange]);
+    assert_eq!(sink.under_lock(&store, TS_LF_READ.0, selection).unwrap(), Ok((0, 2)));
+}
+
+#[test]
+fn edits_are_refused_while_locked() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+
+    let edited = sink.under_lock(&store, TS_LF_READ.0, |store| unsafe { testing::set_text(store, "かな") }.unwrap()).unwrap();
+    assert!(!edited);
+    assert_eq!(sink.received().len(), 1);
+}
+
+#[test]
+fn sink_can_lock_from_a_text_change() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+
+    let (requested, seen) = (Rc::new(RefCell::new(None)), Rc::new(RefCell::new(None)));
+    let (requested_slot, seen_slot) = (requested.clone(), seen.clone());
+    sink.on_lock(move |store| *seen_slot.borrow_mut() = Some(testing::read_text(store)));
+    sink.on_text_change(move |store| *requested_slot.borrow_mut() = Some(unsafe { store.RequestLock(TS_LF_READ.0 | TS_LF_SYNC) }));
+    assert!(unsafe { testing::set_text(&store, "かな") }.unwrap());
+    assert_eq!(requested.borrow_mut().take().map(|hr| hr.unwrap()), Some(S_OK));
+    assert_eq!(seen.borrow_mut().take(), Some(Ok("かな".to_string())));
+    assert!(matches!(sink.received()[..], [Received::TextChange { .. }, Received::LockGranted { .. }, Received::SelectionChange]));
+}
+
+#[test]
+fn input_scopes_are_refused_while_locked() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+
+    let declared = sink.under_lock(&store, TS_LF_READ.0, |store| unsafe { testing::set_input_scopes(store, &[InputScope::Hiragana]) }.unwrap()).unwrap();
+    assert!(!declared);
+    assert_eq!(sink.take_received().len(), 1);
+    assert!(unsafe { testing::set_input_scopes(&store, &[InputScope::Hiragana]) }.unwrap());
+}
+
+#[test]
+fn get_text_ranges() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+
+    let results = sink.under_lock(&store, TS_LF_READ.0, |store| {
+        [get_text(store, 1, 3, 16), get_text(store, 0, -1, 2), get_text(store, 4, -1, 16), get_text(store, 3, 2, 16), get_text(store, 0, 5, 16), get_text(store, -1, 2, 16)]
+    }).unwrap();
+    assert_eq!(
+        results,
+        [Ok(("んか".to_string(), 3)), Ok(("へん".to_string(), 2)), Ok((String::new(), 4)), Err(TS_E_INVALIDPOS), Err(TS_E_INVALIDPOS), Err(TS_E_INVALIDPOS)]
+    );
+}
+
+#[test]
+fn get_text_splits_runs_at_region_boundaries() {
+    let _com = Com::new().unwrap();
+    let store = StoreState::with_text(&region::join(&["やまだ", "", "123"])).build().unwrap();
+    let sink = MockSink::advise(&store, TS_AS_TEXT_CHANGE).unwrap();
+
+    let runs = |max: usize| {
+        sink.under_lock(&store, TS_LF_READ.0, move |store| {
+            let mut text = [0u16; 16];
+            let mut runs = vec![TS_RUNINFO::default(); max];
+            let (mut copied, mut run_count, mut next) = (0, 0, 0);
+            unsafe { store.GetText(0, -1, &mut text, &mut copied, &mut runs, &mut run_count, &mut next) }.unwrap();
+            (runs[..run_count as usize].iter().map(|run| run.uCount).collect::<Vec<_>>(), next)
+        }).unwrap()
+    };
+    assert_eq!(runs(8), (vec![3, 1, 1, 3], 8));
+    assert_eq!(runs(2), (vec![3, 1], 4));
+    assert_eq!(region::ranges(&"やまだ\u{0}\u{0}123".encode_utf16().collect::<Vec<_>>()), [0..3, 4..4, 5..8]);
+}
+
+#[test]
+fn selection_does_not_split_surrogate_pairs() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("a\u{1F600}b", ALL).unwrap();
+
+    let selected = sink.under_lock(&store, TS_LF_READWRITE.0, |store| set_selection(store, 2, 2).and_then(|()| selection(store))).unwrap();
+    assert_eq!(selected, Ok((1, 3)));
+}
+
+#[test]
+fn stores_start_in_the_given_state() {
+    let _com = Com::new().unwrap();
+    let store = StoreState { selection: Some((1, 9)), ..StoreState::with_text("へんかん") }.build().unwrap();
+    let sink = MockSink::advise(&store, TS_AS_TEXT_CHANGE | TS_AS_SEL_CHANGE).unwrap();
+
+    assert_eq!(sink.under_lock(&store, TS_LF_READ.0, |store| get_text(store, 0, -1, 16)).unwrap(), Ok(("へんかん".to_string(), 4)));
+    assert_eq!(sink.under_lock(&store, TS_LF_READ.0, selection).unwrap(), Ok((1, 4)));
+    assert_eq!(sink.received(), [Received::LockGranted { flags: TS_LF_READ.0 }, Received::LockGranted { flags: TS_LF_READ.0 }]);
+}
+
+#[test]
+fn transitory_stores_report_it_in_their_status() {
+    let _com = Com::new().unwrap();
+    let regular = StoreState::with_text("へんかん").build().unwrap();
+    let transitory = StoreState { transitory: true, ..StoreState::with_text("へんかん") }.build().unwrap();
+
+    assert_eq!(unsafe { regular.GetStatus() }.unwrap().dwStaticFlags & TS_SS_TRANSITORY, 0);
+    assert_eq!(unsafe { transitory.GetStatus() }.unwrap().dwStaticFlags, TS_SS_REGIONS | TS_SS_TRANSITORY);
+}
+
+#[test]
+fn finishing_loading_notifies_the_sink_once() {
+    let _com = Com::new().unwrap();
+    let store = StoreState::with_text("へんかん").build().unwrap();
+    let sink = MockSink::advise(&store, TS_AS_STATUS_CHANGE).unwrap();
+    assert_eq!(unsafe { store.GetStatus() }.unwrap().dwDynamicFlags, TS_SD_READONLY | TS_SD_LOADING);
+
+    assert!(unsafe { testing::finish_loading(&store) }.unwrap());
+    assert!(!unsafe { testing::finish_loading(&store) }.unwrap());
+    assert_eq!(unsafe { store.GetStatus() }.unwrap().dwDynamicFlags, TS_SD_READONLY);
+    assert_eq!(sink.received(), [Received::StatusChange { flags: TS_SD_READONLY }]);
+
+    let loaded = StoreState { status: Some(0), ..StoreState::with_text("へんかん") }.build().unwrap();
+    assert_eq!(unsafe { loaded.GetStatus() }.unwrap().dwDynamicFlags, 0);
+}
+
+/// Lays out each character as a 10x20 cell from the origin.
+struct Cells;
+
+impl LayoutProvider for Cells {
+    fn text_ext(&self, start: i32, end: i32) -> Option<(RECT, bool)> {
+        Some((RECT { left: start * 10, top: 0, right: end * 10, bottom: 20 }, false))
+    }
+
+    fn screen_ext(&self) -> Option<RECT> {
+        Some(RECT { left: 0, top: 0, right: 100, bottom: 20 })
+    }
+}
+
+#[test]
+fn layout_changes_are_forwarded_to_the_sink() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("へんかん", ALL).unwrap();
+    let text_ext = |store: &ITextStoreACP| {
+        let (mut rect, mut clipped) = (RECT::default(), BOOL(0));
+        unsafe { store.GetTextExt(layout::VIEW, 1, 3, &mut rect, &mut clipped) }.map(|()| rect).map_err(|e| e.code())
+    };
+    assert_eq!(sink.under_lock(&store, TS_LF_READ.0, text_ext).unwrap(), Err(E_NOTIMPL));
+
+    unsafe { testing::set_layout_provider(&store, Some(Arc::new(Cells))) }.unwrap();
+    assert_eq!(sink.under_lock(&store, TS_LF_READ.0, text_ext).unwrap(), Ok(RECT { left: 10, top: 0, right: 30, bottom: 20 }));
+    assert_eq!(unsafe { store.GetScreenExt(layout::VIEW) }.unwrap().right, 100);
+    assert_eq!(text_ext(&store), Err(TS_E_NOLOCK));
+
+    unsafe { testing::set_layout_provider(&store, None) }.unwrap();
+    let changes: Vec<_> = sink.received().into_iter().filter(|received| matches!(received, Received::LayoutChange { .. })).collect();
+    assert_eq!(
+        changes,
+        [Received::LayoutChange { code: TS_LC_CREATE.0, view: layout::VIEW }, Received::LayoutChange { code: TS_LC_DESTROY.0, view: layout::VIEW }]
+    );
+}
+
+/// The input scopes reported at `acp`.
+fn scopes_at(store: &ITextStoreACP, acp: i32) -> Vec<InputScope> {
+    unsafe { store.RequestAttrsAtPosition(acp, &[GUID_PROP_INPUTSCOPE], 0) }.unwrap();
+    let mut values = [TS_ATTRVAL::default()];
+    let mut fetched = 0;
+    unsafe { store.RetrieveRequestedAttrs(&mut values, &mut fetched) }.unwrap();
+    assert_eq!(fetched, 1);
+
+    let value = ManuallyDrop::into_inner(std::mem::take(&mut values[0].varValue));
+    testing::input_scopes(&value).unwrap()
+}
+
+#[test]
+fn range_input_scopes_override_the_document() {
+    let _com = Com::new().unwrap();
+    let store = StoreState {
+        input_scopes: vec![InputScope::Text],
+        range_input_scopes: vec![(0..3, vec![InputScope::Hiragana]), (8..11, vec![InputScope::Digits])],
+        ..StoreState::with_text("やまだ たろう 123")
+    }
+    .build()
+    .unwrap();
+
+    assert_eq!(scopes_at(&store, 0), [InputScope::Hiragana]);
+    assert_eq!(scopes_at(&store, 4), [InputScope::Text]);
+    assert_eq!(scopes_at(&store, 9), [InputScope::Digits]);
+
+    let find = |start, halt, flags| {
+        let (mut next, mut found, mut offset) = (0, BOOL(0), 0);
+        unsafe { store.FindNextAttrTransition(start, halt, &[GUID_PROP_INPUTSCOPE], flags, &mut next, &mut found, &mut offset) }.unwrap();
+        (next, found.as_bool())
+    };
+    assert_eq!(find(0, -1, 0), (3, true));
+    assert_eq!(find(3, -1, 0), (8, true));
+    assert_eq!(find(4, 7, 0), (7, false));
+    assert_eq!(find(10, 0, TS_ATTR_FIND_BACKWARDS), (8, true));
+
+    assert!(unsafe { testing::set_text(&store, "かな") }.unwrap());
+    assert_eq!(scopes_at(&store, 0), [InputScope::Text]);
+}
+
+#[test]
+fn changing_input_scopes_notifies_the_changed_range() {
+    let _com = Com::new().unwrap();
+    let (store, sink) = testing::advised("やまだ たろう 123", ALL).unwrap();
+    let attrs_change = |start, end| Received::AttrsChange { start, end, count: 1 };
+
+    assert!(unsafe { testing::set_input_scopes(&store, &[InputScope::Text]) }.unwrap());
+    assert_eq!(sink.take_received(), [attrs_change(0, 11)]);
+    assert!(unsafe { testing::set_input_scopes(&store, &[InputScope::Text]) }.unwrap());
+    assert_eq!(sink.take_received(), []);
+
+    assert!(unsafe { testing::set_range_input_scopes(&store, 8..11, &[InputScope::Digits]) }.unwrap());
+    assert_eq!(sink.take_received(), [attrs_change(8, 11)]);
+    assert!(unsafe { testing::set_range_input_scopes(&store, 0..11, &[InputScope::Text]) }.unwrap());
+    assert_eq!(sink.take_received(), [attrs_change(8, 11)]);
+
+    assert!(unsafe { testing::set_input_scopes(&store, &[InputScope::Hiragana]) }.unwrap());
+    assert_eq!(sink.take_received(), []);
+}
+
+#[test]
+fn document_input_scopes_are_reported_as_an_attribute() {
+    let _com = Com::new().unwrap();
+    let store = StoreState { input_scopes: vec![InputScope::Hiragana, InputScope::PersonalName], ..StoreState::with_text("へんかん") }.build().unwrap();
+
+    assert_eq!(scopes_at(&store, 0), [InputScope::Hiragana, InputScope::PersonalName]);
+
+    let (mut next, mut found, mut offset) = (0, BOOL(1), 0);
+    unsafe { store.FindNextAttrTransition(0, -1, &[GUID_PROP_INPUTSCOPE], 0, &mut next, &mut found, &mut offset) }.unwrap();
+    assert_eq!((next, found), (4, BOOL(0)));
+}